@@ -0,0 +1,144 @@
+use crate::cells::{MAX_COLUMNS, MAX_ROWS, column_label_from_index};
+use crate::formulas::{Reference, map_odf_references, map_references};
+use crate::row_blocks::RowBlock;
+use std::collections::HashMap;
+
+/// A card of the template sheet, such as a name badge in `A1:C6`, tiled
+/// `across` × `down` times per sheet. Every card is filled with the next
+/// record, left to right and then top to bottom; a new sheet starts when the
+/// sheet is full.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CardLayout {
+    pub card: RowBlock,
+    pub across: u32,
+    pub down: u32,
+}
+
+impl CardLayout {
+    /// Parses a card range such as `A1:C6` and a tiling such as `2x5`.
+    pub fn parse(card: &str, tiles: &str) -> Result<Self, String> {
+        let range = RowBlock::parse(card)
+            .filter(|card| card.last_col != u32::MAX)
+            .ok_or_else(|| format!("\"{}\" is not a card range such as A1:C6", card.trim()))?;
+        let (across, down) = parse_tiles(tiles).ok_or_else(|| {
+            format!(
+                "\"{}\" is not a number of cards across and down such as 2x5",
+                tiles.trim()
+            )
+        })?;
+        Self::new(range, across, down)
+    }
+
+    /// Fails when the cards do not fit on a sheet.
+    pub fn new(card: RowBlock, across: u32, down: u32) -> Result<Self, String> {
+        let layout = Self { card, across, down };
+        let end = |first: u32, size: u32, count: u32| {
+            size.checked_mul(count)
+                .and_then(|size| size.checked_add(first))
+        };
+        if end(card.first_col, layout.width(), across).is_none_or(|end| end > MAX_COLUMNS) {
+            return Err(format!(
+                "{} cards of {} across reach past column {} of a sheet",
+                across,
+                card,
+                column_label_from_index(MAX_COLUMNS - 1)
+            ));
+        }
+        if end(card.first_row, card.height(), down).is_none_or(|end| end > MAX_ROWS) {
+            return Err(format!(
+                "{} cards of {} down reach past row {} of a sheet",
+                down, card, MAX_ROWS
+            ));
+        }
+        Ok(layout)
+    }
+
+    pub fn per_sheet(&self) -> usize {
+        self.across as usize * self.down as usize
+    }
+
+    pub fn width(&self) -> u32 {
+        self.card.last_col - self.card.first_col + 1
+    }
+
+    /// Rows and columns between the template card and card `index`.
+    pub fn offset(&self, index: usize) -> (u32, u32) {
+        let across = self.across as usize;
+        // Cards fit on the sheet, so the offsets of its cards fit in a `u32`.
+        (
+            (index / across * self.card.height() as usize) as u32,
+            (index % across * self.width() as usize) as u32,
+        )
+    }
+
+    /// Where a cell of the template card ends up in card `index`.
+    pub fn cell(&self, index: usize, (row, col): (u32, u32)) -> (u32, u32) {
+        let (rows, cols) = self.offset(index);
+        (row + rows, col + cols)
+    }
+
+    pub fn contains(&self, (row, col): (u32, u32)) -> bool {
+        self.card.contains_row(row) && self.card.contains_col(col)
+    }
+
+    /// A formula of the template card as written into card `index`; relative
+    /// references move with the card.
+    pub fn move_formula(&self, formula: &str, sheet: &str, index: usize) -> String {
+        map_references(formula, sheet, &mut self.mover(index))
+    }
+
+    /// Like [`CardLayout::move_formula`] for OpenDocument formulas.
+    pub fn move_odf_formula(&self, formula: &str, sheet: &str, index: usize) -> String {
+        map_odf_references(formula, sheet, &mut self.mover(index))
+    }
+
+    fn mover(
+        &self,
+        index: usize,
+    ) -> impl FnMut(Reference, Option<Reference>) -> (Reference, Option<Reference>) {
+        let (rows, cols) = self.offset(index);
+        let moved = move |reference: Reference| reference.moved(i64::from(rows), i64::from(cols));
+        move |first, second| (moved(first), second.map(moved))
+    }
+}
+
+/// Parses a tiling such as `2x5`: cards across, then down, at most as many
+/// as a sheet has columns and rows.
+pub fn parse_tiles(text: &str) -> Option<(u32, u32)> {
+    let text = text.trim().to_ascii_lowercase();
+    let (across, down) = text.split_once(['x', '×', '*'])?;
+    let across = across.trim().parse::<u32>().ok()?;
+    let down = down.trim().parse::<u32>().ok()?;
+    ((1..=MAX_COLUMNS).contains(&across) && (1..=MAX_ROWS).contains(&down))
+        .then_some((across, down))
+}
+
+/// The cards of one generated sheet with the placeholder values of each
+/// filled card; cards past the end of `records` stay empty.
+#[derive(Clone, Copy)]
+pub struct CardContent<'a> {
+    pub layout: CardLayout,
+    pub records: &'a [HashMap<String, String>],
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_a_layout() {
+        let layout = CardLayout::parse("A1:C6", "2 x 5").unwrap();
+        assert_eq!((layout.across, layout.down, layout.per_sheet()), (2, 5, 10));
+        assert_eq!(layout.cell(3, (1, 1)), (7, 4));
+    }
+
+    #[test]
+    fn rejects_layouts_past_the_sheet() {
+        assert!(parse_tiles("100000x100000").is_none());
+        assert!(parse_tiles("0x5").is_none());
+        assert!(CardLayout::parse("A1:C6", "5462x1").is_err());
+        assert!(CardLayout::parse("A1:C6", "5461x1").is_ok());
+        assert!(CardLayout::parse("A1:C6", "1x174763").is_err());
+        assert!(CardLayout::parse("A:C", "2x5").is_err());
+    }
+}
@@ -0,0 +1,360 @@
+use crate::cards::CardContent;
+use crate::row_blocks::RowExpansion;
+use chrono::{NaiveDate, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+
+/// Values to write into one sheet, keyed by zero-based `(row, column)`.
+pub type CellReplacements = BTreeMap<(u32, u32), CellValue>;
+
+/// What goes into one generated copy of the template sheet.
+pub struct SheetContent<'a> {
+    pub name: String,
+    pub cells: &'a CellReplacements,
+    /// Values of the `{{placeholders}}` in the sheet's text, by name.
+    pub placeholders: &'a HashMap<String, String>,
+    /// How often the repeated template rows are written, if there are any.
+    pub rows: Option<RowExpansion>,
+    /// The cards of the sheet when the template is tiled with cards.
+    pub cards: Option<CardContent<'a>>,
+}
+
+/// Rows of a sheet in Excel and LibreOffice.
+pub const MAX_ROWS: u32 = 1_048_576;
+/// Columns of a sheet, up to `XFD`.
+pub const MAX_COLUMNS: u32 = 16_384;
+
+/// Date format of `date` values without an explicit source format.
+pub const DEFAULT_DATE_FORMAT: &str = "%Y-%m-%d";
+/// Date format of `datetime` values without an explicit source format.
+pub const DEFAULT_DATETIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+/// A typed value written into a template cell.
+#[derive(Clone, Debug, PartialEq)]
+pub enum CellValue {
+    Text(String),
+    Number(f64),
+    Boolean(bool),
+    DateTime(NaiveDateTime),
+}
+
+impl CellValue {
+    /// Spreadsheet date serial: days since 1899-12-30, or since 1904-01-01
+    /// in workbooks using the 1904 date system, with the time of day as
+    /// fraction.
+    pub fn date_serial(value: &NaiveDateTime, date1904: bool) -> f64 {
+        let (year, month, day) = if date1904 {
+            (1904, 1, 1)
+        } else {
+            (1899, 12, 30)
+        };
+        let epoch = NaiveDate::from_ymd_opt(year, month, day)
+            .and_then(|date| date.and_hms_opt(0, 0, 0))
+            .unwrap_or_default();
+        (*value - epoch).num_milliseconds() as f64 / 86_400_000.0
+    }
+}
+
+impl fmt::Display for CellValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CellValue::Text(value) => write!(f, "{}", value),
+            CellValue::Number(value) => write!(f, "{}", value),
+            CellValue::Boolean(true) => write!(f, "TRUE"),
+            CellValue::Boolean(false) => write!(f, "FALSE"),
+            CellValue::DateTime(value) if value.time() == Default::default() => {
+                write!(f, "{}", value.date())
+            }
+            CellValue::DateTime(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+/// How the text of a data column is written into a cell.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ValueType {
+    /// Numbers and booleans become typed cells, everything else text.
+    /// Numbers with leading zeros, such as postal codes, stay text.
+    #[default]
+    Auto,
+    Text,
+    Number,
+    Integer,
+    Boolean,
+    Date,
+    #[serde(rename = "datetime")]
+    DateTime,
+}
+
+impl ValueType {
+    pub const ALL: [ValueType; 7] = [
+        ValueType::Auto,
+        ValueType::Text,
+        ValueType::Number,
+        ValueType::Integer,
+        ValueType::Boolean,
+        ValueType::Date,
+        ValueType::DateTime,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            ValueType::Auto => "auto",
+            ValueType::Text => "text",
+            ValueType::Number => "number",
+            ValueType::Integer => "integer",
+            ValueType::Boolean => "boolean",
+            ValueType::Date => "date",
+            ValueType::DateTime => "datetime",
+        }
+    }
+
+    pub fn from_label(label: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|value_type| value_type.label().eq_ignore_ascii_case(label.trim()))
+    }
+
+    pub fn is_auto(&self) -> bool {
+        *self == ValueType::Auto
+    }
+
+    /// Whether values are parsed with a source format.
+    pub fn uses_format(self) -> bool {
+        matches!(self, ValueType::Date | ValueType::DateTime)
+    }
+
+    pub fn default_format(self) -> &'static str {
+        match self {
+            ValueType::DateTime => DEFAULT_DATETIME_FORMAT,
+            _ => DEFAULT_DATE_FORMAT,
+        }
+    }
+
+    /// Converts `raw` into a cell value. Dates are parsed with `format`, a
+    /// `chrono` format string such as `%d.%m.%Y`. Empty values always become
+    /// empty text.
+    pub fn convert(self, raw: &str, format: Option<&str>) -> Result<CellValue, String> {
+        let trimmed = raw.trim();
+        if trimmed.is_empty() {
+            return Ok(CellValue::Text(String::new()));
+        }
+        match self {
+            ValueType::Auto => Ok(detect_value(raw)),
+            ValueType::Text => Ok(CellValue::Text(raw.to_string())),
+            ValueType::Number => parse_number(trimmed)
+                .map(CellValue::Number)
+                .ok_or_else(|| format!("\"{}\" is not a number", raw)),
+            ValueType::Integer => trimmed
+                .parse::<i64>()
+                .map(|value| CellValue::Number(value as f64))
+                .map_err(|_| format!("\"{}\" is not an integer", raw)),
+            ValueType::Boolean => parse_boolean(trimmed)
+                .map(CellValue::Boolean)
+                .ok_or_else(|| format!("\"{}\" is not a boolean", raw)),
+            ValueType::Date | ValueType::DateTime => {
+                let format = format
+                    .filter(|format| !format.trim().is_empty())
+                    .unwrap_or(self.default_format());
+                parse_date_time(trimmed, format)
+                    .map(CellValue::DateTime)
+                    .ok_or_else(|| format!("\"{}\" does not match the date format {}", raw, format))
+            }
+        }
+    }
+    /// Like [`ValueType::convert`] for a typed spreadsheet value, which is
+    /// kept when it already has the wanted type; `raw` is its text.
+    pub fn convert_value(
+        self,
+        value: &CellValue,
+        raw: &str,
+        format: Option<&str>,
+    ) -> Result<CellValue, String> {
+        match (self, value) {
+            (ValueType::Auto, _)
+            | (ValueType::Number, CellValue::Number(_))
+            | (ValueType::Boolean, CellValue::Boolean(_))
+            | (ValueType::DateTime, CellValue::DateTime(_)) => Ok(value.clone()),
+            (ValueType::Integer, CellValue::Number(number)) if number.fract() == 0.0 => {
+                Ok(value.clone())
+            }
+            (ValueType::Date, CellValue::DateTime(date_time)) => Ok(CellValue::DateTime(
+                date_time.date().and_time(Default::default()),
+            )),
+            (ValueType::Text, _) => Ok(CellValue::Text(raw.to_string())),
+            _ => self.convert(raw, format),
+        }
+    }
+}
+
+fn detect_value(raw: &str) -> CellValue {
+    let trimmed = raw.trim();
+    let digits = trimmed.trim_start_matches(['-', '+']);
+    let leading_zero = digits.len() > 1 && digits.starts_with('0') && !digits.starts_with("0.");
+    if !leading_zero && let Some(value) = parse_number(trimmed) {
+        return CellValue::Number(value);
+    }
+    match trimmed.to_ascii_lowercase().as_str() {
+        "true" => CellValue::Boolean(true),
+        "false" => CellValue::Boolean(false),
+        _ => CellValue::Text(raw.to_string()),
+    }
+}
+
+/// Parses plain decimal numbers; `inf`, `NaN` and hexadecimal are text.
+fn parse_number(value: &str) -> Option<f64> {
+    let is_decimal = value
+        .chars()
+        .all(|ch| ch.is_ascii_digit() || matches!(ch, '.' | '-' | '+' | 'e' | 'E'));
+    if !is_decimal || !value.chars().any(|ch| ch.is_ascii_digit()) {
+        return None;
+    }
+    value.parse::<f64>().ok().filter(|value| value.is_finite())
+}
+
+pub fn parse_boolean(value: &str) -> Option<bool> {
+    match value.to_ascii_lowercase().as_str() {
+        "true" | "yes" | "1" => Some(true),
+        "false" | "no" | "0" => Some(false),
+        _ => None,
+    }
+}
+
+pub fn parse_date_time(value: &str, format: &str) -> Option<NaiveDateTime> {
+    NaiveDateTime::parse_from_str(value, format)
+        .ok()
+        .or_else(|| {
+            NaiveDate::parse_from_str(value, format)
+                .ok()
+                .and_then(|date| date.and_hms_opt(0, 0, 0))
+        })
+}
+
+/// Parses an A1-style reference such as `B4` into a zero-based
+/// `(row, column)`, rejecting cells outside a sheet.
+pub fn parse_cell_reference(cell: &str) -> Option<(u32, u32)> {
+    let letters = cell.chars().take_while(char::is_ascii_alphabetic).count();
+    let (col_part, row_part) = cell.split_at(letters);
+    if col_part.is_empty() || row_part.is_empty() || !row_part.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+
+    let mut col_index: u32 = 0;
+    for ch in col_part.bytes() {
+        col_index = col_index
+            .checked_mul(26)?
+            .checked_add(u32::from(ch.to_ascii_uppercase() - b'A' + 1))?;
+        if col_index > MAX_COLUMNS {
+            return None;
+        }
+    }
+    let row = row_part.parse::<u32>().ok()?;
+    if !(1..=MAX_ROWS).contains(&row) {
+        return None;
+    }
+    Some((row - 1, col_index - 1))
+}
+
+/// Most cells a single mapping may write to.
+const MAX_TARGET_CELLS: usize = 10_000;
+
+/// Parses the cells a mapping writes to: a cell such as `B4`, a range such
+/// as `B2:D2`, or a list of both separated by commas or semicolons such as
+/// `B2, H40`. Cells are returned in reading order without duplicates.
+pub fn parse_cell_targets(text: &str) -> Result<Vec<(u32, u32)>, String> {
+    let invalid = || format!("\"{}\" is not a cell reference", text.trim());
+    let mut cells = BTreeSet::new();
+    for part in text.split([',', ';']).map(str::trim) {
+        if part.is_empty() {
+            continue;
+        }
+        let (first, last) = match part.split_once(':') {
+            Some((first, last)) => (
+                parse_cell_reference(first.trim()).ok_or_else(invalid)?,
+                parse_cell_reference(last.trim()).ok_or_else(invalid)?,
+            ),
+            None => {
+                let cell = parse_cell_reference(part).ok_or_else(invalid)?;
+                (cell, cell)
+            }
+        };
+        let (top, bottom) = (first.0.min(last.0), first.0.max(last.0));
+        let (left, right) = (first.1.min(last.1), first.1.max(last.1));
+        let count = (bottom - top + 1) as usize * (right - left + 1) as usize;
+        if cells.len() + count > MAX_TARGET_CELLS {
+            return Err(format!(
+                "\"{}\" covers more than {} cells",
+                text.trim(),
+                MAX_TARGET_CELLS
+            ));
+        }
+        for row in top..=bottom {
+            for col in left..=right {
+                cells.insert((row, col));
+            }
+        }
+    }
+    if cells.is_empty() {
+        return Err(invalid());
+    }
+    Ok(cells.into_iter().collect())
+}
+
+/// The A1-style label of a zero-based cell position.
+pub fn cell_label(row: u32, col: u32) -> String {
+    format!("{}{}", column_label_from_index(col), row + 1)
+}
+
+pub fn column_label_from_index(index: u32) -> String {
+    let mut idx = index + 1;
+    let mut label = String::new();
+    while idx > 0 {
+        let rem = ((idx - 1) % 26) as u8;
+        label.insert(0, char::from(b'A' + rem));
+        idx = (idx - 1) / 26;
+    }
+    label
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_cell_references() {
+        assert_eq!(parse_cell_reference("B4"), Some((3, 1)));
+        assert_eq!(
+            parse_cell_reference("xfd1048576"),
+            Some((1_048_575, 16_383))
+        );
+        for invalid in [
+            "",
+            "A",
+            "4",
+            "A0",
+            "A1B",
+            "1A",
+            "XFE1",
+            "A1048577",
+            "AAAAAAAA1",
+        ] {
+            assert_eq!(parse_cell_reference(invalid), None, "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn parses_cell_targets() {
+        assert_eq!(parse_cell_targets("B2,"), Ok(vec![(1, 1)]));
+        assert_eq!(
+            parse_cell_targets("D2:B2; B2 ,A3"),
+            Ok(vec![(1, 1), (1, 2), (1, 3), (2, 0)])
+        );
+        assert!(parse_cell_targets("A1B").is_err());
+        assert!(parse_cell_targets(" , ").is_err());
+        assert!(parse_cell_targets("A1:XFD1048576").is_err());
+        assert!(parse_cell_targets("A1:A10000").is_ok());
+        assert!(parse_cell_targets("A1:A10000,B1").is_err());
+    }
+}
@@ -0,0 +1,451 @@
+use encoding_rs::{CoderResult, Decoder, Encoding, UTF_8, UTF_16BE, UTF_16LE, WINDOWS_1252};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom};
+use std::ops::Range;
+use std::path::Path;
+
+/// Delimiters tried when none is set.
+const DELIMITERS: [char; 5] = [',', ';', '\t', '|', ':'];
+
+/// Lines looked at to detect the delimiter, quote and header row.
+const SNIFF_LINES: usize = 50;
+
+/// Bytes read from the start of a file to detect its dialect.
+const SNIFF_BYTES: usize = 64 * 1024;
+
+/// Size of the buffers used while decoding.
+const DECODE_BUFFER: usize = 16 * 1024;
+
+/// How a CSV file is read. Unset delimiters, quotes and encodings are
+/// detected from the file.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CsvOptions {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub delimiter: Option<char>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quote: Option<char>,
+    /// Escape character inside quotes, such as `\`; quotes are escaped by
+    /// doubling them otherwise.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub escape: Option<char>,
+    /// Lines starting with this character are ignored.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comment: Option<char>,
+    /// Encoding label such as `utf-8`, `windows-1252` or `utf-16le`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub encoding: Option<String>,
+    /// Preamble lines above the header row. Without it, leading lines
+    /// without a delimiter are skipped.
+    #[serde(skip_serializing_if = "is_zero")]
+    pub skip_lines: usize,
+    /// Trims whitespace around headers and values.
+    #[serde(skip_serializing_if = "is_false")]
+    pub trim: bool,
+    /// Accepts rows with more or fewer fields than the others.
+    #[serde(skip_serializing_if = "is_false")]
+    pub flexible: bool,
+}
+
+fn is_zero(value: &usize) -> bool {
+    *value == 0
+}
+
+fn is_false(value: &bool) -> bool {
+    !value
+}
+
+/// The dialect a CSV file is read with, from the options or detected.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CsvDialect {
+    pub delimiter: char,
+    pub quote: char,
+    pub encoding: &'static Encoding,
+    /// Lines skipped above the header row.
+    pub skipped_lines: usize,
+    /// Whether the first row looks like column names.
+    pub has_headers: bool,
+}
+
+impl CsvOptions {
+    pub fn is_empty(&self) -> bool {
+        *self == CsvOptions::default()
+    }
+
+    /// Detects what the options leave open from the start of a file.
+    pub fn detect(&self, path: &Path) -> Result<CsvDialect, String> {
+        let mut file = File::open(path).map_err(|err| err.to_string())?;
+        self.sniff(&mut file)
+    }
+
+    fn sniff(&self, file: &mut File) -> Result<CsvDialect, String> {
+        let mut sample = Vec::with_capacity(SNIFF_BYTES);
+        file.take(SNIFF_BYTES as u64)
+            .read_to_end(&mut sample)
+            .map_err(|err| err.to_string())?;
+        let encoding = match self.encoding.as_deref().map(str::trim) {
+            Some(label) if !label.is_empty() => Encoding::for_label(label.as_bytes())
+                .ok_or_else(|| format!("Unknown encoding \"{}\"", label))?,
+            _ => detect_encoding(&sample),
+        };
+        let (text, encoding, _) = encoding.decode(&sample);
+        // The last line of a partial sample may be cut off.
+        let text = match sample.len() < SNIFF_BYTES {
+            true => &text[..],
+            false => text.rfind('\n').map_or(&text[..], |end| &text[..end + 1]),
+        };
+        let text = skip_lines(text, self.skip_lines);
+
+        for (name, value) in [
+            ("delimiter", self.delimiter),
+            ("quote", self.quote),
+            ("escape", self.escape),
+            ("comment", self.comment),
+        ] {
+            if value.is_some_and(|ch| !ch.is_ascii()) {
+                return Err(format!("The {} must be an ASCII character", name));
+            }
+        }
+        let lines = sample_lines(text, self.comment);
+        let delimiter = self
+            .delimiter
+            .unwrap_or_else(|| detect_delimiter(&lines, self.quote.unwrap_or('"')));
+        let (text, skipped_lines) = match self.skip_lines {
+            0 => {
+                let preamble = preamble_lines(text, delimiter);
+                (skip_lines(text, preamble), preamble)
+            }
+            count => (text, count),
+        };
+        let lines = sample_lines(text, self.comment);
+        let quote = self
+            .quote
+            .unwrap_or_else(|| detect_quote(&lines, delimiter));
+        let has_headers = detect_headers(&lines, delimiter, quote);
+        Ok(CsvDialect {
+            delimiter,
+            quote,
+            encoding,
+            skipped_lines,
+            has_headers,
+        })
+    }
+
+    /// Opens a CSV file for reading record by record, decoding it on the
+    /// fly and skipping the lines above the header row.
+    pub fn open(&self, path: &Path, has_headers: bool) -> Result<(CsvDialect, CsvReader), String> {
+        let mut file = File::open(path).map_err(|err| err.to_string())?;
+        let dialect = self.sniff(&mut file)?;
+        file.seek(SeekFrom::Start(0))
+            .map_err(|err| err.to_string())?;
+
+        let mut input = BufReader::new(DecodingReader::new(file, dialect.encoding));
+        let mut line = Vec::new();
+        for _ in 0..dialect.skipped_lines {
+            line.clear();
+            input
+                .read_until(b'\n', &mut line)
+                .map_err(|err| err.to_string())?;
+        }
+        let reader = csv::ReaderBuilder::new()
+            .has_headers(has_headers)
+            .delimiter(dialect.delimiter as u8)
+            .quote(dialect.quote as u8)
+            .escape(self.escape.map(|ch| ch as u8))
+            .comment(self.comment.map(|ch| ch as u8))
+            .flexible(self.flexible)
+            .trim(match self.trim {
+                true => csv::Trim::All,
+                false => csv::Trim::None,
+            })
+            .from_reader(Box::new(input) as Box<dyn Read + Send>);
+        Ok((dialect, reader))
+    }
+}
+
+/// A CSV reader over decoded file contents.
+pub type CsvReader = csv::Reader<Box<dyn Read + Send>>;
+
+/// Reads a file as UTF-8, decoding it from another encoding as it goes.
+struct DecodingReader<R> {
+    inner: R,
+    decoder: Decoder,
+    input: Vec<u8>,
+    input_range: Range<usize>,
+    output: Vec<u8>,
+    output_range: Range<usize>,
+    eof: bool,
+    finished: bool,
+}
+
+impl<R: Read> DecodingReader<R> {
+    fn new(inner: R, encoding: &'static Encoding) -> Self {
+        Self {
+            inner,
+            decoder: encoding.new_decoder_with_bom_removal(),
+            input: vec![0; DECODE_BUFFER],
+            input_range: 0..0,
+            output: vec![0; DECODE_BUFFER],
+            output_range: 0..0,
+            eof: false,
+            finished: false,
+        }
+    }
+}
+
+impl<R: Read> Read for DecodingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.output_range.is_empty() && !self.finished {
+            if self.input_range.is_empty() && !self.eof {
+                let read = self.inner.read(&mut self.input)?;
+                self.input_range = 0..read;
+                self.eof = read == 0;
+            }
+            let (result, read, written, _) = self.decoder.decode_to_utf8(
+                &self.input[self.input_range.clone()],
+                &mut self.output,
+                self.eof,
+            );
+            self.input_range.start += read;
+            self.output_range = 0..written;
+            self.finished = self.eof && result == CoderResult::InputEmpty;
+        }
+        let count = buf.len().min(self.output_range.len());
+        let start = self.output_range.start;
+        buf[..count].copy_from_slice(&self.output[start..start + count]);
+        self.output_range.start += count;
+        Ok(count)
+    }
+}
+
+/// A byte order mark decides the encoding; otherwise UTF-8 when the bytes
+/// are valid UTF-8, UTF-16 when every other byte is zero, and Windows-1252
+/// for anything else.
+fn detect_encoding(bytes: &[u8]) -> &'static Encoding {
+    if let Some((encoding, _)) = Encoding::for_bom(bytes) {
+        return encoding;
+    }
+    // A sample may end within a character.
+    match std::str::from_utf8(bytes) {
+        Ok(_) => return UTF_8,
+        Err(err) if err.error_len().is_none() => return UTF_8,
+        Err(_) => {}
+    }
+    let sample = &bytes[..bytes.len().min(1000)];
+    let zeros_at = |offset: usize| {
+        sample
+            .iter()
+            .skip(offset)
+            .step_by(2)
+            .filter(|byte| **byte == 0)
+            .count()
+    };
+    let half = sample.len() / 2;
+    if half > 0 && zeros_at(1) * 10 >= half * 9 {
+        UTF_16LE
+    } else if half > 0 && zeros_at(0) * 10 >= half * 9 {
+        UTF_16BE
+    } else {
+        WINDOWS_1252
+    }
+}
+
+fn skip_lines(text: &str, count: usize) -> &str {
+    let mut rest = text;
+    for _ in 0..count {
+        match rest.find('\n') {
+            Some(end) => rest = &rest[end + 1..],
+            None => return "",
+        }
+    }
+    rest
+}
+
+/// Leading lines without `delimiter`, such as an export date above the
+/// header row. None when no line has a delimiter.
+fn preamble_lines(text: &str, delimiter: char) -> usize {
+    text.lines()
+        .position(|line| line.contains(delimiter))
+        .unwrap_or(0)
+}
+
+/// The first non-empty lines that are not comments.
+fn sample_lines(text: &str, comment: Option<char>) -> Vec<&str> {
+    text.lines()
+        .map(|line| line.trim_end_matches('\r'))
+        .filter(|line| !line.trim().is_empty())
+        .filter(|line| comment.is_none_or(|comment| !line.starts_with(comment)))
+        .take(SNIFF_LINES)
+        .collect()
+}
+
+/// Occurrences of `delimiter` outside of quotes.
+fn count_outside_quotes(line: &str, delimiter: char, quote: char) -> usize {
+    let mut quoted = false;
+    let mut count = 0;
+    for ch in line.chars() {
+        if ch == quote {
+            quoted = !quoted;
+        } else if ch == delimiter && !quoted {
+            count += 1;
+        }
+    }
+    count
+}
+
+/// The delimiter found the same number of times on the most lines, and the
+/// most often among equally consistent ones. Defaults to a comma.
+fn detect_delimiter(lines: &[&str], quote: char) -> char {
+    let mut best = (',', 0, 0);
+    for delimiter in DELIMITERS {
+        let counts = lines
+            .iter()
+            .map(|line| count_outside_quotes(line, delimiter, quote))
+            .collect::<Vec<_>>();
+        let Some(&common) = counts
+            .iter()
+            .filter(|count| **count > 0)
+            .max_by_key(|count| counts.iter().filter(|other| other == count).count())
+        else {
+            continue;
+        };
+        let consistent = counts.iter().filter(|count| **count == common).count();
+        if (consistent, common) > (best.1, best.2) {
+            best = (delimiter, consistent, common);
+        }
+    }
+    best.0
+}
+
+/// A single quote when fields start with one more often than with a double
+/// quote.
+fn detect_quote(lines: &[&str], delimiter: char) -> char {
+    let starts_with = |quote: char| {
+        lines
+            .iter()
+            .flat_map(|line| line.split(delimiter))
+            .filter(|field| field.trim_start().starts_with(quote))
+            .count()
+    };
+    match starts_with('\'') > starts_with('"') {
+        true => '\'',
+        false => '"',
+    }
+}
+
+/// The first row holds column names when its fields are distinct, not
+/// empty and not numbers, and some column below it holds numbers or none
+/// of the rows below repeats them.
+fn detect_headers(lines: &[&str], delimiter: char, quote: char) -> bool {
+    let split = |line: &str| {
+        line.split(delimiter)
+            .map(|field| field.trim().trim_matches(quote).trim().to_string())
+            .collect::<Vec<_>>()
+    };
+    let Some((first, rest)) = lines.split_first() else {
+        return false;
+    };
+    let first = split(first);
+    let rest = rest.iter().map(|line| split(line)).collect::<Vec<_>>();
+    let is_number = |value: &str| value.replace(',', ".").parse::<f64>().is_ok();
+    let mut distinct = first.clone();
+    distinct.sort();
+    distinct.dedup();
+    if distinct.len() != first.len()
+        || first
+            .iter()
+            .any(|value| value.is_empty() || is_number(value))
+    {
+        return false;
+    }
+    if rest.is_empty() {
+        return true;
+    }
+    let numeric_column = (0..first.len()).any(|column| {
+        rest.iter()
+            .filter_map(|row| row.get(column))
+            .filter(|value| !value.is_empty())
+            .all(|value| is_number(value))
+            && rest
+                .iter()
+                .any(|row| row.get(column).is_some_and(|value| !value.is_empty()))
+    });
+    let repeated = rest.iter().any(|row| {
+        row.iter()
+            .zip(&first)
+            .any(|(value, header)| !value.is_empty() && value == header)
+    });
+    numeric_column || !repeated
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    /// Detects the dialect of `bytes` and reads them as records, headers
+    /// first.
+    fn read(name: &str, bytes: &[u8]) -> (CsvDialect, Vec<Vec<String>>) {
+        let path: PathBuf =
+            std::env::temp_dir().join(format!("csv-dialect-{}-{}.csv", std::process::id(), name));
+        std::fs::write(&path, bytes).unwrap();
+        let (dialect, mut reader) = CsvOptions::default().open(&path, false).unwrap();
+        let records = reader
+            .records()
+            .map(|record| record.unwrap().iter().map(String::from).collect())
+            .collect();
+        std::fs::remove_file(&path).unwrap();
+        (dialect, records)
+    }
+
+    #[test]
+    fn sniffs_semicolons() {
+        let (dialect, records) = read(
+            "semicolon",
+            "Export 2024-03-01\nName;Amount;Note\nAda;1,5;\"a; b\"\nBob;2;\n".as_bytes(),
+        );
+        assert_eq!(dialect.delimiter, ';');
+        assert_eq!(dialect.quote, '"');
+        assert_eq!(dialect.encoding, UTF_8);
+        assert_eq!(dialect.skipped_lines, 1);
+        assert!(dialect.has_headers);
+        assert_eq!(records[1], ["Ada", "1,5", "a; b"]);
+    }
+
+    #[test]
+    fn sniffs_tabs_without_headers() {
+        let (dialect, records) = read("tab", b"1\t2,5\tx\n3\t4\ty\n");
+        assert_eq!(dialect.delimiter, '\t');
+        assert!(!dialect.has_headers);
+        assert_eq!(records, [["1", "2,5", "x"], ["3", "4", "y"]]);
+    }
+
+    #[test]
+    fn sniffs_utf16() {
+        let text = "Name,City\r\nZoë,Köln\r\n";
+        let little_endian = text.encode_utf16().flat_map(u16::to_le_bytes);
+        let big_endian = text.encode_utf16().flat_map(u16::to_be_bytes);
+
+        let (dialect, records) = read("utf16le", &little_endian.collect::<Vec<_>>());
+        assert_eq!(dialect.encoding, UTF_16LE);
+        assert_eq!(records[1], ["Zoë", "Köln"]);
+
+        let with_bom = [0xFE, 0xFF]
+            .into_iter()
+            .chain(big_endian)
+            .collect::<Vec<_>>();
+        let (dialect, records) = read("utf16be", &with_bom);
+        assert_eq!(dialect.encoding, UTF_16BE);
+        assert_eq!(dialect.delimiter, ',');
+        assert_eq!(records[0], ["Name", "City"]);
+        assert_eq!(records[1], ["Zoë", "Köln"]);
+    }
+
+    #[test]
+    fn falls_back_to_windows_1252() {
+        let (dialect, records) = read("latin", b"Name;City\nJos\xe9;M\xfcnchen\n");
+        assert_eq!(dialect.encoding, WINDOWS_1252);
+        assert_eq!(records[1], ["José", "München"]);
+    }
+}
@@ -0,0 +1,460 @@
+use crate::cells::{CellValue, parse_cell_reference};
+use crate::csv_dialect::{CsvDialect, CsvOptions, CsvReader};
+use crate::json_data::{JsonOptions, load_json};
+use crate::sqlite_data::{SqliteOptions, load_sqlite};
+use calamine::{Data, Reader, open_workbook_auto};
+use chrono::NaiveDate;
+use std::path::{Path, PathBuf};
+
+/// Records to generate output from. Every row has one value per header, in
+/// header order; short rows are read as empty values.
+#[derive(Clone, Default)]
+pub struct DataTable {
+    pub headers: Vec<String>,
+    pub rows: Vec<Vec<String>>,
+    /// One-based numbers of the rows in the source when only some of them
+    /// were selected; empty when the rows are numbered consecutively.
+    pub row_numbers: Vec<usize>,
+    /// The values of spreadsheet sources that are numbers, booleans or
+    /// dates, by row and column; empty for text sources such as CSV files.
+    pub typed: Vec<Vec<Option<CellValue>>>,
+}
+
+impl DataTable {
+    /// The number of a row in the source, used for `{{#}}` and messages.
+    pub fn row_number(&self, index: usize) -> usize {
+        self.row_numbers.get(index).copied().unwrap_or(index + 1)
+    }
+
+    /// The rows at `indices`, keeping their row numbers.
+    pub fn select(&self, indices: &[usize]) -> DataTable {
+        let indices = indices
+            .iter()
+            .copied()
+            .filter(|index| *index < self.rows.len());
+        DataTable {
+            headers: self.headers.clone(),
+            rows: indices
+                .clone()
+                .map(|index| self.rows[index].clone())
+                .collect(),
+            row_numbers: indices
+                .clone()
+                .map(|index| self.row_number(index))
+                .collect(),
+            typed: if self.typed.is_empty() {
+                Vec::new()
+            } else {
+                indices.map(|index| self.typed[index].clone()).collect()
+            },
+        }
+    }
+
+    /// Adds the rows of `other`, a later part of the same source whose
+    /// headers may have grown.
+    pub fn append(&mut self, other: DataTable) {
+        if self.row_numbers.is_empty() {
+            self.row_numbers = (1..=self.rows.len()).collect();
+        }
+        let row_numbers = (0..other.rows.len()).map(|index| other.row_number(index));
+        self.row_numbers.extend(row_numbers);
+        if self.typed.is_empty() && !other.typed.is_empty() {
+            self.typed = vec![Vec::new(); self.rows.len()];
+        }
+        if !self.typed.is_empty() {
+            let mut typed = other.typed;
+            typed.resize(other.rows.len(), Vec::new());
+            self.typed.extend(typed);
+        }
+        self.rows.extend(other.rows);
+        if other.headers.len() > self.headers.len() {
+            self.headers = other.headers;
+        }
+    }
+
+    pub fn column_index(&self, name: &str) -> Option<usize> {
+        self.headers.iter().position(|header| header == name)
+    }
+
+    pub fn value(&self, row: usize, column: usize) -> &str {
+        self.rows
+            .get(row)
+            .and_then(|values| values.get(column))
+            .map(String::as_str)
+            .unwrap_or_default()
+    }
+
+    /// The typed value of a spreadsheet cell, if it is not text.
+    pub fn typed_value(&self, row: usize, column: usize) -> Option<&CellValue> {
+        self.typed
+            .get(row)
+            .and_then(|values| values.get(column))
+            .and_then(Option::as_ref)
+    }
+}
+
+/// Where the records come from.
+#[derive(Clone)]
+pub enum DataSource {
+    /// A CSV file. Without headers, columns are named `Column 1`, `Column 2`, …
+    Csv {
+        path: PathBuf,
+        has_headers: bool,
+        options: CsvOptions,
+    },
+    /// A sheet of an XLSX, XLS or ODS workbook, the first one by default.
+    /// `skip_rows` rows above the header row are ignored, and `range` such
+    /// as `B3:F200` limits the cells that are read.
+    Workbook {
+        path: PathBuf,
+        sheet: Option<String>,
+        has_headers: bool,
+        skip_rows: usize,
+        range: Option<String>,
+    },
+    /// A JSON array of records or newline-delimited JSON.
+    Json { path: PathBuf, options: JsonOptions },
+    /// A table or query of a SQLite database.
+    Sqlite {
+        path: PathBuf,
+        options: SqliteOptions,
+    },
+}
+
+impl DataSource {
+    pub fn load(&self) -> Result<DataTable, String> {
+        match self {
+            DataSource::Csv {
+                path,
+                has_headers,
+                options,
+            } => load_csv(path, *has_headers, options),
+            DataSource::Workbook {
+                path,
+                sheet,
+                has_headers,
+                skip_rows,
+                range,
+            } => load_workbook(
+                path,
+                sheet.as_deref(),
+                *has_headers,
+                *skip_rows,
+                range.as_deref(),
+            ),
+            DataSource::Json { path, options } => load_json(path, options),
+            DataSource::Sqlite { path, options } => load_sqlite(path, options),
+        }
+    }
+
+    /// The first `limit` records. CSV files are only read that far.
+    pub fn load_preview(&self, limit: usize) -> Result<DataTable, String> {
+        match self {
+            DataSource::Csv {
+                path,
+                has_headers,
+                options,
+            } => {
+                let mut chunks = CsvChunks::open(path, *has_headers, options, limit)?;
+                Ok(chunks
+                    .next()
+                    .transpose()?
+                    .unwrap_or(chunks.table(Vec::new())))
+            }
+            _ => {
+                let mut data = self.load()?;
+                data.rows.truncate(limit);
+                data.typed.truncate(limit);
+                Ok(data)
+            }
+        }
+    }
+
+    /// The number of records, reading CSV files without keeping their rows.
+    pub fn count_rows(&self) -> Result<usize, String> {
+        match self {
+            DataSource::Csv {
+                path,
+                has_headers,
+                options,
+            } => {
+                let (_, mut reader) = options.open(path, *has_headers)?;
+                let mut record = csv::ByteRecord::new();
+                let mut count = 0;
+                while reader
+                    .read_byte_record(&mut record)
+                    .map_err(|err| err.to_string())?
+                {
+                    count += 1;
+                }
+                Ok(count)
+            }
+            _ => self.load().map(|data| data.rows.len()),
+        }
+    }
+
+    /// The records in tables of at most `size` rows, numbered as in the
+    /// whole source. CSV files are read one table at a time.
+    pub fn chunks(
+        &self,
+        size: usize,
+    ) -> Result<Box<dyn Iterator<Item = Result<DataTable, String>>>, String> {
+        match self {
+            DataSource::Csv {
+                path,
+                has_headers,
+                options,
+            } => Ok(Box::new(CsvChunks::open(
+                path,
+                *has_headers,
+                options,
+                size,
+            )?)),
+            _ => {
+                let data = self.load()?;
+                let size = size.max(1);
+                Ok(Box::new((0..data.rows.len()).step_by(size).map(
+                    move |start| {
+                        let end = (start + size).min(data.rows.len());
+                        Ok(data.select(&(start..end).collect::<Vec<_>>()))
+                    },
+                )))
+            }
+        }
+    }
+}
+
+/// Whether a data file is a workbook rather than a CSV file, by extension.
+pub fn is_workbook_data(path: &Path) -> bool {
+    let extension = path
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_ascii_lowercase());
+    matches!(
+        extension.as_deref(),
+        Some("xlsx" | "xlsm" | "xlsb" | "xls" | "ods")
+    )
+}
+
+/// Reads a sheet of a workbook, keeping numbers, booleans and dates as
+/// typed values. The rows are also available as text, with dates written
+/// like `2024-01-31` or `2024-01-31 08:30:00`. Empty rows are skipped.
+pub fn load_workbook(
+    path: &Path,
+    sheet: Option<&str>,
+    has_headers: bool,
+    skip_rows: usize,
+    range: Option<&str>,
+) -> Result<DataTable, String> {
+    let mut workbook = open_workbook_auto(path).map_err(|err| err.to_string())?;
+    let sheet_names = workbook.sheet_names().to_vec();
+    let sheet = match sheet {
+        Some(sheet) if sheet_names.iter().any(|name| name == sheet) => sheet.to_string(),
+        Some(sheet) => {
+            return Err(format!(
+                "Sheet \"{}\" not found; the workbook has: {}",
+                sheet,
+                sheet_names.join(", ")
+            ));
+        }
+        None => sheet_names
+            .first()
+            .cloned()
+            .ok_or_else(|| "The workbook has no sheets".to_string())?,
+    };
+    let mut cells = workbook
+        .worksheet_range(&sheet)
+        .map_err(|err| err.to_string())?;
+    if let Some(range) = range.map(str::trim).filter(|range| !range.is_empty()) {
+        let bounds = range
+            .split_once(':')
+            .and_then(|(first, last)| {
+                let first = parse_cell_reference(&first.trim().to_ascii_uppercase())?;
+                let last = parse_cell_reference(&last.trim().to_ascii_uppercase())?;
+                Some((first, last))
+            })
+            .ok_or_else(|| format!("\"{}\" is not a cell range such as A1:F200", range))?;
+        cells = cells.range(bounds.0, bounds.1);
+    }
+
+    let mut rows = cells
+        .rows()
+        .skip(skip_rows)
+        .filter(|row| row.iter().any(|cell| !matches!(cell, Data::Empty)));
+    let headers = match has_headers {
+        true => rows
+            .next()
+            .map(|row| {
+                row.iter()
+                    .enumerate()
+                    .map(|(index, cell)| match data_value(cell) {
+                        (text, _) if text.trim().is_empty() => format!("Column {}", index + 1),
+                        (text, _) => text,
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default(),
+        false => Vec::new(),
+    };
+    let (rows, typed): (Vec<Vec<String>>, Vec<Vec<Option<CellValue>>>) =
+        rows.map(|row| row.iter().map(data_value).unzip()).unzip();
+
+    let column_count = headers
+        .len()
+        .max(rows.iter().map(|row| row.len()).max().unwrap_or(0));
+    let mut headers = headers;
+    while headers.len() < column_count {
+        headers.push(format!("Column {}", headers.len() + 1));
+    }
+
+    Ok(DataTable {
+        headers,
+        rows,
+        row_numbers: Vec::new(),
+        typed,
+    })
+}
+
+/// The text of a workbook cell and its typed value unless it is text.
+/// Errors such as `#N/A` are read as their text.
+fn data_value(cell: &Data) -> (String, Option<CellValue>) {
+    let typed = match cell {
+        Data::Empty => return (String::new(), None),
+        Data::String(text) | Data::DurationIso(text) => return (text.clone(), None),
+        Data::Error(err) => return (err.to_string(), None),
+        Data::Float(value) => CellValue::Number(*value),
+        Data::Int(value) => CellValue::Number(*value as f64),
+        Data::Bool(value) => CellValue::Boolean(*value),
+        Data::DateTime(value) if value.is_datetime() => {
+            let (year, month, day, hour, minute, second, milli) = value.to_ymd_hms_milli();
+            match NaiveDate::from_ymd_opt(year.into(), month.into(), day.into()).and_then(|date| {
+                date.and_hms_milli_opt(hour.into(), minute.into(), second.into(), milli.into())
+            }) {
+                Some(date_time) => CellValue::DateTime(date_time),
+                None => CellValue::Number(value.as_f64()),
+            }
+        }
+        Data::DateTime(value) => CellValue::Number(value.as_f64()),
+        Data::DateTimeIso(text) => match text.parse::<chrono::NaiveDateTime>() {
+            Ok(date_time) => CellValue::DateTime(date_time),
+            Err(_) => match text.parse::<NaiveDate>() {
+                Ok(date) => CellValue::DateTime(date.and_time(Default::default())),
+                Err(_) => return (text.clone(), None),
+            },
+        },
+    };
+    (typed.to_string(), Some(typed))
+}
+
+/// Detects the dialect of a CSV file; `options` fix parts of it.
+pub fn detect_csv_dialect(path: &Path, options: &CsvOptions) -> Result<CsvDialect, String> {
+    options.detect(path)
+}
+
+pub fn load_csv(path: &Path, has_headers: bool, options: &CsvOptions) -> Result<DataTable, String> {
+    let mut chunks = CsvChunks::open(path, has_headers, options, usize::MAX)?;
+    Ok(chunks
+        .next()
+        .transpose()?
+        .unwrap_or(chunks.table(Vec::new())))
+}
+
+/// Reads the records of a CSV file a table of `size` rows at a time.
+struct CsvChunks {
+    reader: CsvReader,
+    headers: Vec<String>,
+    size: usize,
+    /// Rows read so far.
+    position: usize,
+    done: bool,
+}
+
+impl CsvChunks {
+    fn open(
+        path: &Path,
+        has_headers: bool,
+        options: &CsvOptions,
+        size: usize,
+    ) -> Result<Self, String> {
+        let (_, mut reader) = options.open(path, has_headers)?;
+        let headers = if has_headers {
+            reader
+                .headers()
+                .map_err(|err| err.to_string())?
+                .iter()
+                .enumerate()
+                .map(|(idx, value)| {
+                    if value.is_empty() {
+                        format!("Column {}", idx + 1)
+                    } else {
+                        value.to_string()
+                    }
+                })
+                .collect()
+        } else {
+            Vec::new()
+        };
+        Ok(Self {
+            reader,
+            headers,
+            size: size.max(1),
+            position: 0,
+            done: false,
+        })
+    }
+
+    /// A table of `rows` read after the previous ones. Columns past the
+    /// headers are named `Column 4`, `Column 5`, …
+    fn table(&mut self, rows: Vec<Vec<String>>) -> DataTable {
+        let column_count = rows.iter().map(|row| row.len()).max().unwrap_or(0);
+        while self.headers.len() < column_count {
+            self.headers
+                .push(format!("Column {}", self.headers.len() + 1));
+        }
+        let first = self.position;
+        self.position += rows.len();
+        DataTable {
+            headers: self.headers.clone(),
+            row_numbers: match first {
+                0 => Vec::new(),
+                first => (first + 1..=self.position).collect(),
+            },
+            rows,
+            typed: Vec::new(),
+        }
+    }
+
+    fn read_rows(&mut self) -> Result<Vec<Vec<String>>, String> {
+        let mut rows = Vec::new();
+        let mut record = csv::StringRecord::new();
+        while rows.len() < self.size {
+            if !self
+                .reader
+                .read_record(&mut record)
+                .map_err(|err| err.to_string())?
+            {
+                self.done = true;
+                break;
+            }
+            rows.push(record.iter().map(|cell| cell.to_string()).collect());
+        }
+        Ok(rows)
+    }
+}
+
+impl Iterator for CsvChunks {
+    type Item = Result<DataTable, String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        match self.read_rows() {
+            Ok(rows) if rows.is_empty() => None,
+            Ok(rows) => Some(Ok(self.table(rows))),
+            Err(err) => {
+                self.done = true;
+                Some(Err(err))
+            }
+        }
+    }
+}
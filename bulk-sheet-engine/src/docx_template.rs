@@ -0,0 +1,760 @@
+use crate::package::{build_package, read_package};
+use crate::placeholders::{PlaceholderMarkup, collect_placeholders, replace_placeholders};
+use crate::xml_utils::{attribute_value, unescaped_attribute, with_attribute, xml_escape};
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::{Reader as XmlReader, Writer as XmlWriter};
+use std::collections::HashMap;
+use std::path::Path;
+
+const WORD_MARKUP: PlaceholderMarkup = PlaceholderMarkup {
+    paragraph_tags: &[b"w:p"],
+    text_tag: Some(b"w:t"),
+    line_break: Some("</w:t><w:br/><w:t xml:space=\"preserve\">"),
+};
+
+const DOCUMENT_PART: &str = "word/document.xml";
+const DOCUMENT_RELS_PART: &str = "word/_rels/document.xml.rels";
+const CONTENT_TYPES_PART: &str = "[Content_Types].xml";
+const FOOTNOTES_PART: &str = "word/footnotes.xml";
+const ENDNOTES_PART: &str = "word/endnotes.xml";
+/// Word drops bookmarks with longer names.
+const MAX_BOOKMARK_NAME: usize = 40;
+
+fn is_placeholder_part(name: &str) -> bool {
+    name == DOCUMENT_PART
+        || name == FOOTNOTES_PART
+        || name == ENDNOTES_PART
+        || ((name.starts_with("word/header") || name.starts_with("word/footer"))
+            && name.ends_with(".xml"))
+}
+
+pub fn read_docx_placeholders(path: &Path) -> Result<Vec<String>, String> {
+    let entries = read_package(path)?;
+    let mut names = Vec::new();
+    // Body placeholders first so the binding table follows the reading order.
+    let parts = entries
+        .iter()
+        .filter(|(name, _)| name == DOCUMENT_PART)
+        .chain(
+            entries
+                .iter()
+                .filter(|(name, _)| name != DOCUMENT_PART && is_placeholder_part(name)),
+        );
+    for (_, data) in parts {
+        collect_placeholders(data, &WORD_MARKUP, &mut names)?;
+    }
+    Ok(names)
+}
+
+/// A Word document template filled through `{{placeholder}}` tokens.
+pub struct DocxTemplate {
+    entries: Vec<(String, Vec<u8>)>,
+}
+
+impl DocxTemplate {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        Ok(Self {
+            entries: read_package(path)?,
+        })
+    }
+
+    fn part(&self, name: &str) -> Option<&[u8]> {
+        self.entries
+            .iter()
+            .find(|(entry, _)| entry == name)
+            .map(|(_, data)| data.as_slice())
+    }
+
+    /// Builds a document filled with the values of a single record.
+    pub fn render_record(&self, values: &HashMap<String, String>) -> Result<Vec<u8>, String> {
+        let mut replaced = Vec::new();
+        for (name, data) in &self.entries {
+            if is_placeholder_part(name) {
+                replaced.push((
+                    name.as_str(),
+                    replace_placeholders(data, &WORD_MARKUP, values)?,
+                ));
+            }
+        }
+        build_package(&self.entries, &replaced)
+    }
+
+    /// Builds a single document with a section break after each record.
+    /// Every record gets its own copies of the headers, footers and notes so
+    /// that their placeholders can differ per section.
+    pub fn render_merged(&self, records: &[HashMap<String, String>]) -> Result<Vec<u8>, String> {
+        let part = |name: &str| self.part(name);
+        let document = part(DOCUMENT_PART).ok_or_else(|| "Document body missing".to_string())?;
+        let body = split_document_body(document)?;
+        let relationships = String::from_utf8_lossy(
+            part(DOCUMENT_RELS_PART).ok_or_else(|| "Document relationships missing".to_string())?,
+        )
+        .into_owned();
+        let content_types = String::from_utf8_lossy(
+            part(CONTENT_TYPES_PART).ok_or_else(|| "Document content types missing".to_string())?,
+        )
+        .into_owned();
+        let header_parts = parse_header_relationships(&relationships)?;
+        let mut footnotes = part(FOOTNOTES_PART)
+            .map(|data| NotesPart::parse(data, b"w:footnote"))
+            .transpose()?;
+        let mut endnotes = part(ENDNOTES_PART)
+            .map(|data| NotesPart::parse(data, b"w:endnote"))
+            .transpose()?;
+        let id_stride = max_drawing_or_bookmark_id(&body.content)? + 1;
+
+        let mut replaced: Vec<(String, Vec<u8>)> = Vec::new();
+        let mut new_relationships = String::new();
+        let mut new_content_types = String::new();
+        let mut merged = body.head.clone();
+
+        for (index, values) in records.iter().enumerate() {
+            let mut ids = RecordIds {
+                id_offset: index as u32 * id_stride,
+                bookmark_suffix: format!("_{}", index + 1),
+                ..RecordIds::default()
+            };
+
+            for header in &header_parts {
+                let source = part(&header.part_name)
+                    .ok_or_else(|| format!("Document part {} missing", header.part_name))?;
+                let data = replace_placeholders(source, &WORD_MARKUP, values)?;
+                if index == 0 {
+                    replaced.push((header.part_name.clone(), data));
+                    continue;
+                }
+
+                let suffix = format!("_{}", index + 1);
+                let part_name = with_file_suffix(&header.part_name, &suffix);
+                let relationship_id = format!("{}{}", header.id, suffix);
+                new_relationships.push_str(&format!(
+                    "<Relationship Id=\"{}\" Type=\"{}\" Target=\"{}\"/>",
+                    xml_escape(&relationship_id),
+                    xml_escape(&header.type_attr),
+                    xml_escape(&with_file_suffix(&header.target, &suffix))
+                ));
+                if let Some(content_type) = override_content_type(&content_types, &header.part_name)
+                {
+                    new_content_types.push_str(&format!(
+                        "<Override PartName=\"/{}\" ContentType=\"{}\"/>",
+                        xml_escape(&part_name),
+                        xml_escape(&content_type)
+                    ));
+                }
+                if let Some(rels) = part(&part_relationships_path(&header.part_name)) {
+                    replaced.push((part_relationships_path(&part_name), rels.to_vec()));
+                }
+                ids.relationships.insert(header.id.clone(), relationship_id);
+                replaced.push((part_name, data));
+            }
+
+            if let Some(notes) = footnotes.as_mut() {
+                ids.footnotes = notes.add_record(index, values)?;
+            }
+            if let Some(notes) = endnotes.as_mut() {
+                ids.endnotes = notes.add_record(index, values)?;
+            }
+
+            let content = replace_placeholders(&body.content, &WORD_MARKUP, values)?;
+            merged.extend(remap_record_ids(&content, &ids)?);
+            let section = remap_record_ids(&body.section, &ids)?;
+            if index + 1 < records.len() {
+                // A section break is a paragraph whose properties carry the
+                // settings of the section it closes.
+                merged.extend_from_slice(b"<w:p><w:pPr>");
+                merged.extend(section);
+                merged.extend_from_slice(b"</w:pPr></w:p>");
+            } else {
+                merged.extend(section);
+            }
+        }
+        merged.extend_from_slice(&body.tail);
+
+        replaced.push((DOCUMENT_PART.to_string(), merged));
+        if let Some(notes) = footnotes {
+            replaced.push((FOOTNOTES_PART.to_string(), notes.into_xml()));
+        }
+        if let Some(notes) = endnotes {
+            replaced.push((ENDNOTES_PART.to_string(), notes.into_xml()));
+        }
+        replaced.push((
+            DOCUMENT_RELS_PART.to_string(),
+            insert_before_closing(&relationships, "</Relationships>", &new_relationships),
+        ));
+        replaced.push((
+            CONTENT_TYPES_PART.to_string(),
+            insert_before_closing(&content_types, "</Types>", &new_content_types),
+        ));
+
+        build_package(&self.entries, &replaced)
+    }
+}
+
+struct DocumentBody {
+    /// Everything up to and including the `w:body` start tag.
+    head: Vec<u8>,
+    content: Vec<u8>,
+    /// The trailing `w:sectPr` holding the page setup of the last section.
+    section: Vec<u8>,
+    tail: Vec<u8>,
+}
+
+fn split_document_body(document: &[u8]) -> Result<DocumentBody, String> {
+    let mut reader = XmlReader::from_reader(document);
+    reader.trim_text(false);
+    let mut head = XmlWriter::new(Vec::new());
+    let mut content = XmlWriter::new(Vec::new());
+    let mut tail = XmlWriter::new(Vec::new());
+    let mut child = XmlWriter::new(Vec::new());
+    let mut section = Vec::new();
+    let mut buffer = Vec::new();
+    let mut in_body = false;
+    let mut after_body = false;
+    let mut depth = 0usize;
+    let mut child_is_section = false;
+
+    loop {
+        let event = reader
+            .read_event_into(&mut buffer)
+            .map_err(|err| err.to_string())?
+            .into_owned();
+        buffer.clear();
+
+        if matches!(event, Event::Eof) {
+            if !after_body {
+                return Err("Document body missing".to_string());
+            }
+            break;
+        }
+        if after_body {
+            tail.write_event(event).map_err(|err| err.to_string())?;
+            continue;
+        }
+        if !in_body {
+            let opens_body =
+                matches!(&event, Event::Start(start) if start.name().as_ref() == b"w:body");
+            head.write_event(event).map_err(|err| err.to_string())?;
+            in_body = opens_body;
+            continue;
+        }
+
+        if depth == 0 {
+            match &event {
+                Event::End(_) => {
+                    in_body = false;
+                    after_body = true;
+                    tail.write_event(event).map_err(|err| err.to_string())?;
+                    continue;
+                }
+                Event::Start(start) | Event::Empty(start) => {
+                    // Only the last section properties close the body; any
+                    // earlier ones stay part of the content.
+                    content.get_mut().append(&mut std::mem::take(&mut section));
+                    child_is_section = start.name().as_ref() == b"w:sectPr";
+                }
+                _ => {
+                    content.write_event(event).map_err(|err| err.to_string())?;
+                    continue;
+                }
+            }
+        }
+
+        match &event {
+            Event::Start(_) => depth += 1,
+            Event::End(_) => depth -= 1,
+            _ => {}
+        }
+        child.write_event(event).map_err(|err| err.to_string())?;
+        if depth == 0 {
+            let mut data = std::mem::take(child.get_mut());
+            if child_is_section {
+                section = data;
+            } else {
+                content.get_mut().append(&mut data);
+            }
+        }
+    }
+
+    Ok(DocumentBody {
+        head: head.into_inner(),
+        content: content.into_inner(),
+        section,
+        tail: tail.into_inner(),
+    })
+}
+
+struct HeaderPart {
+    id: String,
+    type_attr: String,
+    target: String,
+    part_name: String,
+}
+
+fn parse_header_relationships(xml: &str) -> Result<Vec<HeaderPart>, String> {
+    let mut reader = XmlReader::from_str(xml);
+    reader.trim_text(true);
+    let mut buffer = Vec::new();
+    let mut parts = Vec::new();
+
+    loop {
+        match reader
+            .read_event_into(&mut buffer)
+            .map_err(|err| err.to_string())?
+        {
+            Event::Eof => break,
+            Event::Empty(event) if event.name().as_ref() == b"Relationship" => {
+                let (Some(id), Some(type_attr), Some(target)) = (
+                    attribute_value(&event, b"Id"),
+                    attribute_value(&event, b"Type"),
+                    attribute_value(&event, b"Target"),
+                ) else {
+                    buffer.clear();
+                    continue;
+                };
+                if type_attr.ends_with("/header") || type_attr.ends_with("/footer") {
+                    let part_name = match target.strip_prefix('/') {
+                        Some(absolute) => absolute.to_string(),
+                        None => format!("word/{}", target),
+                    };
+                    parts.push(HeaderPart {
+                        id,
+                        type_attr,
+                        target,
+                        part_name,
+                    });
+                }
+            }
+            _ => {}
+        }
+        buffer.clear();
+    }
+
+    Ok(parts)
+}
+
+/// Footnotes or endnotes of the template. Notes referenced by later records
+/// are appended as copies with fresh ids.
+struct NotesPart {
+    head: Vec<u8>,
+    notes: Vec<Note>,
+    tail: Vec<u8>,
+    output: Vec<u8>,
+    next_id: i64,
+}
+
+struct Note {
+    start: BytesStart<'static>,
+    id: Option<i64>,
+    /// Separator notes carry a `w:type` and are never referenced from the body.
+    is_separator: bool,
+    data: Vec<u8>,
+}
+
+impl NotesPart {
+    fn parse(xml: &[u8], note_tag: &[u8]) -> Result<Self, String> {
+        let mut reader = XmlReader::from_reader(xml);
+        reader.trim_text(false);
+        let mut head = XmlWriter::new(Vec::new());
+        let mut tail = XmlWriter::new(Vec::new());
+        let mut note = XmlWriter::new(Vec::new());
+        let mut notes = Vec::new();
+        let mut buffer = Vec::new();
+        let mut current: Option<BytesStart<'static>> = None;
+        let mut depth = 0usize;
+
+        loop {
+            let event = reader
+                .read_event_into(&mut buffer)
+                .map_err(|err| err.to_string())?
+                .into_owned();
+            buffer.clear();
+
+            if let Some(start) = current.as_ref() {
+                let closes = matches!(&event, Event::End(_)) && depth == 0;
+                match &event {
+                    Event::Start(_) => depth += 1,
+                    Event::End(_) if depth > 0 => depth -= 1,
+                    _ => {}
+                }
+                note.write_event(event).map_err(|err| err.to_string())?;
+                if closes {
+                    notes.push(Note::new(start.clone(), std::mem::take(note.get_mut())));
+                    current = None;
+                }
+                continue;
+            }
+
+            match event {
+                Event::Eof => break,
+                Event::Start(start) if start.name().as_ref() == note_tag => {
+                    note.write_event(Event::Start(start.clone()))
+                        .map_err(|err| err.to_string())?;
+                    current = Some(start);
+                }
+                Event::Empty(start) if start.name().as_ref() == note_tag => {
+                    note.write_event(Event::Empty(start.clone()))
+                        .map_err(|err| err.to_string())?;
+                    notes.push(Note::new(start, std::mem::take(note.get_mut())));
+                }
+                other if notes.is_empty() => {
+                    head.write_event(other).map_err(|err| err.to_string())?;
+                }
+                other => {
+                    tail.write_event(other).map_err(|err| err.to_string())?;
+                }
+            }
+        }
+
+        let next_id = notes.iter().filter_map(|note| note.id).max().unwrap_or(0) + 1;
+        Ok(Self {
+            head: head.into_inner(),
+            notes,
+            tail: tail.into_inner(),
+            output: Vec::new(),
+            next_id,
+        })
+    }
+
+    /// Adds the notes of one record and returns the id mapping for its body.
+    fn add_record(
+        &mut self,
+        index: usize,
+        values: &HashMap<String, String>,
+    ) -> Result<HashMap<String, String>, String> {
+        let mut ids = HashMap::new();
+        for note in &self.notes {
+            if index == 0 {
+                self.output
+                    .extend(replace_placeholders(&note.data, &WORD_MARKUP, values)?);
+                continue;
+            }
+            let Some(id) = note.id.filter(|_| !note.is_separator) else {
+                continue;
+            };
+            let new_id = self.next_id.to_string();
+            self.next_id += 1;
+            ids.insert(id.to_string(), new_id.clone());
+
+            let renumbered = renumber_note(&note.data, &note.start, &new_id)?;
+            self.output
+                .extend(replace_placeholders(&renumbered, &WORD_MARKUP, values)?);
+        }
+        Ok(ids)
+    }
+
+    fn into_xml(self) -> Vec<u8> {
+        let mut xml = self.head;
+        xml.extend(self.output);
+        xml.extend(self.tail);
+        xml
+    }
+}
+
+impl Note {
+    fn new(start: BytesStart<'static>, data: Vec<u8>) -> Self {
+        let id = attribute_value(&start, b"w:id").and_then(|id| id.parse::<i64>().ok());
+        let is_separator = attribute_value(&start, b"w:type").is_some();
+        Self {
+            start,
+            id,
+            is_separator,
+            data,
+        }
+    }
+}
+
+fn renumber_note(data: &[u8], start: &BytesStart, new_id: &str) -> Result<Vec<u8>, String> {
+    let mut reader = XmlReader::from_reader(data);
+    reader.trim_text(false);
+    let mut writer = XmlWriter::new(Vec::new());
+    let mut buffer = Vec::new();
+    let mut is_first = true;
+
+    loop {
+        let event = reader
+            .read_event_into(&mut buffer)
+            .map_err(|err| err.to_string())?;
+        let event = match event {
+            Event::Eof => break,
+            Event::Start(_) if is_first => Event::Start(with_attribute(start, "w:id", new_id)),
+            Event::Empty(_) if is_first => Event::Empty(with_attribute(start, "w:id", new_id)),
+            other => other,
+        };
+        is_first = false;
+        writer.write_event(event).map_err(|err| err.to_string())?;
+        buffer.clear();
+    }
+
+    Ok(writer.into_inner())
+}
+
+/// Identifiers that have to be unique per record in a merged document.
+#[derive(Default)]
+struct RecordIds {
+    relationships: HashMap<String, String>,
+    footnotes: HashMap<String, String>,
+    endnotes: HashMap<String, String>,
+    id_offset: u32,
+    bookmark_suffix: String,
+}
+
+fn remap_record_ids(xml: &[u8], ids: &RecordIds) -> Result<Vec<u8>, String> {
+    let mut reader = XmlReader::from_reader(xml);
+    reader.trim_text(false);
+    let mut writer = XmlWriter::new(Vec::new());
+    let mut buffer = Vec::new();
+
+    loop {
+        let event = reader
+            .read_event_into(&mut buffer)
+            .map_err(|err| err.to_string())?;
+        let event = match event {
+            Event::Eof => break,
+            Event::Start(start) => Event::Start(remap_tag(start, ids)),
+            Event::Empty(start) => Event::Empty(remap_tag(start, ids)),
+            other => other,
+        };
+        writer.write_event(event).map_err(|err| err.to_string())?;
+        buffer.clear();
+    }
+
+    Ok(writer.into_inner())
+}
+
+fn remap_tag<'a>(start: BytesStart<'a>, ids: &RecordIds) -> BytesStart<'a> {
+    let mapped = |key: &[u8], map: &HashMap<String, String>| {
+        attribute_value(&start, key).and_then(|value| map.get(&value).cloned())
+    };
+    let offset = |key: &[u8]| {
+        attribute_value(&start, key)
+            .and_then(|value| value.parse::<u32>().ok())
+            .map(|value| (value + ids.id_offset).to_string())
+    };
+
+    let name = start.name().as_ref().to_vec();
+    match name.as_slice() {
+        b"w:headerReference" | b"w:footerReference" => match mapped(b"r:id", &ids.relationships) {
+            Some(id) => with_attribute(&start, "r:id", &id),
+            None => start,
+        },
+        b"w:footnoteReference" => match mapped(b"w:id", &ids.footnotes) {
+            Some(id) => with_attribute(&start, "w:id", &id),
+            None => start,
+        },
+        b"w:endnoteReference" => match mapped(b"w:id", &ids.endnotes) {
+            Some(id) => with_attribute(&start, "w:id", &id),
+            None => start,
+        },
+        b"w:bookmarkStart" | b"w:bookmarkEnd" if ids.id_offset > 0 => {
+            let mut updated = match offset(b"w:id") {
+                Some(id) => with_attribute(&start, "w:id", &id),
+                None => start.into_owned(),
+            };
+            if let Some(name) = unescaped_attribute(&updated, b"w:name")
+                && !name.starts_with('_')
+            {
+                let kept = MAX_BOOKMARK_NAME.saturating_sub(ids.bookmark_suffix.chars().count());
+                let name: String = name.chars().take(kept).collect();
+                updated = with_attribute(
+                    &updated,
+                    "w:name",
+                    &format!("{}{}", name, ids.bookmark_suffix),
+                );
+            }
+            updated
+        }
+        b"wp:docPr" if ids.id_offset > 0 => match offset(b"id") {
+            Some(id) => with_attribute(&start, "id", &id),
+            None => start,
+        },
+        _ => start,
+    }
+}
+
+fn max_drawing_or_bookmark_id(xml: &[u8]) -> Result<u32, String> {
+    let mut reader = XmlReader::from_reader(xml);
+    let mut buffer = Vec::new();
+    let mut max_id = 0u32;
+
+    loop {
+        match reader
+            .read_event_into(&mut buffer)
+            .map_err(|err| err.to_string())?
+        {
+            Event::Eof => break,
+            Event::Start(start) | Event::Empty(start) => {
+                let key: Option<&[u8]> = match start.name().as_ref() {
+                    b"w:bookmarkStart" | b"w:bookmarkEnd" => Some(b"w:id"),
+                    b"wp:docPr" => Some(b"id"),
+                    _ => None,
+                };
+                if let Some(id) = key
+                    .and_then(|key| attribute_value(&start, key))
+                    .and_then(|value| value.parse::<u32>().ok())
+                {
+                    max_id = max_id.max(id);
+                }
+            }
+            _ => {}
+        }
+        buffer.clear();
+    }
+
+    Ok(max_id)
+}
+
+fn override_content_type(content_types: &str, part_name: &str) -> Option<String> {
+    let mut reader = XmlReader::from_str(content_types);
+    let mut buffer = Vec::new();
+    let wanted = format!("/{}", part_name);
+    loop {
+        match reader.read_event_into(&mut buffer).ok()? {
+            Event::Eof => return None,
+            Event::Empty(event)
+                if event.name().as_ref() == b"Override"
+                    && attribute_value(&event, b"PartName").as_deref() == Some(wanted.as_str()) =>
+            {
+                return attribute_value(&event, b"ContentType");
+            }
+            _ => {}
+        }
+        buffer.clear();
+    }
+}
+
+fn with_file_suffix(path: &str, suffix: &str) -> String {
+    match path.rsplit_once('.') {
+        Some((stem, extension)) if !extension.contains('/') => {
+            format!("{}{}.{}", stem, suffix, extension)
+        }
+        _ => format!("{}{}", path, suffix),
+    }
+}
+
+fn part_relationships_path(part_name: &str) -> String {
+    match part_name.rsplit_once('/') {
+        Some((folder, file)) => format!("{}/_rels/{}.rels", folder, file),
+        None => format!("_rels/{}.rels", part_name),
+    }
+}
+
+fn insert_before_closing(xml: &str, closing_tag: &str, addition: &str) -> Vec<u8> {
+    match xml.rfind(closing_tag) {
+        Some(position) => {
+            let mut updated = String::with_capacity(xml.len() + addition.len());
+            updated.push_str(&xml[..position]);
+            updated.push_str(addition);
+            updated.push_str(&xml[position..]);
+            updated.into_bytes()
+        }
+        None => xml.as_bytes().to_vec(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Read};
+
+    const BOOKMARK: &str = "A_bookmark_name_that_fills_forty_chars__";
+
+    fn template() -> DocxTemplate {
+        let document = format!(
+            concat!(
+                r#"<w:document><w:body><w:p><w:r><w:t>Dear {{{{Na</w:t></w:r><w:r><w:rPr><w:b/></w:rPr><w:t>me}}}}</w:t></w:r>"#,
+                r#"<w:r><w:footnoteReference w:id="1"/></w:r><w:bookmarkStart w:id="0" w:name="{}"/><w:bookmarkEnd w:id="0"/></w:p>"#,
+                r#"<w:sectPr><w:headerReference w:type="default" r:id="rId1"/></w:sectPr></w:body></w:document>"#,
+            ),
+            BOOKMARK
+        );
+        let entries = [
+            (
+                CONTENT_TYPES_PART,
+                r#"<Types><Override PartName="/word/header1.xml" ContentType="header+xml"/></Types>"#.to_string(),
+            ),
+            (
+                DOCUMENT_RELS_PART,
+                r#"<Relationships><Relationship Id="rId1" Type="http://x/header" Target="header1.xml"/></Relationships>"#.to_string(),
+            ),
+            (DOCUMENT_PART, document),
+            (
+                "word/header1.xml",
+                r#"<w:hdr><w:p><w:r><w:t>{{Name}}</w:t></w:r></w:p></w:hdr>"#.to_string(),
+            ),
+            (
+                FOOTNOTES_PART,
+                concat!(
+                    r#"<w:footnotes><w:footnote w:type="separator" w:id="-1"><w:p/></w:footnote>"#,
+                    r#"<w:footnote w:id="1"><w:p><w:r><w:t>On {{Name}}</w:t></w:r></w:p></w:footnote></w:footnotes>"#,
+                )
+                .to_string(),
+            ),
+        ];
+        DocxTemplate {
+            entries: entries
+                .into_iter()
+                .map(|(name, data)| (name.to_string(), data.into_bytes()))
+                .collect(),
+        }
+    }
+
+    fn part(package: &[u8], name: &str) -> String {
+        let mut archive = zip::ZipArchive::new(Cursor::new(package)).unwrap();
+        let mut text = String::new();
+        archive
+            .by_name(name)
+            .unwrap()
+            .read_to_string(&mut text)
+            .unwrap();
+        text
+    }
+
+    fn record(name: &str) -> HashMap<String, String> {
+        HashMap::from([("Name".to_string(), name.to_string())])
+    }
+
+    #[test]
+    fn replaces_placeholders_split_across_runs() {
+        let package = template().render_record(&record("Ann")).unwrap();
+        assert!(part(&package, DOCUMENT_PART).contains(concat!(
+            r#"<w:p><w:r><w:t xml:space="preserve">Dear Ann</w:t></w:r>"#,
+            r#"<w:r><w:rPr><w:b/></w:rPr><w:t xml:space="preserve"></w:t></w:r>"#,
+        )));
+        assert!(part(&package, "word/header1.xml").contains(">Ann</w:t>"));
+        assert!(part(&package, FOOTNOTES_PART).contains(">On Ann</w:t>"));
+    }
+
+    #[test]
+    fn merges_records_with_own_headers_and_notes() {
+        let package = template()
+            .render_merged(&[record("Ann"), record("Bob")])
+            .unwrap();
+
+        let document = part(&package, DOCUMENT_PART);
+        assert!(document.contains(concat!(
+            r#"<w:p><w:pPr><w:sectPr><w:headerReference w:type="default" r:id="rId1"/></w:sectPr></w:pPr></w:p>"#,
+            r#"<w:p><w:r><w:t xml:space="preserve">Dear Bob</w:t></w:r>"#,
+        )));
+        assert!(document.contains(concat!(
+            r#"<w:footnoteReference w:id="2"/></w:r>"#,
+            r#"<w:bookmarkStart w:id="1" w:name="A_bookmark_name_that_fills_forty_chars_2"/><w:bookmarkEnd w:id="1"/>"#,
+        )));
+        assert!(document.ends_with(
+            r#"<w:sectPr><w:headerReference w:type="default" r:id="rId1_2"/></w:sectPr></w:body></w:document>"#
+        ));
+
+        assert!(part(&package, DOCUMENT_RELS_PART).contains(
+            r#"<Relationship Id="rId1_2" Type="http://x/header" Target="header1_2.xml"/>"#
+        ));
+        assert!(
+            part(&package, CONTENT_TYPES_PART)
+                .contains(r#"<Override PartName="/word/header1_2.xml" ContentType="header+xml"/>"#)
+        );
+        assert!(part(&package, "word/header1.xml").contains(">Ann</w:t>"));
+        assert!(part(&package, "word/header1_2.xml").contains(">Bob</w:t>"));
+        assert!(part(&package, FOOTNOTES_PART).ends_with(concat!(
+            r#"<w:footnote w:id="1"><w:p><w:r><w:t xml:space="preserve">On Ann</w:t></w:r></w:p></w:footnote>"#,
+            r#"<w:footnote w:id="2"><w:p><w:r><w:t xml:space="preserve">On Bob</w:t></w:r></w:p></w:footnote></w:footnotes>"#,
+        )));
+    }
+}
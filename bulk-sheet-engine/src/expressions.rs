@@ -0,0 +1,725 @@
+use crate::cells::{DEFAULT_DATE_FORMAT, DEFAULT_DATETIME_FORMAT, parse_boolean, parse_date_time};
+use crate::formulas::number_text;
+use crate::output::ROW_NUMBER_TOKEN;
+use crate::placeholders::find_placeholders;
+use chrono::{Local, NaiveDateTime};
+use std::cmp::Ordering;
+use std::fmt::Write;
+
+/// A derived value such as `{{First}} {{Last}}`: text with `{{…}}` tokens
+/// that is filled for every data row. A token holds a column name, `#` for
+/// the row number or an expression such as `upper(Last)`, `Amount * 1.19`
+/// or `default(Phone, "n/a")`.
+///
+/// Expressions support `+ - * /`, `&` for concatenation, comparisons
+/// (`== != < > <= >=`), `and`, `or`, `not`, string and number literals,
+/// `true`, `false`, `[Column name]` for columns that are no plain
+/// identifiers, and the functions `upper`, `lower`, `trim`,
+/// `default(value, fallback)`, `date(value, format[, source format])`,
+/// `round(value[, digits])`, `row()` and `today([format])`.
+pub struct Expression {
+    parts: Vec<Part>,
+}
+
+enum Part {
+    Text(String),
+    Node(Node),
+}
+
+impl Expression {
+    /// Parses `text`, resolving column names against `headers`.
+    pub fn parse(text: &str, headers: &[String]) -> Result<Self, String> {
+        let mut parts = Vec::new();
+        let mut position = 0;
+        for (range, token) in find_placeholders(text) {
+            if range.start > position {
+                parts.push(Part::Text(text[position..range.start].to_string()));
+            }
+            let node = if token == ROW_NUMBER_TOKEN {
+                Node::RowNumber
+            } else if let Some(index) = headers.iter().position(|header| header == &token) {
+                Node::Column(index)
+            } else {
+                parse_token(&token, headers)
+                    .map_err(|err| format!("{} in \"{}\"", err, &text[range.clone()]))?
+            };
+            parts.push(Part::Node(node));
+            position = range.end;
+        }
+        if position < text.len() {
+            parts.push(Part::Text(text[position..].to_string()));
+        }
+        Ok(Self { parts })
+    }
+
+    /// Parses a condition such as `Status == "active"` or `Amount > 0`.
+    /// Conditions may also be written with `{{…}}` tokens.
+    pub fn condition(text: &str, headers: &[String]) -> Result<Self, String> {
+        if text.contains("{{") {
+            return Self::parse(text, headers);
+        }
+        let node = parse_token(text.trim(), headers)?;
+        Ok(Self {
+            parts: vec![Part::Node(node)],
+        })
+    }
+
+    /// Whether a condition holds for one data row. Text such as `yes` or
+    /// `0` is read as a boolean and empty text is false.
+    pub fn is_true(&self, row: &[String], row_number: usize) -> Result<bool, String> {
+        match self.parts.as_slice() {
+            [Part::Node(node)] => node.evaluate(row, row_number)?.to_bool(),
+            _ => Value::Text(self.evaluate(row, row_number)?).to_bool(),
+        }
+    }
+
+    /// The value for one data row; `row_number` starts at 1.
+    pub fn evaluate(&self, row: &[String], row_number: usize) -> Result<String, String> {
+        let mut text = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Text(value) => text.push_str(value),
+                Part::Node(node) => text.push_str(&node.evaluate(row, row_number)?.into_text()),
+            }
+        }
+        Ok(text)
+    }
+}
+
+fn parse_token(token: &str, headers: &[String]) -> Result<Node, String> {
+    let tokens = tokenize(token)?;
+    let mut parser = Parser {
+        tokens,
+        position: 0,
+        headers,
+    };
+    let node = parser.disjunction().map_err(|err| {
+        // A name with spaces that is no column reads like a typo.
+        if token
+            .chars()
+            .all(|ch| ch.is_alphanumeric() || ch == '_' || ch == ' ')
+        {
+            format!("Column \"{}\" not found", token)
+        } else {
+            err
+        }
+    })?;
+    match parser.tokens.get(parser.position) {
+        Some(token) => Err(format!("Unexpected {}", token)),
+        None => Ok(node),
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Operator {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Concatenate,
+    Equal,
+    NotEqual,
+    Less,
+    Greater,
+    LessOrEqual,
+    GreaterOrEqual,
+    And,
+    Or,
+}
+
+#[derive(Clone, Copy)]
+enum Function {
+    Upper,
+    Lower,
+    Trim,
+    Default,
+    Date,
+    Round,
+    Row,
+    Today,
+}
+
+impl Function {
+    /// The function and its minimum and maximum number of arguments.
+    fn from_name(name: &str) -> Option<(Self, usize, usize)> {
+        let function = match name.to_ascii_lowercase().as_str() {
+            "upper" => (Function::Upper, 1, 1),
+            "lower" => (Function::Lower, 1, 1),
+            "trim" => (Function::Trim, 1, 1),
+            "default" => (Function::Default, 2, 2),
+            "date" => (Function::Date, 2, 3),
+            "round" => (Function::Round, 1, 2),
+            "row" => (Function::Row, 0, 0),
+            "today" => (Function::Today, 0, 1),
+            _ => return None,
+        };
+        Some(function)
+    }
+}
+
+enum Node {
+    Number(f64),
+    Text(String),
+    Column(usize),
+    RowNumber,
+    Boolean(bool),
+    Negate(Box<Node>),
+    Not(Box<Node>),
+    Binary(Operator, Box<Node>, Box<Node>),
+    Call(Function, Vec<Node>),
+}
+
+impl Node {
+    fn evaluate(&self, row: &[String], row_number: usize) -> Result<Value, String> {
+        match self {
+            Node::Number(value) => Ok(Value::Number(*value)),
+            Node::Text(value) => Ok(Value::Text(value.clone())),
+            Node::Column(index) => Ok(Value::Text(row.get(*index).cloned().unwrap_or_default())),
+            Node::RowNumber => Ok(Value::Number(row_number as f64)),
+            Node::Boolean(value) => Ok(Value::Boolean(*value)),
+            Node::Negate(node) => number(-node.evaluate(row, row_number)?.to_number()?),
+            Node::Not(node) => Ok(Value::Boolean(!node.evaluate(row, row_number)?.to_bool()?)),
+            Node::Binary(Operator::And, left, right) => Ok(Value::Boolean(
+                left.evaluate(row, row_number)?.to_bool()?
+                    && right.evaluate(row, row_number)?.to_bool()?,
+            )),
+            Node::Binary(Operator::Or, left, right) => Ok(Value::Boolean(
+                left.evaluate(row, row_number)?.to_bool()?
+                    || right.evaluate(row, row_number)?.to_bool()?,
+            )),
+            Node::Binary(Operator::Concatenate, left, right) => Ok(Value::Text(
+                left.evaluate(row, row_number)?.into_text()
+                    + &right.evaluate(row, row_number)?.into_text(),
+            )),
+            Node::Binary(operator, left, right) => {
+                let left = left.evaluate(row, row_number)?;
+                let right = right.evaluate(row, row_number)?;
+                let ordering = || compare(&left, &right);
+                match operator {
+                    Operator::Equal => Ok(Value::Boolean(ordering() == Ordering::Equal)),
+                    Operator::NotEqual => Ok(Value::Boolean(ordering() != Ordering::Equal)),
+                    Operator::Less => Ok(Value::Boolean(ordering() == Ordering::Less)),
+                    Operator::Greater => Ok(Value::Boolean(ordering() == Ordering::Greater)),
+                    Operator::LessOrEqual => Ok(Value::Boolean(ordering() != Ordering::Greater)),
+                    Operator::GreaterOrEqual => Ok(Value::Boolean(ordering() != Ordering::Less)),
+                    _ => {
+                        let (left, right) = (left.to_number()?, right.to_number()?);
+                        match operator {
+                            Operator::Add => number(left + right),
+                            Operator::Subtract => number(left - right),
+                            Operator::Multiply => number(left * right),
+                            Operator::Divide if right == 0.0 => Err("Division by zero".to_string()),
+                            _ => number(left / right),
+                        }
+                    }
+                }
+            }
+            Node::Call(function, arguments) => {
+                let mut values = Vec::with_capacity(arguments.len());
+                for argument in arguments {
+                    values.push(argument.evaluate(row, row_number)?);
+                }
+                call(*function, values, row_number)
+            }
+        }
+    }
+}
+
+fn call(function: Function, values: Vec<Value>, row_number: usize) -> Result<Value, String> {
+    let mut texts = values.iter().map(Value::to_text);
+    let text = texts.next().unwrap_or_default();
+    match function {
+        Function::Upper => Ok(Value::Text(text.to_uppercase())),
+        Function::Lower => Ok(Value::Text(text.to_lowercase())),
+        Function::Trim => Ok(Value::Text(text.trim().to_string())),
+        Function::Default if text.trim().is_empty() => Ok(values[1].clone()),
+        Function::Default => Ok(values[0].clone()),
+        Function::Date => {
+            let format = texts.next().unwrap_or_default();
+            let source_format = texts.next();
+            if text.trim().is_empty() {
+                return Ok(Value::Text(String::new()));
+            }
+            let date = match &source_format {
+                Some(source_format) => parse_date_time(text.trim(), source_format),
+                None => parse_date_time(text.trim(), DEFAULT_DATE_FORMAT)
+                    .or_else(|| parse_date_time(text.trim(), DEFAULT_DATETIME_FORMAT)),
+            }
+            .ok_or_else(|| {
+                format!(
+                    "\"{}\" does not match the date format {}",
+                    text,
+                    source_format.as_deref().unwrap_or(DEFAULT_DATE_FORMAT)
+                )
+            })?;
+            format_date(&date, &format).map(Value::Text)
+        }
+        Function::Round => {
+            let digits = match values.get(1) {
+                Some(value) => value.to_number()?.clamp(-15.0, 15.0) as i32,
+                None => 0,
+            };
+            let factor = 10f64.powi(digits);
+            number((values[0].to_number()? * factor).round() / factor)
+        }
+        Function::Row => Ok(Value::Number(row_number as f64)),
+        Function::Today => {
+            let today = Local::now().date_naive().and_hms_opt(0, 0, 0);
+            let format = Some(text).filter(|format| !format.is_empty());
+            format_date(
+                &today.unwrap_or_default(),
+                format.as_deref().unwrap_or(DEFAULT_DATE_FORMAT),
+            )
+            .map(Value::Text)
+        }
+    }
+}
+
+/// Formats a date, failing on invalid format strings instead of panicking.
+fn format_date(date: &NaiveDateTime, format: &str) -> Result<String, String> {
+    let mut text = String::new();
+    write!(text, "{}", date.format(format))
+        .map_err(|_| format!("\"{}\" is not a date format", format))?;
+    Ok(text)
+}
+
+/// Compares with `true` and `false` as booleans, numbers by value and
+/// everything else as text, ignoring case.
+fn compare(left: &Value, right: &Value) -> Ordering {
+    if (matches!(left, Value::Boolean(_)) || matches!(right, Value::Boolean(_)))
+        && let (Ok(left), Ok(right)) = (left.to_bool(), right.to_bool())
+    {
+        return left.cmp(&right);
+    }
+    match (left.to_number(), right.to_number()) {
+        (Ok(left), Ok(right)) => left.partial_cmp(&right).unwrap_or(Ordering::Equal),
+        _ => left
+            .to_text()
+            .to_lowercase()
+            .cmp(&right.to_text().to_lowercase()),
+    }
+}
+
+#[derive(Clone)]
+enum Value {
+    Text(String),
+    Number(f64),
+    Boolean(bool),
+}
+
+impl Value {
+    /// Empty text counts as zero, like an empty cell.
+    fn to_number(&self) -> Result<f64, String> {
+        match self {
+            Value::Number(value) => Ok(*value),
+            Value::Boolean(value) => Ok(f64::from(u8::from(*value))),
+            Value::Text(value) if value.trim().is_empty() => Ok(0.0),
+            Value::Text(value) => value
+                .trim()
+                .parse::<f64>()
+                .ok()
+                .filter(|value| value.is_finite())
+                .ok_or_else(|| format!("\"{}\" is not a number", value)),
+        }
+    }
+
+    fn to_bool(&self) -> Result<bool, String> {
+        match self {
+            Value::Boolean(value) => Ok(*value),
+            Value::Number(value) => Ok(*value != 0.0),
+            Value::Text(value) if value.trim().is_empty() => Ok(false),
+            Value::Text(value) => {
+                parse_boolean(value.trim()).ok_or_else(|| format!("\"{}\" is not a boolean", value))
+            }
+        }
+    }
+
+    fn to_text(&self) -> String {
+        match self {
+            Value::Text(value) => value.clone(),
+            Value::Number(value) => number_text(*value),
+            Value::Boolean(true) => "TRUE".to_string(),
+            Value::Boolean(false) => "FALSE".to_string(),
+        }
+    }
+
+    fn into_text(self) -> String {
+        match self {
+            Value::Text(value) => value,
+            value => value.to_text(),
+        }
+    }
+}
+
+fn number(value: f64) -> Result<Value, String> {
+    if value.is_finite() {
+        Ok(Value::Number(value))
+    } else {
+        Err("The result is not a finite number".to_string())
+    }
+}
+
+#[derive(Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    Text(String),
+    Name(String),
+    /// `[Column name]`
+    Column(String),
+    RowNumber,
+    Operator(&'static str),
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Number(value) => write!(f, "{}", number_text(*value)),
+            Token::Text(value) => write!(f, "\"{}\"", value),
+            Token::Name(name) => write!(f, "\"{}\"", name),
+            Token::Column(name) => write!(f, "[{}]", name),
+            Token::RowNumber => write!(f, "#"),
+            Token::Operator(operator) => write!(f, "\"{}\"", operator),
+        }
+    }
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    const OPERATORS: [&str; 19] = [
+        "==", "!=", "<>", "<=", ">=", "&&", "||", "+", "-", "*", "/", "&", "(", ")", ",", "=", "<",
+        ">", "!",
+    ];
+    let chars = text.chars().collect::<Vec<_>>();
+    let mut tokens = Vec::new();
+    let mut index = 0;
+
+    while index < chars.len() {
+        let ch = chars[index];
+        if ch.is_whitespace() {
+            index += 1;
+        } else if ch == '"' {
+            let mut value = String::new();
+            index += 1;
+            loop {
+                match chars.get(index) {
+                    Some('"') if chars.get(index + 1) == Some(&'"') => {
+                        value.push('"');
+                        index += 2;
+                    }
+                    Some('"') => {
+                        index += 1;
+                        break;
+                    }
+                    Some(ch) => {
+                        value.push(*ch);
+                        index += 1;
+                    }
+                    None => return Err("Missing closing quote".to_string()),
+                }
+            }
+            tokens.push(Token::Text(value));
+        } else if ch == '[' {
+            let length = chars[index + 1..]
+                .iter()
+                .position(|ch| *ch == ']')
+                .ok_or_else(|| "Missing \"]\"".to_string())?;
+            let name = chars[index + 1..index + 1 + length]
+                .iter()
+                .collect::<String>();
+            tokens.push(Token::Column(name.trim().to_string()));
+            index += length + 2;
+        } else if ch.is_ascii_digit()
+            || (ch == '.' && chars.get(index + 1).is_some_and(char::is_ascii_digit))
+        {
+            let start = index;
+            while index < chars.len() && (chars[index].is_ascii_digit() || chars[index] == '.') {
+                index += 1;
+            }
+            let value = chars[start..index].iter().collect::<String>();
+            let value = value
+                .parse::<f64>()
+                .map_err(|_| format!("\"{}\" is not a number", value))?;
+            tokens.push(Token::Number(value));
+        } else if ch.is_alphabetic() || ch == '_' {
+            let start = index;
+            while index < chars.len() && (chars[index].is_alphanumeric() || chars[index] == '_') {
+                index += 1;
+            }
+            tokens.push(Token::Name(chars[start..index].iter().collect()));
+        } else if ch == '#' {
+            tokens.push(Token::RowNumber);
+            index += 1;
+        } else {
+            let rest = chars[index..].iter().take(2).collect::<String>();
+            let operator = OPERATORS
+                .into_iter()
+                .find(|operator| rest.starts_with(operator))
+                .ok_or_else(|| format!("Unexpected \"{}\"", ch))?;
+            tokens.push(Token::Operator(operator));
+            index += operator.len();
+        }
+    }
+
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    tokens: Vec<Token>,
+    position: usize,
+    headers: &'a [String],
+}
+
+impl Parser<'_> {
+    fn accept(&mut self, operator: &'static str) -> bool {
+        if self.tokens.get(self.position) == Some(&Token::Operator(operator)) {
+            self.position += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Accepts `and`, `or` and `not` in any case.
+    fn accept_keyword(&mut self, keyword: &str) -> bool {
+        if matches!(self.tokens.get(self.position), Some(Token::Name(name)) if name.eq_ignore_ascii_case(keyword))
+        {
+            self.position += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, operator: &'static str) -> Result<(), String> {
+        if self.accept(operator) {
+            Ok(())
+        } else {
+            Err(format!("\"{}\" expected", operator))
+        }
+    }
+
+    fn disjunction(&mut self) -> Result<Node, String> {
+        let mut left = self.conjunction()?;
+        while self.accept("||") || self.accept_keyword("or") {
+            let right = self.conjunction()?;
+            left = Node::Binary(Operator::Or, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn conjunction(&mut self) -> Result<Node, String> {
+        let mut left = self.negation()?;
+        while self.accept("&&") || self.accept_keyword("and") {
+            let right = self.negation()?;
+            left = Node::Binary(Operator::And, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn negation(&mut self) -> Result<Node, String> {
+        if self.accept("!") || self.accept_keyword("not") {
+            return Ok(Node::Not(Box::new(self.negation()?)));
+        }
+        self.comparison()
+    }
+
+    fn comparison(&mut self) -> Result<Node, String> {
+        let left = self.concatenation()?;
+        let operator = if self.accept("==") || self.accept("=") {
+            Operator::Equal
+        } else if self.accept("!=") || self.accept("<>") {
+            Operator::NotEqual
+        } else if self.accept("<=") {
+            Operator::LessOrEqual
+        } else if self.accept(">=") {
+            Operator::GreaterOrEqual
+        } else if self.accept("<") {
+            Operator::Less
+        } else if self.accept(">") {
+            Operator::Greater
+        } else {
+            return Ok(left);
+        };
+        let right = self.concatenation()?;
+        Ok(Node::Binary(operator, Box::new(left), Box::new(right)))
+    }
+
+    fn concatenation(&mut self) -> Result<Node, String> {
+        let mut left = self.additive()?;
+        while self.accept("&") {
+            let right = self.additive()?;
+            left = Node::Binary(Operator::Concatenate, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn additive(&mut self) -> Result<Node, String> {
+        let mut left = self.multiplicative()?;
+        loop {
+            let operator = if self.accept("+") {
+                Operator::Add
+            } else if self.accept("-") {
+                Operator::Subtract
+            } else {
+                return Ok(left);
+            };
+            let right = self.multiplicative()?;
+            left = Node::Binary(operator, Box::new(left), Box::new(right));
+        }
+    }
+
+    fn multiplicative(&mut self) -> Result<Node, String> {
+        let mut left = self.unary()?;
+        loop {
+            let operator = if self.accept("*") {
+                Operator::Multiply
+            } else if self.accept("/") {
+                Operator::Divide
+            } else {
+                return Ok(left);
+            };
+            let right = self.unary()?;
+            left = Node::Binary(operator, Box::new(left), Box::new(right));
+        }
+    }
+
+    fn unary(&mut self) -> Result<Node, String> {
+        if self.accept("-") {
+            return Ok(Node::Negate(Box::new(self.unary()?)));
+        }
+        if self.accept("+") {
+            return self.unary();
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Node, String> {
+        let token = self
+            .tokens
+            .get(self.position)
+            .cloned()
+            .ok_or_else(|| "Unexpected end of expression".to_string())?;
+        self.position += 1;
+        match token {
+            Token::Number(value) => Ok(Node::Number(value)),
+            Token::Text(value) => Ok(Node::Text(value)),
+            Token::RowNumber => Ok(Node::RowNumber),
+            Token::Operator("(") => {
+                let node = self.disjunction()?;
+                self.expect(")")?;
+                Ok(node)
+            }
+            Token::Name(name) if self.accept("(") => {
+                let (function, min, max) = Function::from_name(&name)
+                    .ok_or_else(|| format!("Unknown function \"{}\"", name))?;
+                let arguments = self.arguments()?;
+                if !(min..=max).contains(&arguments.len()) {
+                    return Err(if min == max {
+                        format!("\"{}\" takes {} argument(s)", name, min)
+                    } else {
+                        format!("\"{}\" takes {} to {} arguments", name, min, max)
+                    });
+                }
+                Ok(Node::Call(function, arguments))
+            }
+            Token::Name(name) | Token::Column(name) => {
+                match self.headers.iter().position(|header| header == &name) {
+                    Some(index) => Ok(Node::Column(index)),
+                    None if name.eq_ignore_ascii_case("true") => Ok(Node::Boolean(true)),
+                    None if name.eq_ignore_ascii_case("false") => Ok(Node::Boolean(false)),
+                    None => Err(format!("Column \"{}\" not found", name)),
+                }
+            }
+            token => Err(format!("Unexpected {}", token)),
+        }
+    }
+
+    fn arguments(&mut self) -> Result<Vec<Node>, String> {
+        let mut arguments = Vec::new();
+        if self.accept(")") {
+            return Ok(arguments);
+        }
+        loop {
+            arguments.push(self.disjunction()?);
+            if self.accept(")") {
+                return Ok(arguments);
+            }
+            if !self.accept(",") {
+                return Err("\")\" expected".to_string());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers() -> Vec<String> {
+        ["First", "Last", "Amount", "Unit price", "Status"]
+            .map(String::from)
+            .to_vec()
+    }
+
+    fn row() -> Vec<String> {
+        ["Ada", " Lovelace ", "4", "2.5", ""]
+            .map(String::from)
+            .to_vec()
+    }
+
+    fn evaluate(text: &str) -> Result<String, String> {
+        Expression::parse(text, &headers())?.evaluate(&row(), 7)
+    }
+
+    fn holds(condition: &str) -> Result<bool, String> {
+        Expression::condition(condition, &headers())?.is_true(&row(), 7)
+    }
+
+    #[test]
+    fn follows_operator_precedence() {
+        assert_eq!(evaluate("{{1 + 2 * 3}}"), Ok("7".to_string()));
+        assert_eq!(evaluate("{{(1 + 2) * 3}}"), Ok("9".to_string()));
+        assert_eq!(evaluate("{{-Amount + 1}}"), Ok("-3".to_string()));
+        assert_eq!(
+            evaluate("{{Amount * [Unit price] & \" EUR\"}}"),
+            Ok("10 EUR".to_string())
+        );
+        assert_eq!(holds("Amount > 3 and not Status or false"), Ok(true));
+        assert_eq!(holds("Amount > 5 or Amount < 2 and true"), Ok(false));
+    }
+
+    #[test]
+    fn fills_text_and_functions() {
+        assert_eq!(
+            evaluate("{{First}} {{upper(trim(Last))}} #{{#}}"),
+            Ok("Ada LOVELACE #7".to_string())
+        );
+        assert_eq!(
+            evaluate("{{default(Status, \"n/a\")}}"),
+            Ok("n/a".to_string())
+        );
+        assert_eq!(evaluate("{{round(10 / 3, 2)}}"), Ok("3.33".to_string()));
+        assert_eq!(
+            evaluate("{{date(\"2024-03-01\", \"%d.%m.%Y\")}}"),
+            Ok("01.03.2024".to_string())
+        );
+    }
+
+    #[test]
+    fn reports_errors() {
+        assert_eq!(
+            evaluate("{{Missing name}}"),
+            Err("Column \"Missing name\" not found in \"{{Missing name}}\"".to_string())
+        );
+        assert!(Expression::parse("{{nope(First)}}", &headers()).is_err());
+        assert!(Expression::parse("{{upper()}}", &headers()).is_err());
+        assert!(Expression::parse("{{(1 + 2}}", &headers()).is_err());
+        assert!(Expression::parse("{{\"open}}", &headers()).is_err());
+        assert!(Expression::parse("{{1 2}}", &headers()).is_err());
+        assert_eq!(
+            evaluate("{{Amount / 0}}"),
+            Err("Division by zero".to_string())
+        );
+        assert!(evaluate("{{First * 2}}").is_err());
+        assert!(holds("First").is_err());
+    }
+}
@@ -0,0 +1,1028 @@
+use crate::cells::{column_label_from_index, parse_cell_reference};
+use std::cmp::Ordering;
+use std::fmt;
+
+/// A cell value as seen by formulas.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Empty,
+    Number(f64),
+    Text(String),
+    Boolean(bool),
+}
+
+impl Value {
+    fn to_number(&self) -> Option<f64> {
+        match self {
+            Value::Empty => Some(0.0),
+            Value::Number(value) => Some(*value),
+            Value::Boolean(value) => Some(f64::from(u8::from(*value))),
+            Value::Text(value) => value.trim().parse::<f64>().ok(),
+        }
+    }
+
+    fn to_bool(&self) -> Option<bool> {
+        match self {
+            Value::Empty => Some(false),
+            Value::Number(value) => Some(*value != 0.0),
+            Value::Boolean(value) => Some(*value),
+            Value::Text(value) if value.eq_ignore_ascii_case("true") => Some(true),
+            Value::Text(value) if value.eq_ignore_ascii_case("false") => Some(false),
+            Value::Text(_) => None,
+        }
+    }
+
+    fn to_text(&self) -> String {
+        match self {
+            Value::Empty => String::new(),
+            Value::Number(value) => number_text(*value),
+            Value::Text(value) => value.clone(),
+            Value::Boolean(true) => "TRUE".to_string(),
+            Value::Boolean(false) => "FALSE".to_string(),
+        }
+    }
+}
+
+/// Formats a number the way spreadsheets convert it to text, with at most
+/// 15 significant digits.
+pub fn number_text(value: f64) -> String {
+    let rounded = format!("{:.14e}", value).parse::<f64>().unwrap_or(value);
+    format!("{}", rounded)
+}
+
+/// Evaluates a formula such as `SUM(B2:B9)*1.19` against the cells of its
+/// sheet. `cell` returns the value at a zero-based `(row, column)`, or `None`
+/// when it is unknown.
+///
+/// Supports arithmetic, comparisons, `&`, `SUM`, `IF`, `CONCAT` and
+/// `CONCATENATE`. Returns `None` for anything else, including references to
+/// other sheets and errors such as division by zero.
+pub fn evaluate(formula: &str, cell: &mut dyn FnMut(u32, u32) -> Option<Value>) -> Option<Value> {
+    let tokens = tokenize(formula.trim().trim_start_matches('='))?;
+    let mut parser = Parser {
+        tokens,
+        position: 0,
+        cell,
+    };
+    let value = parser.comparison()?;
+    if parser.position != parser.tokens.len() {
+        return None;
+    }
+    match value {
+        Value::Empty => Some(Value::Number(0.0)),
+        value => Some(value),
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Number(f64),
+    Text(String),
+    Cell(u32, u32),
+    Range((u32, u32), (u32, u32)),
+    Name(String),
+    Operator(&'static str),
+}
+
+fn tokenize(formula: &str) -> Option<Vec<Token>> {
+    const OPERATORS: [&str; 16] = [
+        "<>", "<=", ">=", "+", "-", "*", "/", "^", "&", "=", "<", ">", "%", "(", ")", ",",
+    ];
+    let chars = formula.chars().collect::<Vec<_>>();
+    let mut tokens = Vec::new();
+    let mut index = 0;
+
+    while index < chars.len() {
+        let ch = chars[index];
+        if ch.is_whitespace() {
+            index += 1;
+        } else if ch == '"' {
+            let mut text = String::new();
+            index += 1;
+            loop {
+                match chars.get(index) {
+                    Some('"') if chars.get(index + 1) == Some(&'"') => {
+                        text.push('"');
+                        index += 2;
+                    }
+                    Some('"') => {
+                        index += 1;
+                        break;
+                    }
+                    Some(ch) => {
+                        text.push(*ch);
+                        index += 1;
+                    }
+                    None => return None,
+                }
+            }
+            tokens.push(Token::Text(text));
+        } else if ch.is_ascii_digit() || (ch == '.' && chars.get(index + 1)?.is_ascii_digit()) {
+            let start = index;
+            while index < chars.len() && (chars[index].is_ascii_digit() || chars[index] == '.') {
+                index += 1;
+            }
+            if matches!(chars.get(index), Some('e' | 'E')) {
+                index += 1;
+                if matches!(chars.get(index), Some('+' | '-')) {
+                    index += 1;
+                }
+                while index < chars.len() && chars[index].is_ascii_digit() {
+                    index += 1;
+                }
+            }
+            let text = chars[start..index].iter().collect::<String>();
+            tokens.push(Token::Number(text.parse().ok()?));
+        } else if ch.is_ascii_alphabetic() || ch == '$' || ch == '_' {
+            let start = index;
+            while index < chars.len()
+                && (chars[index].is_ascii_alphanumeric() || matches!(chars[index], '$' | '_' | '.'))
+            {
+                index += 1;
+            }
+            let name = chars[start..index].iter().collect::<String>();
+            // Other sheets and whole rows or columns are not supported.
+            if matches!(chars.get(index), Some('!')) {
+                return None;
+            }
+            match parse_reference(&name) {
+                Some(first) if chars.get(index) == Some(&':') => {
+                    let start = index + 1;
+                    let mut end = start;
+                    while end < chars.len()
+                        && (chars[end].is_ascii_alphanumeric() || chars[end] == '$')
+                    {
+                        end += 1;
+                    }
+                    let second = parse_reference(&chars[start..end].iter().collect::<String>())?;
+                    tokens.push(Token::Range(first, second));
+                    index = end;
+                }
+                Some((row, col)) => tokens.push(Token::Cell(row, col)),
+                None => tokens.push(Token::Name(name.to_ascii_uppercase())),
+            }
+        } else {
+            let rest = chars[index..].iter().take(2).collect::<String>();
+            let operator = OPERATORS
+                .into_iter()
+                .find(|operator| rest.starts_with(operator))?;
+            tokens.push(Token::Operator(operator));
+            index += operator.len();
+        }
+    }
+
+    Some(tokens)
+}
+
+/// Parses `B4` or `$B$4` into a zero-based position.
+fn parse_reference(name: &str) -> Option<(u32, u32)> {
+    let name = name.replace('$', "");
+    let letters = name.chars().take_while(char::is_ascii_alphabetic).count();
+    if letters == 0 || letters > 3 || letters == name.len() {
+        return None;
+    }
+    parse_cell_reference(&name)
+}
+
+/// A function argument: ranges stay unevaluated so that functions can skip
+/// text cells in them.
+enum Argument {
+    Range((u32, u32), (u32, u32)),
+    Value(Value),
+}
+
+struct Parser<'a> {
+    tokens: Vec<Token>,
+    position: usize,
+    cell: &'a mut dyn FnMut(u32, u32) -> Option<Value>,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn accept(&mut self, operator: &str) -> bool {
+        if matches!(self.peek(), Some(Token::Operator(current)) if *current == operator) {
+            self.position += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn accept_any(&mut self, operators: &[&'static str]) -> Option<&'static str> {
+        match self.peek() {
+            Some(Token::Operator(operator)) if operators.contains(operator) => {
+                let operator = *operator;
+                self.position += 1;
+                Some(operator)
+            }
+            _ => None,
+        }
+    }
+
+    fn comparison(&mut self) -> Option<Value> {
+        let mut left = self.concatenation()?;
+        while let Some(operator) = self.accept_any(&["=", "<>", "<", ">", "<=", ">="]) {
+            let right = self.concatenation()?;
+            let ordering = compare(&left, &right);
+            left = Value::Boolean(match operator {
+                "=" => ordering == Ordering::Equal,
+                "<>" => ordering != Ordering::Equal,
+                "<" => ordering == Ordering::Less,
+                ">" => ordering == Ordering::Greater,
+                "<=" => ordering != Ordering::Greater,
+                _ => ordering != Ordering::Less,
+            });
+        }
+        Some(left)
+    }
+
+    fn concatenation(&mut self) -> Option<Value> {
+        let mut left = self.additive()?;
+        while self.accept("&") {
+            let right = self.additive()?;
+            left = Value::Text(left.to_text() + &right.to_text());
+        }
+        Some(left)
+    }
+
+    fn additive(&mut self) -> Option<Value> {
+        let mut left = self.multiplicative()?;
+        while let Some(operator) = self.accept_any(&["+", "-"]) {
+            let right = self.multiplicative()?.to_number()?;
+            let left_number = left.to_number()?;
+            left = number(if operator == "+" {
+                left_number + right
+            } else {
+                left_number - right
+            })?;
+        }
+        Some(left)
+    }
+
+    fn multiplicative(&mut self) -> Option<Value> {
+        let mut left = self.power()?;
+        while let Some(operator) = self.accept_any(&["*", "/"]) {
+            let right = self.power()?.to_number()?;
+            let left_number = left.to_number()?;
+            left = number(if operator == "*" {
+                left_number * right
+            } else {
+                left_number / right
+            })?;
+        }
+        Some(left)
+    }
+
+    fn power(&mut self) -> Option<Value> {
+        let mut left = self.unary()?;
+        while self.accept("^") {
+            let right = self.unary()?.to_number()?;
+            left = number(left.to_number()?.powf(right))?;
+        }
+        Some(left)
+    }
+
+    fn unary(&mut self) -> Option<Value> {
+        if self.accept("-") {
+            return number(-self.unary()?.to_number()?);
+        }
+        if self.accept("+") {
+            return self.unary();
+        }
+        let value = self.primary()?;
+        if self.accept("%") {
+            return number(value.to_number()? / 100.0);
+        }
+        Some(value)
+    }
+
+    fn primary(&mut self) -> Option<Value> {
+        let token = self.peek()?.clone();
+        self.position += 1;
+        match token {
+            Token::Number(value) => Some(Value::Number(value)),
+            Token::Text(value) => Some(Value::Text(value)),
+            Token::Cell(row, col) => (self.cell)(row, col),
+            Token::Operator("(") => {
+                let value = self.comparison()?;
+                self.accept(")").then_some(value)
+            }
+            Token::Name(name) if name == "TRUE" || name == "FALSE" => {
+                if self.accept("(") && !self.accept(")") {
+                    return None;
+                }
+                Some(Value::Boolean(name == "TRUE"))
+            }
+            Token::Name(name) if name == "IF" && self.accept("(") => self.if_call(),
+            Token::Name(name) if self.accept("(") => {
+                let arguments = self.arguments()?;
+                self.call(name.trim_start_matches("_XLFN."), arguments)
+            }
+            _ => None,
+        }
+    }
+
+    fn arguments(&mut self) -> Option<Vec<Argument>> {
+        let mut arguments = Vec::new();
+        if self.accept(")") {
+            return Some(arguments);
+        }
+        loop {
+            let is_reference = matches!(self.peek(), Some(Token::Cell(..) | Token::Range(..)))
+                && matches!(
+                    self.tokens.get(self.position + 1),
+                    Some(Token::Operator("," | ")"))
+                );
+            if is_reference {
+                let argument = match self.peek()? {
+                    Token::Cell(row, col) => Argument::Range((*row, *col), (*row, *col)),
+                    Token::Range(first, second) => Argument::Range(*first, *second),
+                    _ => return None,
+                };
+                self.position += 1;
+                arguments.push(argument);
+            } else {
+                arguments.push(Argument::Value(self.comparison()?));
+            }
+            if self.accept(")") {
+                return Some(arguments);
+            }
+            if !self.accept(",") {
+                return None;
+            }
+        }
+    }
+
+    fn call(&mut self, name: &str, arguments: Vec<Argument>) -> Option<Value> {
+        match name {
+            "SUM" => {
+                let mut total = 0.0;
+                for argument in arguments {
+                    match argument {
+                        Argument::Range(first, second) => {
+                            for value in self.range_values(first, second)? {
+                                if let Value::Number(value) = value {
+                                    total += value;
+                                }
+                            }
+                        }
+                        Argument::Value(value) => total += value.to_number()?,
+                    }
+                }
+                number(total)
+            }
+            "CONCAT" | "CONCATENATE" => {
+                let mut text = String::new();
+                for argument in arguments {
+                    match argument {
+                        Argument::Range(first, second) => {
+                            for value in self.range_values(first, second)? {
+                                text.push_str(&value.to_text());
+                            }
+                        }
+                        Argument::Value(value) => text.push_str(&value.to_text()),
+                    }
+                }
+                Some(Value::Text(text))
+            }
+            _ => None,
+        }
+    }
+
+    /// `IF` evaluates only the branch it returns, so that
+    /// `IF(B3=0,0,A1/B3)` works when `B3` is empty.
+    fn if_call(&mut self) -> Option<Value> {
+        let condition = self.comparison()?.to_bool()?;
+        if !self.accept(",") {
+            return None;
+        }
+        let mut value = if condition {
+            self.comparison()?
+        } else {
+            self.skip_argument()?;
+            Value::Boolean(false)
+        };
+        if self.accept(",") {
+            if condition {
+                self.skip_argument()?;
+            } else {
+                value = self.comparison()?;
+            }
+        }
+        self.accept(")").then_some(value)
+    }
+
+    /// Moves past a function argument without evaluating it.
+    fn skip_argument(&mut self) -> Option<()> {
+        let start = self.position;
+        let mut depth = 0usize;
+        loop {
+            match self.peek()? {
+                Token::Operator("," | ")") if depth == 0 => {
+                    return (self.position > start).then_some(());
+                }
+                Token::Operator("(") => depth += 1,
+                Token::Operator(")") => depth -= 1,
+                _ => {}
+            }
+            self.position += 1;
+        }
+    }
+
+    /// Values of a range, row by row.
+    fn range_values(&mut self, first: (u32, u32), second: (u32, u32)) -> Option<Vec<Value>> {
+        let (top, bottom) = (first.0.min(second.0), first.0.max(second.0));
+        let (left, right) = (first.1.min(second.1), first.1.max(second.1));
+        // Guards against references such as `A1:XFD1048576`.
+        if u64::from(bottom - top + 1) * u64::from(right - left + 1) > 100_000 {
+            return None;
+        }
+        let mut values = Vec::new();
+        for row in top..=bottom {
+            for col in left..=right {
+                values.push((self.cell)(row, col)?);
+            }
+        }
+        Some(values)
+    }
+}
+
+fn number(value: f64) -> Option<Value> {
+    value.is_finite().then_some(Value::Number(value))
+}
+
+/// Orders values like spreadsheets do: numbers before text before booleans,
+/// with text compared case-insensitively and empty cells matching `0`, `""`
+/// and `FALSE`.
+fn compare(left: &Value, right: &Value) -> Ordering {
+    fn rank(value: &Value) -> u8 {
+        match value {
+            Value::Empty | Value::Number(_) => 0,
+            Value::Text(_) => 1,
+            Value::Boolean(_) => 2,
+        }
+    }
+    let (left, right) = match (left, right) {
+        (Value::Empty, Value::Text(_)) => (Value::Text(String::new()), right.clone()),
+        (Value::Text(_), Value::Empty) => (left.clone(), Value::Text(String::new())),
+        (Value::Empty, Value::Boolean(_)) => (Value::Boolean(false), right.clone()),
+        (Value::Boolean(_), Value::Empty) => (left.clone(), Value::Boolean(false)),
+        _ => (left.clone(), right.clone()),
+    };
+    match (&left, &right) {
+        (Value::Text(left), Value::Text(right)) => left.to_lowercase().cmp(&right.to_lowercase()),
+        (Value::Boolean(left), Value::Boolean(right)) => left.cmp(right),
+        (Value::Empty | Value::Number(_), Value::Empty | Value::Number(_)) => {
+            let left = left.to_number().unwrap_or_default();
+            let right = right.to_number().unwrap_or_default();
+            left.partial_cmp(&right).unwrap_or(Ordering::Equal)
+        }
+        _ => rank(&left).cmp(&rank(&right)),
+    }
+}
+
+/// Points references such as `Invoice!A1` or `'My sheet'!A1:B4` at another
+/// sheet, leaving string literals alone.
+pub fn rename_sheet_references(formula: &str, old_name: &str, new_name: &str) -> String {
+    replace_prefixes(
+        formula,
+        &sheet_prefixes(old_name),
+        &format!("{}!", quoted_sheet_name(new_name)),
+    )
+}
+
+/// Whether a formula refers to a sheet, ignoring string literals.
+pub fn refers_to_sheet(formula: &str, name: &str) -> bool {
+    replace_prefixes(formula, &sheet_prefixes(name), "").len() != formula.len()
+}
+
+fn sheet_prefixes(name: &str) -> [String; 2] {
+    [
+        format!("'{}'!", name.replace('\'', "''")),
+        format!("{}!", name),
+    ]
+}
+
+/// Points structured references such as `Table1[Amount]` at another table.
+pub fn rename_table_references(formula: &str, old_name: &str, new_name: &str) -> String {
+    replace_prefixes(
+        formula,
+        &[format!("{}[", old_name)],
+        &format!("{}[", new_name),
+    )
+}
+
+/// Replaces every occurrence of one of `prefixes` that starts a name,
+/// ignoring case and string literals.
+fn replace_prefixes(formula: &str, prefixes: &[String], replacement: &str) -> String {
+    let mut result = String::with_capacity(formula.len());
+    let mut rest = formula;
+    let mut previous: Option<char> = None;
+
+    while let Some(ch) = rest.chars().next() {
+        if ch == '"' {
+            // Copy the string literal; doubled quotes stay inside it.
+            let mut end = 1;
+            while end < rest.len() {
+                match rest[end..].find('"') {
+                    Some(index) if rest[end + index + 1..].starts_with('"') => end += index + 2,
+                    Some(index) => {
+                        end += index + 1;
+                        break;
+                    }
+                    None => end = rest.len(),
+                }
+            }
+            result.push_str(&rest[..end]);
+            rest = &rest[end..];
+            previous = Some('"');
+            continue;
+        }
+        let at_boundary =
+            previous.is_none_or(|ch| !(ch.is_alphanumeric() || ch == '_' || ch == '.'));
+        let prefix = prefixes
+            .iter()
+            .find(|prefix| at_boundary && starts_with_ignore_case(rest, prefix));
+        if let Some(prefix) = prefix {
+            result.push_str(replacement);
+            rest = &rest[prefix.len()..];
+            previous = replacement.chars().last();
+        } else {
+            result.push(ch);
+            rest = &rest[ch.len_utf8()..];
+            previous = Some(ch);
+        }
+    }
+    result
+}
+
+/// The sheet name as written in references, quoted unless it only contains
+/// letters, digits, `_` and `.` and does not start with a digit.
+pub fn quoted_sheet_name(name: &str) -> String {
+    let plain = name
+        .chars()
+        .next()
+        .is_some_and(|ch| ch.is_alphabetic() || ch == '_')
+        && name
+            .chars()
+            .all(|ch| ch.is_alphanumeric() || ch == '_' || ch == '.')
+        && parse_reference(name).is_none();
+    if plain {
+        name.to_string()
+    } else {
+        format!("'{}'", name.replace('\'', "''"))
+    }
+}
+
+fn starts_with_ignore_case(text: &str, prefix: &str) -> bool {
+    text.get(..prefix.len())
+        .is_some_and(|start| start.eq_ignore_ascii_case(prefix))
+}
+
+/// A cell, whole-row or whole-column reference in a formula. Positions are
+/// zero-based; `$` marks the absolute parts.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Reference {
+    pub row: Option<u32>,
+    pub col: Option<u32>,
+    pub absolute_row: bool,
+    pub absolute_col: bool,
+}
+
+impl Reference {
+    /// Parses `B4`, `$B$4`, a column such as `B` or a row such as `$4`.
+    pub fn parse(text: &str) -> Option<Self> {
+        let (absolute_col, rest) = strip_dollar(text);
+        let letters = rest.chars().take_while(char::is_ascii_alphabetic).count();
+        let (column, rest) = rest.split_at(letters);
+        let (absolute_row, digits) = strip_dollar(rest);
+        if letters > 3 || !digits.chars().all(|ch| ch.is_ascii_digit()) {
+            return None;
+        }
+        let row = match digits {
+            "" => None,
+            digits => Some(digits.parse::<u32>().ok()?.checked_sub(1)?),
+        };
+        let col = column
+            .chars()
+            .map(|ch| u32::from(ch.to_ascii_uppercase() as u8 - b'A' + 1))
+            .reduce(|index, digit| index * 26 + digit)
+            .map(|index| index - 1);
+        match (row, col) {
+            (None, None) => None,
+            // `$4` is a row; `B$` is nothing.
+            (Some(_), None) if absolute_row => None,
+            (Some(row), None) => Some(Self {
+                row: Some(row),
+                col: None,
+                absolute_row: absolute_col,
+                absolute_col: false,
+            }),
+            (None, Some(_)) if absolute_row => None,
+            (row, col) => Some(Self {
+                row,
+                col,
+                absolute_row,
+                absolute_col,
+            }),
+        }
+    }
+
+    /// The reference in a cell `rows` and `cols` away, as when the formula
+    /// is copied there: absolute parts stay.
+    pub fn moved(self, rows: i64, cols: i64) -> Self {
+        let shift = |index: Option<u32>, absolute: bool, by: i64| match index {
+            Some(index) if !absolute => Some((i64::from(index) + by).max(0) as u32),
+            index => index,
+        };
+        Self {
+            row: shift(self.row, self.absolute_row, rows),
+            col: shift(self.col, self.absolute_col, cols),
+            ..self
+        }
+    }
+
+    fn is_cell(&self) -> bool {
+        self.row.is_some() && self.col.is_some()
+    }
+
+    fn same_kind(&self, other: &Self) -> bool {
+        self.row.is_some() == other.row.is_some() && self.col.is_some() == other.col.is_some()
+    }
+}
+
+impl fmt::Display for Reference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(col) = self.col {
+            if self.absolute_col {
+                write!(f, "$")?;
+            }
+            write!(f, "{}", column_label_from_index(col))?;
+        }
+        if let Some(row) = self.row {
+            if self.absolute_row {
+                write!(f, "$")?;
+            }
+            write!(f, "{}", row + 1)?;
+        }
+        Ok(())
+    }
+}
+
+fn strip_dollar(text: &str) -> (bool, &str) {
+    match text.strip_prefix('$') {
+        Some(rest) => (true, rest),
+        None => (false, text),
+    }
+}
+
+/// Maps a cell, or the two ends of a range, to new references.
+pub type ReferenceMap<'a> =
+    dyn FnMut(Reference, Option<Reference>) -> (Reference, Option<Reference>) + 'a;
+
+/// Rewrites the references of an A1-style formula such as
+/// `SUM(F10:F12)*Invoice!B2` that point at `sheet`, with or without the
+/// sheet name. References to other sheets, string literals and structured
+/// references are left alone.
+pub fn map_references(formula: &str, sheet: &str, map: &mut ReferenceMap) -> String {
+    let chars = formula.chars().collect::<Vec<_>>();
+    let mut result = String::with_capacity(formula.len());
+    let mut index = 0;
+
+    while index < chars.len() {
+        match chars[index] {
+            '"' => {
+                let end = quoted_end(&chars, index);
+                result.extend(&chars[index..end]);
+                index = end;
+            }
+            '[' => {
+                let end = chars[index..]
+                    .iter()
+                    .scan(0i32, |depth, ch| {
+                        *depth += match ch {
+                            '[' => 1,
+                            ']' => -1,
+                            _ => 0,
+                        };
+                        Some(*depth)
+                    })
+                    .position(|depth| depth == 0)
+                    .map_or(chars.len(), |end| index + end + 1);
+                result.extend(&chars[index..end]);
+                index = end;
+            }
+            '\'' => {
+                let end = quoted_end(&chars, index);
+                result.extend(&chars[index..end]);
+                if chars.get(end) == Some(&'!') {
+                    let name = chars[index + 1..end - 1]
+                        .iter()
+                        .collect::<String>()
+                        .replace("''", "'");
+                    result.push('!');
+                    index = map_reference_at(
+                        &chars,
+                        end + 1,
+                        name.eq_ignore_ascii_case(sheet),
+                        map,
+                        &mut result,
+                    );
+                } else {
+                    index = end;
+                }
+            }
+            ch if is_name_char(ch) => {
+                let end = name_end(&chars, index);
+                if chars.get(end) == Some(&'!') {
+                    let name = chars[index..end].iter().collect::<String>();
+                    result.extend(&chars[index..=end]);
+                    index = map_reference_at(
+                        &chars,
+                        end + 1,
+                        name.eq_ignore_ascii_case(sheet),
+                        map,
+                        &mut result,
+                    );
+                } else {
+                    index = map_reference_at(&chars, index, true, map, &mut result);
+                }
+            }
+            ch => {
+                result.push(ch);
+                index += 1;
+            }
+        }
+    }
+    result
+}
+
+/// The index after the literal quoted with `'` or `"` that starts at
+/// `start`; doubled quotes stay inside it.
+fn quoted_end(chars: &[char], start: usize) -> usize {
+    let quote = chars[start];
+    let mut index = start + 1;
+    while index < chars.len() {
+        if chars[index] == quote {
+            if chars.get(index + 1) == Some(&quote) {
+                index += 2;
+                continue;
+            }
+            return index + 1;
+        }
+        index += 1;
+    }
+    chars.len()
+}
+
+fn is_name_char(ch: char) -> bool {
+    ch.is_alphanumeric() || matches!(ch, '$' | '_' | '.')
+}
+
+fn name_end(chars: &[char], start: usize) -> usize {
+    chars[start..]
+        .iter()
+        .position(|ch| !is_name_char(*ch))
+        .map_or(chars.len(), |end| start + end)
+}
+
+/// Writes the name at `start`, mapped if it is a reference or range and
+/// `mapped` is set, and returns the index after it.
+fn map_reference_at(
+    chars: &[char],
+    start: usize,
+    mapped: bool,
+    map: &mut ReferenceMap,
+    result: &mut String,
+) -> usize {
+    let end = name_end(chars, start);
+    let text = chars[start..end].iter().collect::<String>();
+    let first = Reference::parse(&text).filter(|_| chars.get(end) != Some(&'('));
+    let Some(first) = first else {
+        result.push_str(&text);
+        return end;
+    };
+    let second = match chars.get(end) {
+        Some(':') => {
+            let second_end = name_end(chars, end + 1);
+            Reference::parse(&chars[end + 1..second_end].iter().collect::<String>())
+                .filter(|second| second.same_kind(&first))
+                .map(|second| (second, second_end))
+        }
+        _ => None,
+    };
+    match second {
+        Some((second, after)) => {
+            if mapped {
+                let (first, second) = map(first, Some(second));
+                result.push_str(&first.to_string());
+                if let Some(second) = second {
+                    result.push(':');
+                    result.push_str(&second.to_string());
+                }
+            } else {
+                result.extend(&chars[start..after]);
+            }
+            after
+        }
+        None => {
+            // Lone rows and columns are numbers or names.
+            if mapped && first.is_cell() {
+                result.push_str(&map(first, None).0.to_string());
+            } else {
+                result.push_str(&text);
+            }
+            end
+        }
+    }
+}
+
+/// Rewrites the references of an OpenDocument formula such as
+/// `of:=SUM([.F10:.F12])` that point at `sheet`, with or without the sheet
+/// name.
+pub fn map_odf_references(formula: &str, sheet: &str, map: &mut ReferenceMap) -> String {
+    let chars = formula.chars().collect::<Vec<_>>();
+    let mut result = String::with_capacity(formula.len());
+    let mut index = 0;
+
+    while index < chars.len() {
+        match chars[index] {
+            '"' => {
+                let end = quoted_end(&chars, index);
+                result.extend(&chars[index..end]);
+                index = end;
+            }
+            '[' => {
+                let mut end = index + 1;
+                while end < chars.len() && chars[end] != ']' {
+                    end = if chars[end] == '\'' {
+                        quoted_end(&chars, end)
+                    } else {
+                        end + 1
+                    };
+                }
+                let address = chars[index + 1..end.min(chars.len())]
+                    .iter()
+                    .collect::<String>();
+                result.push('[');
+                result.push_str(&map_odf_range(&address, sheet, map));
+                if end < chars.len() {
+                    result.push(']');
+                }
+                index = end + 1;
+            }
+            ch => {
+                result.push(ch);
+                index += 1;
+            }
+        }
+    }
+    result
+}
+
+/// Rewrites OpenDocument range addresses such as `Invoice.A1:Invoice.F30` or
+/// `.F10:.F12` that point at `sheet`. Several ranges are separated by
+/// spaces.
+pub fn map_odf_range(ranges: &str, sheet: &str, map: &mut ReferenceMap) -> String {
+    ranges
+        .split(' ')
+        .map(|range| {
+            let parts = split_odf_range(range);
+            let parsed = parts
+                .iter()
+                .map(|part| {
+                    let (table, cell) = split_odf_address(part);
+                    Reference::parse(cell).map(|reference| (table, reference))
+                })
+                .collect::<Option<Vec<_>>>();
+            let is_sheet = |table: &str| {
+                let table = table.trim_start_matches('$').trim_end_matches('.');
+                let name = match table.strip_prefix('\'') {
+                    Some(quoted) => quoted.trim_end_matches('\'').replace("''", "'"),
+                    None => table.to_string(),
+                };
+                name.is_empty() || name == sheet
+            };
+            match parsed.as_deref() {
+                Some([(table, first)]) if first.is_cell() && is_sheet(table) => {
+                    format!("{}{}", table, map(*first, None).0)
+                }
+                Some([(first_table, first), (second_table, second)])
+                    if first.same_kind(second) && is_sheet(first_table) =>
+                {
+                    let (first, second) = map(*first, Some(*second));
+                    match second {
+                        Some(second) => {
+                            format!("{}{}:{}{}", first_table, first, second_table, second)
+                        }
+                        None => format!("{}{}", first_table, first),
+                    }
+                }
+                _ => range.to_string(),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Splits a range address at the `:` outside quoted table names.
+fn split_odf_range(range: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut quoted = false;
+    let mut start = 0;
+    for (index, ch) in range.char_indices() {
+        match ch {
+            '\'' => quoted = !quoted,
+            ':' if !quoted => {
+                parts.push(&range[start..index]);
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&range[start..]);
+    parts
+}
+
+/// Splits `Table.A1` or `.A1` into the table part including the dot and the
+/// cell.
+fn split_odf_address(address: &str) -> (&str, &str) {
+    let mut quoted = false;
+    let mut dot = None;
+    for (index, ch) in address.char_indices() {
+        match ch {
+            '\'' => quoted = !quoted,
+            '.' if !quoted => dot = Some(index),
+            _ => {}
+        }
+    }
+    match dot {
+        Some(dot) => (&address[..=dot], &address[dot + 1..]),
+        None => ("", address),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Evaluates `formula` with `A1` = 10, `A2` = 4, `B1` = "x" and every
+    /// other cell empty.
+    fn eval(formula: &str) -> Option<Value> {
+        evaluate(formula, &mut |row, col| match (row, col) {
+            (0, 0) => Some(Value::Number(10.0)),
+            (1, 0) => Some(Value::Number(4.0)),
+            (0, 1) => Some(Value::Text("x".to_string())),
+            _ => Some(Value::Empty),
+        })
+    }
+
+    #[test]
+    fn follows_operator_precedence() {
+        assert_eq!(eval("=1+2*3"), Some(Value::Number(7.0)));
+        assert_eq!(eval("=(1+2)*3"), Some(Value::Number(9.0)));
+        assert_eq!(eval("=2^3*2"), Some(Value::Number(16.0)));
+        assert_eq!(eval("=-A2+A1"), Some(Value::Number(6.0)));
+        assert_eq!(eval("=50%*A1"), Some(Value::Number(5.0)));
+        assert_eq!(eval("=1+2&3"), Some(Value::Text("33".to_string())));
+        assert_eq!(eval("=A1>A2+5"), Some(Value::Boolean(true)));
+        assert_eq!(eval("=SUM(A1:A3)*2"), Some(Value::Number(28.0)));
+        assert_eq!(eval("=CONCAT(B1,A2)"), Some(Value::Text("x4".to_string())));
+    }
+
+    #[test]
+    fn fails_on_errors() {
+        assert_eq!(eval("=A1/C1"), None);
+        assert_eq!(eval("=B1+1"), None);
+        assert_eq!(eval("=Lookup!A1"), None);
+        assert_eq!(eval("=VLOOKUP(A1,A1:A2,1)"), None);
+        assert_eq!(eval("=(1+2"), None);
+        assert_eq!(eval("=1+"), None);
+        assert_eq!(eval("=IF(B1,1,2)"), None);
+    }
+
+    #[test]
+    fn evaluates_only_the_chosen_branch() {
+        assert_eq!(eval("=IF(C1=0,0,A1/C1)"), Some(Value::Number(0.0)));
+        assert_eq!(eval("=IF(A2=0,0,A1/A2)"), Some(Value::Number(2.5)));
+        assert_eq!(eval("=IF(A1>0,A2,SUM(A1/0))"), Some(Value::Number(4.0)));
+        assert_eq!(eval("=IF(A1<0,1)"), Some(Value::Boolean(false)));
+        assert_eq!(eval("=IF(A1<0,1/0,)"), None);
+        assert_eq!(eval("=IF(A1>0,1,2"), None);
+    }
+
+    #[test]
+    fn renames_sheets_outside_string_literals() {
+        assert_eq!(
+            rename_sheet_references("Invoice!A1&\"Invoice!A1\"", "Invoice", "Invoice 2"),
+            "'Invoice 2'!A1&\"Invoice!A1\""
+        );
+        assert_eq!(
+            rename_sheet_references("SUM('My sheet'!B2:B4)", "My sheet", "Out"),
+            "SUM(Out!B2:B4)"
+        );
+    }
+}
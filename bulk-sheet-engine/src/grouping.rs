@@ -0,0 +1,173 @@
+use crate::data::DataTable;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+/// Orders the data rows and combines rows with the same key into one sheet
+/// or file.
+#[derive(Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Grouping {
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub sort_by: Vec<SortKey>,
+    /// Key columns such as `CustomerId`; empty gives every row its own
+    /// sheet.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub group_by: Vec<String>,
+}
+
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct SortKey {
+    pub column: String,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub descending: bool,
+}
+
+impl Grouping {
+    pub fn is_empty(&self) -> bool {
+        self.sort_by.is_empty() && self.group_by.is_empty()
+    }
+
+    /// Row indices of `data` per group, in output order. Groups follow the
+    /// sort order of their first row; without sort keys the file order is
+    /// kept.
+    pub fn groups(&self, data: &DataTable) -> Result<Vec<Vec<usize>>, String> {
+        let column = |name: &String| {
+            data.column_index(name)
+                .ok_or_else(|| format!("Column \"{}\" not found", name))
+        };
+        let sort_columns = self
+            .sort_by
+            .iter()
+            .map(|key| column(&key.column).map(|index| (index, key.descending)))
+            .collect::<Result<Vec<_>, String>>()?;
+        let group_columns = self
+            .group_by
+            .iter()
+            .map(column)
+            .collect::<Result<Vec<_>, String>>()?;
+
+        let mut indices = (0..data.rows.len()).collect::<Vec<_>>();
+        indices.sort_by(|left, right| {
+            sort_columns
+                .iter()
+                .map(|(column, descending)| {
+                    let ordering =
+                        compare_values(data.value(*left, *column), data.value(*right, *column));
+                    if *descending {
+                        ordering.reverse()
+                    } else {
+                        ordering
+                    }
+                })
+                .find(|ordering| ordering.is_ne())
+                .unwrap_or(Ordering::Equal)
+        });
+        if group_columns.is_empty() {
+            return Ok(indices.into_iter().map(|index| vec![index]).collect());
+        }
+
+        let mut groups: Vec<Vec<usize>> = Vec::new();
+        let mut positions: HashMap<Vec<&str>, usize> = HashMap::new();
+        for index in indices {
+            let key = group_columns
+                .iter()
+                .map(|column| data.value(index, *column).trim())
+                .collect::<Vec<_>>();
+            match positions.get(&key) {
+                Some(position) => groups[*position].push(index),
+                None => {
+                    positions.insert(key, groups.len());
+                    groups.push(vec![index]);
+                }
+            }
+        }
+        Ok(groups)
+    }
+
+    /// The first row of each group, which fills names, placeholders and
+    /// regular mappings. With key columns, `{{#}}` counts groups.
+    pub fn first_rows(&self, data: &DataTable, groups: &[Vec<usize>]) -> DataTable {
+        let mut first_rows = data.select(&groups.iter().map(|group| group[0]).collect::<Vec<_>>());
+        if !self.group_by.is_empty() {
+            first_rows.row_numbers = (1..=groups.len()).collect();
+        }
+        first_rows
+    }
+}
+
+/// Compares numbers by value and other values as text, ignoring case; empty
+/// values sort first, then numbers, then text.
+fn compare_values(left: &str, right: &str) -> Ordering {
+    let (left, right) = (left.trim(), right.trim());
+    // `NaN` and `inf` are text here.
+    let number = |value: &str| {
+        value
+            .parse::<f64>()
+            .ok()
+            .filter(|number| number.is_finite())
+    };
+    let rank = |value: &str| match number(value) {
+        _ if value.is_empty() => 0,
+        Some(_) => 1,
+        None => 2,
+    };
+    rank(left)
+        .cmp(&rank(right))
+        .then_with(|| match (number(left), number(right)) {
+            (Some(left), Some(right)) => left.total_cmp(&right),
+            _ => left.to_lowercase().cmp(&right.to_lowercase()),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(values: &[&str]) -> DataTable {
+        DataTable {
+            headers: vec!["Amount".to_string()],
+            rows: values.iter().map(|value| vec![value.to_string()]).collect(),
+            ..DataTable::default()
+        }
+    }
+
+    #[test]
+    fn sorts_numbers_before_text() {
+        let data = table(&["1a", "10", "", "2", "b", "NaN", "-1.5", "A"]);
+        let grouping = Grouping {
+            sort_by: vec![SortKey {
+                column: "Amount".to_string(),
+                descending: false,
+            }],
+            group_by: Vec::new(),
+        };
+        let sorted = grouping
+            .groups(&data)
+            .unwrap()
+            .into_iter()
+            .map(|group| data.value(group[0], 0))
+            .collect::<Vec<_>>();
+        assert_eq!(sorted, ["", "-1.5", "2", "10", "1a", "A", "b", "NaN"]);
+    }
+
+    #[test]
+    fn sorts_a_large_mixed_column() {
+        let values = (0..2000)
+            .map(|index| match index % 3 {
+                0 => index.to_string(),
+                1 => format!("{}a", index),
+                _ => "NaN".to_string(),
+            })
+            .collect::<Vec<_>>();
+        let data = table(&values.iter().map(String::as_str).collect::<Vec<_>>());
+        let grouping = Grouping {
+            sort_by: vec![SortKey {
+                column: "Amount".to_string(),
+                descending: true,
+            }],
+            group_by: Vec::new(),
+        };
+        assert_eq!(grouping.groups(&data).unwrap().len(), 2000);
+    }
+}
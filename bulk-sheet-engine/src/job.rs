@@ -0,0 +1,793 @@
+use crate::cards::{CardContent, CardLayout};
+use crate::cells::{CellReplacements, SheetContent, ValueType, parse_cell_targets};
+use crate::data::{DataSource, DataTable};
+use crate::docx_template::DocxTemplate;
+use crate::expressions::Expression;
+use crate::grouping::Grouping;
+use crate::ods_template::OdsTemplate;
+use crate::odt_template::OdtTemplate;
+use crate::output::{
+    OutputMode, OutputOptions, OutputSink, OutputTarget, sheet_names, unique_file_names,
+};
+use crate::row_blocks::RowExpansion;
+use crate::selection::RowFilter;
+use crate::template::{Template, TemplateFormat, read_template_placeholders};
+use crate::xlsx_template::XlsxTemplate;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::ops::Range;
+
+/// Writes the values of a data column into template cells: a cell such as
+/// `B4`, a range such as `B2:D2` or a list such as `B2, H40`. Derived
+/// mappings write an [`Expression`] such as `{{First}} {{Last}}` instead.
+///
+/// With grouped rows, mappings read the group's first row; detail mappings
+/// write every row of the group, each one row below the previous, or into
+/// its own copy of the template's repeated rows.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Mapping {
+    /// Unused by derived mappings.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub column: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expression: Option<String>,
+    pub cell: String,
+    #[serde(default, rename = "type", skip_serializing_if = "ValueType::is_auto")]
+    pub value_type: ValueType,
+    /// Source format of date values, e.g. `%d.%m.%Y`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub format: Option<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub detail: bool,
+}
+
+impl Mapping {
+    pub fn new(column: impl Into<String>, cell: impl Into<String>) -> Self {
+        Self {
+            column: column.into(),
+            expression: None,
+            cell: cell.into(),
+            value_type: ValueType::Auto,
+            format: None,
+            detail: false,
+        }
+    }
+
+    /// A mapping that writes a value derived from the row, see [`Expression`].
+    pub fn derived(expression: impl Into<String>, cell: impl Into<String>) -> Self {
+        Self {
+            expression: Some(expression.into()),
+            ..Self::new(String::new(), cell)
+        }
+    }
+
+    /// Names the mapping in error messages.
+    fn label(&self) -> String {
+        match &self.expression {
+            Some(_) => format!("derived value for {}", self.cell.trim()),
+            None => format!("column \"{}\"", self.column),
+        }
+    }
+
+    pub fn with_type(mut self, value_type: ValueType, format: Option<String>) -> Self {
+        self.value_type = value_type;
+        self.format = format;
+        self
+    }
+
+    pub fn with_detail(mut self, detail: bool) -> Self {
+        self.detail = detail;
+        self
+    }
+}
+
+/// A complete generation run. Workbook templates are filled through
+/// `mappings`; `{{placeholders}}` in workbook cell text and in text documents
+/// are bound to the data columns of the same name.
+#[derive(Clone)]
+pub struct Job {
+    pub data_source: DataSource,
+    pub template: Template,
+    pub mappings: Vec<Mapping>,
+    /// Rows to generate; the default selects every row.
+    pub filter: RowFilter,
+    /// Row order and groups; the default makes one sheet or file per row.
+    pub grouping: Grouping,
+    pub output: OutputOptions,
+}
+
+/// Rows read from the data source at a time.
+const CHUNK_ROWS: usize = 1000;
+
+/// Selected rows held in memory at most for single-file output, sorting,
+/// grouping and cards, which need all of them at once. Well above the
+/// half a million rows of large exports, and low enough to fail before a
+/// typical machine runs out of memory.
+const MAX_HELD_ROWS: usize = 2_000_000;
+
+impl Job {
+    pub fn run(&self) -> Result<Report, String> {
+        if self.output.mode == OutputMode::FilePerRow
+            && self.grouping.is_empty()
+            && self.template.cards.is_none()
+        {
+            return self.run_in_chunks();
+        }
+        generate(
+            &self.selected_rows()?,
+            &self.template,
+            &self.mappings,
+            &self.grouping,
+            &self.output,
+        )
+    }
+
+    /// Loads the template as [`Job::run`] does, so that a template that
+    /// cannot be used, for example one without a kept sheet, can be told
+    /// apart from failures while generating.
+    pub fn check_template(&self) -> Result<(), String> {
+        LoadedTemplate::load(&self.template, &self.output).map(|_| ())
+    }
+
+    /// Writes one file per row while reading the data, so that large CSV
+    /// files are never held in memory at once.
+    fn run_in_chunks(&self) -> Result<Report, String> {
+        let loaded = LoadedTemplate::load(&self.template, &self.output)?;
+        let mut writer = None;
+        let mut source_rows = 0;
+        let mut rows = 0;
+        for chunk in self.data_source.chunks(CHUNK_ROWS)? {
+            let chunk = chunk?;
+            source_rows += chunk.rows.len();
+            let data = chunk.select(&self.filter.indices(&chunk)?);
+            if data.rows.is_empty() {
+                continue;
+            }
+            let (render, sheets) = renderer(
+                &data,
+                &loaded,
+                &self.template,
+                &self.mappings,
+                &self.grouping,
+                &self.output,
+            )?;
+            let writer = match &mut writer {
+                Some(writer) => writer,
+                None => writer.insert(FileWriter::new(&self.template, &self.output)?),
+            };
+            writer.write(&render, &sheets)?;
+            rows += data.rows.len();
+        }
+        let Some(writer) = writer else {
+            return Err(self.no_rows_error(source_rows));
+        };
+        Ok(Report {
+            format: self.template.format(),
+            mode: self.output.mode,
+            rows,
+            count: writer.finish()?,
+        })
+    }
+
+    /// The rows that pass the filter, read a chunk at a time so that only
+    /// the selected ones are kept. Fails when there are more than
+    /// [`MAX_HELD_ROWS`] of them.
+    fn selected_rows(&self) -> Result<DataTable, String> {
+        let mut selected = DataTable::default();
+        let mut source_rows = 0;
+        for chunk in self.data_source.chunks(CHUNK_ROWS)? {
+            let chunk = chunk?;
+            source_rows += chunk.rows.len();
+            selected.append(chunk.select(&self.filter.indices(&chunk)?));
+            if selected.rows.len() > MAX_HELD_ROWS {
+                return Err(format!(
+                    "More than {} rows are selected. Single-file output, sorting, grouping and \
+                     cards need all of them at once; select fewer rows or write one file per row.",
+                    MAX_HELD_ROWS
+                ));
+            }
+        }
+        if selected.rows.is_empty() {
+            return Err(self.no_rows_error(source_rows));
+        }
+        Ok(selected)
+    }
+
+    fn no_rows_error(&self, source_rows: usize) -> String {
+        match source_rows {
+            0 => "The data source does not contain data rows.",
+            _ => "No data rows match the row selection.",
+        }
+        .to_string()
+    }
+}
+
+/// Summary of a finished generation.
+#[derive(Clone, Copy)]
+pub struct Report {
+    pub format: TemplateFormat,
+    pub mode: OutputMode,
+    /// Number of data rows used.
+    pub rows: usize,
+    /// Number of sheets, merged records or files written; one per group
+    /// when rows are grouped, or one per full sheet of cards.
+    pub count: usize,
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.mode {
+            OutputMode::FilePerRow => {
+                write!(f, "Created {} file(s) using the template.", self.count)
+            }
+            OutputMode::SingleFile if self.format.is_document() => {
+                write!(f, "Merged {} record(s) into one document.", self.count)
+            }
+            OutputMode::SingleFile => {
+                write!(f, "Created {} sheet(s) using the template.", self.count)
+            }
+        }
+    }
+}
+
+/// Fills the template with every row or group of `data` and writes the
+/// result as described by `output`. With cards, every sheet or file holds
+/// as many rows or groups as the sheet has cards.
+pub fn generate(
+    data: &DataTable,
+    template: &Template,
+    mappings: &[Mapping],
+    grouping: &Grouping,
+    output: &OutputOptions,
+) -> Result<Report, String> {
+    if data.rows.is_empty() {
+        return Err("The data source does not contain data rows.".to_string());
+    }
+    let loaded = LoadedTemplate::load(template, output)?;
+    let (render, sheets) = renderer(data, &loaded, template, mappings, grouping, output)?;
+
+    let count = match output.mode {
+        OutputMode::SingleFile => {
+            let sheet_count = sheets.rows.len();
+            let bytes = render(0..sheet_count)?;
+            fs::write(&output.path, bytes).map_err(|err| err.to_string())?;
+            sheet_count
+        }
+        OutputMode::FilePerRow => {
+            let mut writer = FileWriter::new(template, output)?;
+            writer.write(&render, &sheets)?;
+            writer.finish()?
+        }
+    };
+
+    Ok(Report {
+        format: template.format(),
+        mode: output.mode,
+        rows: data.rows.len(),
+        count,
+    })
+}
+
+/// Returns a function that builds the output for a range of sheets, with
+/// one data row per sheet that names it.
+fn renderer<'a>(
+    data: &DataTable,
+    loaded: &'a LoadedTemplate,
+    template: &Template,
+    mappings: &[Mapping],
+    grouping: &Grouping,
+    output: &OutputOptions,
+) -> Result<(RowRenderer<'a>, DataTable), String> {
+    let groups = grouping.groups(data)?;
+    let records = grouping.first_rows(data, &groups);
+
+    let format = template.format();
+    if template.cards.is_some() && format.is_document() {
+        return Err("Cards need a workbook template.".to_string());
+    }
+    if template.cards.is_some() && template.repeat_rows.is_some() {
+        return Err("Repeated rows cannot be combined with cards.".to_string());
+    }
+    let pages = match template.cards {
+        Some(layout) => (0..records.rows.len())
+            .step_by(layout.per_sheet())
+            .map(|start| start..(start + layout.per_sheet()).min(records.rows.len()))
+            .collect::<Vec<_>>(),
+        None => (0..records.rows.len())
+            .map(|index| index..index + 1)
+            .collect(),
+    };
+    // With cards, sheet names and `{{#}}` in them count sheets.
+    let card_sheets = template.cards.map(|_| {
+        let mut sheets = records.select(&pages.iter().map(|page| page.start).collect::<Vec<_>>());
+        sheets.row_numbers = (1..=pages.len()).collect();
+        sheets
+    });
+    let sheets = card_sheets.as_ref().unwrap_or(&records);
+
+    let render = if format.is_document() {
+        document_renderer(sheets, loaded, output)?
+    } else {
+        let expansions = groups
+            .iter()
+            .map(|group| {
+                template.repeat_rows.map(|block| RowExpansion {
+                    block,
+                    count: group.len() as u32,
+                })
+            })
+            .collect::<Vec<_>>();
+        let mut cells = group_replacements(data, &records, mappings, &groups, &expansions)?;
+        let mut expansions = expansions;
+        if let Some(layout) = &template.cards {
+            cells = card_replacements(layout, &cells, &pages);
+            expansions = vec![None; pages.len()];
+        }
+        workbook_renderer(
+            sheets,
+            &records,
+            SheetRows {
+                cells,
+                expansions,
+                records: pages,
+                cards: template.cards,
+            },
+            loaded,
+            mappings,
+            output,
+        )?
+    };
+
+    Ok((render, sheets.clone()))
+}
+
+/// Writes generated files into a directory or archive, keeping their names
+/// unique across every call to [`FileWriter::write`].
+struct FileWriter {
+    sink: OutputSink,
+    pattern: String,
+    extension: &'static str,
+    used: HashSet<String>,
+    count: usize,
+}
+
+impl FileWriter {
+    fn new(template: &Template, output: &OutputOptions) -> Result<Self, String> {
+        let (_, extension) = template.format().output_filter();
+        Ok(Self {
+            sink: match output.target {
+                OutputTarget::Directory => OutputSink::directory(&output.path)?,
+                OutputTarget::ZipArchive => OutputSink::archive(&output.path)?,
+            },
+            pattern: output.effective_file_name_pattern(template),
+            extension,
+            used: HashSet::new(),
+            count: 0,
+        })
+    }
+
+    /// Writes one file per row of `sheets`.
+    fn write(&mut self, render: &RowRenderer<'_>, sheets: &DataTable) -> Result<(), String> {
+        let file_names = unique_file_names(&self.pattern, self.extension, sheets, &mut self.used);
+        for (index, file_name) in file_names.iter().enumerate() {
+            self.sink.add(file_name, &render(index..index + 1)?)?;
+        }
+        self.count += file_names.len();
+        Ok(())
+    }
+
+    /// Finishes the output and returns the number of files written.
+    fn finish(self) -> Result<usize, String> {
+        self.sink.finish()?;
+        Ok(self.count)
+    }
+}
+
+/// Placeholders of a document template that match a data column, with the
+/// column index.
+pub fn bind_placeholders(placeholders: &[String], headers: &[String]) -> Vec<(String, usize)> {
+    placeholders
+        .iter()
+        .filter_map(|name| {
+            headers
+                .iter()
+                .position(|header| header == name)
+                .map(|index| (name.clone(), index))
+        })
+        .collect()
+}
+
+/// Converts the mapped values of every data row into typed cell values.
+/// Fails on unknown columns, invalid cell references or expressions and
+/// values that do not match their mapping's type.
+pub fn row_replacements(
+    data: &DataTable,
+    mappings: &[Mapping],
+) -> Result<Vec<CellReplacements>, String> {
+    let mut positions = Vec::new();
+    for mapping in mappings {
+        let source = match &mapping.expression {
+            Some(expression) => MappingSource::Expression(
+                Expression::parse(expression, &data.headers)
+                    .map_err(|err| format!("Derived value for {}: {}", mapping.cell.trim(), err))?,
+            ),
+            None => MappingSource::Column(
+                data.column_index(&mapping.column)
+                    .ok_or_else(|| format!("Column \"{}\" not found", mapping.column))?,
+            ),
+        };
+        let cells = parse_cell_targets(&mapping.cell)?;
+        positions.push((source, cells, mapping));
+    }
+
+    let mut row_replacements = Vec::with_capacity(data.rows.len());
+    for (row_index, row_values) in data.rows.iter().enumerate() {
+        let row_number = data.row_number(row_index);
+        let mut replacements = BTreeMap::new();
+        for (source, cells, mapping) in &positions {
+            let value = match source {
+                MappingSource::Column(column_index) => row_values.get(*column_index).cloned(),
+                MappingSource::Expression(expression) => {
+                    Some(expression.evaluate(row_values, row_number).map_err(|err| {
+                        format!("Row {}, {}: {}", row_number, mapping.label(), err)
+                    })?)
+                }
+            };
+            let typed = match source {
+                MappingSource::Column(column_index) => data.typed_value(row_index, *column_index),
+                MappingSource::Expression(_) => None,
+            };
+            if let Some(value) = value {
+                let value = match typed {
+                    Some(typed) => {
+                        mapping
+                            .value_type
+                            .convert_value(typed, &value, mapping.format.as_deref())
+                    }
+                    None => mapping
+                        .value_type
+                        .convert(&value, mapping.format.as_deref()),
+                }
+                .map_err(|err| format!("Row {}, {}: {}", row_number, mapping.label(), err))?;
+                for cell in cells {
+                    replacements.insert(*cell, value.clone());
+                }
+            }
+        }
+        row_replacements.push(replacements);
+    }
+
+    Ok(row_replacements)
+}
+
+/// The cell values of every group. Regular mappings read the group's first
+/// row from `first_rows`; detail mappings write each row of the group one row
+/// further down, or one copy of the repeated rows further down. Cells below
+/// the repeated rows move with them.
+fn group_replacements(
+    data: &DataTable,
+    first_rows: &DataTable,
+    mappings: &[Mapping],
+    groups: &[Vec<usize>],
+    expansions: &[Option<RowExpansion>],
+) -> Result<Vec<CellReplacements>, String> {
+    let (details, regular): (Vec<Mapping>, Vec<Mapping>) =
+        mappings.iter().cloned().partition(|mapping| mapping.detail);
+    let mut replacements = row_replacements(first_rows, &regular)?;
+    for (sheet, expansion) in replacements.iter_mut().zip(expansions) {
+        if let Some(expansion) = expansion {
+            *sheet = std::mem::take(sheet)
+                .into_iter()
+                .map(|((row, col), value)| ((expansion.row(row), col), value))
+                .collect();
+        }
+    }
+    if details.is_empty() {
+        return Ok(replacements);
+    }
+
+    let mut detail_rows = row_replacements(&data.select(&groups.concat()), &details)?.into_iter();
+    for ((group, sheet), expansion) in groups.iter().zip(&mut replacements).zip(expansions) {
+        let step = expansion.map_or(1, |expansion| expansion.block.height());
+        for index in 0..group.len() as u32 {
+            for ((row, col), value) in detail_rows.next().unwrap_or_default() {
+                let row = expansion.map_or(row, |expansion| expansion.row(row));
+                sheet.insert((row + index * step, col), value);
+            }
+        }
+    }
+    Ok(replacements)
+}
+
+/// The cell values of every sheet of cards. Values inside the card go to
+/// the card of their record; values outside it come from the sheet's first
+/// record.
+fn card_replacements(
+    layout: &CardLayout,
+    records: &[CellReplacements],
+    pages: &[Range<usize>],
+) -> Vec<CellReplacements> {
+    pages
+        .iter()
+        .map(|page| {
+            let mut sheet = CellReplacements::new();
+            for (index, record) in records[page.clone()].iter().enumerate() {
+                for (cell, value) in record {
+                    if layout.contains(*cell) {
+                        sheet.insert(layout.cell(index, *cell), value.clone());
+                    } else if index == 0 {
+                        sheet.insert(*cell, value.clone());
+                    }
+                }
+            }
+            sheet
+        })
+        .collect()
+}
+
+/// The cell values of every sheet, how often its repeated rows are written
+/// and which records it shows.
+struct SheetRows {
+    cells: Vec<CellReplacements>,
+    expansions: Vec<Option<RowExpansion>>,
+    /// One record per sheet, or one per card.
+    records: Vec<Range<usize>>,
+    cards: Option<CardLayout>,
+}
+
+/// Where the values of a mapping come from.
+enum MappingSource {
+    Column(usize),
+    Expression(Expression),
+}
+
+/// Builds the output file for a range of data rows.
+type RowRenderer<'a> = Box<dyn Fn(Range<usize>) -> Result<Vec<u8>, String> + 'a>;
+
+/// A template read once and filled with any number of rows.
+struct LoadedTemplate {
+    kind: LoadedKind,
+    /// The template sheet of a workbook; empty for text documents.
+    sheet_name: String,
+    placeholders: Vec<String>,
+}
+
+enum LoadedKind {
+    Xlsx(Box<XlsxTemplate>),
+    Ods(OdsTemplate),
+    Docx(DocxTemplate),
+    Odt(OdtTemplate),
+}
+
+impl LoadedTemplate {
+    fn load(template: &Template, output: &OutputOptions) -> Result<Self, String> {
+        let path = &template.path;
+        let format = template.format();
+        if format.is_document() {
+            let placeholders = read_template_placeholders(path)?;
+            let kind = match format {
+                TemplateFormat::Docx => LoadedKind::Docx(DocxTemplate::load(path)?),
+                _ => LoadedKind::Odt(OdtTemplate::load(path)?),
+            };
+            return Ok(Self {
+                kind,
+                sheet_name: String::new(),
+                placeholders,
+            });
+        }
+        let sheet_name = template.sheet_name()?;
+        let (kind, placeholders) = match format {
+            TemplateFormat::Ods => {
+                let template = OdsTemplate::load(path, &sheet_name, &output.kept_sheets)?;
+                let placeholders = template.placeholders().to_vec();
+                (LoadedKind::Ods(template), placeholders)
+            }
+            _ => {
+                let template = XlsxTemplate::load(path, &sheet_name, &output.kept_sheets)?;
+                let placeholders = template.placeholders().to_vec();
+                (LoadedKind::Xlsx(Box::new(template)), placeholders)
+            }
+        };
+        Ok(Self {
+            kind,
+            sheet_name,
+            placeholders,
+        })
+    }
+
+    fn render_sheets(&self, sheets: &[SheetContent]) -> Result<Vec<u8>, String> {
+        match &self.kind {
+            LoadedKind::Xlsx(template) => template.render(sheets),
+            LoadedKind::Ods(template) => template.render(sheets),
+            _ => Err("Sheets need a workbook template.".to_string()),
+        }
+    }
+
+    /// Fills a text document with one record, or merges several into one.
+    fn render_records(
+        &self,
+        records: &[HashMap<String, String>],
+        merged: bool,
+    ) -> Result<Vec<u8>, String> {
+        match (&self.kind, merged) {
+            (LoadedKind::Docx(template), false) => template.render_record(&records[0]),
+            (LoadedKind::Docx(template), true) => template.render_merged(records),
+            (LoadedKind::Odt(template), false) => template.render_record(&records[0]),
+            (LoadedKind::Odt(template), true) => template.render_merged(records),
+            _ => Err("Records need a text document template.".to_string()),
+        }
+    }
+}
+
+/// Returns a function that builds a workbook from a range of sheets.
+/// `sheets` has one data row per sheet and names them; `records` fills the
+/// placeholders.
+fn workbook_renderer<'a>(
+    sheets: &DataTable,
+    records: &DataTable,
+    rows: SheetRows,
+    template: &'a LoadedTemplate,
+    mappings: &[Mapping],
+    output: &OutputOptions,
+) -> Result<RowRenderer<'a>, String> {
+    // Sheets only need distinct names when they share a workbook.
+    let sheet_names = sheet_names(
+        &output.effective_sheet_name_pattern(&template.sheet_name),
+        sheets,
+        output.mode == OutputMode::SingleFile,
+        &output.kept_sheet_names(),
+    );
+    let records = workbook_records(records, mappings, &template.placeholders)?;
+    Ok(Box::new(move |range| {
+        template.render_sheets(&output_sheets(&sheet_names, &rows, &records, range))
+    }))
+}
+
+/// The placeholder values of every data row for a workbook template. Fails
+/// when neither mappings nor bound placeholders fill the template.
+fn workbook_records(
+    data: &DataTable,
+    mappings: &[Mapping],
+    placeholders: &[String],
+) -> Result<Vec<HashMap<String, String>>, String> {
+    let placeholders = bind_placeholders(placeholders, &data.headers);
+    if mappings.is_empty() && placeholders.is_empty() {
+        return Err("No column mappings configured.".to_string());
+    }
+    Ok(placeholder_records(data, &placeholders))
+}
+
+/// The values of the bound placeholders for every data row.
+fn placeholder_records(
+    data: &DataTable,
+    placeholders: &[(String, usize)],
+) -> Vec<HashMap<String, String>> {
+    data.rows
+        .iter()
+        .map(|row_values| {
+            placeholders
+                .iter()
+                .map(|(name, column_index)| {
+                    let value = row_values.get(*column_index).cloned().unwrap_or_default();
+                    (name.clone(), value)
+                })
+                .collect::<HashMap<_, _>>()
+        })
+        .collect()
+}
+
+/// Returns a function that fills a document template with a range of data
+/// rows, merging them when more than one file is not wanted.
+fn document_renderer<'a>(
+    data: &DataTable,
+    template: &'a LoadedTemplate,
+    output: &OutputOptions,
+) -> Result<RowRenderer<'a>, String> {
+    let placeholders = bind_placeholders(&template.placeholders, &data.headers);
+    if placeholders.is_empty() {
+        return Err("No placeholders bound to CSV columns.".to_string());
+    }
+
+    let merged = output.mode == OutputMode::SingleFile;
+    let records = placeholder_records(data, &placeholders);
+    Ok(Box::new(move |range: Range<usize>| {
+        template.render_records(&records[range], merged)
+    }))
+}
+
+/// Pairs the sheets in `range` with their names and records.
+fn output_sheets<'a>(
+    sheet_names: &[String],
+    rows: &'a SheetRows,
+    records: &'a [HashMap<String, String>],
+    range: Range<usize>,
+) -> Vec<SheetContent<'a>> {
+    range
+        .map(|index| {
+            let sheet_records = &records[rows.records[index].clone()];
+            SheetContent {
+                name: sheet_names[index].clone(),
+                cells: &rows.cells[index],
+                placeholders: &sheet_records[0],
+                rows: rows.expansions[index],
+                cards: rows.cards.map(|layout| CardContent {
+                    layout,
+                    records: sheet_records,
+                }),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::csv_dialect::CsvOptions;
+    use std::path::PathBuf;
+
+    /// A CSV file with `rows` rows numbered from 1 in column `Id`.
+    fn csv_source(name: &str, rows: usize) -> (PathBuf, DataSource) {
+        let path = std::env::temp_dir().join(format!("job-{}-{}.csv", std::process::id(), name));
+        let mut text = String::from("Id,Name\n");
+        for row in 1..=rows {
+            text.push_str(&format!("{},name {}\n", row, row));
+        }
+        fs::write(&path, text).unwrap();
+        let source = DataSource::Csv {
+            path: path.clone(),
+            has_headers: true,
+            options: CsvOptions::default(),
+        };
+        (path, source)
+    }
+
+    #[test]
+    fn reads_chunks_across_the_chunk_size() {
+        let (path, source) = csv_source("chunks", CHUNK_ROWS * 2 + 5);
+        let chunks = source
+            .chunks(CHUNK_ROWS)
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(
+            chunks
+                .iter()
+                .map(|chunk| chunk.rows.len())
+                .collect::<Vec<_>>(),
+            [CHUNK_ROWS, CHUNK_ROWS, 5]
+        );
+        let second = &chunks[1];
+        assert_eq!(second.headers, ["Id", "Name"]);
+        assert_eq!(second.row_number(0), CHUNK_ROWS + 1);
+        assert_eq!(second.rows[0][0], (CHUNK_ROWS + 1).to_string());
+
+        let job = Job {
+            data_source: source,
+            template: Template {
+                path: PathBuf::from("template.xlsx"),
+                sheet: None,
+                repeat_rows: None,
+                cards: None,
+            },
+            mappings: Vec::new(),
+            filter: RowFilter {
+                rows: format!("{}-{}", CHUNK_ROWS - 1, CHUNK_ROWS + 2),
+                condition: String::new(),
+            },
+            grouping: Grouping::default(),
+            output: OutputOptions::new("out.xlsx"),
+        };
+        let selected = job.selected_rows().unwrap();
+        let numbers = (0..selected.rows.len())
+            .map(|index| selected.row_number(index))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            numbers,
+            (CHUNK_ROWS - 1..=CHUNK_ROWS + 2).collect::<Vec<_>>()
+        );
+        assert_eq!(selected.rows[3][1], format!("name {}", CHUNK_ROWS + 2));
+        fs::remove_file(path).unwrap();
+    }
+}
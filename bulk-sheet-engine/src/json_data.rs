@@ -0,0 +1,310 @@
+use crate::cells::CellValue;
+use crate::data::DataTable;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::Path;
+
+/// How records are read from JSON data. Nested objects become dotted
+/// columns such as `customer.address.city`, and arrays become indexed
+/// columns such as `tags.0` unless they are expanded into rows.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct JsonOptions {
+    /// Dotted path of the array holding the records, such as `data.orders`.
+    /// By default the top-level array, or the only array of objects in the
+    /// top-level object.
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub records: String,
+    /// Dotted path of an array within each record, such as `items`, whose
+    /// elements become one row each; the other fields of the record are
+    /// repeated on every row.
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub expand: String,
+}
+
+impl JsonOptions {
+    pub fn is_empty(&self) -> bool {
+        self.records.trim().is_empty() && self.expand.trim().is_empty()
+    }
+}
+
+/// Whether a data file holds JSON or newline-delimited JSON, by extension.
+pub fn is_json_data(path: &Path) -> bool {
+    let extension = path
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_ascii_lowercase());
+    matches!(extension.as_deref(), Some("json" | "ndjson" | "jsonl"))
+}
+
+/// Reads a JSON array of records, or one record per line for `.ndjson` and
+/// `.jsonl` files. Numbers and booleans are kept as typed values.
+pub fn load_json(path: &Path, options: &JsonOptions) -> Result<DataTable, String> {
+    let text = std::fs::read_to_string(path).map_err(|err| err.to_string())?;
+    let text = text.trim_start_matches('\u{feff}');
+    let extension = path
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_ascii_lowercase());
+    let document = match extension.as_deref() {
+        Some("ndjson" | "jsonl") => Value::Array(parse_lines(text)?),
+        _ => match serde_json::from_str::<Value>(text) {
+            Ok(document) => document,
+            Err(err) => Value::Array(parse_lines(text).map_err(|_| err.to_string())?),
+        },
+    };
+    let records = find_records(&document, options.records.trim())?;
+
+    let expand = options.expand.trim();
+    let mut headers = Vec::new();
+    let mut columns = HashMap::new();
+    let mut cells = Vec::new();
+    let mut expanded = false;
+    for record in records {
+        let mut flattened = Flattened::default();
+        flatten(String::new(), record, expand, &mut flattened);
+        expanded |= flattened.expanded.is_some();
+        let elements = match flattened.expanded {
+            Some(elements) if !elements.is_empty() => elements.iter().map(Some).collect(),
+            _ => vec![None],
+        };
+        for element in elements {
+            let mut fields = flattened.fields.clone();
+            if let Some(element) = element {
+                let mut detail = Flattened::default();
+                flatten(expand.to_string(), element, "", &mut detail);
+                fields.extend(detail.fields);
+            }
+            let row = fields
+                .into_iter()
+                .map(|(name, value)| {
+                    let column = *columns.entry(name.clone()).or_insert_with(|| {
+                        headers.push(name);
+                        headers.len() - 1
+                    });
+                    (column, value)
+                })
+                .collect::<Vec<_>>();
+            cells.push(row);
+        }
+    }
+    if !expand.is_empty() && !expanded {
+        return Err(format!("No array \"{}\" in the JSON records", expand));
+    }
+
+    let mut rows = Vec::with_capacity(cells.len());
+    let mut typed = Vec::with_capacity(cells.len());
+    for row_cells in cells {
+        let mut row = vec![String::new(); headers.len()];
+        let mut typed_row = vec![None; headers.len()];
+        for (column, value) in row_cells {
+            (row[column], typed_row[column]) = json_value(value);
+        }
+        rows.push(row);
+        typed.push(typed_row);
+    }
+
+    Ok(DataTable {
+        headers,
+        rows,
+        row_numbers: Vec::new(),
+        typed,
+    })
+}
+
+/// Newline-delimited JSON: one value per non-empty line.
+fn parse_lines(text: &str) -> Result<Vec<Value>, String> {
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(index, line)| {
+            serde_json::from_str(line).map_err(|err| format!("Line {}: {}", index + 1, err))
+        })
+        .collect()
+}
+
+fn find_records<'a>(document: &'a Value, path: &str) -> Result<Vec<&'a Value>, String> {
+    let value = match path {
+        "" => match document {
+            Value::Object(fields) => {
+                let mut arrays = fields.values().filter(|value| {
+                    value
+                        .as_array()
+                        .is_some_and(|items| items.iter().any(Value::is_object))
+                });
+                match (arrays.next(), arrays.next()) {
+                    (Some(array), None) => array,
+                    _ => document,
+                }
+            }
+            _ => document,
+        },
+        path => path.split('.').try_fold(document, |value, key| {
+            match value {
+                Value::Object(fields) => fields.get(key),
+                Value::Array(items) => key.parse::<usize>().ok().and_then(|index| items.get(index)),
+                _ => None,
+            }
+            .ok_or_else(|| format!("No \"{}\" in the JSON data", path))
+        })?,
+    };
+    Ok(match value {
+        Value::Array(items) => items.iter().collect(),
+        value => vec![value],
+    })
+}
+
+/// The scalar fields of a record by dotted name, and the elements of the
+/// expanded array.
+#[derive(Default)]
+struct Flattened<'a> {
+    fields: Vec<(String, &'a Value)>,
+    expanded: Option<&'a [Value]>,
+}
+
+fn flatten<'a>(path: String, value: &'a Value, expand: &str, flattened: &mut Flattened<'a>) {
+    let join = |key: &str| match path.is_empty() {
+        true => key.to_string(),
+        false => format!("{}.{}", path, key),
+    };
+    match value {
+        Value::Array(items) if !expand.is_empty() && path == expand => {
+            flattened.expanded = Some(items);
+        }
+        Value::Object(fields) if !fields.is_empty() => {
+            for (key, value) in fields {
+                flatten(join(key), value, expand, flattened);
+            }
+        }
+        Value::Array(items) if !items.is_empty() => {
+            for (index, item) in items.iter().enumerate() {
+                flatten(join(&index.to_string()), item, expand, flattened);
+            }
+        }
+        Value::Object(_) | Value::Array(_) => flattened.fields.push((path, &Value::Null)),
+        value if path.is_empty() => flattened.fields.push(("value".to_string(), value)),
+        value => flattened.fields.push((path, value)),
+    }
+}
+
+/// The text of a JSON value and its typed value unless it is text.
+fn json_value(value: &Value) -> (String, Option<CellValue>) {
+    match value {
+        Value::String(text) => (text.clone(), None),
+        Value::Number(number) => (number.to_string(), number.as_f64().map(CellValue::Number)),
+        Value::Bool(value) => {
+            let typed = CellValue::Boolean(*value);
+            (typed.to_string(), Some(typed))
+        }
+        _ => (String::new(), None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(file_name: &str, text: &str, records: &str, expand: &str) -> Result<DataTable, String> {
+        let path =
+            std::env::temp_dir().join(format!("json-data-{}-{}", std::process::id(), file_name));
+        std::fs::write(&path, text).unwrap();
+        let options = JsonOptions {
+            records: records.to_string(),
+            expand: expand.to_string(),
+        };
+        let table = load_json(&path, &options);
+        std::fs::remove_file(&path).unwrap();
+        table
+    }
+
+    #[test]
+    fn flattens_nested_records() {
+        let table = load(
+            "nested.json",
+            r#"[{"id": 1, "customer": {"name": "Ann", "address": {"city": "Oslo"}}, "tags": ["a", "b"], "paid": true},
+                {"id": 2, "customer": {}, "tags": [], "note": null}]"#,
+            "",
+            "",
+        )
+        .unwrap();
+        assert_eq!(
+            table.headers,
+            [
+                "id",
+                "customer.name",
+                "customer.address.city",
+                "tags.0",
+                "tags.1",
+                "paid",
+                "customer",
+                "tags",
+                "note"
+            ]
+        );
+        assert_eq!(
+            table.rows[0],
+            ["1", "Ann", "Oslo", "a", "b", "TRUE", "", "", ""]
+        );
+        assert_eq!(table.rows[1], ["2", "", "", "", "", "", "", "", ""]);
+        assert_eq!(table.typed[0][0], Some(CellValue::Number(1.0)));
+        assert_eq!(table.typed[0][1], None);
+        assert_eq!(table.typed[0][5], Some(CellValue::Boolean(true)));
+    }
+
+    #[test]
+    fn expands_arrays_into_rows() {
+        let text = r#"{"orders": [
+            {"id": 1, "items": [{"sku": "A", "qty": 2}, {"sku": "B", "qty": 1}]},
+            {"id": 2, "items": []}
+        ]}"#;
+        let table = load("expand.json", text, "", "items").unwrap();
+        assert_eq!(table.headers, ["id", "items.sku", "items.qty"]);
+        assert_eq!(
+            table.rows,
+            [["1", "A", "2"], ["1", "B", "1"], ["2", "", ""]]
+        );
+
+        assert_eq!(
+            load("missing.json", text, "orders", "lines")
+                .err()
+                .as_deref(),
+            Some("No array \"lines\" in the JSON records")
+        );
+    }
+
+    #[test]
+    fn finds_the_array_of_records() {
+        let document: Value = serde_json::from_str(
+            r#"{"count": 2, "names": ["x"], "data": {"orders": [{"id": 1}, {"id": 2}]}, "rows": [{"id": 3}]}"#,
+        )
+        .unwrap();
+        assert_eq!(find_records(&document, "").unwrap(), [&document["rows"][0]]);
+        assert_eq!(find_records(&document, "data.orders").unwrap().len(), 2);
+        assert_eq!(
+            find_records(&document, "rows.0").unwrap(),
+            [&document["rows"][0]]
+        );
+        assert_eq!(
+            find_records(&document, "data.lines").unwrap_err(),
+            "No \"data.lines\" in the JSON data"
+        );
+
+        let document: Value =
+            serde_json::from_str(r#"{"a": [{"id": 1}], "b": [{"id": 2}]}"#).unwrap();
+        assert_eq!(find_records(&document, "").unwrap(), [&document]);
+    }
+
+    #[test]
+    fn reads_one_record_per_line() {
+        let lines = "\u{feff}{\"id\": 1}\n\n{\"id\": 2, \"name\": \"Bo\"}\n";
+        for file_name in ["lines.ndjson", "lines.json"] {
+            let table = load(file_name, lines, "", "").unwrap();
+            assert_eq!(table.headers, ["id", "name"]);
+            assert_eq!(table.rows, [["1", ""], ["2", "Bo"]]);
+        }
+
+        let error = load("broken.jsonl", "{\"id\": 1}\n{\"id\": \n", "", "")
+            .err()
+            .unwrap_or_default();
+        assert!(error.starts_with("Line 2: "), "{}", error);
+    }
+}
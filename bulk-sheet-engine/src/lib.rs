@@ -0,0 +1,78 @@
+//! Bulk generation of workbooks and documents from a template and tabular
+//! data, independent of any user interface.
+//!
+//! ```no_run
+//! use bulk_sheet_engine::{
+//!     CsvOptions, DataSource, Grouping, Job, Mapping, OutputOptions, RowFilter, Template, ValueType,
+//! };
+//!
+//! let job = Job {
+//!     data_source: DataSource::Csv {
+//!         path: "customers.csv".into(),
+//!         has_headers: true,
+//!         options: CsvOptions::default(),
+//!     },
+//!     template: Template {
+//!         path: "invoice.xlsx".into(),
+//!         sheet: Some("Invoice".to_string()),
+//!         repeat_rows: None,
+//!         cards: None,
+//!     },
+//!     mappings: vec![Mapping::new("Total", "F30").with_type(ValueType::Number, None)],
+//!     filter: RowFilter::default(),
+//!     grouping: Grouping::default(),
+//!     output: OutputOptions::new("invoices.xlsx"),
+//! };
+//! let report = job.run()?;
+//! println!("{}", report);
+//! # Ok::<(), String>(())
+//! ```
+
+mod cards;
+mod cells;
+mod csv_dialect;
+mod data;
+mod docx_template;
+mod expressions;
+mod formulas;
+mod grouping;
+mod job;
+mod json_data;
+mod ods_template;
+mod odt_template;
+mod output;
+mod package;
+mod placeholders;
+mod row_blocks;
+mod selection;
+mod sqlite_data;
+mod template;
+mod xlsx_parts;
+mod xlsx_template;
+mod xml_utils;
+
+pub use cards::{CardLayout, parse_tiles};
+pub use cells::{
+    CellReplacements, CellValue, DEFAULT_DATE_FORMAT, DEFAULT_DATETIME_FORMAT, MAX_COLUMNS,
+    MAX_ROWS, ValueType, cell_label, column_label_from_index, parse_cell_reference,
+    parse_cell_targets,
+};
+pub use csv_dialect::{CsvDialect, CsvOptions};
+pub use data::{
+    DataSource, DataTable, detect_csv_dialect, is_workbook_data, load_csv, load_workbook,
+};
+pub use expressions::Expression;
+pub use grouping::{Grouping, SortKey};
+pub use job::{Job, Mapping, Report, bind_placeholders, generate, row_replacements};
+pub use json_data::{JsonOptions, is_json_data, load_json};
+pub use output::{
+    KeptSheet, OutputMode, OutputOptions, OutputTarget, ROW_NUMBER_TOKEN, SheetPosition,
+    output_file_names, sanitize_file_name, sanitize_sheet_name, sheet_names,
+};
+pub use row_blocks::RowBlock;
+pub use selection::{RowFilter, format_row_ranges, parse_row_ranges};
+pub use sqlite_data::{SqliteOptions, is_sqlite_data, load_sqlite, read_sqlite_tables};
+pub use template::{
+    Template, TemplateFormat, read_sheet_cells, read_sheet_names, read_sheet_placeholders,
+    read_template_placeholders,
+};
@@ -0,0 +1,1023 @@
+use crate::cards::{CardContent, CardLayout};
+use crate::cells::{CellValue, SheetContent, cell_label};
+use crate::output::{KeptSheet, SheetPosition};
+use crate::package::{build_package, read_package};
+use crate::placeholders::{ODF_TEXT_MARKUP, collect_placeholders, replace_placeholders};
+use crate::row_blocks::RowExpansion;
+use crate::xml_utils::{attribute_value, unescaped_attribute, with_attribute};
+use quick_xml::events::{BytesEnd, BytesStart, BytesText, Event};
+use quick_xml::{Reader as XmlReader, Writer as XmlWriter};
+use std::collections::HashMap;
+use std::path::Path;
+
+const TABLE: &[u8] = b"table:table";
+const TABLE_ROW: &[u8] = b"table:table-row";
+const TABLE_CELL: &[u8] = b"table:table-cell";
+const COVERED_TABLE_CELL: &[u8] = b"table:covered-table-cell";
+const TABLE_COLUMN: &[u8] = b"table:table-column";
+const ROWS_REPEATED: &str = "table:number-rows-repeated";
+const COLUMNS_REPEATED: &str = "table:number-columns-repeated";
+const FORMULA: &[u8] = b"table:formula";
+const PRINT_RANGES: &[u8] = b"table:print-ranges";
+
+/// Cell attributes that describe layout rather than the value and therefore
+/// survive when a mapped value is written into the cell.
+const KEPT_CELL_ATTRIBUTES: [&[u8]; 4] = [
+    b"table:style-name",
+    b"table:number-columns-spanned",
+    b"table:number-rows-spanned",
+    b"table:content-validation-name",
+];
+
+/// A spreadsheet template whose selected table is copied once per generated
+/// sheet. Tables listed as kept are copied unchanged before or after them.
+pub struct OdsTemplate {
+    entries: Vec<(String, Vec<u8>)>,
+    sheet_name: String,
+    content_head: Vec<u8>,
+    content_tail: Vec<u8>,
+    table: OdsTable,
+    kept_before: Vec<OdsTable>,
+    kept_after: Vec<OdsTable>,
+    placeholders: Vec<String>,
+}
+
+impl OdsTemplate {
+    pub fn load(path: &Path, sheet_name: &str, kept_sheets: &[KeptSheet]) -> Result<Self, String> {
+        let entries = read_package(path)?;
+        let content = entries
+            .iter()
+            .find(|(name, _)| name == "content.xml")
+            .map(|(_, data)| data.as_slice())
+            .ok_or_else(|| "Spreadsheet content missing".to_string())?;
+        let SpreadsheetContent {
+            head: content_head,
+            tables,
+            tail: content_tail,
+        } = split_content(content)?;
+
+        let table_name = |table: &OdsTable| unescaped_attribute(&table.start, b"table:name");
+        let table = tables
+            .iter()
+            .find(|table| table_name(table).as_deref() == Some(sheet_name))
+            .cloned()
+            .ok_or_else(|| "Template sheet not found".to_string())?;
+
+        let mut kept_before = Vec::new();
+        let mut kept_after = Vec::new();
+        for kept in kept_sheets {
+            if kept.name == sheet_name {
+                return Err(format!(
+                    "The template sheet \"{}\" cannot be kept as well",
+                    kept.name
+                ));
+            }
+            if !tables
+                .iter()
+                .any(|table| table_name(table).as_deref() == Some(kept.name.as_str()))
+            {
+                let names = tables.iter().filter_map(table_name).collect::<Vec<_>>();
+                return Err(format!(
+                    "Sheet \"{}\" not found; the template has: {}",
+                    kept.name,
+                    names.join(", ")
+                ));
+            }
+        }
+        // Kept tables stay in their template order.
+        for table in &tables {
+            let name = table_name(table);
+            let position = kept_sheets
+                .iter()
+                .find(|kept| Some(kept.name.as_str()) == name.as_deref())
+                .map(|kept| kept.position);
+            match position {
+                Some(SheetPosition::Before) => kept_before.push(table.clone()),
+                Some(SheetPosition::After) => kept_after.push(table.clone()),
+                None => {}
+            }
+        }
+
+        let mut table_xml = XmlWriter::new(Vec::new());
+        table.write(&mut table_xml, sheet_name, sheet_name)?;
+        let mut placeholders = Vec::new();
+        collect_placeholders(&table_xml.into_inner(), &ODF_TEXT_MARKUP, &mut placeholders)?;
+
+        Ok(Self {
+            entries,
+            sheet_name: sheet_name.to_string(),
+            content_head,
+            content_tail,
+            table,
+            kept_before,
+            kept_after,
+            placeholders,
+        })
+    }
+
+    /// The `{{placeholders}}` in the text of the template table's cells.
+    /// Headers and footers belong to page styles shared by all tables and
+    /// are left alone.
+    pub fn placeholders(&self) -> &[String] {
+        &self.placeholders
+    }
+
+    /// Builds a spreadsheet with one copy of the template table per entry,
+    /// with replacements keyed by zero-based `(row, column)`.
+    pub fn render(&self, sheets: &[SheetContent]) -> Result<Vec<u8>, String> {
+        let mut writer = XmlWriter::new(self.content_head.clone());
+        for table in &self.kept_before {
+            table.write_unchanged(&mut writer)?;
+        }
+        for sheet in sheets {
+            let mut table = self.table.clone();
+            if let Some(expansion) = &sheet.rows {
+                table.expand_rows(expansion, &self.sheet_name);
+            }
+            if let Some(cards) = &sheet.cards {
+                table.tile_cards(cards, &self.sheet_name)?;
+            }
+            for ((row, col), value) in sheet.cells {
+                table.set_cell_value(*row, *col, value)?;
+            }
+            if sheet.placeholders.is_empty() {
+                table.write(&mut writer, &self.sheet_name, &sheet.name)?;
+            } else {
+                let mut table_xml = XmlWriter::new(Vec::new());
+                table.write(&mut table_xml, &self.sheet_name, &sheet.name)?;
+                let table_xml = replace_placeholders(
+                    &table_xml.into_inner(),
+                    &ODF_TEXT_MARKUP,
+                    sheet.placeholders,
+                )?;
+                writer.get_mut().extend_from_slice(&table_xml);
+            }
+        }
+        for table in &self.kept_after {
+            table.write_unchanged(&mut writer)?;
+        }
+        let mut content = writer.into_inner();
+        content.extend_from_slice(&self.content_tail);
+
+        build_package(&self.entries, &[("content.xml", content)])
+    }
+}
+
+#[derive(Clone)]
+struct OdsTable {
+    start: BytesStart<'static>,
+    items: Vec<TableItem>,
+}
+
+#[derive(Clone)]
+enum TableItem {
+    Row(OdsRow),
+    Other(Event<'static>),
+}
+
+#[derive(Clone)]
+struct OdsRow {
+    start: BytesStart<'static>,
+    cells: Vec<OdsCell>,
+}
+
+#[derive(Clone)]
+struct OdsCell {
+    start: BytesStart<'static>,
+    children: Vec<Event<'static>>,
+}
+
+impl OdsTable {
+    /// Replaces the value of the cell at the zero-based position, splitting
+    /// repeated rows and cells so that only the addressed cell changes. Rows
+    /// and cells beyond the end of the table are added.
+    fn set_cell_value(&mut self, row: u32, col: u32, value: &CellValue) -> Result<(), String> {
+        let cell = self
+            .materialize_row(row)
+            .and_then(|row| row.materialize_cell(col))
+            .ok_or_else(|| format!("Cell {} cannot be written", cell_label(row, col)))?;
+        cell.set_value(value);
+        Ok(())
+    }
+
+    fn materialize_row(&mut self, row: u32) -> Option<&mut OdsRow> {
+        let mut first_row = 0u32;
+        let mut found = None;
+        for (index, item) in self.items.iter().enumerate() {
+            let TableItem::Row(current) = item else {
+                continue;
+            };
+            let count = repeat_count(&current.start, ROWS_REPEATED);
+            if row < first_row.saturating_add(count) {
+                found = Some((index, row - first_row, count));
+                break;
+            }
+            first_row = first_row.saturating_add(count);
+        }
+
+        let Some((index, offset, count)) = found else {
+            // Pad the table with empty rows up to the addressed row.
+            let index = self
+                .items
+                .iter()
+                .rposition(|item| matches!(item, TableItem::Row(_)))
+                .map_or(self.items.len(), |index| index + 1);
+            let mut rows = vec![TableItem::Row(OdsRow::empty())];
+            if row > first_row {
+                rows.insert(
+                    0,
+                    TableItem::Row(OdsRow::empty().with_repeat(row - first_row)),
+                );
+            }
+            self.items.splice(index..index, rows);
+            return self.materialize_row(row);
+        };
+        let TableItem::Row(current) = self.items.remove(index) else {
+            return None;
+        };
+        let mut split = Vec::new();
+        if offset > 0 {
+            split.push(current.with_repeat(offset));
+        }
+        split.push(current.with_repeat(1));
+        if count - offset > 1 {
+            split.push(current.with_repeat(count - offset - 1));
+        }
+        let target_index = index + usize::from(offset > 0);
+        self.items
+            .splice(index..index, split.into_iter().map(TableItem::Row));
+
+        match &mut self.items[target_index] {
+            TableItem::Row(row) => Some(row),
+            TableItem::Other(_) => None,
+        }
+    }
+
+    /// Repeats the block rows below the block and adjusts formulas and print
+    /// ranges to the inserted rows. Copies only keep the cells in the
+    /// block's columns.
+    fn expand_rows(&mut self, expansion: &RowExpansion, sheet: &str) {
+        let block = expansion.block;
+        if expansion.added_rows() == 0 {
+            return;
+        }
+        for row in block.first_row..=block.last_row {
+            self.materialize_row(row);
+        }
+
+        let mut block_rows = Vec::new();
+        let mut insert_at = self.items.len();
+        let mut first_row = 0u32;
+        for (index, item) in self.items.iter_mut().enumerate() {
+            let TableItem::Row(row) = item else {
+                continue;
+            };
+            let copy = block.contains_row(first_row).then_some(0);
+            if copy.is_some() {
+                block_rows.push(row.clone());
+                insert_at = index + 1;
+            }
+            row.map_formulas(|formula| expansion.shift_odf_formula(formula, sheet, copy));
+            first_row = first_row.saturating_add(repeat_count(&row.start, ROWS_REPEATED));
+        }
+
+        let mut copies = Vec::new();
+        for copy in 1..expansion.count {
+            for row in &block_rows {
+                let mut row = row.clone();
+                row.clear_outside(block.first_col, block.last_col);
+                row.map_formulas(|formula| expansion.shift_odf_formula(formula, sheet, Some(copy)));
+                copies.push(TableItem::Row(row));
+            }
+        }
+        self.items.splice(insert_at..insert_at, copies);
+
+        if let Some(ranges) = unescaped_attribute(&self.start, PRINT_RANGES) {
+            self.start = with_attribute(
+                &self.start,
+                "table:print-ranges",
+                &expansion.shift_odf_ranges(&ranges, sheet),
+            );
+        }
+    }
+
+    /// Copies the template card into every further card that has a record,
+    /// filling the copy's placeholders with the record and moving its
+    /// formulas along. The styles of the card's rows and columns, and so
+    /// their sizes, apply to all cards.
+    fn tile_cards(&mut self, cards: &CardContent, sheet: &str) -> Result<(), String> {
+        let layout = &cards.layout;
+        let card = layout.card;
+        if layout.per_sheet() < 2 {
+            return Ok(());
+        }
+        self.copy_card_columns(layout);
+
+        for row in card.first_row..=card.last_row {
+            let Some(mut source) = self.materialize_row(row).cloned() else {
+                continue;
+            };
+            let row_style = unescaped_attribute(&source.start, b"table:style-name");
+            let cells = (card.first_col..=card.last_col)
+                .map(|col| {
+                    source
+                        .materialize_cell(col)
+                        .map_or_else(OdsCell::empty, |cell| cell.with_repeat(1))
+                })
+                .collect::<Vec<_>>();
+            for index in 1..layout.per_sheet() {
+                let target_row = layout.cell(index, (row, 0)).0;
+                if let (Some(style), true) = (&row_style, target_row != row)
+                    && let Some(target) = self.materialize_row(target_row)
+                {
+                    target.start = with_attribute(&target.start, "table:style-name", style);
+                }
+                let Some(record) = cards.records.get(index) else {
+                    continue;
+                };
+                for (col, cell) in (card.first_col..).zip(&cells) {
+                    let mut cell = cell.with_placeholders(record)?;
+                    if let Some(formula) = unescaped_attribute(&cell.start, FORMULA) {
+                        cell.start = with_attribute(
+                            &cell.start,
+                            "table:formula",
+                            &layout.move_odf_formula(&formula, sheet, index),
+                        );
+                    }
+                    let (row, col) = layout.cell(index, (row, col));
+                    if let Some(target) = self
+                        .materialize_row(row)
+                        .and_then(|row| row.materialize_cell(col))
+                    {
+                        *target = cell;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Gives the columns of the cards right of the template card the style
+    /// of the card's columns. Tables whose columns are not listed in one
+    /// sequence of `<table:table-column>` elements are left alone.
+    fn copy_card_columns(&mut self, layout: &CardLayout) {
+        let is_column = |item: &TableItem| matches!(item, TableItem::Other(Event::Empty(start)) if start.name().as_ref() == TABLE_COLUMN);
+        let Some(first) = self.items.iter().position(is_column) else {
+            return;
+        };
+        let end = first
+            + self.items[first..]
+                .iter()
+                .take_while(|item| is_column(item))
+                .count();
+        if self.items[end..].iter().any(is_column) {
+            return;
+        }
+
+        let needed = layout
+            .cell(layout.across as usize - 1, (0, layout.card.last_col))
+            .1 as usize
+            + 1;
+        let mut columns = Vec::new();
+        let mut rest = Vec::new();
+        for item in &self.items[first..end] {
+            let TableItem::Other(Event::Empty(start)) = item else {
+                continue;
+            };
+            let count = repeat_count(start, COLUMNS_REPEATED) as usize;
+            let single = with_repeat(start, COLUMNS_REPEATED, 1);
+            let taken = count.min(needed.saturating_sub(columns.len()));
+            columns.extend(std::iter::repeat_n(single.clone(), taken));
+            if count > taken {
+                rest.push((single, count - taken));
+            }
+        }
+        columns.resize(
+            needed.max(columns.len()),
+            BytesStart::new("table:table-column"),
+        );
+        for index in 1..layout.across as usize {
+            for col in layout.card.first_col..=layout.card.last_col {
+                let target = layout.cell(index, (0, col)).1 as usize;
+                columns[target] = columns[col as usize].clone();
+            }
+        }
+
+        let mut runs: Vec<(BytesStart<'static>, usize)> = Vec::new();
+        for start in columns.into_iter().map(|start| (start, 1)).chain(rest) {
+            match runs.last_mut() {
+                Some((last, count)) if last.as_ref() == start.0.as_ref() => *count += start.1,
+                _ => runs.push(start),
+            }
+        }
+        let items = runs.into_iter().map(|(start, count)| {
+            TableItem::Other(Event::Empty(with_repeat(
+                &start,
+                COLUMNS_REPEATED,
+                count as u32,
+            )))
+        });
+        self.items.splice(first..end, items);
+    }
+
+    fn write_unchanged(&self, writer: &mut XmlWriter<Vec<u8>>) -> Result<(), String> {
+        let name = unescaped_attribute(&self.start, b"table:name").unwrap_or_default();
+        self.write(writer, &name, &name)
+    }
+
+    fn write(
+        &self,
+        writer: &mut XmlWriter<Vec<u8>>,
+        template_name: &str,
+        name: &str,
+    ) -> Result<(), String> {
+        let mut start = BytesStart::new("table:table");
+        for attr in self.start.attributes().with_checks(false).flatten() {
+            match attr.key.as_ref() {
+                b"table:name" => start.push_attribute(("table:name", name)),
+                b"table:print-ranges" => {
+                    let ranges = attr.unescape_value().map_err(|err| err.to_string())?;
+                    start.push_attribute((
+                        "table:print-ranges",
+                        rename_range_sheet(&ranges, template_name, name).as_str(),
+                    ));
+                }
+                _ => start.push_attribute(attr),
+            }
+        }
+        writer
+            .write_event(Event::Start(start))
+            .map_err(|err| err.to_string())?;
+        for item in &self.items {
+            match item {
+                TableItem::Row(row) => row.write(writer)?,
+                TableItem::Other(event) => writer
+                    .write_event(event.clone())
+                    .map_err(|err| err.to_string())?,
+            }
+        }
+        writer
+            .write_event(Event::End(BytesEnd::new("table:table")))
+            .map_err(|err| err.to_string())
+    }
+}
+
+impl OdsRow {
+    fn empty() -> Self {
+        Self {
+            start: BytesStart::new("table:table-row"),
+            cells: vec![OdsCell::empty()],
+        }
+    }
+
+    fn with_repeat(&self, count: u32) -> Self {
+        Self {
+            start: with_repeat(&self.start, ROWS_REPEATED, count),
+            cells: self.cells.clone(),
+        }
+    }
+
+    fn map_formulas(&mut self, map: impl Fn(&str) -> String) {
+        for cell in &mut self.cells {
+            if let Some(formula) = unescaped_attribute(&cell.start, FORMULA) {
+                cell.start = with_attribute(&cell.start, "table:formula", &map(&formula));
+            }
+        }
+    }
+
+    /// Empties the cells outside the columns `first..=last`, keeping their
+    /// style.
+    fn clear_outside(&mut self, first: u32, last: u32) {
+        let mut cells = Vec::new();
+        let mut col = 0u32;
+        for cell in self.cells.drain(..) {
+            let end = col.saturating_add(repeat_count(&cell.start, COLUMNS_REPEATED));
+            let inside_start = col.max(first).min(end);
+            let inside_end = end.min(last.saturating_add(1)).max(inside_start);
+            if inside_start > col {
+                cells.push(cell.cleared().with_repeat(inside_start - col));
+            }
+            if inside_end > inside_start {
+                cells.push(cell.with_repeat(inside_end - inside_start));
+            }
+            if end > inside_end {
+                cells.push(cell.cleared().with_repeat(end - inside_end));
+            }
+            col = end;
+        }
+        self.cells = cells;
+    }
+
+    fn materialize_cell(&mut self, col: u32) -> Option<&mut OdsCell> {
+        let mut first_col = 0u32;
+        let mut found = None;
+        for (index, cell) in self.cells.iter().enumerate() {
+            let count = repeat_count(&cell.start, COLUMNS_REPEATED);
+            if col < first_col.saturating_add(count) {
+                found = Some((index, col - first_col, count));
+                break;
+            }
+            first_col = first_col.saturating_add(count);
+        }
+
+        let Some((index, offset, count)) = found else {
+            // Pad the row with empty cells up to the addressed column.
+            if col > first_col {
+                self.cells
+                    .push(OdsCell::empty().with_repeat(col - first_col));
+            }
+            self.cells.push(OdsCell::empty());
+            return self.cells.last_mut();
+        };
+        let current = self.cells.remove(index);
+        let mut split = Vec::new();
+        if offset > 0 {
+            split.push(current.with_repeat(offset));
+        }
+        split.push(current.with_repeat(1));
+        if count - offset > 1 {
+            split.push(current.with_repeat(count - offset - 1));
+        }
+        let target_index = index + usize::from(offset > 0);
+        self.cells.splice(index..index, split);
+        self.cells.get_mut(target_index)
+    }
+
+    fn write(&self, writer: &mut XmlWriter<Vec<u8>>) -> Result<(), String> {
+        writer
+            .write_event(Event::Start(self.start.clone()))
+            .map_err(|err| err.to_string())?;
+        for cell in &self.cells {
+            cell.write(writer)?;
+        }
+        writer
+            .write_event(Event::End(self.start.to_end().into_owned()))
+            .map_err(|err| err.to_string())
+    }
+}
+
+impl OdsCell {
+    fn empty() -> Self {
+        Self {
+            start: BytesStart::new("table:table-cell"),
+            children: Vec::new(),
+        }
+    }
+
+    fn with_repeat(&self, count: u32) -> Self {
+        Self {
+            start: with_repeat(&self.start, COLUMNS_REPEATED, count),
+            children: self.children.clone(),
+        }
+    }
+
+    /// The cell with its `{{placeholders}}` replaced by the record's values.
+    fn with_placeholders(&self, values: &HashMap<String, String>) -> Result<Self, String> {
+        if self.children.is_empty() || values.is_empty() {
+            return Ok(self.clone());
+        }
+        let mut cell_xml = XmlWriter::new(Vec::new());
+        self.write(&mut cell_xml)?;
+        let cell_xml = replace_placeholders(&cell_xml.into_inner(), &ODF_TEXT_MARKUP, values)?;
+
+        let mut reader = XmlReader::from_reader(cell_xml.as_slice());
+        reader.trim_text(false);
+        let mut buffer = Vec::new();
+        let mut children = Vec::new();
+        let mut depth = 0usize;
+        loop {
+            let event = reader
+                .read_event_into(&mut buffer)
+                .map_err(|err| err.to_string())?
+                .into_owned();
+            buffer.clear();
+            match event {
+                Event::Eof => break,
+                Event::Start(_) if depth == 0 => depth = 1,
+                Event::End(_) if depth == 1 => break,
+                event => {
+                    match &event {
+                        Event::Start(_) => depth += 1,
+                        Event::End(_) => depth -= 1,
+                        _ => {}
+                    }
+                    children.push(event);
+                }
+            }
+        }
+        Ok(Self {
+            start: self.start.clone(),
+            children,
+        })
+    }
+
+    /// The cell without content, keeping its style and repetition.
+    fn cleared(&self) -> Self {
+        let name = String::from_utf8_lossy(self.start.name().as_ref()).into_owned();
+        let mut start = BytesStart::new(name);
+        for attr in self.start.attributes().with_checks(false).flatten() {
+            if matches!(
+                attr.key.as_ref(),
+                b"table:style-name" | b"table:number-columns-repeated"
+            ) {
+                start.push_attribute(attr);
+            }
+        }
+        Self {
+            start,
+            children: Vec::new(),
+        }
+    }
+
+    fn set_value(&mut self, value: &CellValue) {
+        let name = String::from_utf8_lossy(self.start.name().as_ref()).into_owned();
+        let mut start = BytesStart::new(name);
+        for attr in self.start.attributes().with_checks(false).flatten() {
+            if KEPT_CELL_ATTRIBUTES.contains(&attr.key.as_ref()) {
+                start.push_attribute(attr);
+            }
+        }
+        match value {
+            CellValue::Text(_) => start.push_attribute(("office:value-type", "string")),
+            CellValue::Number(number) => {
+                start.push_attribute(("office:value-type", "float"));
+                start.push_attribute(("office:value", number.to_string().as_str()));
+            }
+            CellValue::Boolean(flag) => {
+                start.push_attribute(("office:value-type", "boolean"));
+                start.push_attribute(("office:boolean-value", flag.to_string().as_str()));
+            }
+            CellValue::DateTime(date_time) => {
+                start.push_attribute(("office:value-type", "date"));
+                start.push_attribute((
+                    "office:date-value",
+                    date_time.format("%Y-%m-%dT%H:%M:%S").to_string().as_str(),
+                ));
+            }
+        }
+        self.start = start;
+
+        // The text is only a cached display value for typed cells.
+        self.children.clear();
+        for line in value.to_string().split('\n') {
+            self.children.push(Event::Start(BytesStart::new("text:p")));
+            self.children
+                .push(Event::Text(BytesText::new(line).into_owned()));
+            self.children.push(Event::End(BytesEnd::new("text:p")));
+        }
+    }
+
+    fn write(&self, writer: &mut XmlWriter<Vec<u8>>) -> Result<(), String> {
+        if self.children.is_empty() {
+            return writer
+                .write_event(Event::Empty(self.start.clone()))
+                .map_err(|err| err.to_string());
+        }
+        writer
+            .write_event(Event::Start(self.start.clone()))
+            .map_err(|err| err.to_string())?;
+        for event in &self.children {
+            writer
+                .write_event(event.clone())
+                .map_err(|err| err.to_string())?;
+        }
+        writer
+            .write_event(Event::End(self.start.to_end().into_owned()))
+            .map_err(|err| err.to_string())
+    }
+}
+
+/// `content.xml` split into the markup before the spreadsheet tables, the
+/// tables themselves and the markup after the first table.
+struct SpreadsheetContent {
+    head: Vec<u8>,
+    tables: Vec<OdsTable>,
+    tail: Vec<u8>,
+}
+
+fn split_content(content: &[u8]) -> Result<SpreadsheetContent, String> {
+    let mut reader = XmlReader::from_reader(content);
+    reader.trim_text(false);
+    let mut head = XmlWriter::new(Vec::new());
+    let mut tail = XmlWriter::new(Vec::new());
+    let mut tables = Vec::new();
+    let mut buffer = Vec::new();
+
+    loop {
+        let event = reader
+            .read_event_into(&mut buffer)
+            .map_err(|err| err.to_string())?
+            .into_owned();
+        buffer.clear();
+        match event {
+            Event::Eof => break,
+            Event::Start(start) if start.name().as_ref() == TABLE => {
+                let items = read_table_items(&mut reader, &mut buffer)?;
+                tables.push(OdsTable { start, items });
+            }
+            other => {
+                let writer = if tables.is_empty() {
+                    &mut head
+                } else {
+                    &mut tail
+                };
+                writer.write_event(other).map_err(|err| err.to_string())?;
+            }
+        }
+    }
+
+    Ok(SpreadsheetContent {
+        head: head.into_inner(),
+        tables,
+        tail: tail.into_inner(),
+    })
+}
+
+fn read_table_items(
+    reader: &mut XmlReader<&[u8]>,
+    buffer: &mut Vec<u8>,
+) -> Result<Vec<TableItem>, String> {
+    let mut items = Vec::new();
+    let mut depth = 0usize;
+
+    loop {
+        let event = reader
+            .read_event_into(buffer)
+            .map_err(|err| err.to_string())?
+            .into_owned();
+        buffer.clear();
+        match event {
+            Event::Eof => return Err("Unexpected end of spreadsheet content".to_string()),
+            Event::End(end) if depth == 0 && end.name().as_ref() == TABLE => break,
+            Event::Start(start) if start.name().as_ref() == TABLE_ROW => {
+                let cells = read_row_cells(reader, buffer)?;
+                items.push(TableItem::Row(OdsRow { start, cells }));
+            }
+            Event::Empty(start) if start.name().as_ref() == TABLE_ROW => {
+                items.push(TableItem::Row(OdsRow {
+                    start,
+                    cells: Vec::new(),
+                }));
+            }
+            Event::Start(start) => {
+                depth += 1;
+                items.push(TableItem::Other(Event::Start(start)));
+            }
+            Event::End(end) => {
+                depth = depth.saturating_sub(1);
+                items.push(TableItem::Other(Event::End(end)));
+            }
+            other => items.push(TableItem::Other(other)),
+        }
+    }
+
+    Ok(items)
+}
+
+fn read_row_cells(
+    reader: &mut XmlReader<&[u8]>,
+    buffer: &mut Vec<u8>,
+) -> Result<Vec<OdsCell>, String> {
+    let mut cells = Vec::new();
+    let mut current: Option<OdsCell> = None;
+    let mut depth = 0usize;
+
+    loop {
+        let event = reader
+            .read_event_into(buffer)
+            .map_err(|err| err.to_string())?
+            .into_owned();
+        buffer.clear();
+
+        if let Some(cell) = current.as_mut() {
+            match &event {
+                Event::Start(_) => depth += 1,
+                Event::End(_) if depth == 0 => {
+                    cells.extend(current.take());
+                    continue;
+                }
+                Event::End(_) => depth -= 1,
+                Event::Eof => return Err("Unexpected end of spreadsheet content".to_string()),
+                _ => {}
+            }
+            cell.children.push(event);
+            continue;
+        }
+
+        match event {
+            Event::Eof => return Err("Unexpected end of spreadsheet content".to_string()),
+            Event::End(_) => break,
+            Event::Start(start) if is_cell(&start) => {
+                current = Some(OdsCell {
+                    start,
+                    children: Vec::new(),
+                });
+            }
+            Event::Empty(start) if is_cell(&start) => cells.push(OdsCell {
+                start,
+                children: Vec::new(),
+            }),
+            _ => {}
+        }
+    }
+
+    Ok(cells)
+}
+
+fn is_cell(start: &BytesStart) -> bool {
+    let name = start.name();
+    name.as_ref() == TABLE_CELL || name.as_ref() == COVERED_TABLE_CELL
+}
+
+fn repeat_count(start: &BytesStart, key: &str) -> u32 {
+    attribute_value(start, key.as_bytes())
+        .and_then(|value| value.parse::<u32>().ok())
+        .unwrap_or(1)
+        .max(1)
+}
+
+fn with_repeat(start: &BytesStart<'static>, key: &str, count: u32) -> BytesStart<'static> {
+    let name = String::from_utf8_lossy(start.name().as_ref()).into_owned();
+    let mut updated = BytesStart::new(name);
+    for attr in start.attributes().with_checks(false).flatten() {
+        if attr.key.as_ref() != key.as_bytes() {
+            updated.push_attribute(attr);
+        }
+    }
+    if count > 1 {
+        updated.push_attribute((key, count.to_string().as_str()));
+    }
+    updated
+}
+
+/// Points the sheet part of ODF range addresses such as `Sheet1.A1:Sheet1.D10`
+/// at the renamed table.
+fn rename_range_sheet(ranges: &str, old_name: &str, new_name: &str) -> String {
+    let quoted_new = format!("'{}'", new_name.replace('\'', "''"));
+    let quoted_old = format!("'{}'.", old_name.replace('\'', "''"));
+    let renamed = ranges.replace(&quoted_old, &format!("{}.", quoted_new));
+    renamed
+        .split(' ')
+        .map(|range| {
+            range
+                .split(':')
+                .map(|part| {
+                    let (absolute, unqualified) = match part.strip_prefix('$') {
+                        Some(rest) => ("$", rest),
+                        None => ("", part),
+                    };
+                    match unqualified.strip_prefix(old_name) {
+                        Some(rest) if rest.starts_with('.') => {
+                            format!("{}{}{}", absolute, quoted_new, rest)
+                        }
+                        _ => part.to_string(),
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join(":")
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn table(xml: &str) -> OdsTable {
+        let content = format!("<office:spreadsheet>{}</office:spreadsheet>", xml);
+        split_content(content.as_bytes()).unwrap().tables.remove(0)
+    }
+
+    fn written(table: &OdsTable) -> String {
+        let mut writer = XmlWriter::new(Vec::new());
+        table.write_unchanged(&mut writer).unwrap();
+        String::from_utf8(writer.into_inner()).unwrap()
+    }
+
+    #[test]
+    fn writes_into_repeated_runs() {
+        let mut table = table(concat!(
+            r#"<table:table table:name="T"><table:table-row table:number-rows-repeated="4">"#,
+            r#"<table:table-cell table:style-name="ce1" table:number-columns-repeated="3"/>"#,
+            r#"</table:table-row></table:table>"#,
+        ));
+        table.set_cell_value(2, 1, &CellValue::Number(5.0)).unwrap();
+        assert_eq!(
+            written(&table),
+            concat!(
+                r#"<table:table table:name="T"><table:table-row table:number-rows-repeated="2">"#,
+                r#"<table:table-cell table:style-name="ce1" table:number-columns-repeated="3"/>"#,
+                r#"</table:table-row><table:table-row>"#,
+                r#"<table:table-cell table:style-name="ce1"/>"#,
+                r#"<table:table-cell table:style-name="ce1" office:value-type="float" office:value="5"><text:p>5</text:p></table:table-cell>"#,
+                r#"<table:table-cell table:style-name="ce1"/>"#,
+                r#"</table:table-row><table:table-row>"#,
+                r#"<table:table-cell table:style-name="ce1" table:number-columns-repeated="3"/>"#,
+                r#"</table:table-row></table:table>"#,
+            )
+        );
+    }
+
+    #[test]
+    fn writes_past_the_last_row_and_cell() {
+        let mut table = table(concat!(
+            r#"<table:table table:name="T"><table:table-row><table:table-cell office:value-type="string">"#,
+            r#"<text:p>a</text:p></table:table-cell></table:table-row></table:table>"#,
+        ));
+        table
+            .set_cell_value(3, 2, &CellValue::Text("x & y".to_string()))
+            .unwrap();
+        assert_eq!(
+            written(&table),
+            concat!(
+                r#"<table:table table:name="T"><table:table-row><table:table-cell office:value-type="string">"#,
+                r#"<text:p>a</text:p></table:table-cell></table:table-row>"#,
+                r#"<table:table-row table:number-rows-repeated="2"><table:table-cell/></table:table-row>"#,
+                r#"<table:table-row><table:table-cell/><table:table-cell/>"#,
+                r#"<table:table-cell office:value-type="string"><text:p>x &amp; y</text:p></table:table-cell>"#,
+                r#"</table:table-row></table:table>"#,
+            )
+        );
+    }
+
+    #[test]
+    fn writes_kept_tables_unchanged() {
+        let xml = concat!(
+            r#"<table:table table:name="R&amp;D &quot;1&quot;" table:print-ranges="&apos;R&amp;D &quot;1&quot;&apos;.A1:&apos;R&amp;D &quot;1&quot;&apos;.B2">"#,
+            r#"<table:table-column table:number-columns-repeated="2"/>"#,
+            r#"<table:table-row table:number-rows-repeated="3"><table:table-cell table:number-columns-repeated="2"/></table:table-row>"#,
+            r#"<table:table-row><table:table-cell office:value-type="float" office:value="1" table:formula="of:=[.A1]&lt;2">"#,
+            r#"<text:p>1</text:p></table:table-cell></table:table-row></table:table>"#,
+        );
+        assert_eq!(written(&table(xml)), xml);
+    }
+
+    /// Writes a spreadsheet package with tables named `names` and loads
+    /// it.
+    fn load(names: &[&str], sheet: &str, kept: &[KeptSheet]) -> Result<OdsTemplate, String> {
+        let tables = names
+            .iter()
+            .map(|name| {
+                format!(
+                    r#"<table:table table:name="{}"><table:table-row><table:table-cell/></table:table-row></table:table>"#,
+                    crate::xml_utils::xml_escape(name)
+                )
+            })
+            .collect::<String>();
+        let content = format!(
+            "<office:document-content><office:body><office:spreadsheet>{}</office:spreadsheet></office:body></office:document-content>",
+            tables
+        );
+        let entries = [
+            (
+                "mimetype".to_string(),
+                b"application/vnd.oasis.opendocument.spreadsheet".to_vec(),
+            ),
+            ("content.xml".to_string(), content.into_bytes()),
+        ];
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "ods-template-{}-{}.ods",
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::write(&path, build_package::<&str>(&entries, &[])?).unwrap();
+        let template = OdsTemplate::load(&path, sheet, kept);
+        std::fs::remove_file(&path).unwrap();
+        template
+    }
+
+    #[test]
+    fn finds_sheets_with_escaped_names() {
+        let template = load(&["Tpl", "R&D <'x'>"], "R&D <'x'>", &[]).unwrap();
+        assert_eq!(template.sheet_name, "R&D <'x'>");
+        assert!(load(&["Tpl"], "R&amp;D", &[]).is_err());
+    }
+
+    #[test]
+    fn keeps_sheets_with_escaped_names() {
+        let kept = |name: &str, position| KeptSheet {
+            name: name.to_string(),
+            position,
+        };
+        let template = load(
+            &["Tpl", "R&D", "\"Notes\""],
+            "Tpl",
+            &[
+                kept("R&D", SheetPosition::Before),
+                kept("\"Notes\"", SheetPosition::After),
+            ],
+        )
+        .unwrap();
+        assert!(written(&template.kept_before[0]).contains(r#"table:name="R&amp;D""#));
+        assert!(written(&template.kept_after[0]).contains(r#"table:name="&quot;Notes&quot;""#));
+
+        let missing = load(&["Tpl", "R&D"], "Tpl", &[kept("L&K", SheetPosition::After)]);
+        assert_eq!(
+            missing.err().as_deref(),
+            Some("Sheet \"L&K\" not found; the template has: Tpl, R&D")
+        );
+    }
+}
@@ -0,0 +1,463 @@
+use crate::package::{build_package, read_package};
+use crate::placeholders::{ODF_TEXT_MARKUP, collect_placeholders, replace_placeholders};
+use crate::xml_utils::{unescaped_attribute, with_attribute, xml_escape};
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::{Reader as XmlReader, Writer as XmlWriter};
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::Path;
+
+/// Parts of an ODF text package that may contain placeholders. Headers and
+/// footers live in the master pages of `styles.xml`.
+const PLACEHOLDER_PARTS: [&str; 2] = ["content.xml", "styles.xml"];
+
+/// Children of `office:text` that declare document-wide settings and must
+/// appear only once in a merged document.
+const BODY_DECLARATIONS: [&[u8]; 9] = [
+    b"office:forms",
+    b"text:tracked-changes",
+    b"text:variable-decls",
+    b"text:sequence-decls",
+    b"text:user-field-decls",
+    b"text:dde-connection-decls",
+    b"text:alphabetical-index-auto-mark-file",
+    b"table:calculation-settings",
+    b"table:content-validations",
+];
+
+const PAGE_BREAK_STYLE: &str = "BulkMergePageBreak";
+
+pub fn read_odt_placeholders(path: &Path) -> Result<Vec<String>, String> {
+    let entries = read_package(path)?;
+    let mut names = Vec::new();
+    for part in PLACEHOLDER_PARTS {
+        if let Some((_, data)) = entries.iter().find(|(name, _)| name == part) {
+            collect_placeholders(data, &ODF_TEXT_MARKUP, &mut names)?;
+        }
+    }
+    Ok(names)
+}
+
+/// A text document template filled through `{{placeholder}}` tokens.
+pub struct OdtTemplate {
+    entries: Vec<(String, Vec<u8>)>,
+}
+
+impl OdtTemplate {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        Ok(Self {
+            entries: read_package(path)?,
+        })
+    }
+
+    fn part(&self, name: &str) -> Option<&[u8]> {
+        self.entries
+            .iter()
+            .find(|(entry, _)| entry == name)
+            .map(|(_, data)| data.as_slice())
+    }
+
+    /// Builds a document filled with the values of a single record.
+    pub fn render_record(&self, values: &HashMap<String, String>) -> Result<Vec<u8>, String> {
+        let mut replaced = Vec::new();
+        for part in PLACEHOLDER_PARTS {
+            if let Some(data) = self.part(part) {
+                replaced.push((part, replace_placeholders(data, &ODF_TEXT_MARKUP, values)?));
+            }
+        }
+        build_package(&self.entries, &replaced)
+    }
+
+    /// Builds a single document holding all records, starting each record
+    /// after a page break. Every record after the first gets its own copies
+    /// of the master pages, so that headers and footers are filled per
+    /// record, and the names and IDs in its body get the record number as a
+    /// suffix to stay unique.
+    pub fn render_merged(&self, records: &[HashMap<String, String>]) -> Result<Vec<u8>, String> {
+        let content = self
+            .part("content.xml")
+            .ok_or_else(|| "Document content missing".to_string())?;
+        let styles = self.part("styles.xml");
+        let master_pages = styles
+            .map(split_master_pages)
+            .transpose()?
+            .unwrap_or_default();
+        let first_master = master_pages.first().map(|(name, _)| name.as_str());
+        let page_break_styles: String = (1..records.len())
+            .map(|index| page_break_style(index, first_master))
+            .collect();
+        let parts = split_document_body(content, &page_break_styles)?;
+
+        let mut merged = parts.head.clone();
+        merged.extend_from_slice(&parts.declarations);
+        for (index, values) in records.iter().enumerate() {
+            if index == 0 {
+                merged.extend(replace_placeholders(&parts.body, &ODF_TEXT_MARKUP, values)?);
+                continue;
+            }
+            merged.extend_from_slice(
+                format!(
+                    "<text:p text:style-name=\"{}\"/>",
+                    page_break_style_name(index)
+                )
+                .as_bytes(),
+            );
+            let body = suffix_record_names(&parts.body, index)?;
+            merged.extend(replace_placeholders(&body, &ODF_TEXT_MARKUP, values)?);
+        }
+        merged.extend_from_slice(&parts.tail);
+
+        let mut replaced = vec![("content.xml", merged)];
+        if let (Some(first), Some(styles)) = (records.first(), styles) {
+            let mut copies = Vec::new();
+            for (index, values) in records.iter().enumerate().skip(1) {
+                for (_, page) in &master_pages {
+                    let page = suffix_record_names(page, index)?;
+                    copies.extend(replace_placeholders(&page, &ODF_TEXT_MARKUP, values)?);
+                }
+            }
+            let filled = replace_placeholders(styles, &ODF_TEXT_MARKUP, first)?;
+            replaced.push((
+                "styles.xml",
+                insert_before_closing(&filled, b"</office:master-styles>", &copies),
+            ));
+        }
+        build_package(&self.entries, &replaced)
+    }
+}
+
+fn page_break_style_name(index: usize) -> String {
+    format!("{}{}", PAGE_BREAK_STYLE, index + 1)
+}
+
+/// A paragraph style that starts the record at `index` on a new page, using
+/// the record's copy of the first master page.
+fn page_break_style(index: usize, master_page: Option<&str>) -> String {
+    let master_page = master_page
+        .map(|name| {
+            format!(
+                " style:master-page-name=\"{}\"",
+                xml_escape(&format!("{}_{}", name, index + 1))
+            )
+        })
+        .unwrap_or_default();
+    format!(
+        "<style:style style:name=\"{}\" style:family=\"paragraph\"{}><style:paragraph-properties fo:break-before=\"page\"/></style:style>",
+        page_break_style_name(index),
+        master_page
+    )
+}
+
+/// Returns the name and markup of every master page in `styles.xml`.
+fn split_master_pages(styles: &[u8]) -> Result<Vec<(String, Vec<u8>)>, String> {
+    let mut reader = XmlReader::from_reader(styles);
+    reader.trim_text(false);
+    let mut buffer = Vec::new();
+    let mut pages = Vec::new();
+    let mut current: Option<(String, XmlWriter<Vec<u8>>)> = None;
+    let mut depth = 0usize;
+
+    loop {
+        let event = reader
+            .read_event_into(&mut buffer)
+            .map_err(|err| err.to_string())?
+            .into_owned();
+        buffer.clear();
+
+        match &event {
+            Event::Eof => break,
+            Event::Start(start) | Event::Empty(start)
+                if current.is_none() && start.name().as_ref() == b"style:master-page" =>
+            {
+                let name = unescaped_attribute(start, b"style:name").unwrap_or_default();
+                current = Some((name, XmlWriter::new(Vec::new())));
+                depth = 0;
+            }
+            _ => {}
+        }
+        let Some((_, writer)) = current.as_mut() else {
+            continue;
+        };
+        match &event {
+            Event::Start(_) => depth += 1,
+            Event::End(_) => depth -= 1,
+            _ => {}
+        }
+        writer.write_event(event).map_err(|err| err.to_string())?;
+        if depth == 0
+            && let Some((name, writer)) = current.take()
+        {
+            pages.push((name, writer.into_inner()));
+        }
+    }
+
+    Ok(pages)
+}
+
+/// Attributes that name an element, or refer to a named element, and must
+/// be unique in a merged document. `None` matches every element.
+const RECORD_NAMES: [(Option<&[u8]>, &str); 12] = [
+    (None, "xml:id"),
+    (None, "draw:name"),
+    (Some(b"text:list"), "text:continue-list"),
+    (Some(b"text:section"), "text:name"),
+    (Some(b"text:bookmark"), "text:name"),
+    (Some(b"text:bookmark-start"), "text:name"),
+    (Some(b"text:bookmark-end"), "text:name"),
+    (Some(b"text:bookmark-ref"), "text:ref-name"),
+    (Some(b"text:note"), "text:id"),
+    (Some(b"text:note-ref"), "text:ref-name"),
+    (Some(b"style:master-page"), "style:name"),
+    (Some(b"style:master-page"), "style:next-style-name"),
+];
+
+/// Adds the number of the record at `index` to the names listed in
+/// `RECORD_NAMES`.
+fn suffix_record_names(xml: &[u8], index: usize) -> Result<Vec<u8>, String> {
+    let suffix = format!("_{}", index + 1);
+    let mut reader = XmlReader::from_reader(xml);
+    reader.trim_text(false);
+    let mut writer = XmlWriter::new(Vec::new());
+    let mut buffer = Vec::new();
+
+    loop {
+        let event = reader
+            .read_event_into(&mut buffer)
+            .map_err(|err| err.to_string())?;
+        let event = match event {
+            Event::Eof => break,
+            Event::Start(start) => Event::Start(suffix_names(start, &suffix)),
+            Event::Empty(start) => Event::Empty(suffix_names(start, &suffix)),
+            other => other,
+        };
+        writer.write_event(event).map_err(|err| err.to_string())?;
+        buffer.clear();
+    }
+
+    Ok(writer.into_inner())
+}
+
+fn suffix_names<'a>(start: BytesStart<'a>, suffix: &str) -> BytesStart<'a> {
+    let element = start.name().as_ref().to_vec();
+    let mut updated = start;
+    for (owner, key) in RECORD_NAMES {
+        if owner.is_some_and(|owner| owner != element.as_slice()) {
+            continue;
+        }
+        if let Some(name) = unescaped_attribute(&updated, key.as_bytes()) {
+            updated = with_attribute(&updated, key, &format!("{}{}", name, suffix));
+        }
+    }
+    updated
+}
+
+fn insert_before_closing(xml: &[u8], closing_tag: &[u8], addition: &[u8]) -> Vec<u8> {
+    let position = xml
+        .windows(closing_tag.len())
+        .rposition(|window| window == closing_tag);
+    match position {
+        Some(position) => [&xml[..position], addition, &xml[position..]].concat(),
+        None => xml.to_vec(),
+    }
+}
+
+struct DocumentBody {
+    /// Everything up to and including the `office:text` start tag, with the
+    /// page break styles added to the automatic styles.
+    head: Vec<u8>,
+    declarations: Vec<u8>,
+    body: Vec<u8>,
+    tail: Vec<u8>,
+}
+
+fn split_document_body(content: &[u8], page_break_styles: &str) -> Result<DocumentBody, String> {
+    let mut reader = XmlReader::from_reader(content);
+    reader.trim_text(false);
+    let mut head = XmlWriter::new(Vec::new());
+    let mut declarations = XmlWriter::new(Vec::new());
+    let mut body = XmlWriter::new(Vec::new());
+    let mut tail = XmlWriter::new(Vec::new());
+    let mut buffer = Vec::new();
+
+    let mut has_styles = false;
+    let mut in_text = false;
+    let mut after_text = false;
+    let mut depth = 0usize;
+    let mut is_declaration = false;
+
+    loop {
+        let event = reader
+            .read_event_into(&mut buffer)
+            .map_err(|err| err.to_string())?
+            .into_owned();
+        buffer.clear();
+
+        if after_text {
+            if matches!(event, Event::Eof) {
+                break;
+            }
+            tail.write_event(event).map_err(|err| err.to_string())?;
+            continue;
+        }
+
+        if in_text {
+            match &event {
+                Event::End(end) if depth == 0 && end.name().as_ref() == b"office:text" => {
+                    in_text = false;
+                    after_text = true;
+                    tail.write_event(event).map_err(|err| err.to_string())?;
+                    continue;
+                }
+                Event::Start(start) | Event::Empty(start) if depth == 0 => {
+                    is_declaration = BODY_DECLARATIONS.contains(&start.name().as_ref());
+                }
+                Event::Text(_) if depth == 0 => is_declaration = false,
+                Event::Eof => return Err("Unexpected end of document content".to_string()),
+                _ => {}
+            }
+            match &event {
+                Event::Start(_) => depth += 1,
+                Event::End(_) => depth = depth.saturating_sub(1),
+                _ => {}
+            }
+            let writer = if is_declaration {
+                &mut declarations
+            } else {
+                &mut body
+            };
+            writer.write_event(event).map_err(|err| err.to_string())?;
+            continue;
+        }
+
+        match &event {
+            Event::Eof => return Err("Document text body missing".to_string()),
+            Event::Empty(start) if start.name().as_ref() == b"office:automatic-styles" => {
+                has_styles = true;
+                let styles = format!(
+                    "<office:automatic-styles>{}</office:automatic-styles>",
+                    page_break_styles
+                );
+                head.get_mut()
+                    .write_all(styles.as_bytes())
+                    .map_err(|err| err.to_string())?;
+                continue;
+            }
+            Event::End(end) if end.name().as_ref() == b"office:automatic-styles" => {
+                has_styles = true;
+                head.get_mut()
+                    .write_all(page_break_styles.as_bytes())
+                    .map_err(|err| err.to_string())?;
+            }
+            Event::Start(start) if start.name().as_ref() == b"office:body" && !has_styles => {
+                has_styles = true;
+                let styles = format!(
+                    "<office:automatic-styles>{}</office:automatic-styles>",
+                    page_break_styles
+                );
+                head.get_mut()
+                    .write_all(styles.as_bytes())
+                    .map_err(|err| err.to_string())?;
+            }
+            Event::Start(start) if start.name().as_ref() == b"office:text" => {
+                in_text = true;
+            }
+            _ => {}
+        }
+        head.write_event(event).map_err(|err| err.to_string())?;
+    }
+
+    Ok(DocumentBody {
+        head: head.into_inner(),
+        declarations: declarations.into_inner(),
+        body: body.into_inner(),
+        tail: tail.into_inner(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Read};
+
+    fn template(body: &str, master_pages: &str) -> OdtTemplate {
+        let content = format!(
+            "<office:document-content><office:automatic-styles/><office:body><office:text><text:sequence-decls/>{}</office:text></office:body></office:document-content>",
+            body
+        );
+        let styles = format!(
+            "<office:document-styles><office:master-styles>{}</office:master-styles></office:document-styles>",
+            master_pages
+        );
+        OdtTemplate {
+            entries: vec![
+                (
+                    "mimetype".to_string(),
+                    b"application/vnd.oasis.opendocument.text".to_vec(),
+                ),
+                ("content.xml".to_string(), content.into_bytes()),
+                ("styles.xml".to_string(), styles.into_bytes()),
+            ],
+        }
+    }
+
+    fn part(package: &[u8], name: &str) -> String {
+        let mut archive = zip::ZipArchive::new(Cursor::new(package)).unwrap();
+        let mut text = String::new();
+        archive
+            .by_name(name)
+            .unwrap()
+            .read_to_string(&mut text)
+            .unwrap();
+        text
+    }
+
+    fn record(name: &str) -> HashMap<String, String> {
+        HashMap::from([("Name".to_string(), name.to_string())])
+    }
+
+    #[test]
+    fn replaces_placeholders_split_across_spans() {
+        let template = template(
+            r#"<text:p>Dear <text:span text:style-name="T1">{{Na</text:span>me}}, <text:span>{{ Missing }}</text:span></text:p>"#,
+            "",
+        );
+        let package = template.render_record(&record("Ann & Bob")).unwrap();
+        let content = part(&package, "content.xml");
+        assert!(content.contains(
+            r#"<text:p>Dear <text:span text:style-name="T1">Ann &amp; Bob</text:span>, <text:span>{{ Missing }}</text:span></text:p>"#
+        ));
+    }
+
+    #[test]
+    fn merges_records_with_unique_names_and_headers() {
+        let template = template(
+            concat!(
+                r#"<text:section text:name="Letter"><text:p xml:id="p1">{{Name}}<text:bookmark text:name="Top &amp; tail"/></text:p>"#,
+                r#"<draw:frame draw:name="Logo"/></text:section>"#,
+            ),
+            r#"<style:master-page style:name="Standard"><style:header><text:p>To {{Name}}</text:p></style:header></style:master-page>"#,
+        );
+        let package = template
+            .render_merged(&[record("Ann"), record("Bob")])
+            .unwrap();
+
+        let content = part(&package, "content.xml");
+        assert!(content.contains(concat!(
+            r#"<office:automatic-styles><style:style style:name="BulkMergePageBreak2" style:family="paragraph" style:master-page-name="Standard_2">"#,
+            r#"<style:paragraph-properties fo:break-before="page"/></style:style></office:automatic-styles>"#,
+        )));
+        assert_eq!(content.matches("<text:sequence-decls/>").count(), 1);
+        assert!(content.contains(concat!(
+            r#"<text:section text:name="Letter"><text:p xml:id="p1">Ann<text:bookmark text:name="Top &amp; tail"/></text:p>"#,
+            r#"<draw:frame draw:name="Logo"/></text:section><text:p text:style-name="BulkMergePageBreak2"/>"#,
+            r#"<text:section text:name="Letter_2"><text:p xml:id="p1_2">Bob<text:bookmark text:name="Top &amp; tail_2"/></text:p>"#,
+            r#"<draw:frame draw:name="Logo_2"/></text:section>"#,
+        )));
+
+        let styles = part(&package, "styles.xml");
+        assert!(styles.contains(concat!(
+            r#"<office:master-styles><style:master-page style:name="Standard"><style:header><text:p>To Ann</text:p></style:header></style:master-page>"#,
+            r#"<style:master-page style:name="Standard_2"><style:header><text:p>To Bob</text:p></style:header></style:master-page></office:master-styles>"#,
+        )));
+    }
+}
@@ -0,0 +1,446 @@
+use crate::data::DataTable;
+use crate::placeholders::find_placeholders;
+use crate::template::Template;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OutputMode {
+    /// All rows go into one workbook (one sheet per row) or one merged
+    /// document.
+    #[default]
+    SingleFile,
+    FilePerRow,
+}
+
+/// Where per-row files are written.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OutputTarget {
+    #[default]
+    Directory,
+    ZipArchive,
+}
+
+/// Where a sheet of the template workbook other than the template sheet
+/// goes in the output, relative to the generated sheets.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SheetPosition {
+    Before,
+    #[default]
+    After,
+}
+
+/// A sheet of the template workbook, such as a lookup table, that is copied
+/// into every output workbook.
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeptSheet {
+    pub name: String,
+    #[serde(default)]
+    pub position: SheetPosition,
+}
+
+/// Where and how generated output is written.
+#[derive(Clone)]
+pub struct OutputOptions {
+    /// Output file, or the folder or ZIP archive for per-row output.
+    pub path: PathBuf,
+    pub mode: OutputMode,
+    pub target: OutputTarget,
+    /// Pattern for per-row file names; empty for a default based on the
+    /// template name.
+    pub file_name_pattern: String,
+    /// Pattern for sheet names; empty for the template sheet name.
+    pub sheet_name_pattern: String,
+    /// Other sheets of a template workbook to carry over. Sheets that are
+    /// not listed are left out.
+    pub kept_sheets: Vec<KeptSheet>,
+}
+
+impl OutputOptions {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            mode: OutputMode::default(),
+            target: OutputTarget::default(),
+            file_name_pattern: String::new(),
+            sheet_name_pattern: String::new(),
+            kept_sheets: Vec::new(),
+        }
+    }
+
+    pub fn kept_sheet_names(&self) -> Vec<String> {
+        self.kept_sheets
+            .iter()
+            .map(|sheet| sheet.name.clone())
+            .collect()
+    }
+
+    /// The per-row file name pattern, falling back to one built from the
+    /// template name.
+    pub fn effective_file_name_pattern(&self, template: &Template) -> String {
+        if !self.file_name_pattern.trim().is_empty() {
+            return self.file_name_pattern.clone();
+        }
+        let (_, extension) = template.format().output_filter();
+        default_file_name_pattern(Some(&template.path), extension)
+    }
+
+    /// The sheet name pattern, falling back to the template sheet name, which
+    /// is numbered when all rows share one workbook.
+    pub fn effective_sheet_name_pattern(&self, template_sheet: &str) -> String {
+        if !self.sheet_name_pattern.trim().is_empty() {
+            return self.sheet_name_pattern.clone();
+        }
+        match self.mode {
+            OutputMode::SingleFile => format!("{} {{{{{}}}}}", template_sheet, ROW_NUMBER_TOKEN),
+            OutputMode::FilePerRow => template_sheet.to_string(),
+        }
+    }
+}
+
+/// Token in a file name pattern that is replaced by the one-based row number.
+pub const ROW_NUMBER_TOKEN: &str = "#";
+
+/// Names that Windows reserves for devices, with or without an extension.
+const RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Excel's limit on the length of a sheet name.
+const SHEET_NAME_LIMIT: usize = 31;
+
+/// Bytes kept of a file name, leaving room for an extension and a ` (2)`
+/// suffix within the 255 bytes most file systems allow.
+const FILE_NAME_LIMIT: usize = 200;
+
+/// Default pattern for per-row output, e.g. `invoice_{{#}}.xlsx`.
+pub fn default_file_name_pattern(template_path: Option<&Path>, extension: &str) -> String {
+    let stem = template_path
+        .and_then(Path::file_stem)
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "output".to_string());
+    format!("{}_{{{{{}}}}}.{}", stem, ROW_NUMBER_TOKEN, extension)
+}
+
+/// Fills `{{column}}` tokens with the row's values and `{{#}}` with the row
+/// number. Tokens that match no column are left empty.
+pub fn render_name_pattern(
+    pattern: &str,
+    headers: &[String],
+    row: &[String],
+    row_number: usize,
+) -> String {
+    let mut rendered = String::new();
+    let mut position = 0;
+    for (range, name) in find_placeholders(pattern) {
+        rendered.push_str(&pattern[position..range.start]);
+        if name == ROW_NUMBER_TOKEN {
+            rendered.push_str(&row_number.to_string());
+        } else if let Some(value) = headers
+            .iter()
+            .position(|header| header == &name)
+            .and_then(|index| row.get(index))
+        {
+            rendered.push_str(value.trim());
+        }
+        position = range.end;
+    }
+    rendered.push_str(&pattern[position..]);
+    rendered
+}
+
+/// Turns arbitrary text into a file name that is valid on common platforms,
+/// shortened to 200 bytes.
+pub fn sanitize_file_name(name: &str) -> String {
+    let mut sanitized = name
+        .chars()
+        .map(|ch| match ch {
+            '<' | '>' | ':' | '"' | '/' | '\\' | '|' | '?' | '*' => '_',
+            ch if ch.is_control() => '_',
+            ch => ch,
+        })
+        .collect::<String>();
+    let mut end = sanitized.len().min(FILE_NAME_LIMIT);
+    while !sanitized.is_char_boundary(end) {
+        end -= 1;
+    }
+    sanitized = sanitized[..end]
+        .trim()
+        .trim_end_matches(['.', ' '])
+        .to_string();
+    if sanitized.is_empty() {
+        return "output".to_string();
+    }
+
+    let base = sanitized.split('.').next().unwrap_or_default();
+    if RESERVED_NAMES
+        .iter()
+        .any(|reserved| reserved.eq_ignore_ascii_case(base.trim_end()))
+    {
+        sanitized.insert(0, '_');
+    }
+    sanitized
+}
+
+/// Builds one sanitized, unique file name per row from `pattern`. The
+/// extension is always `extension`, whether or not the pattern has it.
+pub fn output_file_names(pattern: &str, extension: &str, data: &DataTable) -> Vec<String> {
+    unique_file_names(pattern, extension, data, &mut HashSet::new())
+}
+
+/// Like [`output_file_names`], avoiding the names in `used` and adding the
+/// new ones to it.
+pub(crate) fn unique_file_names(
+    pattern: &str,
+    extension: &str,
+    data: &DataTable,
+    used: &mut HashSet<String>,
+) -> Vec<String> {
+    let suffix = format!(".{}", extension);
+    let pattern = pattern.trim();
+    let pattern = match pattern.len().checked_sub(suffix.len()) {
+        Some(split)
+            if pattern.is_char_boundary(split)
+                && pattern[split..].eq_ignore_ascii_case(&suffix) =>
+        {
+            &pattern[..split]
+        }
+        _ => pattern,
+    };
+
+    data.rows
+        .iter()
+        .enumerate()
+        .map(|(index, row)| {
+            let stem = sanitize_file_name(&render_name_pattern(
+                pattern,
+                &data.headers,
+                row,
+                data.row_number(index),
+            ));
+            let mut name = format!("{}{}", stem, suffix);
+            let mut counter = 1;
+            // Compare case-insensitively, since Windows and macOS file systems
+            // and most archive tools treat names that way.
+            while !used.insert(name.to_lowercase()) {
+                counter += 1;
+                name = format!("{} ({}){}", stem, counter, suffix);
+            }
+            name
+        })
+        .collect()
+}
+
+/// Turns arbitrary text into a sheet name that Excel and LibreOffice accept:
+/// at most 31 characters, none of `[]:*?/\`, and no leading or trailing
+/// apostrophe.
+pub fn sanitize_sheet_name(name: &str) -> String {
+    let sanitized = name
+        .chars()
+        .map(|ch| match ch {
+            '[' | ']' | ':' | '*' | '?' | '/' | '\\' => '_',
+            ch if ch.is_control() => '_',
+            ch => ch,
+        })
+        .collect::<String>();
+    // Trimmed again after truncating, which can end the name with `'`.
+    let sanitized = trim_sheet_name(&sanitized);
+    let sanitized = trim_sheet_name(truncate_chars(sanitized, SHEET_NAME_LIMIT));
+    if sanitized.is_empty() {
+        "Sheet".to_string()
+    } else if sanitized.eq_ignore_ascii_case("History") {
+        // Reserved by Excel for change tracking.
+        format!("{}_", sanitized)
+    } else {
+        sanitized.to_string()
+    }
+}
+
+/// Builds one sheet name per row from `pattern`. With `unique` set, names
+/// that repeat (ignoring case) get a ` (2)`, ` (3)`, … suffix that still fits
+/// the length limit. Names in `reserved` are always avoided.
+pub fn sheet_names(
+    pattern: &str,
+    data: &DataTable,
+    unique: bool,
+    reserved: &[String],
+) -> Vec<String> {
+    let reserved = reserved
+        .iter()
+        .map(|name| name.to_lowercase())
+        .collect::<HashSet<_>>();
+    let mut used = HashSet::new();
+    data.rows
+        .iter()
+        .enumerate()
+        .map(|(index, row)| {
+            let base = sanitize_sheet_name(&render_name_pattern(
+                pattern,
+                &data.headers,
+                row,
+                data.row_number(index),
+            ));
+            let mut name = base.clone();
+            let mut counter = 1;
+            while reserved.contains(&name.to_lowercase())
+                || (unique && !used.insert(name.to_lowercase()))
+            {
+                counter += 1;
+                let suffix = format!(" ({})", counter);
+                let stem = truncate_chars(&base, SHEET_NAME_LIMIT - suffix.chars().count());
+                name = format!("{}{}", trim_sheet_name(stem), suffix);
+            }
+            name
+        })
+        .collect()
+}
+
+/// Removes whitespace and the apostrophes a sheet name cannot start or end
+/// with.
+fn trim_sheet_name(name: &str) -> &str {
+    name.trim_matches(|ch: char| ch == '\'' || ch.is_whitespace())
+}
+
+fn truncate_chars(text: &str, limit: usize) -> &str {
+    match text.char_indices().nth(limit) {
+        Some((end, _)) => &text[..end],
+        None => text,
+    }
+}
+
+/// Destination for generated files: a directory or a `.zip` archive.
+pub enum OutputSink {
+    Directory(PathBuf),
+    Archive(ZipWriter<File>),
+}
+
+impl OutputSink {
+    pub fn directory(path: &Path) -> Result<Self, String> {
+        fs::create_dir_all(path).map_err(|err| err.to_string())?;
+        Ok(OutputSink::Directory(path.to_path_buf()))
+    }
+
+    pub fn archive(path: &Path) -> Result<Self, String> {
+        let file = File::create(path).map_err(|err| err.to_string())?;
+        Ok(OutputSink::Archive(ZipWriter::new(file)))
+    }
+
+    pub fn add(&mut self, name: &str, data: &[u8]) -> Result<(), String> {
+        match self {
+            OutputSink::Directory(directory) => {
+                fs::write(directory.join(name), data).map_err(|err| err.to_string())
+            }
+            OutputSink::Archive(zip) => {
+                // Office files are already compressed.
+                let options = FileOptions::default().compression_method(CompressionMethod::Stored);
+                zip.start_file(name, options)
+                    .map_err(|err| err.to_string())?;
+                zip.write_all(data).map_err(|err| err.to_string())
+            }
+        }
+    }
+
+    pub fn finish(self) -> Result<(), String> {
+        match self {
+            OutputSink::Directory(_) => Ok(()),
+            OutputSink::Archive(mut zip) => zip.finish().map_err(|err| err.to_string()).map(|_| ()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(values: &[&str]) -> DataTable {
+        DataTable {
+            headers: vec!["Name".to_string()],
+            rows: values.iter().map(|value| vec![value.to_string()]).collect(),
+            row_numbers: Vec::new(),
+            typed: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn sanitizes_file_names() {
+        assert_eq!(sanitize_file_name(" a/b:c*?. "), "a_b_c__");
+        assert_eq!(sanitize_file_name("..."), "output");
+        assert_eq!(sanitize_file_name("con"), "_con");
+        assert_eq!(sanitize_file_name("LPT1.report"), "_LPT1.report");
+        assert_eq!(sanitize_file_name("console"), "console");
+
+        let long = "ä".repeat(150);
+        let sanitized = sanitize_file_name(&long);
+        assert_eq!(sanitized, "ä".repeat(100));
+        assert_eq!(
+            sanitize_file_name(&format!("{}x", "a".repeat(199))).len(),
+            200
+        );
+    }
+
+    #[test]
+    fn keeps_long_file_names_unique() {
+        let long = "x".repeat(300);
+        let names = output_file_names("{{Name}}", "xlsx", &table(&[&long, &long, "A", "a"]));
+        assert_eq!(names[0], format!("{}.xlsx", "x".repeat(200)));
+        assert_eq!(names[1], format!("{} (2).xlsx", "x".repeat(200)));
+        assert!(names.iter().all(|name| name.len() < 255));
+        assert_eq!(names[2..], ["A.xlsx", "a (2).xlsx"]);
+    }
+
+    #[test]
+    fn sanitizes_sheet_names() {
+        assert_eq!(sanitize_sheet_name("'Q1/Q2: [draft]?'"), "Q1_Q2_ _draft__");
+        assert_eq!(sanitize_sheet_name("  ' "), "Sheet");
+        assert_eq!(
+            sanitize_sheet_name("abcdefghijklmnopqrstuvwxyz1234'x"),
+            "abcdefghijklmnopqrstuvwxyz1234"
+        );
+        assert_eq!(sanitize_sheet_name("' 'x' '"), "x");
+        assert_eq!(sanitize_sheet_name("history"), "history_");
+        assert_eq!(sanitize_sheet_name("History 2"), "History 2");
+        assert_eq!(sanitize_sheet_name(&"é".repeat(40)), "é".repeat(31));
+        assert_eq!(
+            sanitize_sheet_name("Quarterly results for the year 2024"),
+            "Quarterly results for the year"
+        );
+    }
+
+    #[test]
+    fn builds_unique_sheet_names() {
+        let long = "Quarterly results for the year 2024";
+        let data = table(&[long, long, "Summary", "summary", "Other"]);
+        let reserved = ["SUMMARY".to_string()];
+
+        let names = sheet_names("{{Name}}", &data, true, &reserved);
+        assert_eq!(
+            names,
+            [
+                "Quarterly results for the year",
+                "Quarterly results for the y (2)",
+                "Summary (2)",
+                "summary (3)",
+                "Other",
+            ]
+        );
+        assert!(names.iter().all(|name| name.chars().count() <= 31));
+
+        let names = sheet_names("{{Name}}", &data, false, &reserved);
+        assert_eq!(names[0], names[1]);
+        assert_eq!(names[2..4], ["Summary (2)", "summary (2)"]);
+
+        let quoted = "abcdefghijklmnopqrstuvwxyz'1234";
+        let names = sheet_names("{{Name}}", &table(&[quoted, quoted]), true, &[]);
+        assert_eq!(names, [quoted, "abcdefghijklmnopqrstuvwxyz (2)"]);
+        assert!(names.iter().all(|name| !name.ends_with('\'')));
+    }
+}
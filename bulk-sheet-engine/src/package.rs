@@ -0,0 +1,70 @@
+use std::fs::File;
+use std::io::{Cursor, Read, Write};
+use std::path::Path;
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+pub fn read_package(path: &Path) -> Result<Vec<(String, Vec<u8>)>, String> {
+    let file = File::open(path).map_err(|err| err.to_string())?;
+    let mut archive = ZipArchive::new(file).map_err(|err| err.to_string())?;
+    let mut entries = Vec::new();
+    for index in 0..archive.len() {
+        let mut file = archive.by_index(index).map_err(|err| err.to_string())?;
+        if !file.is_file() {
+            continue;
+        }
+        let mut data = Vec::new();
+        file.read_to_end(&mut data).map_err(|err| err.to_string())?;
+        entries.push((file.name().to_string(), data));
+    }
+    Ok(entries)
+}
+
+/// Packs the entries in their original order, substituting the replaced
+/// parts. Replaced parts that are not in the package are appended.
+pub fn build_package<S: AsRef<str>>(
+    entries: &[(String, Vec<u8>)],
+    replaced: &[(S, Vec<u8>)],
+) -> Result<Vec<u8>, String> {
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+
+    // The mimetype entry has to come first and stay uncompressed so that
+    // office suites can sniff the package type.
+    if let Some((_, data)) = entries.iter().find(|(name, _)| name == "mimetype") {
+        zip.start_file(
+            "mimetype",
+            FileOptions::default().compression_method(CompressionMethod::Stored),
+        )
+        .map_err(|err| err.to_string())?;
+        zip.write_all(data).map_err(|err| err.to_string())?;
+    }
+
+    for (name, data) in entries {
+        if name == "mimetype" {
+            continue;
+        }
+        let data = replaced
+            .iter()
+            .find(|(part, _)| part.as_ref() == name)
+            .map(|(_, data)| data)
+            .unwrap_or(data);
+        zip.start_file(name, options)
+            .map_err(|err| err.to_string())?;
+        zip.write_all(data).map_err(|err| err.to_string())?;
+    }
+
+    for (name, data) in replaced {
+        let name = name.as_ref();
+        if entries.iter().any(|(entry, _)| entry == name) {
+            continue;
+        }
+        zip.start_file(name, options)
+            .map_err(|err| err.to_string())?;
+        zip.write_all(data).map_err(|err| err.to_string())?;
+    }
+
+    zip.finish()
+        .map_err(|err| err.to_string())
+        .map(Cursor::into_inner)
+}
@@ -0,0 +1,316 @@
+use crate::xml_utils::xml_escape;
+use quick_xml::events::{BytesStart, BytesText, Event};
+use quick_xml::{Reader as XmlReader, Writer as XmlWriter};
+use std::collections::HashMap;
+use std::ops::Range;
+
+/// Describes where a document format keeps its running text, so that
+/// `{{name}}` tokens split over several runs can be matched per paragraph.
+pub struct PlaceholderMarkup {
+    pub paragraph_tags: &'static [&'static [u8]],
+    /// Element that holds the text of a run. `None` treats every text node
+    /// inside a paragraph as content.
+    pub text_tag: Option<&'static [u8]>,
+    /// Markup inserted for line breaks in replacement values.
+    pub line_break: Option<&'static str>,
+}
+
+pub const ODF_TEXT_MARKUP: PlaceholderMarkup = PlaceholderMarkup {
+    paragraph_tags: &[b"text:p", b"text:h"],
+    text_tag: None,
+    line_break: Some("<text:line-break/>"),
+};
+
+/// Returns the byte range and trimmed name of every `{{name}}` token.
+pub fn find_placeholders(text: &str) -> Vec<(Range<usize>, String)> {
+    let mut found = Vec::new();
+    let mut offset = 0;
+    while let Some(start) = text[offset..].find("{{") {
+        let start = offset + start;
+        let Some(length) = text[start + 2..].find("}}") else {
+            break;
+        };
+        let end = start + 2 + length + 2;
+        let name = text[start + 2..end - 2].trim();
+        if name.is_empty() || name.contains("{{") {
+            offset = start + 2;
+            continue;
+        }
+        found.push((start..end, name.to_string()));
+        offset = end;
+    }
+    found
+}
+
+/// Appends the names of all placeholders in `xml` that are not yet listed.
+pub fn collect_placeholders(
+    xml: &[u8],
+    markup: &PlaceholderMarkup,
+    names: &mut Vec<String>,
+) -> Result<(), String> {
+    rewrite_paragraphs(xml, markup, None, names).map(|_| ())
+}
+
+/// Replaces every placeholder whose name has a value; unknown placeholders are
+/// kept as they are.
+pub fn replace_placeholders(
+    xml: &[u8],
+    markup: &PlaceholderMarkup,
+    values: &HashMap<String, String>,
+) -> Result<Vec<u8>, String> {
+    rewrite_paragraphs(xml, markup, Some(values), &mut Vec::new())
+}
+
+struct BufferedEvent {
+    event: Event<'static>,
+    /// Index of the buffered start tag of the enclosing text element.
+    text_owner: Option<usize>,
+    is_text: bool,
+}
+
+fn rewrite_paragraphs(
+    xml: &[u8],
+    markup: &PlaceholderMarkup,
+    values: Option<&HashMap<String, String>>,
+    names: &mut Vec<String>,
+) -> Result<Vec<u8>, String> {
+    let mut reader = XmlReader::from_reader(xml);
+    reader.trim_text(false);
+    let mut writer = XmlWriter::new(Vec::new());
+    let mut buffer = Vec::new();
+    let mut paragraphs: Vec<Vec<BufferedEvent>> = Vec::new();
+    let mut text_owner: Option<usize> = None;
+
+    loop {
+        let event = reader
+            .read_event_into(&mut buffer)
+            .map_err(|err| err.to_string())?
+            .into_owned();
+        buffer.clear();
+
+        let is_paragraph = |name: &[u8]| markup.paragraph_tags.contains(&name);
+        match &event {
+            Event::Eof => break,
+            Event::Start(start) if is_paragraph(start.name().as_ref()) => {
+                paragraphs.push(Vec::new());
+            }
+            Event::Start(start)
+                if markup.text_tag == Some(start.name().as_ref()) && !paragraphs.is_empty() =>
+            {
+                text_owner = paragraphs.last().map(|paragraph| paragraph.len());
+            }
+            Event::End(end) if markup.text_tag == Some(end.name().as_ref()) => {
+                text_owner = None;
+            }
+            _ => {}
+        }
+
+        let Some(paragraph) = paragraphs.last_mut() else {
+            writer.write_event(event).map_err(|err| err.to_string())?;
+            continue;
+        };
+
+        let closes_paragraph =
+            matches!(&event, Event::End(end) if is_paragraph(end.name().as_ref()));
+        let is_text =
+            matches!(event, Event::Text(_)) && (markup.text_tag.is_none() || text_owner.is_some());
+        paragraph.push(BufferedEvent {
+            event,
+            text_owner,
+            is_text,
+        });
+
+        if closes_paragraph {
+            let finished = paragraphs.pop().unwrap_or_default();
+            let events = rewrite_paragraph(finished, markup, values, names)?;
+            match paragraphs.last_mut() {
+                Some(parent) => parent.extend(events.into_iter().map(|event| BufferedEvent {
+                    event,
+                    text_owner: None,
+                    is_text: false,
+                })),
+                None => {
+                    for event in events {
+                        writer.write_event(event).map_err(|err| err.to_string())?;
+                    }
+                }
+            }
+        }
+    }
+
+    for paragraph in paragraphs {
+        for buffered in paragraph {
+            writer
+                .write_event(buffered.event)
+                .map_err(|err| err.to_string())?;
+        }
+    }
+
+    Ok(writer.into_inner())
+}
+
+fn rewrite_paragraph(
+    mut paragraph: Vec<BufferedEvent>,
+    markup: &PlaceholderMarkup,
+    values: Option<&HashMap<String, String>>,
+    names: &mut Vec<String>,
+) -> Result<Vec<Event<'static>>, String> {
+    let mut text = String::new();
+    let mut nodes = Vec::new();
+    for (index, buffered) in paragraph.iter().enumerate() {
+        if let (true, Event::Text(content)) = (buffered.is_text, &buffered.event) {
+            let content = content.unescape().map_err(|err| err.to_string())?;
+            let start = text.len();
+            text.push_str(&content);
+            nodes.push((index, start..text.len()));
+        }
+    }
+
+    let placeholders = find_placeholders(&text);
+    for (_, name) in &placeholders {
+        if !names.contains(name) {
+            names.push(name.clone());
+        }
+    }
+
+    let replacements = match values {
+        Some(values) => placeholders
+            .into_iter()
+            .filter_map(|(range, name)| values.get(&name).map(|value| (range, value)))
+            .collect::<Vec<_>>(),
+        None => Vec::new(),
+    };
+    if replacements.is_empty() {
+        return Ok(paragraph
+            .into_iter()
+            .map(|buffered| buffered.event)
+            .collect());
+    }
+
+    for (index, node) in nodes {
+        let mut updated = String::new();
+        let mut position = node.start;
+        for (range, value) in &replacements {
+            if range.end <= node.start || range.start >= node.end {
+                continue;
+            }
+            if range.start >= position {
+                updated.push_str(&xml_escape(&text[position..range.start]));
+                updated.push_str(&escape_value(value, markup));
+            }
+            position = position.max(range.end.min(node.end));
+        }
+        if position == node.start {
+            continue;
+        }
+        updated.push_str(&xml_escape(&text[position..node.end]));
+
+        paragraph[index].event = Event::Text(BytesText::from_escaped(updated));
+        if let Some(owner) = paragraph[index].text_owner {
+            preserve_space(&mut paragraph[owner].event);
+        }
+    }
+
+    Ok(paragraph
+        .into_iter()
+        .map(|buffered| buffered.event)
+        .collect())
+}
+
+fn escape_value(value: &str, markup: &PlaceholderMarkup) -> String {
+    match markup.line_break {
+        Some(line_break) => value
+            .split('\n')
+            .map(xml_escape)
+            .collect::<Vec<_>>()
+            .join(line_break),
+        None => xml_escape(value),
+    }
+}
+
+/// Marks a text element as whitespace-preserving, since replacement values may
+/// start or end with spaces.
+fn preserve_space(event: &mut Event<'static>) {
+    let Event::Start(start) = event else {
+        return;
+    };
+    let has_space = start
+        .attributes()
+        .with_checks(false)
+        .flatten()
+        .any(|attr| attr.key.as_ref() == b"xml:space");
+    if has_space {
+        return;
+    }
+    let mut updated: BytesStart<'static> = start.clone();
+    updated.push_attribute(("xml:space", "preserve"));
+    *event = Event::Start(updated);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Rich text runs as in spreadsheet inline strings.
+    const RUN_MARKUP: PlaceholderMarkup = PlaceholderMarkup {
+        paragraph_tags: &[b"is"],
+        text_tag: Some(b"t"),
+        line_break: None,
+    };
+
+    fn replaced(xml: &str, markup: &PlaceholderMarkup, values: &[(&str, &str)]) -> String {
+        let values = values
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        String::from_utf8(replace_placeholders(xml.as_bytes(), markup, &values).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn finds_placeholder_names() {
+        let text = "{{ a }} {{}} {{b {{c}} }}x{{d";
+        assert_eq!(
+            find_placeholders(text),
+            [(0..7, "a".to_string()), (17..22, "c".to_string())]
+        );
+    }
+
+    #[test]
+    fn replaces_placeholders_split_across_runs() {
+        let xml = concat!(
+            r#"<is><r><rPr><b/></rPr><t>Hi {{Na</t></r><r><t>me}}</t></r>"#,
+            r#"<r><t xml:space="preserve"> and {{Other}}</t></r></is>"#,
+        );
+        assert_eq!(
+            replaced(xml, &RUN_MARKUP, &[("Name", "A & B")]),
+            concat!(
+                r#"<is><r><rPr><b/></rPr><t xml:space="preserve">Hi A &amp; B</t></r><r><t xml:space="preserve"></t></r>"#,
+                r#"<r><t xml:space="preserve"> and {{Other}}</t></r></is>"#,
+            )
+        );
+    }
+
+    #[test]
+    fn rewrites_nested_paragraphs_on_their_own() {
+        let xml = concat!(
+            r#"<office:text>{{A}}<text:p>{{A}}<draw:frame><draw:text-box><text:p>{{B}}</text:p></draw:text-box></draw:frame>"#,
+            r#" {{A}}</text:p><text:p>{{</text:p><text:p>B}}</text:p></office:text>"#,
+        );
+        assert_eq!(
+            replaced(xml, &ODF_TEXT_MARKUP, &[("A", "x\ny"), ("B", "<b>")]),
+            concat!(
+                r#"<office:text>{{A}}<text:p>x<text:line-break/>y<draw:frame><draw:text-box><text:p>&lt;b&gt;</text:p></draw:text-box></draw:frame>"#,
+                r#" x<text:line-break/>y</text:p><text:p>{{</text:p><text:p>B}}</text:p></office:text>"#,
+            )
+        );
+        let mut names = Vec::new();
+        collect_placeholders(xml.as_bytes(), &ODF_TEXT_MARKUP, &mut names).unwrap();
+        assert_eq!(names, ["B", "A"]);
+
+        // A paragraph left open at the end is written as it is.
+        assert_eq!(
+            replaced("<is><t>{{A}}", &RUN_MARKUP, &[("A", "x")]),
+            "<is><t>{{A}}"
+        );
+    }
+}
@@ -0,0 +1,251 @@
+use crate::cells::{cell_label, parse_cell_reference};
+use crate::formulas::{Reference, map_odf_range, map_odf_references, map_references};
+use std::fmt;
+
+/// Template rows such as `A10:F10` that are repeated once per detail row of
+/// a sheet. The rows below move down to make room, and formulas, merged
+/// cells and print areas follow them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RowBlock {
+    pub first_row: u32,
+    pub last_row: u32,
+    pub first_col: u32,
+    /// `u32::MAX` for whole rows.
+    pub last_col: u32,
+}
+
+impl RowBlock {
+    /// Parses a cell range such as `A10:F12`, a single row of cells such as
+    /// `A10` or whole rows such as `10:12`.
+    pub fn parse(text: &str) -> Option<Self> {
+        let text = text.trim();
+        let (first, last) = text.split_once(':').unwrap_or((text, text));
+        let (first, last) = (first.trim(), last.trim());
+        if let (Ok(first), Ok(last)) = (first.parse::<u32>(), last.parse::<u32>()) {
+            return (first > 0 && last > 0).then(|| Self {
+                first_row: first.min(last) - 1,
+                last_row: first.max(last) - 1,
+                first_col: 0,
+                last_col: u32::MAX,
+            });
+        }
+        let first = parse_cell_reference(&first.to_ascii_uppercase())?;
+        let last = parse_cell_reference(&last.to_ascii_uppercase())?;
+        Some(Self {
+            first_row: first.0.min(last.0),
+            last_row: first.0.max(last.0),
+            first_col: first.1.min(last.1),
+            last_col: first.1.max(last.1),
+        })
+    }
+
+    pub fn height(&self) -> u32 {
+        self.last_row - self.first_row + 1
+    }
+
+    pub fn contains_row(&self, row: u32) -> bool {
+        (self.first_row..=self.last_row).contains(&row)
+    }
+
+    pub fn contains_col(&self, col: u32) -> bool {
+        (self.first_col..=self.last_col).contains(&col)
+    }
+}
+
+impl fmt::Display for RowBlock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.last_col == u32::MAX {
+            write!(f, "{}:{}", self.first_row + 1, self.last_row + 1)
+        } else {
+            write!(
+                f,
+                "{}:{}",
+                cell_label(self.first_row, self.first_col),
+                cell_label(self.last_row, self.last_col)
+            )
+        }
+    }
+}
+
+/// The row block of one generated sheet, repeated `count` times.
+#[derive(Clone, Copy)]
+pub struct RowExpansion {
+    pub block: RowBlock,
+    pub count: u32,
+}
+
+impl RowExpansion {
+    /// Number of rows inserted below the block.
+    pub fn added_rows(&self) -> u32 {
+        self.count.saturating_sub(1) * self.block.height()
+    }
+
+    /// Where a template row ends up; rows below the block move down.
+    pub fn row(&self, row: u32) -> u32 {
+        if row > self.block.last_row {
+            row + self.added_rows()
+        } else {
+            row
+        }
+    }
+
+    /// Where a range of template rows ends up. Ranges that end inside the
+    /// block grow to cover its copies, so that `SUM(F10:F10)` sums every
+    /// detail row.
+    pub fn rows(&self, first: u32, last: u32) -> (u32, u32) {
+        let (first, last) = (first.min(last), first.max(last));
+        if self.block.contains_row(last) {
+            (first, last + self.added_rows())
+        } else {
+            (self.row(first), self.row(last))
+        }
+    }
+
+    /// The references of a formula after the rows were inserted. `copy` is
+    /// `None` outside the block and the copy number for formulas in the
+    /// block, whose relative references to block rows move with their copy
+    /// like filled down cells.
+    pub fn shift_formula(&self, formula: &str, sheet: &str, copy: Option<u32>) -> String {
+        map_references(formula, sheet, &mut |first, second| {
+            self.shift_references(first, second, copy)
+        })
+    }
+
+    /// Like [`RowExpansion::shift_formula`] for OpenDocument formulas.
+    pub fn shift_odf_formula(&self, formula: &str, sheet: &str, copy: Option<u32>) -> String {
+        map_odf_references(formula, sheet, &mut |first, second| {
+            self.shift_references(first, second, copy)
+        })
+    }
+
+    /// Shifts OpenDocument range addresses such as print ranges.
+    pub fn shift_odf_ranges(&self, ranges: &str, sheet: &str) -> String {
+        map_odf_range(ranges, sheet, &mut |first, second| {
+            self.shift_references(first, second, None)
+        })
+    }
+
+    /// Shifts a list of ranges such as `A1:B2 D5` of an XLSX `sqref` or
+    /// `ref` attribute.
+    pub fn shift_ranges(&self, ranges: &str) -> String {
+        ranges
+            .split(' ')
+            .map(|range| self.shift_formula(range, "", None))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    fn shift_references(
+        &self,
+        mut first: Reference,
+        second: Option<Reference>,
+        copy: Option<u32>,
+    ) -> (Reference, Option<Reference>) {
+        let offset = copy.unwrap_or(0) * self.block.height();
+        // Relative references to rows of the block move with the copy; other
+        // references keep pointing at the same cells.
+        let copied = |row: u32, absolute: bool| match self.block.contains_row(row) && !absolute {
+            true => row + offset,
+            false => self.row(row),
+        };
+        match second {
+            Some(mut second) => {
+                if let (Some(start), Some(end)) = (first.row, second.row) {
+                    // Within the block, ranges keep their size.
+                    let (start, end) = if copy.is_some() {
+                        (
+                            copied(start, first.absolute_row),
+                            copied(end, second.absolute_row),
+                        )
+                    } else if start <= end {
+                        self.rows(start, end)
+                    } else {
+                        let (end, start) = self.rows(end, start);
+                        (start, end)
+                    };
+                    first.row = Some(start);
+                    second.row = Some(end);
+                }
+                (first, Some(second))
+            }
+            None => {
+                first.row = first.row.map(|row| copied(row, first.absolute_row));
+                (first, None)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item_rows(count: u32) -> RowExpansion {
+        RowExpansion {
+            block: RowBlock::parse("A10:F10").unwrap(),
+            count,
+        }
+    }
+
+    #[test]
+    fn parses_row_blocks() {
+        let parsed = |text| RowBlock::parse(text).map(|block| block.to_string());
+        assert_eq!(parsed(" f12 : a10 ").as_deref(), Some("A10:F12"));
+        assert_eq!(parsed("12:10").as_deref(), Some("10:12"));
+        assert_eq!(parsed("10").as_deref(), Some("10:10"));
+        assert_eq!(parsed("B7").as_deref(), Some("B7:B7"));
+        assert_eq!(parsed("0:1"), None);
+        assert_eq!(parsed("A1:"), None);
+    }
+
+    #[test]
+    fn shifts_formulas_below_the_block() {
+        let rows = item_rows(3);
+        let shifted = |formula| rows.shift_formula(formula, "Invoice", None);
+        assert_eq!(shifted("SUM(F10:F10)"), "SUM(F10:F12)");
+        assert_eq!(
+            shifted("SUM(F5:F11)+SUM(F11:F5)"),
+            "SUM(F5:F13)+SUM(F13:F5)"
+        );
+        assert_eq!(shifted("$F$11*F$9"), "$F$13*F$9");
+        assert_eq!(shifted("Invoice!F11+Other!F11"), "Invoice!F13+Other!F11");
+        assert_eq!(shifted("SUM(F:F)+SUM(5:11)"), "SUM(F:F)+SUM(5:13)");
+        assert_eq!(rows.shift_ranges("A1:B2 F11:G11 F10"), "A1:B2 F13:G13 F10");
+        assert_eq!(
+            item_rows(1).shift_formula("SUM(F10:F11)", "", None),
+            "SUM(F10:F11)"
+        );
+    }
+
+    #[test]
+    fn shifts_formulas_in_copies_of_the_block() {
+        let rows = item_rows(3);
+        let copied = |formula| rows.shift_formula(formula, "Invoice", Some(2));
+        assert_eq!(copied("D10*E10"), "D12*E12");
+        assert_eq!(copied("$B$5*D10+D$10"), "$B$5*D12+D$10");
+        assert_eq!(copied("SUM(D10:E10)"), "SUM(D12:E12)");
+        assert_eq!(copied("F11+F9"), "F13+F9");
+        assert_eq!(copied("SUM(F5:F10)"), "SUM(F5:F12)");
+    }
+
+    #[test]
+    fn shifts_odf_formulas_and_ranges() {
+        let rows = item_rows(3);
+        assert_eq!(
+            rows.shift_odf_formula("of:=SUM([.F10:.F10])+[.F11]+[Other.F11]", "Invoice", None),
+            "of:=SUM([.F10:.F12])+[.F13]+[Other.F11]"
+        );
+        assert_eq!(
+            rows.shift_odf_formula("of:=[.D10]*[.E11]", "Invoice", Some(1)),
+            "of:=[.D11]*[.E13]"
+        );
+        assert_eq!(
+            rows.shift_odf_ranges("Invoice.A1:Invoice.F20 Other.A1:Other.F20", "Invoice"),
+            "Invoice.A1:Invoice.F22 Other.A1:Other.F20"
+        );
+        assert_eq!(
+            rows.shift_odf_ranges("$Invoice.$A$1:.$F$20", "Invoice"),
+            "$Invoice.$A$1:.$F$22"
+        );
+    }
+}
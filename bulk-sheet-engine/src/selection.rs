@@ -0,0 +1,171 @@
+use crate::data::DataTable;
+use crate::expressions::Expression;
+use serde::{Deserialize, Serialize};
+
+/// Chooses the data rows to generate. Rows must match both parts; empty
+/// parts select every row.
+#[derive(Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RowFilter {
+    /// One-based row numbers and ranges such as `1-20,35` or `40-`.
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub rows: String,
+    /// Condition such as `Status == "active"` or `Amount > 0`, see
+    /// [`Expression::condition`].
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub condition: String,
+}
+
+impl RowFilter {
+    pub fn is_empty(&self) -> bool {
+        self.rows.trim().is_empty() && self.condition.trim().is_empty()
+    }
+
+    /// Indices of the rows of `data` that pass the filter.
+    pub fn indices(&self, data: &DataTable) -> Result<Vec<usize>, String> {
+        let ranges = parse_row_ranges(&self.rows)?;
+        let condition = match self.condition.trim() {
+            "" => None,
+            text => Some(
+                Expression::condition(text, &data.headers)
+                    .map_err(|err| format!("Row condition: {}", err))?,
+            ),
+        };
+
+        let mut indices = Vec::new();
+        for (index, row) in data.rows.iter().enumerate() {
+            let row_number = data.row_number(index);
+            if !ranges.is_empty()
+                && !ranges
+                    .iter()
+                    .any(|(first, last)| (*first..=*last).contains(&row_number))
+            {
+                continue;
+            }
+            if let Some(condition) = &condition
+                && !condition
+                    .is_true(row, row_number)
+                    .map_err(|err| format!("Row {}, row condition: {}", row_number, err))?
+            {
+                continue;
+            }
+            indices.push(index);
+        }
+        Ok(indices)
+    }
+
+    /// The rows of `data` that pass the filter. Fails when a filter is set
+    /// but no row passes it.
+    pub fn apply(&self, data: &DataTable) -> Result<DataTable, String> {
+        if self.is_empty() {
+            return Ok(data.clone());
+        }
+        let indices = self.indices(data)?;
+        if indices.is_empty() && !data.rows.is_empty() {
+            return Err("No data rows match the row selection.".to_string());
+        }
+        Ok(data.select(&indices))
+    }
+}
+
+/// Parses `1-20, 35, 40-` into inclusive `(first, last)` row numbers; an
+/// open end reaches to the last row.
+pub fn parse_row_ranges(text: &str) -> Result<Vec<(usize, usize)>, String> {
+    let mut ranges = Vec::new();
+    for part in text.split([',', ';']) {
+        let part = part.trim();
+        if part.is_empty() {
+            continue;
+        }
+        let invalid = || format!("\"{}\" is not a row number or range such as 1-20", part);
+        let number = |text: &str| {
+            text.trim()
+                .parse::<usize>()
+                .ok()
+                .filter(|number| *number > 0)
+                .ok_or_else(invalid)
+        };
+        let range = match part.split_once('-') {
+            Some((first, "")) => (number(first)?, usize::MAX),
+            Some((first, last)) => (number(first)?, number(last)?),
+            None => (number(part)?, number(part)?),
+        };
+        if range.0 > range.1 {
+            return Err(invalid());
+        }
+        ranges.push(range);
+    }
+    Ok(ranges)
+}
+
+/// Writes sorted row numbers as compact ranges such as `1-3,5`.
+pub fn format_row_ranges(numbers: &[usize]) -> String {
+    let mut parts = Vec::new();
+    let mut index = 0;
+    while index < numbers.len() {
+        let first = numbers[index];
+        let mut last = first;
+        while numbers.get(index + 1) == Some(&(last + 1)) {
+            last += 1;
+            index += 1;
+        }
+        parts.push(if first == last {
+            first.to_string()
+        } else {
+            format!("{}-{}", first, last)
+        });
+        index += 1;
+    }
+    parts.join(",")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_row_ranges() {
+        assert_eq!(parse_row_ranges(""), Ok(Vec::new()));
+        assert_eq!(parse_row_ranges(" , ;"), Ok(Vec::new()));
+        assert_eq!(
+            parse_row_ranges("1-20, 35; 40-,"),
+            Ok(vec![(1, 20), (35, 35), (40, usize::MAX)])
+        );
+        for invalid in [
+            "0",
+            "5-3",
+            "a",
+            "3-x",
+            "-4",
+            "1-2-3",
+            "99999999999999999999",
+        ] {
+            assert!(parse_row_ranges(invalid).is_err(), "{}", invalid);
+        }
+        assert_eq!(format_row_ranges(&[1, 2, 3, 5, 7, 8]), "1-3,5,7-8");
+    }
+
+    #[test]
+    fn selects_rows_in_range() {
+        let data = DataTable {
+            headers: vec!["Name".to_string()],
+            rows: ["a", "b", "c", "d"]
+                .map(|name| vec![name.to_string()])
+                .to_vec(),
+            row_numbers: Vec::new(),
+            typed: Vec::new(),
+        };
+        let filter = |rows: &str| RowFilter {
+            rows: rows.to_string(),
+            condition: String::new(),
+        };
+        assert_eq!(filter("1,3-").apply(&data).unwrap().rows.len(), 3);
+        assert_eq!(filter("3-10").indices(&data), Ok(vec![2, 3]));
+        assert_eq!(
+            filter("5-9").apply(&data).err().as_deref(),
+            Some("No data rows match the row selection.")
+        );
+        let selected = filter("2-3").apply(&data).unwrap();
+        assert_eq!(selected.row_number(0), 2);
+    }
+}
@@ -0,0 +1,187 @@
+use crate::cells::CellValue;
+use crate::data::DataTable;
+use rusqlite::types::ValueRef;
+use rusqlite::{Connection, OpenFlags};
+use serde::{Deserialize, Serialize};
+use std::io::Read;
+use std::path::Path;
+
+/// Header of every SQLite database file.
+const SQLITE_HEADER: &[u8; 16] = b"SQLite format 3\0";
+
+/// What is read from a SQLite database: the rows of a query, or of a table
+/// or view when no query is given.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SqliteOptions {
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub table: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub query: String,
+}
+
+impl SqliteOptions {
+    /// The SQL statement to run.
+    pub fn statement(&self) -> Result<String, String> {
+        match (self.query.trim(), self.table.trim()) {
+            ("", "") => Err("Choose a table or enter a query".to_string()),
+            ("", table) => Ok(format!("SELECT * FROM \"{}\"", table.replace('"', "\"\""))),
+            (query, _) => Ok(query.to_string()),
+        }
+    }
+}
+
+/// Whether a data file is a SQLite database, by its header.
+pub fn is_sqlite_data(path: &Path) -> bool {
+    let mut header = [0; 16];
+    std::fs::File::open(path)
+        .and_then(|mut file| file.read_exact(&mut header))
+        .is_ok_and(|_| &header == SQLITE_HEADER)
+}
+
+fn open_database(path: &Path) -> Result<Connection, String> {
+    Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .map_err(|err| err.to_string())
+}
+
+/// Names of the tables and views of a SQLite database.
+pub fn read_sqlite_tables(path: &Path) -> Result<Vec<String>, String> {
+    let connection = open_database(path)?;
+    let mut statement = connection
+        .prepare(
+            "SELECT name FROM sqlite_master WHERE type IN ('table', 'view') \
+             AND name NOT LIKE 'sqlite_%' ORDER BY name",
+        )
+        .map_err(|err| err.to_string())?;
+    statement
+        .query_map([], |row| row.get::<_, String>(0))
+        .and_then(|names| names.collect())
+        .map_err(|err| err.to_string())
+}
+
+/// Runs the query of `options` on a database opened read-only. The result
+/// columns become the headers; integers and reals are kept as numbers.
+pub fn load_sqlite(path: &Path, options: &SqliteOptions) -> Result<DataTable, String> {
+    let connection = open_database(path)?;
+    let mut statement = connection
+        .prepare(&options.statement()?)
+        .map_err(|err| err.to_string())?;
+    let headers = statement
+        .column_names()
+        .iter()
+        .enumerate()
+        .map(|(index, name)| match name.trim() {
+            "" => format!("Column {}", index + 1),
+            name => name.to_string(),
+        })
+        .collect::<Vec<_>>();
+
+    let mut rows = Vec::new();
+    let mut typed = Vec::new();
+    let mut result = statement.query([]).map_err(|err| err.to_string())?;
+    while let Some(row) = result.next().map_err(|err| err.to_string())? {
+        let (values, typed_values) = (0..headers.len())
+            .map(|index| {
+                row.get_ref(index)
+                    .map(sqlite_value)
+                    .map_err(|err| err.to_string())
+            })
+            .collect::<Result<Vec<_>, _>>()?
+            .into_iter()
+            .unzip();
+        rows.push(values);
+        typed.push(typed_values);
+    }
+
+    Ok(DataTable {
+        headers,
+        rows,
+        row_numbers: Vec::new(),
+        typed,
+    })
+}
+
+/// The text of a SQLite value and its typed value unless it is text.
+fn sqlite_value(value: ValueRef) -> (String, Option<CellValue>) {
+    match value {
+        ValueRef::Null => (String::new(), None),
+        ValueRef::Integer(value) => (value.to_string(), Some(CellValue::Number(value as f64))),
+        ValueRef::Real(value) => (value.to_string(), Some(CellValue::Number(value))),
+        ValueRef::Text(text) | ValueRef::Blob(text) => {
+            (String::from_utf8_lossy(text).into_owned(), None)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(table: &str, query: &str) -> SqliteOptions {
+        SqliteOptions {
+            table: table.to_string(),
+            query: query.to_string(),
+        }
+    }
+
+    #[test]
+    fn builds_the_statement() {
+        assert_eq!(
+            options(" my \"items\" ", "").statement().as_deref(),
+            Ok("SELECT * FROM \"my \"\"items\"\"\"")
+        );
+        assert_eq!(
+            options("items", " SELECT 1 ").statement().as_deref(),
+            Ok("SELECT 1")
+        );
+        assert!(options(" ", " ").statement().is_err());
+    }
+
+    #[test]
+    fn reads_tables_and_queries() {
+        let path = std::env::temp_dir().join(format!("sqlite-data-{}.db", std::process::id()));
+        let text_path = path.with_extension("txt");
+        // A database left over from a failed run would already hold the table.
+        let _ = std::fs::remove_file(&path);
+        let connection = Connection::open(&path).unwrap();
+        connection
+            .execute_batch(concat!(
+                "CREATE TABLE \"my \"\"items\"\"\" (name TEXT, qty INTEGER, price REAL, note, data BLOB);",
+                "INSERT INTO \"my \"\"items\"\"\" VALUES ('Pen', 3, 1.5, NULL, x'6869');",
+                "CREATE VIEW totals AS SELECT name, qty * price FROM \"my \"\"items\"\"\";",
+            ))
+            .unwrap();
+        drop(connection);
+        std::fs::write(&text_path, "SQLite format 2 and more text").unwrap();
+
+        assert!(is_sqlite_data(&path));
+        assert!(!is_sqlite_data(&text_path));
+        assert!(!is_sqlite_data(&path.with_extension("missing")));
+        assert_eq!(
+            read_sqlite_tables(&path).unwrap(),
+            ["my \"items\"", "totals"]
+        );
+
+        let table = load_sqlite(&path, &options("my \"items\"", "")).unwrap();
+        assert_eq!(table.headers, ["name", "qty", "price", "note", "data"]);
+        assert_eq!(table.rows, [["Pen", "3", "1.5", "", "hi"]]);
+        assert_eq!(
+            table.typed,
+            [[
+                None,
+                Some(CellValue::Number(3.0)),
+                Some(CellValue::Number(1.5)),
+                None,
+                None
+            ]]
+        );
+
+        let table = load_sqlite(&path, &options("", "SELECT *, 1 AS \" \" FROM totals")).unwrap();
+        assert_eq!(table.headers, ["name", "qty * price", "Column 3"]);
+        assert_eq!(table.rows, [["Pen", "4.5", "1"]]);
+        assert!(load_sqlite(&path, &options("missing", "")).is_err());
+
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&text_path).unwrap();
+    }
+}
@@ -0,0 +1,139 @@
+use crate::cards::CardLayout;
+use crate::cells::column_label_from_index;
+use crate::docx_template::read_docx_placeholders;
+use crate::ods_template::OdsTemplate;
+use crate::odt_template::read_odt_placeholders;
+use crate::row_blocks::RowBlock;
+use crate::xlsx_template::XlsxTemplate;
+use calamine::{Data, DataType, Reader, open_workbook_auto};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// A template file. `sheet` selects the template sheet of a workbook and
+/// defaults to the first one; text documents have no sheets.
+#[derive(Clone)]
+pub struct Template {
+    pub path: PathBuf,
+    pub sheet: Option<String>,
+    /// Rows of the template sheet written once per row of a group, such as
+    /// the item row of an invoice.
+    pub repeat_rows: Option<RowBlock>,
+    /// Several records per sheet, one per card, such as labels or badges.
+    pub cards: Option<CardLayout>,
+}
+
+impl Template {
+    pub fn format(&self) -> TemplateFormat {
+        TemplateFormat::from_path(&self.path)
+    }
+
+    /// The template sheet, checked against the sheets of the workbook.
+    pub fn sheet_name(&self) -> Result<String, String> {
+        let sheet_names = read_sheet_names(&self.path)?;
+        match &self.sheet {
+            Some(sheet) if sheet_names.contains(sheet) => Ok(sheet.clone()),
+            Some(sheet) => Err(format!(
+                "Sheet \"{}\" not found; the template has: {}",
+                sheet,
+                sheet_names.join(", ")
+            )),
+            None => sheet_names
+                .first()
+                .cloned()
+                .ok_or_else(|| "The template has no sheets".to_string()),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum TemplateFormat {
+    Xlsx,
+    Ods,
+    Odt,
+    Docx,
+}
+
+impl TemplateFormat {
+    pub fn from_path(path: &Path) -> Self {
+        let extension = path
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("ods") => TemplateFormat::Ods,
+            Some("odt") => TemplateFormat::Odt,
+            Some("docx") => TemplateFormat::Docx,
+            _ => TemplateFormat::Xlsx,
+        }
+    }
+
+    pub fn output_filter(&self) -> (&'static str, &'static str) {
+        match self {
+            TemplateFormat::Xlsx => ("Excel", "xlsx"),
+            TemplateFormat::Ods => ("OpenDocument Spreadsheet", "ods"),
+            TemplateFormat::Odt => ("OpenDocument Text", "odt"),
+            TemplateFormat::Docx => ("Word document", "docx"),
+        }
+    }
+
+    /// Document templates are filled through `{{placeholder}}` tokens instead
+    /// of cell mappings.
+    pub fn is_document(&self) -> bool {
+        matches!(self, TemplateFormat::Odt | TemplateFormat::Docx)
+    }
+}
+
+/// Lists the sheets of a workbook in their original order.
+pub fn read_sheet_names(path: &Path) -> Result<Vec<String>, String> {
+    let workbook = open_workbook_auto(path).map_err(|err| err.to_string())?;
+    Ok(workbook.sheet_names().to_vec())
+}
+
+/// Reads the `{{placeholder}}` names of a text document template.
+pub fn read_template_placeholders(path: &Path) -> Result<Vec<String>, String> {
+    match TemplateFormat::from_path(path) {
+        TemplateFormat::Docx => read_docx_placeholders(path),
+        _ => read_odt_placeholders(path),
+    }
+}
+
+/// Reads the `{{placeholder}}` names in the cell text of a workbook sheet,
+/// and in its headers and footers for XLSX.
+pub fn read_sheet_placeholders(path: &Path, sheet: &str) -> Result<Vec<String>, String> {
+    match TemplateFormat::from_path(path) {
+        TemplateFormat::Ods => Ok(OdsTemplate::load(path, sheet, &[])?.placeholders().to_vec()),
+        _ => Ok(XlsxTemplate::load(path, sheet, &[])?
+            .placeholders()
+            .to_vec()),
+    }
+}
+
+/// Reads the non-empty cells of a sheet, keyed by references such as `B4`.
+pub fn read_sheet_cells(path: &Path, sheet: &str) -> Result<HashMap<String, String>, String> {
+    let mut workbook = open_workbook_auto(path).map_err(|err| err.to_string())?;
+    let range = workbook
+        .worksheet_range(sheet)
+        .map_err(|err| err.to_string())?;
+
+    let mut values = HashMap::new();
+    for (row, col, value) in range.cells() {
+        if value.is_empty() {
+            continue;
+        }
+        let label = format!("{}{}", column_label_from_index(col as u32), row + 1);
+        values.insert(label, stringify_data(value));
+    }
+    Ok(values)
+}
+
+fn stringify_data(data: &Data) -> String {
+    match data {
+        Data::String(value) => value.clone(),
+        Data::Float(value) => format!("{}", value),
+        Data::Int(value) => value.to_string(),
+        Data::Bool(value) => value.to_string(),
+        Data::DateTimeIso(value) | Data::DurationIso(value) => value.clone(),
+        Data::DateTime(value) => format!("{:?}", value),
+        Data::Error(err) => format!("Error: {:?}", err),
+        Data::Empty => String::new(),
+    }
+}
@@ -0,0 +1,790 @@
+use crate::formulas::{rename_sheet_references, rename_table_references};
+use crate::row_blocks::RowExpansion;
+use crate::xml_utils::{attribute_value, with_attribute};
+use quick_xml::events::{BytesStart, BytesText, Event};
+use quick_xml::{Reader as XmlReader, Writer as XmlWriter};
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+/// Relationship types whose targets are shared by every copy of a sheet
+/// instead of being copied with it.
+const SHARED_PART_TYPES: [&str; 3] = ["/image", "/pivotCacheDefinition", "/externalLink"];
+
+/// Shapes of a VML drawing are numbered in blocks of this size, one block per
+/// drawing.
+const VML_SHAPE_BLOCK: i64 = 1024;
+
+/// A part that belongs to a worksheet, such as a table, drawing, chart or
+/// comments.
+struct DependentPart {
+    name: String,
+    /// Last segment of the relationship type, e.g. `table` or `chart`.
+    kind: String,
+    data: Vec<u8>,
+    relationships: Option<Vec<u8>>,
+    content_type: Option<String>,
+}
+
+/// A copied part to add to the package.
+pub struct CopiedPart {
+    pub name: String,
+    pub data: Vec<u8>,
+    pub content_type: Option<String>,
+}
+
+/// The parts copied for one generated sheet.
+pub struct SheetPartsCopy {
+    /// The sheet's relationships, pointing at the copies.
+    pub relationships: Option<Vec<u8>>,
+    pub parts: Vec<CopiedPart>,
+    /// Tables that had to be renamed, as `(old, new)`.
+    pub renamed_tables: Vec<(String, String)>,
+}
+
+/// The parts reachable from a worksheet's relationships. Every generated
+/// sheet gets its own copy so that no two sheets share a table, drawing or
+/// comment part.
+pub struct DependentParts {
+    sheet_entry: String,
+    parts: Vec<DependentPart>,
+}
+
+impl DependentParts {
+    pub fn collect(
+        entries: &BTreeMap<String, Vec<u8>>,
+        sheet_entry: &str,
+        content_types: &HashMap<String, String>,
+    ) -> Result<Self, String> {
+        let mut parts = Vec::new();
+        let mut visited = HashSet::new();
+        let mut owners = vec![sheet_entry.to_string()];
+
+        while let Some(owner) = owners.pop() {
+            let Some(relationships) = entries.get(&relationships_path(&owner)) else {
+                continue;
+            };
+            for relationship in read_relationships(relationships)? {
+                if relationship.external
+                    || SHARED_PART_TYPES
+                        .iter()
+                        .any(|kind| relationship.kind.ends_with(kind))
+                {
+                    continue;
+                }
+                let name = resolve_target(&owner, &relationship.target);
+                if name == sheet_entry || !visited.insert(name.clone()) {
+                    continue;
+                }
+                let Some(data) = entries.get(&name) else {
+                    continue;
+                };
+                parts.push(DependentPart {
+                    kind: relationship
+                        .kind
+                        .rsplit('/')
+                        .next()
+                        .unwrap_or_default()
+                        .to_string(),
+                    data: data.clone(),
+                    relationships: entries.get(&relationships_path(&name)).cloned(),
+                    content_type: content_types.get(&format!("/{}", name)).cloned(),
+                    name: name.clone(),
+                });
+                owners.push(name);
+            }
+        }
+
+        Ok(Self {
+            sheet_entry: sheet_entry.to_string(),
+            parts,
+        })
+    }
+
+    /// Package entries of the parts and of their relationships, which are
+    /// replaced by the copies.
+    pub fn entry_names(&self) -> HashSet<String> {
+        self.parts
+            .iter()
+            .flat_map(|part| [part.name.clone(), relationships_path(&part.name)])
+            .collect()
+    }
+
+    /// Copies the parts for a generated sheet named `sheet_name`, with unique
+    /// part names, table names and ids, and with chart ranges pointing at
+    /// the new sheet. With repeated rows, tables, drawings and comments below
+    /// the block move down with their cells.
+    pub fn copy_for_sheet(
+        &self,
+        names: &mut PartNames,
+        sheet_relationships: Option<&[u8]>,
+        template_name: &str,
+        sheet_name: &str,
+        rows: Option<&RowExpansion>,
+    ) -> Result<SheetPartsCopy, String> {
+        let renamed = self
+            .parts
+            .iter()
+            .map(|part| (part.name.clone(), names.allocate(&part.name)))
+            .collect::<HashMap<_, _>>();
+
+        let mut parts = Vec::with_capacity(self.parts.len() * 2);
+        let mut renamed_tables = Vec::new();
+        for part in &self.parts {
+            let data = match part.kind.as_str() {
+                "table" => copy_table(&part.data, names, &mut renamed_tables)?,
+                "chart" if template_name != sheet_name => {
+                    rename_chart_ranges(&part.data, template_name, sheet_name)?
+                }
+                "vmlDrawing" => renumber_vml_shapes(&part.data, names),
+                _ => part.data.clone(),
+            };
+            let data = match rows.filter(|rows| rows.added_rows() > 0) {
+                Some(rows) => match part.kind.as_str() {
+                    "table" | "comments" => shift_range_attributes(&data, rows)?,
+                    "drawing" => shift_drawing_anchors(&data, rows)?,
+                    "vmlDrawing" => shift_vml_anchors(&data, rows),
+                    _ => data,
+                },
+                None => data,
+            };
+            let name = renamed[&part.name].clone();
+            if let Some(relationships) = &part.relationships {
+                parts.push(CopiedPart {
+                    name: relationships_path(&name),
+                    data: rewrite_relationship_targets(relationships, &part.name, &renamed)?,
+                    content_type: None,
+                });
+            }
+            parts.push(CopiedPart {
+                name,
+                data,
+                content_type: part.content_type.clone(),
+            });
+        }
+
+        let relationships = sheet_relationships
+            .map(|xml| rewrite_relationship_targets(xml, &self.sheet_entry, &renamed))
+            .transpose()?;
+        Ok(SheetPartsCopy {
+            relationships,
+            parts,
+            renamed_tables,
+        })
+    }
+}
+
+/// Part names, table names and ids, and VML shape blocks in use in the
+/// output package.
+#[derive(Clone)]
+pub struct PartNames {
+    entries: HashSet<String>,
+    /// Lowercase, as table names are case-insensitive.
+    table_names: HashSet<String>,
+    table_ids: HashSet<u32>,
+    vml_blocks: HashSet<i64>,
+}
+
+impl PartNames {
+    /// The names used by `entries` apart from `excluded`.
+    pub fn new(entries: &BTreeMap<String, Vec<u8>>, excluded: &HashSet<String>) -> Self {
+        let mut names = Self {
+            entries: HashSet::new(),
+            table_names: HashSet::new(),
+            table_ids: HashSet::new(),
+            vml_blocks: HashSet::new(),
+        };
+        for (name, data) in entries {
+            if excluded.contains(name) {
+                continue;
+            }
+            names.entries.insert(name.clone());
+            if name.starts_with("xl/tables/")
+                && let Some(table) = root_start(data)
+            {
+                if let Some(id) = attribute_value(&table, b"id").and_then(|id| id.parse().ok()) {
+                    names.table_ids.insert(id);
+                }
+                if let Some(table_name) = attribute_value(&table, b"name") {
+                    names.table_names.insert(table_name.to_lowercase());
+                }
+            } else if name.ends_with(".vml")
+                && let Some(block) = vml_shape_block(data)
+            {
+                names.vml_blocks.insert(block);
+            }
+        }
+        names
+    }
+
+    /// Claims `name`, or the next free name in its folder with the same
+    /// extension, such as `xl/tables/table2.xml`.
+    fn allocate(&mut self, name: &str) -> String {
+        if self.entries.insert(name.to_string()) {
+            return name.to_string();
+        }
+        let (folder, file) = name.rsplit_once('/').unwrap_or(("", name));
+        let (stem, extension) = file.rsplit_once('.').unwrap_or((file, ""));
+        let base = stem.trim_end_matches(|ch: char| ch.is_ascii_digit());
+        let candidate = (1..)
+            .map(|number| {
+                let mut candidate = format!("{}{}", base, number);
+                if !extension.is_empty() {
+                    candidate = format!("{}.{}", candidate, extension);
+                }
+                if !folder.is_empty() {
+                    candidate = format!("{}/{}", folder, candidate);
+                }
+                candidate
+            })
+            .find(|candidate| !self.entries.contains(candidate))
+            .unwrap_or_default();
+        self.entries.insert(candidate.clone());
+        candidate
+    }
+
+    fn allocate_table_name(&mut self, name: &str) -> String {
+        if self.table_names.insert(name.to_lowercase()) {
+            return name.to_string();
+        }
+        let base = name.trim_end_matches(|ch: char| ch.is_ascii_digit());
+        let candidate = (1..)
+            .map(|number| format!("{}{}", base, number))
+            .find(|candidate| !self.table_names.contains(&candidate.to_lowercase()))
+            .unwrap_or_default();
+        self.table_names.insert(candidate.to_lowercase());
+        candidate
+    }
+
+    fn allocate_table_id(&mut self, id: u32) -> u32 {
+        if !self.table_ids.insert(id) {
+            let next = self.table_ids.iter().max().copied().unwrap_or_default() + 1;
+            self.table_ids.insert(next);
+            return next;
+        }
+        id
+    }
+
+    fn allocate_vml_block(&mut self, block: i64) -> i64 {
+        if !self.vml_blocks.insert(block) {
+            let next = self.vml_blocks.iter().max().copied().unwrap_or_default() + 1;
+            self.vml_blocks.insert(next);
+            return next;
+        }
+        block
+    }
+}
+
+struct PartRelationship {
+    kind: String,
+    target: String,
+    external: bool,
+}
+
+fn read_relationships(xml: &[u8]) -> Result<Vec<PartRelationship>, String> {
+    let mut reader = XmlReader::from_reader(xml);
+    reader.trim_text(true);
+    let mut buffer = Vec::new();
+    let mut relationships = Vec::new();
+
+    loop {
+        buffer.clear();
+        match reader
+            .read_event_into(&mut buffer)
+            .map_err(|err| err.to_string())?
+        {
+            Event::Eof => break,
+            Event::Empty(event) | Event::Start(event)
+                if event.name().as_ref() == b"Relationship" =>
+            {
+                relationships.push(PartRelationship {
+                    kind: attribute_value(&event, b"Type").unwrap_or_default(),
+                    target: attribute_value(&event, b"Target").unwrap_or_default(),
+                    external: attribute_value(&event, b"TargetMode").as_deref() == Some("External"),
+                });
+            }
+            _ => {}
+        }
+    }
+
+    Ok(relationships)
+}
+
+/// Points the relationships of `owner` at the renamed parts. Copies live in
+/// the folder of their original, so only the file name changes.
+fn rewrite_relationship_targets(
+    xml: &[u8],
+    owner: &str,
+    renamed: &HashMap<String, String>,
+) -> Result<Vec<u8>, String> {
+    let mut reader = XmlReader::from_reader(xml);
+    reader.trim_text(false);
+    let mut writer = XmlWriter::new(Vec::new());
+    let mut buffer = Vec::new();
+
+    loop {
+        buffer.clear();
+        let event = reader
+            .read_event_into(&mut buffer)
+            .map_err(|err| err.to_string())?;
+        let event = match event {
+            Event::Eof => break,
+            Event::Empty(event) if event.name().as_ref() == b"Relationship" => {
+                let target = attribute_value(&event, b"Target").unwrap_or_default();
+                let external =
+                    attribute_value(&event, b"TargetMode").as_deref() == Some("External");
+                match renamed.get(&resolve_target(owner, &target)) {
+                    Some(new_name) if !external => {
+                        let file_name = new_name.rsplit('/').next().unwrap_or(new_name);
+                        let new_target = match target.rsplit_once('/') {
+                            Some((folder, _)) => format!("{}/{}", folder, file_name),
+                            None => file_name.to_string(),
+                        };
+                        Event::Empty(with_attribute(&event, "Target", &new_target))
+                    }
+                    _ => Event::Empty(event.into_owned()),
+                }
+            }
+            event => event.into_owned(),
+        };
+        writer.write_event(event).map_err(|err| err.to_string())?;
+    }
+
+    Ok(writer.into_inner())
+}
+
+/// Gives a copied table a free id and name, updating the formulas of its
+/// calculated columns. A renamed table is added to `renamed_tables`.
+fn copy_table(
+    xml: &[u8],
+    names: &mut PartNames,
+    renamed_tables: &mut Vec<(String, String)>,
+) -> Result<Vec<u8>, String> {
+    let Some(root) = root_start(xml) else {
+        return Ok(xml.to_vec());
+    };
+    let old_name = attribute_value(&root, b"name").unwrap_or_default();
+    let new_name = names.allocate_table_name(&old_name);
+    let new_id = attribute_value(&root, b"id")
+        .and_then(|id| id.parse().ok())
+        .map(|id| names.allocate_table_id(id));
+    let renamed_table = (new_name != old_name).then(|| (old_name.clone(), new_name.clone()));
+
+    let mut reader = XmlReader::from_reader(xml);
+    reader.trim_text(false);
+    let mut writer = XmlWriter::new(Vec::new());
+    let mut buffer = Vec::new();
+
+    loop {
+        buffer.clear();
+        let event = reader
+            .read_event_into(&mut buffer)
+            .map_err(|err| err.to_string())?;
+        let event = match event {
+            Event::Eof => break,
+            Event::Start(start) if start.name().as_ref() == b"table" => {
+                Event::Start(table_start(&start, renamed_table.as_ref(), new_id))
+            }
+            Event::Empty(start) if start.name().as_ref() == b"table" => {
+                Event::Empty(table_start(&start, renamed_table.as_ref(), new_id))
+            }
+            Event::Text(text) if renamed_table.is_some() => {
+                let formula = text.unescape().map_err(|err| err.to_string())?;
+                let formula = rename_table_references(&formula, &old_name, &new_name);
+                Event::Text(BytesText::new(&formula).into_owned())
+            }
+            event => event.into_owned(),
+        };
+        writer.write_event(event).map_err(|err| err.to_string())?;
+    }
+
+    renamed_tables.extend(renamed_table);
+    Ok(writer.into_inner())
+}
+
+fn table_start(
+    start: &BytesStart,
+    renamed_table: Option<&(String, String)>,
+    id: Option<u32>,
+) -> BytesStart<'static> {
+    let mut updated = start.to_owned();
+    if let Some((_, new_name)) = renamed_table {
+        updated = with_attribute(&updated, "name", new_name);
+        updated = with_attribute(&updated, "displayName", new_name);
+    }
+    if let Some(id) = id {
+        updated = with_attribute(&updated, "id", &id.to_string());
+    }
+    updated
+}
+
+/// Moves the `ref` ranges of a table, its filter and sort state, or of
+/// comments, like the cells they cover.
+fn shift_range_attributes(xml: &[u8], rows: &RowExpansion) -> Result<Vec<u8>, String> {
+    let mut reader = XmlReader::from_reader(xml);
+    reader.trim_text(false);
+    let mut writer = XmlWriter::new(Vec::new());
+    let mut buffer = Vec::new();
+
+    loop {
+        buffer.clear();
+        let event = reader
+            .read_event_into(&mut buffer)
+            .map_err(|err| err.to_string())?;
+        let shifted = |start: &BytesStart| match attribute_value(start, b"ref") {
+            Some(ranges) => with_attribute(start, "ref", &rows.shift_ranges(&ranges)),
+            None => start.to_owned(),
+        };
+        let event = match event {
+            Event::Eof => break,
+            Event::Start(start) => Event::Start(shifted(&start)),
+            Event::Empty(start) => Event::Empty(shifted(&start)),
+            event => event.into_owned(),
+        };
+        writer.write_event(event).map_err(|err| err.to_string())?;
+    }
+
+    Ok(writer.into_inner())
+}
+
+/// Moves the zero-based `<xdr:row>` anchors of a drawing's pictures, shapes
+/// and charts.
+fn shift_drawing_anchors(xml: &[u8], rows: &RowExpansion) -> Result<Vec<u8>, String> {
+    let mut reader = XmlReader::from_reader(xml);
+    reader.trim_text(false);
+    let mut writer = XmlWriter::new(Vec::new());
+    let mut buffer = Vec::new();
+    let mut in_row = false;
+
+    loop {
+        buffer.clear();
+        let event = reader
+            .read_event_into(&mut buffer)
+            .map_err(|err| err.to_string())?;
+        let event = match event {
+            Event::Eof => break,
+            Event::Start(start) => {
+                in_row = start.local_name().as_ref() == b"row";
+                Event::Start(start.into_owned())
+            }
+            Event::End(end) => {
+                in_row = false;
+                Event::End(end.into_owned())
+            }
+            Event::Text(text) if in_row => {
+                let value = text.unescape().map_err(|err| err.to_string())?;
+                match value.trim().parse::<u32>() {
+                    Ok(row) => Event::Text(BytesText::new(&rows.row(row).to_string()).into_owned()),
+                    Err(_) => Event::Text(text.into_owned()),
+                }
+            }
+            event => event.into_owned(),
+        };
+        writer.write_event(event).map_err(|err| err.to_string())?;
+    }
+
+    Ok(writer.into_inner())
+}
+
+/// Moves the comment boxes of a VML drawing: the zero-based `<x:Row>` of
+/// their cell and the rows of their `<x:Anchor>`, the third and seventh of
+/// its eight numbers. VML is edited as text, as it need not be valid XML.
+fn shift_vml_anchors(xml: &[u8], rows: &RowExpansion) -> Vec<u8> {
+    let Ok(text) = std::str::from_utf8(xml) else {
+        return xml.to_vec();
+    };
+    let text = replace_element_text(text, "x:Row", |value| {
+        value
+            .trim()
+            .parse::<u32>()
+            .ok()
+            .map(|row| rows.row(row).to_string())
+    });
+    let text = replace_element_text(&text, "x:Anchor", |value| {
+        let mut numbers = value
+            .split(',')
+            .map(|number| number.trim().parse::<u32>().ok())
+            .collect::<Option<Vec<_>>>()?;
+        if numbers.len() != 8 {
+            return None;
+        }
+        for index in [2, 6] {
+            numbers[index] = rows.row(numbers[index]);
+        }
+        Some(
+            numbers
+                .iter()
+                .map(u32::to_string)
+                .collect::<Vec<_>>()
+                .join(", "),
+        )
+    });
+    text.into_bytes()
+}
+
+/// Replaces the text of every `<name>` element for which `replace` returns
+/// a value.
+fn replace_element_text(
+    text: &str,
+    name: &str,
+    mut replace: impl FnMut(&str) -> Option<String>,
+) -> String {
+    let (open, close) = (format!("<{}>", name), format!("</{}>", name));
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find(&open) {
+        let value_start = start + open.len();
+        let Some(length) = rest[value_start..].find(&close) else {
+            break;
+        };
+        let value = &rest[value_start..value_start + length];
+        result.push_str(&rest[..value_start]);
+        result.push_str(&replace(value).unwrap_or_else(|| value.to_string()));
+        rest = &rest[value_start + length..];
+    }
+    result.push_str(rest);
+    result
+}
+
+/// Points the series and category ranges of a chart at the generated sheet.
+fn rename_chart_ranges(xml: &[u8], old_name: &str, new_name: &str) -> Result<Vec<u8>, String> {
+    let mut reader = XmlReader::from_reader(xml);
+    reader.trim_text(false);
+    let mut writer = XmlWriter::new(Vec::new());
+    let mut buffer = Vec::new();
+    let mut in_formula = false;
+
+    loop {
+        buffer.clear();
+        let event = reader
+            .read_event_into(&mut buffer)
+            .map_err(|err| err.to_string())?;
+        let event = match event {
+            Event::Eof => break,
+            Event::Start(start) => {
+                in_formula = start.local_name().as_ref() == b"f";
+                Event::Start(start.into_owned())
+            }
+            Event::End(end) => {
+                in_formula = false;
+                Event::End(end.into_owned())
+            }
+            Event::Text(text) if in_formula => {
+                let formula = text.unescape().map_err(|err| err.to_string())?;
+                let formula = rename_sheet_references(&formula, old_name, new_name);
+                Event::Text(BytesText::new(&formula).into_owned())
+            }
+            event => event.into_owned(),
+        };
+        writer.write_event(event).map_err(|err| err.to_string())?;
+    }
+
+    Ok(writer.into_inner())
+}
+
+/// Moves the shapes of a copied VML drawing, such as comment boxes, to a free
+/// block of shape ids.
+fn renumber_vml_shapes(xml: &[u8], names: &mut PartNames) -> Vec<u8> {
+    let (Ok(text), Some(old_block)) = (std::str::from_utf8(xml), vml_shape_block(xml)) else {
+        return xml.to_vec();
+    };
+    let new_block = names.allocate_vml_block(old_block);
+    if new_block == old_block {
+        return xml.to_vec();
+    }
+
+    let text = text.replacen(
+        &format!("data=\"{}\"", old_block),
+        &format!("data=\"{}\"", new_block),
+        1,
+    );
+    const SHAPE_PREFIX: &str = "_x0000_s";
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text.as_str();
+    while let Some(index) = rest.find(SHAPE_PREFIX) {
+        let digits_start = index + SHAPE_PREFIX.len();
+        let digits = rest[digits_start..]
+            .chars()
+            .take_while(char::is_ascii_digit)
+            .count();
+        result.push_str(&rest[..digits_start]);
+        match rest[digits_start..digits_start + digits].parse::<i64>() {
+            Ok(id) => {
+                let id = id + (new_block - old_block) * VML_SHAPE_BLOCK;
+                result.push_str(&id.to_string());
+            }
+            Err(_) => result.push_str(&rest[digits_start..digits_start + digits]),
+        }
+        rest = &rest[digits_start + digits..];
+    }
+    result.push_str(rest);
+    result.into_bytes()
+}
+
+/// The first shape id block of a VML drawing, from `<o:idmap data="1"/>`.
+fn vml_shape_block(xml: &[u8]) -> Option<i64> {
+    let text = String::from_utf8_lossy(xml);
+    let idmap = &text[text.find("idmap")?..];
+    let data = &idmap[idmap.find("data=\"")? + 6..];
+    data.chars()
+        .take_while(char::is_ascii_digit)
+        .collect::<String>()
+        .parse()
+        .ok()
+}
+
+/// The root element of an XML part.
+fn root_start(xml: &[u8]) -> Option<BytesStart<'static>> {
+    let mut reader = XmlReader::from_reader(xml);
+    let mut buffer = Vec::new();
+    loop {
+        match reader.read_event_into(&mut buffer).ok()? {
+            Event::Start(start) | Event::Empty(start) => return Some(start.into_owned()),
+            Event::Eof => return None,
+            _ => buffer.clear(),
+        }
+    }
+}
+
+/// Collects the content types of `[Content_Types].xml` overrides by part
+/// name.
+pub fn content_type_overrides(xml: &str) -> Result<HashMap<String, String>, String> {
+    let mut reader = XmlReader::from_str(xml);
+    reader.trim_text(true);
+    let mut buffer = Vec::new();
+    let mut overrides = HashMap::new();
+
+    loop {
+        buffer.clear();
+        match reader
+            .read_event_into(&mut buffer)
+            .map_err(|err| err.to_string())?
+        {
+            Event::Eof => break,
+            Event::Empty(event) if event.name().as_ref() == b"Override" => {
+                if let (Some(part_name), Some(content_type)) = (
+                    attribute_value(&event, b"PartName"),
+                    attribute_value(&event, b"ContentType"),
+                ) {
+                    overrides.insert(part_name, content_type);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Ok(overrides)
+}
+
+/// The relationships part of a package entry, e.g.
+/// `xl/worksheets/_rels/sheet1.xml.rels`.
+pub fn relationships_path(entry: &str) -> String {
+    match entry.rsplit_once('/') {
+        Some((folder, file)) => format!("{}/_rels/{}.rels", folder, file),
+        None => format!("_rels/{}.rels", entry),
+    }
+}
+
+/// Resolves a relationship target of `owner` to a package entry name.
+fn resolve_target(owner: &str, target: &str) -> String {
+    if let Some(absolute) = target.strip_prefix('/') {
+        return absolute.to_string();
+    }
+    let mut segments = owner.split('/').collect::<Vec<_>>();
+    segments.pop();
+    for segment in target.split('/') {
+        match segment {
+            "." | "" => {}
+            ".." => {
+                segments.pop();
+            }
+            segment => segments.push(segment),
+        }
+    }
+    segments.join("/")
+}
+
+/// Points structured references in the formulas of a sheet at its renamed
+/// tables.
+pub fn rename_table_formulas(
+    sheet_xml: &[u8],
+    renamed_tables: &[(String, String)],
+) -> Result<Vec<u8>, String> {
+    if renamed_tables.is_empty() {
+        return Ok(sheet_xml.to_vec());
+    }
+    let mut reader = XmlReader::from_reader(sheet_xml);
+    reader.trim_text(false);
+    let mut writer = XmlWriter::new(Vec::new());
+    let mut buffer = Vec::new();
+    let mut in_formula = false;
+
+    loop {
+        buffer.clear();
+        let event = reader
+            .read_event_into(&mut buffer)
+            .map_err(|err| err.to_string())?;
+        let event = match event {
+            Event::Eof => break,
+            Event::Start(start) => {
+                in_formula = start.name().as_ref() == b"f";
+                Event::Start(start.into_owned())
+            }
+            Event::End(end) => {
+                in_formula = false;
+                Event::End(end.into_owned())
+            }
+            Event::Text(text) if in_formula => {
+                let mut formula = text.unescape().map_err(|err| err.to_string())?.into_owned();
+                for (old_name, new_name) in renamed_tables {
+                    formula = rename_table_references(&formula, old_name, new_name);
+                }
+                Event::Text(BytesText::new(&formula).into_owned())
+            }
+            event => event.into_owned(),
+        };
+        writer.write_event(event).map_err(|err| err.to_string())?;
+    }
+
+    Ok(writer.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::row_blocks::RowBlock;
+
+    fn expansion() -> RowExpansion {
+        RowExpansion {
+            block: RowBlock::parse("A5:C6").unwrap(),
+            count: 3,
+        }
+    }
+
+    #[test]
+    fn moves_tables_below_repeated_rows() {
+        let xml = br#"<table ref="A7:B9"><autoFilter ref="A7:B9"/></table>"#;
+        let shifted = shift_range_attributes(xml, &expansion()).unwrap();
+        assert_eq!(
+            String::from_utf8(shifted).unwrap(),
+            r#"<table ref="A11:B13"><autoFilter ref="A11:B13"/></table>"#
+        );
+    }
+
+    #[test]
+    fn moves_drawing_and_comment_anchors() {
+        let xml = b"<xdr:from><xdr:col>0</xdr:col><xdr:row>2</xdr:row></xdr:from>\
+<xdr:to><xdr:col>1</xdr:col><xdr:row>6</xdr:row></xdr:to>";
+        let shifted = shift_drawing_anchors(xml, &expansion()).unwrap();
+        assert_eq!(
+            String::from_utf8(shifted).unwrap(),
+            "<xdr:from><xdr:col>0</xdr:col><xdr:row>2</xdr:row></xdr:from>\
+<xdr:to><xdr:col>1</xdr:col><xdr:row>10</xdr:row></xdr:to>"
+        );
+
+        let vml = b"<x:Anchor>2, 15, 6, 10, 4, 15, 9, 4</x:Anchor><x:Row>7</x:Row>";
+        let shifted = shift_vml_anchors(vml, &expansion());
+        assert_eq!(
+            String::from_utf8(shifted).unwrap(),
+            "<x:Anchor>2, 15, 10, 10, 4, 15, 13, 4</x:Anchor><x:Row>11</x:Row>"
+        );
+    }
+}
@@ -0,0 +1,2793 @@
+use crate::cards::{CardContent, CardLayout};
+use crate::cells::{CellReplacements, CellValue, SheetContent, cell_label, parse_cell_reference};
+use crate::formulas::{
+    Reference, Value, evaluate, map_references, refers_to_sheet, rename_sheet_references,
+};
+use crate::output::{KeptSheet, SheetPosition};
+use crate::placeholders::{
+    PlaceholderMarkup, collect_placeholders, find_placeholders, replace_placeholders,
+};
+use crate::row_blocks::RowExpansion;
+use crate::xlsx_parts::{
+    CopiedPart, DependentParts, PartNames, content_type_overrides, relationships_path,
+    rename_table_formulas,
+};
+use crate::xml_utils::{attribute_value, with_attribute, xml_escape};
+use quick_xml::events::{BytesEnd, BytesStart, BytesText, Event};
+use quick_xml::{Reader as XmlReader, Writer as XmlWriter};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::File;
+use std::io::{Cursor, Read, Write};
+use std::path::Path;
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+const CALC_CHAIN_ENTRY: &str = "xl/calcChain.xml";
+
+fn write_workbook_from_template(
+    context: &XlsxTemplate,
+    sheets: &[WorksheetExport],
+) -> Result<Vec<u8>, String> {
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+
+    let content_types = build_content_types(
+        &context.content_types_xml,
+        sheets,
+        &context.replaced_entries,
+    )?;
+    let root_rels = build_root_relationships();
+    let app_doc = build_app_doc(sheets);
+    let core_doc = build_core_doc();
+    let workbook_xml = build_workbook_xml(&context.workbook_xml, &context.workbook_sheets, sheets)?;
+    let workbook_rels = build_workbook_rels(&context.preserved_relationships, sheets);
+
+    zip.start_file("[Content_Types].xml", options)
+        .map_err(|err| err.to_string())?;
+    zip.write_all(&content_types)
+        .map_err(|err| err.to_string())?;
+
+    zip.start_file("_rels/.rels", options)
+        .map_err(|err| err.to_string())?;
+    zip.write_all(&root_rels).map_err(|err| err.to_string())?;
+
+    zip.start_file("docProps/app.xml", options)
+        .map_err(|err| err.to_string())?;
+    zip.write_all(&app_doc).map_err(|err| err.to_string())?;
+
+    zip.start_file("docProps/core.xml", options)
+        .map_err(|err| err.to_string())?;
+    zip.write_all(&core_doc).map_err(|err| err.to_string())?;
+
+    zip.start_file("xl/workbook.xml", options)
+        .map_err(|err| err.to_string())?;
+    zip.write_all(&workbook_xml)
+        .map_err(|err| err.to_string())?;
+
+    zip.start_file("xl/_rels/workbook.xml.rels", options)
+        .map_err(|err| err.to_string())?;
+    zip.write_all(&workbook_rels)
+        .map_err(|err| err.to_string())?;
+
+    for sheet in sheets {
+        zip.start_file(format!("xl/{}", sheet.target), options)
+            .map_err(|err| err.to_string())?;
+        zip.write_all(&sheet.data).map_err(|err| err.to_string())?;
+
+        if let Some(rel_data) = &sheet.relationship_part {
+            zip.start_file(relationships_path(&format!("xl/{}", sheet.target)), options)
+                .map_err(|err| err.to_string())?;
+            zip.write_all(rel_data).map_err(|err| err.to_string())?;
+        }
+        for part in &sheet.parts {
+            zip.start_file(&part.name, options)
+                .map_err(|err| err.to_string())?;
+            zip.write_all(&part.data).map_err(|err| err.to_string())?;
+        }
+    }
+
+    for (name, data) in &context.entries {
+        if should_skip_entry(name) || context.replaced_entries.contains(name) {
+            continue;
+        }
+        zip.start_file(name, options)
+            .map_err(|err| err.to_string())?;
+        zip.write_all(data).map_err(|err| err.to_string())?;
+    }
+
+    zip.finish()
+        .map_err(|err| err.to_string())
+        .map(Cursor::into_inner)
+}
+
+/// Writes the replacements into the sheet XML. Cells and rows missing from
+/// the template are inserted at their sorted position and the sheet
+/// dimension is widened to cover them.
+fn update_sheet_xml(template: &[u8], replacements: &CellReplacements) -> Result<Vec<u8>, String> {
+    if replacements.is_empty() {
+        return Ok(template.to_vec());
+    }
+
+    let mut pending = replacements.clone();
+    let mut reader = XmlReader::from_reader(template);
+    reader.trim_text(false);
+    let mut writer = XmlWriter::new(Vec::new());
+    let mut buffer = Vec::new();
+    let mut skip_depth: usize = 0;
+    // Zero-based positions of the open row and of the last row and cell
+    // seen, for rows and cells without an `r` attribute.
+    let mut current_row: Option<u32> = None;
+    let mut last_row: Option<u32> = None;
+    let mut last_col: Option<u32> = None;
+
+    loop {
+        buffer.clear();
+        let event = reader
+            .read_event_into(&mut buffer)
+            .map_err(|err| err.to_string())?;
+        if skip_depth > 0 {
+            match event {
+                Event::Start(_) => skip_depth += 1,
+                Event::End(_) => skip_depth -= 1,
+                Event::Eof => break,
+                _ => {}
+            }
+            continue;
+        }
+
+        match event {
+            Event::Eof => break,
+            Event::Start(start) => match start.name().as_ref() {
+                b"row" => {
+                    let row = row_index(&start, last_row);
+                    write_pending_rows(&mut writer, &mut pending, Some(row))?;
+                    writer
+                        .write_event(Event::Start(row_start(&start, row, &pending)))
+                        .map_err(|err| err.to_string())?;
+                    current_row = Some(row);
+                    last_row = Some(row);
+                    last_col = None;
+                }
+                b"c" => {
+                    if let Some(row) = current_row {
+                        let col = cell_column(&start, last_col);
+                        last_col = Some(col);
+                        write_pending_cells(&mut writer, &mut pending, row, Some(col))?;
+                        if let Some(value) = pending.remove(&(row, col)) {
+                            let attrs = collect_cell_attributes(&start);
+                            write_replaced_cell(
+                                &mut writer,
+                                &cell_label(row, col),
+                                &value,
+                                &attrs,
+                            )?;
+                            skip_depth = 1;
+                            continue;
+                        }
+                    }
+                    writer
+                        .write_event(Event::Start(start.into_owned()))
+                        .map_err(|err| err.to_string())?;
+                }
+                b"dimension" => writer
+                    .write_event(Event::Start(widened_dimension(
+                        &start,
+                        replacements.keys().copied(),
+                    )))
+                    .map_err(|err| err.to_string())?,
+                _ => writer
+                    .write_event(Event::Start(start.into_owned()))
+                    .map_err(|err| err.to_string())?,
+            },
+            Event::Empty(start) => match start.name().as_ref() {
+                b"row" => {
+                    let row = row_index(&start, last_row);
+                    last_row = Some(row);
+                    write_pending_rows(&mut writer, &mut pending, Some(row))?;
+                    if has_pending_row(&pending, row) {
+                        writer
+                            .write_event(Event::Start(row_start(&start, row, &pending)))
+                            .map_err(|err| err.to_string())?;
+                        write_pending_cells(&mut writer, &mut pending, row, None)?;
+                        writer
+                            .write_event(Event::End(BytesEnd::new("row")))
+                            .map_err(|err| err.to_string())?;
+                    } else {
+                        writer
+                            .write_event(Event::Empty(start.into_owned()))
+                            .map_err(|err| err.to_string())?;
+                    }
+                }
+                b"c" => {
+                    if let Some(row) = current_row {
+                        let col = cell_column(&start, last_col);
+                        last_col = Some(col);
+                        write_pending_cells(&mut writer, &mut pending, row, Some(col))?;
+                        if let Some(value) = pending.remove(&(row, col)) {
+                            let attrs = collect_cell_attributes(&start);
+                            write_replaced_cell(
+                                &mut writer,
+                                &cell_label(row, col),
+                                &value,
+                                &attrs,
+                            )?;
+                            continue;
+                        }
+                    }
+                    writer
+                        .write_event(Event::Empty(start.into_owned()))
+                        .map_err(|err| err.to_string())?;
+                }
+                b"sheetData" if !pending.is_empty() => {
+                    writer
+                        .write_event(Event::Start(start.into_owned()))
+                        .map_err(|err| err.to_string())?;
+                    write_pending_rows(&mut writer, &mut pending, None)?;
+                    writer
+                        .write_event(Event::End(BytesEnd::new("sheetData")))
+                        .map_err(|err| err.to_string())?;
+                }
+                b"dimension" => writer
+                    .write_event(Event::Empty(widened_dimension(
+                        &start,
+                        replacements.keys().copied(),
+                    )))
+                    .map_err(|err| err.to_string())?,
+                _ => writer
+                    .write_event(Event::Empty(start.into_owned()))
+                    .map_err(|err| err.to_string())?,
+            },
+            Event::End(end) => {
+                match end.name().as_ref() {
+                    b"row" => {
+                        if let Some(row) = current_row.take() {
+                            write_pending_cells(&mut writer, &mut pending, row, None)?;
+                        }
+                    }
+                    b"sheetData" => write_pending_rows(&mut writer, &mut pending, None)?,
+                    _ => {}
+                }
+                writer
+                    .write_event(Event::End(end.into_owned()))
+                    .map_err(|err| err.to_string())?;
+            }
+            event => writer
+                .write_event(event.into_owned())
+                .map_err(|err| err.to_string())?,
+        }
+    }
+
+    Ok(writer.into_inner())
+}
+
+/// Zero-based index of a `<row>`; rows without `r` follow the previous row.
+fn row_index(start: &BytesStart, last_row: Option<u32>) -> u32 {
+    attribute_value(start, b"r")
+        .and_then(|value| value.parse::<u32>().ok())
+        .map(|row| row.saturating_sub(1))
+        .unwrap_or_else(|| last_row.map_or(0, |row| row + 1))
+}
+
+/// Zero-based column of a `<c>`; cells without `r` follow the previous cell.
+fn cell_column(start: &BytesStart, last_col: Option<u32>) -> u32 {
+    attribute_value(start, b"r")
+        .and_then(|value| parse_cell_reference(&value))
+        .map(|(_, col)| col)
+        .unwrap_or_else(|| last_col.map_or(0, |col| col + 1))
+}
+
+fn has_pending_row(pending: &CellReplacements, row: u32) -> bool {
+    pending.range((row, 0)..=(row, u32::MAX)).next().is_some()
+}
+
+/// The row tag, without the optional `spans` hint when cells will be
+/// inserted into the row.
+fn row_start(start: &BytesStart, row: u32, pending: &CellReplacements) -> BytesStart<'static> {
+    if !has_pending_row(pending, row) {
+        return start.to_owned();
+    }
+    let mut updated = BytesStart::new("row");
+    updated.push_attribute(("r", (row + 1).to_string().as_str()));
+    for attr in start.attributes().with_checks(false).flatten() {
+        if !matches!(attr.key.as_ref(), b"r" | b"spans") {
+            updated.push_attribute(attr);
+        }
+    }
+    updated
+}
+
+/// Writes the pending cells of `row` left of column `before`, or all of them.
+fn write_pending_cells(
+    writer: &mut XmlWriter<Vec<u8>>,
+    pending: &mut CellReplacements,
+    row: u32,
+    before: Option<u32>,
+) -> Result<(), String> {
+    let end = before.unwrap_or(u32::MAX);
+    let positions = pending
+        .range((row, 0)..(row, end))
+        .map(|(position, _)| *position)
+        .collect::<Vec<_>>();
+    for position in positions {
+        if let Some(value) = pending.remove(&position) {
+            write_replaced_cell(writer, &cell_label(position.0, position.1), &value, &[])?;
+        }
+    }
+    Ok(())
+}
+
+/// Writes new rows for the pending cells above row `before`, or all of them.
+fn write_pending_rows(
+    writer: &mut XmlWriter<Vec<u8>>,
+    pending: &mut CellReplacements,
+    before: Option<u32>,
+) -> Result<(), String> {
+    while let Some((&(row, _), _)) = pending.first_key_value() {
+        if before.is_some_and(|before| row >= before) {
+            break;
+        }
+        let mut start = BytesStart::new("row");
+        start.push_attribute(("r", (row + 1).to_string().as_str()));
+        writer
+            .write_event(Event::Start(start))
+            .map_err(|err| err.to_string())?;
+        write_pending_cells(writer, pending, row, None)?;
+        writer
+            .write_event(Event::End(BytesEnd::new("row")))
+            .map_err(|err| err.to_string())?;
+    }
+    Ok(())
+}
+
+/// The `<dimension>` tag with its range extended to the given cells.
+fn widened_dimension(
+    start: &BytesStart,
+    cells: impl Iterator<Item = (u32, u32)>,
+) -> BytesStart<'static> {
+    let mut bounds = attribute_value(start, b"ref")
+        .map(|range| {
+            range
+                .split(':')
+                .filter_map(|cell| parse_cell_reference(cell.trim().trim_start_matches('$')))
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    bounds.extend(cells);
+    let (Some(min_row), Some(max_row), Some(min_col), Some(max_col)) = (
+        bounds.iter().map(|(row, _)| *row).min(),
+        bounds.iter().map(|(row, _)| *row).max(),
+        bounds.iter().map(|(_, col)| *col).min(),
+        bounds.iter().map(|(_, col)| *col).max(),
+    ) else {
+        return start.to_owned();
+    };
+
+    let first = cell_label(min_row, min_col);
+    let last = cell_label(max_row, max_col);
+    let range = if first == last {
+        first
+    } else {
+        format!("{}:{}", first, last)
+    };
+    with_attribute(start, "ref", &range)
+}
+
+/// Shared formulas by `si`: the position of the cell that holds the formula
+/// text, and the text.
+type SharedFormulas = HashMap<String, ((u32, u32), String)>;
+
+/// Repeats the block rows of `expansion` below the block and moves the
+/// rows, merged cells, conditional formats and validations below it down.
+/// Formulas are adjusted to the new rows.
+fn expand_sheet_rows(
+    sheet_xml: &[u8],
+    expansion: &RowExpansion,
+    sheet: &str,
+) -> Result<Vec<u8>, String> {
+    if expansion.added_rows() == 0 {
+        return Ok(sheet_xml.to_vec());
+    }
+
+    let mut reader = XmlReader::from_reader(sheet_xml);
+    reader.trim_text(false);
+    let mut writer = XmlWriter::new(Vec::new());
+    let mut buffer = Vec::new();
+    let mut last_row: Option<u32> = None;
+    let mut block_rows = Vec::new();
+    let mut copies_written = false;
+    let mut shared_formulas = SharedFormulas::new();
+    let mut formula_target = false;
+
+    loop {
+        buffer.clear();
+        let event = reader
+            .read_event_into(&mut buffer)
+            .map_err(|err| err.to_string())?
+            .into_owned();
+        match event {
+            Event::Eof => break,
+            Event::Start(ref start) | Event::Empty(ref start)
+                if start.name().as_ref() == b"row" =>
+            {
+                let row = row_index(start, last_row);
+                last_row = Some(row);
+                let mut events = vec![event.clone()];
+                if matches!(event, Event::Start(_)) {
+                    loop {
+                        buffer.clear();
+                        let event = reader
+                            .read_event_into(&mut buffer)
+                            .map_err(|err| err.to_string())?
+                            .into_owned();
+                        match event {
+                            Event::Eof => break,
+                            Event::End(ref end) if end.name().as_ref() == b"row" => {
+                                events.push(event);
+                                break;
+                            }
+                            event => events.push(event),
+                        }
+                    }
+                }
+                if row > expansion.block.last_row && !copies_written {
+                    write_block_copies(
+                        &mut writer,
+                        &block_rows,
+                        expansion,
+                        sheet,
+                        &shared_formulas,
+                    )?;
+                    copies_written = true;
+                }
+                let copy = expansion.block.contains_row(row).then_some(0);
+                let target = ShiftedRow {
+                    source: row,
+                    target: expansion.row(row),
+                    copy,
+                };
+                write_shifted_row(
+                    &mut writer,
+                    &events,
+                    &target,
+                    expansion,
+                    sheet,
+                    &mut shared_formulas,
+                )?;
+                if copy.is_some() {
+                    block_rows.push((row, events));
+                }
+            }
+            Event::End(ref end) if end.name().as_ref() == b"sheetData" => {
+                if !copies_written {
+                    write_block_copies(
+                        &mut writer,
+                        &block_rows,
+                        expansion,
+                        sheet,
+                        &shared_formulas,
+                    )?;
+                    copies_written = true;
+                }
+                writer.write_event(event).map_err(|err| err.to_string())?;
+            }
+            Event::Start(ref start) if start.name().as_ref() == b"mergeCells" => {
+                let ranges = read_merge_ranges(&mut reader, &mut buffer)?;
+                write_merge_cells(&mut writer, start, &shifted_merges(&ranges, expansion))?;
+            }
+            Event::Start(ref start) | Event::Empty(ref start) => {
+                let updated = match start.name().as_ref() {
+                    b"dimension" | b"hyperlink" => attribute_value(start, b"ref").map(|ranges| {
+                        with_attribute(start, "ref", &expansion.shift_ranges(&ranges))
+                    }),
+                    b"conditionalFormatting" | b"dataValidation" => {
+                        attribute_value(start, b"sqref").map(|ranges| {
+                            with_attribute(start, "sqref", &expansion.shift_ranges(&ranges))
+                        })
+                    }
+                    b"brk" => attribute_value(start, b"id")
+                        .and_then(|id| id.parse::<u32>().ok())
+                        .map(|id| with_attribute(start, "id", &expansion.row(id).to_string())),
+                    _ => None,
+                }
+                .unwrap_or_else(|| start.to_owned());
+                formula_target = matches!(
+                    start.name().as_ref(),
+                    b"formula" | b"formula1" | b"formula2"
+                ) && matches!(event, Event::Start(_));
+                let event = match event {
+                    Event::Empty(_) => Event::Empty(updated),
+                    _ => Event::Start(updated),
+                };
+                writer.write_event(event).map_err(|err| err.to_string())?;
+            }
+            Event::Text(ref text) if formula_target => {
+                let formula = text.unescape().map_err(|err| err.to_string())?;
+                writer
+                    .write_event(Event::Text(BytesText::new(
+                        &expansion.shift_formula(&formula, sheet, None),
+                    )))
+                    .map_err(|err| err.to_string())?;
+            }
+            event => {
+                formula_target = false;
+                writer.write_event(event).map_err(|err| err.to_string())?;
+            }
+        }
+    }
+
+    Ok(writer.into_inner())
+}
+
+/// Reads the ranges of the `<mergeCell>` elements up to `</mergeCells>`.
+fn read_merge_ranges(
+    reader: &mut XmlReader<&[u8]>,
+    buffer: &mut Vec<u8>,
+) -> Result<Vec<String>, String> {
+    let mut ranges = Vec::new();
+    loop {
+        buffer.clear();
+        match reader
+            .read_event_into(buffer)
+            .map_err(|err| err.to_string())?
+        {
+            Event::Eof => break,
+            Event::End(end) if end.name().as_ref() == b"mergeCells" => break,
+            Event::Start(cell) | Event::Empty(cell) if cell.name().as_ref() == b"mergeCell" => {
+                ranges.extend(attribute_value(&cell, b"ref"));
+            }
+            _ => {}
+        }
+    }
+    Ok(ranges)
+}
+
+/// Writes `<mergeCells>` with the given ranges and their count.
+fn write_merge_cells(
+    writer: &mut XmlWriter<Vec<u8>>,
+    start: &BytesStart,
+    ranges: &[String],
+) -> Result<(), String> {
+    let start = with_attribute(start, "count", &ranges.len().to_string());
+    writer
+        .write_event(Event::Start(start))
+        .map_err(|err| err.to_string())?;
+    for range in ranges {
+        let mut cell = BytesStart::new("mergeCell");
+        cell.push_attribute(("ref", range.as_str()));
+        writer
+            .write_event(Event::Empty(cell))
+            .map_err(|err| err.to_string())?;
+    }
+    writer
+        .write_event(Event::End(BytesEnd::new("mergeCells")))
+        .map_err(|err| err.to_string())
+}
+
+/// Merged ranges after the rows were inserted. Merges inside the block are
+/// repeated for every copy.
+fn shifted_merges(ranges: &[String], expansion: &RowExpansion) -> Vec<String> {
+    let block = &expansion.block;
+    let mut shifted = Vec::new();
+    for range in ranges {
+        let cells = range
+            .split(':')
+            .map(|cell| parse_cell_reference(&cell.replace('$', "")))
+            .collect::<Option<Vec<_>>>()
+            .unwrap_or_default();
+        let inside = !cells.is_empty()
+            && cells
+                .iter()
+                .all(|(row, col)| block.contains_row(*row) && block.contains_col(*col));
+        if !inside {
+            shifted.push(expansion.shift_ranges(range));
+            continue;
+        }
+        for copy in 0..expansion.count {
+            shifted.push(expansion.shift_formula(range, "", Some(copy)));
+        }
+    }
+    shifted
+}
+
+/// Where a template row is written, and which copy of the block it is.
+struct ShiftedRow {
+    source: u32,
+    target: u32,
+    /// `None` for rows outside the block.
+    copy: Option<u32>,
+}
+
+/// Writes the copies of the block rows after the first one.
+fn write_block_copies(
+    writer: &mut XmlWriter<Vec<u8>>,
+    block_rows: &[(u32, Vec<Event<'static>>)],
+    expansion: &RowExpansion,
+    sheet: &str,
+    shared_formulas: &SharedFormulas,
+) -> Result<(), String> {
+    let mut shared_formulas = shared_formulas.clone();
+    for copy in 1..expansion.count {
+        for (row, events) in block_rows {
+            let target = ShiftedRow {
+                source: *row,
+                target: row + copy * expansion.block.height(),
+                copy: Some(copy),
+            };
+            write_shifted_row(
+                writer,
+                events,
+                &target,
+                expansion,
+                sheet,
+                &mut shared_formulas,
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Writes the events of a `<row>` at its new position. Copies of block rows
+/// only keep the cells in the block's columns, and their shared formulas are
+/// written out in full.
+fn write_shifted_row(
+    writer: &mut XmlWriter<Vec<u8>>,
+    events: &[Event<'static>],
+    row: &ShiftedRow,
+    expansion: &RowExpansion,
+    sheet: &str,
+    shared_formulas: &mut SharedFormulas,
+) -> Result<(), String> {
+    let is_copy = row.copy.is_some_and(|copy| copy > 0);
+    let mut last_col: Option<u32> = None;
+    let mut col = 0;
+    let mut skip_depth = 0usize;
+    let mut formula: Option<(BytesStart<'static>, String)> = None;
+
+    for event in events {
+        if skip_depth > 0 {
+            match event {
+                Event::Start(_) => skip_depth += 1,
+                Event::End(_) => skip_depth -= 1,
+                _ => {}
+            }
+            continue;
+        }
+        if let Some((_, text)) = formula.as_mut() {
+            match event {
+                Event::Text(event) => {
+                    text.push_str(&event.unescape().map_err(|err| err.to_string())?);
+                    continue;
+                }
+                Event::End(end) if end.name().as_ref() == b"f" => {
+                    if let Some((start, text)) = formula.take() {
+                        write_shifted_formula(
+                            writer,
+                            &start,
+                            Some(&text),
+                            (row, col),
+                            expansion,
+                            sheet,
+                            shared_formulas,
+                        )?;
+                    }
+                    continue;
+                }
+                _ => continue,
+            }
+        }
+
+        let (start, is_empty) = match event {
+            Event::Start(start) => (start, false),
+            Event::Empty(start) => (start, true),
+            event => {
+                writer
+                    .write_event(event.clone())
+                    .map_err(|err| err.to_string())?;
+                continue;
+            }
+        };
+        let updated = match start.name().as_ref() {
+            b"row" => with_attribute(start, "r", &(row.target + 1).to_string()),
+            b"c" => {
+                col = cell_column(start, last_col);
+                last_col = Some(col);
+                if is_copy && !expansion.block.contains_col(col) {
+                    skip_depth = usize::from(!is_empty);
+                    continue;
+                }
+                with_attribute(start, "r", &cell_label(row.target, col))
+            }
+            b"f" if is_empty => {
+                write_shifted_formula(
+                    writer,
+                    start,
+                    None,
+                    (row, col),
+                    expansion,
+                    sheet,
+                    shared_formulas,
+                )?;
+                continue;
+            }
+            b"f" => {
+                formula = Some((start.clone(), String::new()));
+                continue;
+            }
+            _ => start.clone(),
+        };
+        let event = if is_empty {
+            Event::Empty(updated)
+        } else {
+            Event::Start(updated)
+        };
+        writer.write_event(event).map_err(|err| err.to_string())?;
+    }
+    Ok(())
+}
+
+/// Writes the `<f>` of a cell at its new position. `text` is `None` for
+/// cells that share the formula of another cell.
+fn write_shifted_formula(
+    writer: &mut XmlWriter<Vec<u8>>,
+    start: &BytesStart,
+    text: Option<&str>,
+    (row, col): (&ShiftedRow, u32),
+    expansion: &RowExpansion,
+    sheet: &str,
+    shared_formulas: &mut SharedFormulas,
+) -> Result<(), String> {
+    let is_copy = row.copy.is_some_and(|copy| copy > 0);
+    let shared_index = attribute_value(start, b"si")
+        .filter(|_| attribute_value(start, b"t").as_deref() == Some("shared"));
+    let text = match (text, &shared_index) {
+        (Some(text), Some(index)) if !is_copy => {
+            shared_formulas.insert(index.clone(), ((row.source, col), text.to_string()));
+            Some(text.to_string())
+        }
+        (Some(text), _) => Some(text.to_string()),
+        // Copies cannot share the formula of a template cell.
+        (None, Some(index)) if is_copy => shared_formulas
+            .get(index)
+            .map(|(origin, text)| translate_formula(text, sheet, *origin, (row.source, col))),
+        (None, _) => None,
+    };
+
+    let mut updated = BytesStart::new("f");
+    for attr in start.attributes().with_checks(false).flatten() {
+        match attr.key.as_ref() {
+            b"t" | b"si" if is_copy && shared_index.is_some() => {}
+            b"ref" if is_copy && shared_index.is_some() => {}
+            b"ref" => {
+                let range = String::from_utf8_lossy(attr.value.as_ref()).into_owned();
+                let copy = Some(row.copy.unwrap_or(0));
+                updated.push_attribute(("ref", expansion.shift_formula(&range, "", copy).as_str()));
+            }
+            _ => updated.push_attribute(attr),
+        }
+    }
+    match text {
+        Some(text) => {
+            writer
+                .write_event(Event::Start(updated))
+                .map_err(|err| err.to_string())?;
+            writer
+                .write_event(Event::Text(BytesText::new(
+                    &expansion.shift_formula(&text, sheet, row.copy),
+                )))
+                .map_err(|err| err.to_string())?;
+            writer
+                .write_event(Event::End(BytesEnd::new("f")))
+                .map_err(|err| err.to_string())
+        }
+        None if is_copy => Ok(()),
+        None => writer
+            .write_event(Event::Empty(updated))
+            .map_err(|err| err.to_string()),
+    }
+}
+
+/// The formula of a cell sharing the formula written at `origin`: relative
+/// references move by the distance between the cells.
+fn translate_formula(formula: &str, sheet: &str, origin: (u32, u32), cell: (u32, u32)) -> String {
+    let rows = i64::from(cell.0) - i64::from(origin.0);
+    let cols = i64::from(cell.1) - i64::from(origin.1);
+    let moved = |reference: Reference| reference.moved(rows, cols);
+    map_references(formula, sheet, &mut |first, second| {
+        (moved(first), second.map(moved))
+    })
+}
+
+/// A `<row>` of the sheet data with the markup of its cells by column.
+struct DataRow {
+    start: BytesStart<'static>,
+    cells: BTreeMap<u32, Vec<u8>>,
+}
+
+/// Copies the template card into every further card of the sheet that has a
+/// record and fills the copy's placeholders with that record. Formulas move
+/// with the card, and merged cells, conditional formats and validations
+/// inside the card are copied along; the card's row heights and column
+/// widths apply to all cards.
+fn tile_sheet_cards(sheet_xml: &[u8], cards: &CardContent, sheet: &str) -> Result<Vec<u8>, String> {
+    let layout = &cards.layout;
+    let filled = cards.records.len();
+    if layout.per_sheet() < 2 {
+        return Ok(sheet_xml.to_vec());
+    }
+
+    let mut reader = XmlReader::from_reader(sheet_xml);
+    reader.trim_text(false);
+    let mut writer = XmlWriter::new(Vec::new());
+    let mut buffer = Vec::new();
+    let copied_ranges = |ranges: &str| {
+        let mut copied = ranges.split(' ').map(str::to_string).collect::<Vec<_>>();
+        for range in ranges.split(' ') {
+            if range_inside_card(range, layout) {
+                copied.extend((1..filled).map(|index| layout.move_formula(range, "", index)));
+            }
+        }
+        copied
+    };
+
+    loop {
+        buffer.clear();
+        let event = reader
+            .read_event_into(&mut buffer)
+            .map_err(|err| err.to_string())?
+            .into_owned();
+        match event {
+            Event::Eof => break,
+            Event::Start(start) if start.name().as_ref() == b"sheetData" => {
+                let (mut rows, shared_formulas) = read_data_rows(&mut reader, &mut buffer)?;
+                copy_cards(&mut rows, cards, sheet, &shared_formulas)?;
+                writer
+                    .write_event(Event::Start(start))
+                    .map_err(|err| err.to_string())?;
+                for row in rows.values() {
+                    if row.cells.is_empty() {
+                        writer
+                            .write_event(Event::Empty(row.start.clone()))
+                            .map_err(|err| err.to_string())?;
+                        continue;
+                    }
+                    writer
+                        .write_event(Event::Start(row.start.clone()))
+                        .map_err(|err| err.to_string())?;
+                    for markup in row.cells.values() {
+                        writer
+                            .get_mut()
+                            .write_all(markup)
+                            .map_err(|err| err.to_string())?;
+                    }
+                    writer
+                        .write_event(Event::End(BytesEnd::new("row")))
+                        .map_err(|err| err.to_string())?;
+                }
+                writer
+                    .write_event(Event::End(BytesEnd::new("sheetData")))
+                    .map_err(|err| err.to_string())?;
+            }
+            Event::Start(start) if start.name().as_ref() == b"cols" => {
+                let mut columns = Vec::new();
+                loop {
+                    buffer.clear();
+                    match reader
+                        .read_event_into(&mut buffer)
+                        .map_err(|err| err.to_string())?
+                    {
+                        Event::Eof => break,
+                        Event::End(end) if end.name().as_ref() == b"cols" => break,
+                        Event::Start(col) | Event::Empty(col) if col.name().as_ref() == b"col" => {
+                            columns.push(col.into_owned());
+                        }
+                        _ => {}
+                    }
+                }
+                writer
+                    .write_event(Event::Start(start))
+                    .map_err(|err| err.to_string())?;
+                for col in card_columns(&columns, layout) {
+                    writer
+                        .write_event(Event::Empty(col))
+                        .map_err(|err| err.to_string())?;
+                }
+                writer
+                    .write_event(Event::End(BytesEnd::new("cols")))
+                    .map_err(|err| err.to_string())?;
+            }
+            Event::Start(start) if start.name().as_ref() == b"mergeCells" => {
+                let ranges = read_merge_ranges(&mut reader, &mut buffer)?;
+                write_merge_cells(&mut writer, &start, &copied_ranges(&ranges.join(" ")))?;
+            }
+            Event::Start(ref start) | Event::Empty(ref start) => {
+                let updated = match start.name().as_ref() {
+                    b"dimension" => {
+                        let corners = (0..filled).map(|index| {
+                            layout.cell(index, (layout.card.last_row, layout.card.last_col))
+                        });
+                        Some(widened_dimension(start, corners))
+                    }
+                    b"conditionalFormatting" | b"dataValidation" => {
+                        attribute_value(start, b"sqref").map(|ranges| {
+                            with_attribute(start, "sqref", &copied_ranges(&ranges).join(" "))
+                        })
+                    }
+                    _ => None,
+                };
+                let event = match (updated, event) {
+                    (Some(updated), Event::Empty(_)) => Event::Empty(updated),
+                    (Some(updated), _) => Event::Start(updated),
+                    (None, event) => event,
+                };
+                writer.write_event(event).map_err(|err| err.to_string())?;
+            }
+            event => writer.write_event(event).map_err(|err| err.to_string())?,
+        }
+    }
+
+    Ok(writer.into_inner())
+}
+
+/// Whether every cell of a range such as `B2:C3` lies inside the card.
+fn range_inside_card(range: &str, layout: &CardLayout) -> bool {
+    let cells = range
+        .split(':')
+        .map(|cell| parse_cell_reference(&cell.replace('$', "")))
+        .collect::<Option<Vec<_>>>()
+        .unwrap_or_default();
+    !cells.is_empty() && cells.into_iter().all(|cell| layout.contains(cell))
+}
+
+/// Reads the rows up to `</sheetData>`, giving every row and cell its `r`
+/// attribute, and the shared formulas defined in them.
+fn read_data_rows(
+    reader: &mut XmlReader<&[u8]>,
+    buffer: &mut Vec<u8>,
+) -> Result<(BTreeMap<u32, DataRow>, SharedFormulas), String> {
+    let mut rows = BTreeMap::new();
+    let mut shared_formulas = SharedFormulas::new();
+    let mut last_row: Option<u32> = None;
+    let mut current: Option<(u32, DataRow)> = None;
+    let mut last_col: Option<u32> = None;
+
+    loop {
+        buffer.clear();
+        let event = reader
+            .read_event_into(buffer)
+            .map_err(|err| err.to_string())?
+            .into_owned();
+        match event {
+            Event::Eof => break,
+            Event::End(end) if end.name().as_ref() == b"sheetData" => break,
+            Event::Start(ref start) | Event::Empty(ref start)
+                if start.name().as_ref() == b"row" =>
+            {
+                let row = row_index(start, last_row);
+                last_row = Some(row);
+                last_col = None;
+                let data_row = DataRow {
+                    start: with_attribute(start, "r", &(row + 1).to_string()),
+                    cells: BTreeMap::new(),
+                };
+                if matches!(event, Event::Empty(_)) {
+                    rows.insert(row, data_row);
+                } else {
+                    current = Some((row, data_row));
+                }
+            }
+            Event::End(end) if end.name().as_ref() == b"row" => {
+                if let Some((row, data_row)) = current.take() {
+                    rows.insert(row, data_row);
+                }
+            }
+            Event::Start(ref start) | Event::Empty(ref start) if start.name().as_ref() == b"c" => {
+                let Some((row, data_row)) = current.as_mut() else {
+                    continue;
+                };
+                let col = cell_column(start, last_col);
+                last_col = Some(col);
+                let start = with_attribute(start, "r", &cell_label(*row, col));
+                let mut cell = XmlWriter::new(Vec::new());
+                if matches!(event, Event::Empty(_)) {
+                    cell.write_event(Event::Empty(start))
+                        .map_err(|err| err.to_string())?;
+                } else {
+                    cell.write_event(Event::Start(start))
+                        .map_err(|err| err.to_string())?;
+                    let mut shared: Option<(String, String)> = None;
+                    loop {
+                        buffer.clear();
+                        let event = reader
+                            .read_event_into(buffer)
+                            .map_err(|err| err.to_string())?
+                            .into_owned();
+                        match &event {
+                            Event::Eof => break,
+                            Event::Start(f)
+                                if f.name().as_ref() == b"f"
+                                    && attribute_value(f, b"t").as_deref() == Some("shared") =>
+                            {
+                                shared = attribute_value(f, b"si").map(|si| (si, String::new()));
+                            }
+                            Event::Text(text) if shared.is_some() => {
+                                if let Some((_, formula)) = shared.as_mut() {
+                                    formula
+                                        .push_str(&text.unescape().map_err(|err| err.to_string())?);
+                                }
+                            }
+                            Event::End(end) if end.name().as_ref() == b"f" => {
+                                if let Some((si, formula)) = shared.take() {
+                                    shared_formulas.insert(si, ((*row, col), formula));
+                                }
+                            }
+                            _ => {}
+                        }
+                        let done = matches!(&event, Event::End(end) if end.name().as_ref() == b"c");
+                        cell.write_event(event).map_err(|err| err.to_string())?;
+                        if done {
+                            break;
+                        }
+                    }
+                }
+                data_row.cells.insert(col, cell.into_inner());
+            }
+            _ => {}
+        }
+    }
+
+    Ok((rows, shared_formulas))
+}
+
+/// Writes the template card into the further cards of the sheet. Rows of
+/// every card get the height of the card's rows; only cards with a record
+/// get the card's cells.
+fn copy_cards(
+    rows: &mut BTreeMap<u32, DataRow>,
+    cards: &CardContent,
+    sheet: &str,
+    shared_formulas: &SharedFormulas,
+) -> Result<(), String> {
+    let layout = &cards.layout;
+    let card = layout.card;
+    for index in 1..layout.per_sheet() {
+        for row in card.first_row..=card.last_row {
+            let Some(source) = rows.get(&row) else {
+                continue;
+            };
+            let source_start = source.start.clone();
+            let cells = source
+                .cells
+                .range(card.first_col..=card.last_col)
+                .map(|(col, markup)| (*col, markup.clone()))
+                .collect::<Vec<_>>();
+            let target_row = layout.cell(index, (row, 0)).0;
+            let record = cards.records.get(index);
+            let has_layout = source_start
+                .attributes()
+                .with_checks(false)
+                .flatten()
+                .any(|attr| !matches!(attr.key.as_ref(), b"r" | b"spans"));
+            if record.is_none() && !has_layout {
+                continue;
+            }
+            let target = rows.entry(target_row).or_insert_with(|| DataRow {
+                start: with_attribute(
+                    &without_attributes(&source_start, &[b"spans"]),
+                    "r",
+                    &(target_row + 1).to_string(),
+                ),
+                cells: BTreeMap::new(),
+            });
+            let Some(record) = record else {
+                continue;
+            };
+            target.start = without_attributes(&target.start, &[b"spans"]);
+            for (col, markup) in cells {
+                let cell = layout.cell(index, (row, col));
+                let copied = copy_card_cell(&markup, (row, col), cell, sheet, shared_formulas)?;
+                let copied = replace_placeholders(&copied, &CELL_TEXT_MARKUP, record)?;
+                target.cells.insert(cell.1, copied);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The markup of a card cell moved from `source` to `target`. Formulas
+/// move along; copies of shared formulas are written out in full.
+fn copy_card_cell(
+    markup: &[u8],
+    source: (u32, u32),
+    target: (u32, u32),
+    sheet: &str,
+    shared_formulas: &SharedFormulas,
+) -> Result<Vec<u8>, String> {
+    let mut reader = XmlReader::from_reader(markup);
+    reader.trim_text(false);
+    let mut writer = XmlWriter::new(Vec::new());
+    let mut buffer = Vec::new();
+    let mut formula: Option<(BytesStart<'static>, String)> = None;
+
+    loop {
+        buffer.clear();
+        let event = reader
+            .read_event_into(&mut buffer)
+            .map_err(|err| err.to_string())?
+            .into_owned();
+        match event {
+            Event::Eof => break,
+            Event::Start(ref start) | Event::Empty(ref start) if start.name().as_ref() == b"c" => {
+                let start = with_attribute(start, "r", &cell_label(target.0, target.1));
+                let event = match event {
+                    Event::Empty(_) => Event::Empty(start),
+                    _ => Event::Start(start),
+                };
+                writer.write_event(event).map_err(|err| err.to_string())?;
+            }
+            Event::Start(start) if start.name().as_ref() == b"f" => {
+                formula = Some((start, String::new()));
+            }
+            Event::Text(text) if formula.is_some() => {
+                if let Some((_, formula)) = formula.as_mut() {
+                    formula.push_str(&text.unescape().map_err(|err| err.to_string())?);
+                }
+            }
+            Event::End(end) if end.name().as_ref() == b"f" => {
+                if let Some((start, text)) = formula.take() {
+                    write_card_formula(
+                        &mut writer,
+                        &start,
+                        Some(&text),
+                        source,
+                        target,
+                        sheet,
+                        shared_formulas,
+                    )?;
+                }
+            }
+            Event::Empty(start) if start.name().as_ref() == b"f" => {
+                write_card_formula(
+                    &mut writer,
+                    &start,
+                    None,
+                    source,
+                    target,
+                    sheet,
+                    shared_formulas,
+                )?;
+            }
+            event => writer.write_event(event).map_err(|err| err.to_string())?,
+        }
+    }
+
+    Ok(writer.into_inner())
+}
+
+/// Writes the `<f>` of a copied card cell. `text` is `None` for cells that
+/// share the formula of another cell.
+fn write_card_formula(
+    writer: &mut XmlWriter<Vec<u8>>,
+    start: &BytesStart,
+    text: Option<&str>,
+    source: (u32, u32),
+    target: (u32, u32),
+    sheet: &str,
+    shared_formulas: &SharedFormulas,
+) -> Result<(), String> {
+    let shared = attribute_value(start, b"t").as_deref() == Some("shared");
+    let text = match text.filter(|text| !text.is_empty()) {
+        Some(text) => Some(translate_formula(text, sheet, source, target)),
+        None if shared => attribute_value(start, b"si")
+            .and_then(|index| shared_formulas.get(&index))
+            .map(|(origin, text)| translate_formula(text, sheet, *origin, target)),
+        None => None,
+    };
+    let Some(text) = text else {
+        return Ok(());
+    };
+
+    let mut updated = BytesStart::new("f");
+    for attr in start.attributes().with_checks(false).flatten() {
+        match attr.key.as_ref() {
+            b"t" | b"si" | b"ref" if shared => {}
+            b"ref" => {
+                let range = String::from_utf8_lossy(attr.value.as_ref()).into_owned();
+                updated.push_attribute((
+                    "ref",
+                    translate_formula(&range, "", source, target).as_str(),
+                ));
+            }
+            _ => updated.push_attribute(attr),
+        }
+    }
+    writer
+        .write_event(Event::Start(updated))
+        .map_err(|err| err.to_string())?;
+    writer
+        .write_event(Event::Text(BytesText::new(&text)))
+        .map_err(|err| err.to_string())?;
+    writer
+        .write_event(Event::End(BytesEnd::new("f")))
+        .map_err(|err| err.to_string())
+}
+
+/// The `<col>` definitions with the widths of the card's columns repeated
+/// for the cards to its right.
+fn card_columns(columns: &[BytesStart<'static>], layout: &CardLayout) -> Vec<BytesStart<'static>> {
+    // One-based `min` and `max` columns of every definition.
+    let bounds = |col: &BytesStart| {
+        let number =
+            |key: &[u8]| attribute_value(col, key).and_then(|value| value.parse::<u32>().ok());
+        number(b"min").zip(number(b"max"))
+    };
+    let mut ranges = columns
+        .iter()
+        .filter_map(|col| bounds(col).map(|(min, max)| (min, max, col.clone())))
+        .collect::<Vec<_>>();
+    let card = layout.card;
+    for index in 1..layout.across as usize {
+        for col in card.first_col..=card.last_col {
+            let source = ranges
+                .iter()
+                .find(|(min, max, _)| (*min..=*max).contains(&(col + 1)))
+                .map(|(_, _, start)| start.clone());
+            let target = layout.cell(index, (0, col)).1 + 1;
+            let mut split = Vec::new();
+            for (min, max, start) in ranges.drain(..) {
+                if !(min..=max).contains(&target) {
+                    split.push((min, max, start));
+                    continue;
+                }
+                if min < target {
+                    split.push((min, target - 1, start.clone()));
+                }
+                if max > target {
+                    split.push((target + 1, max, start));
+                }
+            }
+            split.extend(source.map(|start| (target, target, start)));
+            ranges = split;
+        }
+    }
+    ranges.sort_by_key(|(min, _, _)| *min);
+    ranges
+        .into_iter()
+        .map(|(min, max, start)| {
+            let start = with_attribute(&start, "min", &min.to_string());
+            with_attribute(&start, "max", &max.to_string())
+        })
+        .collect()
+}
+
+/// A cell of a generated sheet as input to formula evaluation.
+enum SheetCell {
+    /// A constant, or `None` for values that cannot be used, like errors or
+    /// formulas the engine cannot read.
+    Value(Option<Value>),
+    Formula(String),
+}
+
+/// The cells of one sheet with the formula results computed so far.
+struct FormulaSheet {
+    cells: HashMap<(u32, u32), SheetCell>,
+    results: HashMap<(u32, u32), Option<Value>>,
+    evaluating: HashSet<(u32, u32)>,
+}
+
+impl FormulaSheet {
+    /// Deepest chain of formulas referring to formulas that is evaluated.
+    const MAX_DEPTH: usize = 256;
+
+    fn value(&mut self, position: (u32, u32)) -> Option<Value> {
+        if let Some(result) = self.results.get(&position) {
+            return result.clone();
+        }
+        let formula = match self.cells.get(&position) {
+            None => return Some(Value::Empty),
+            Some(SheetCell::Value(value)) => return value.clone(),
+            Some(SheetCell::Formula(formula)) => formula.clone(),
+        };
+        // Circular references and very deep chains are left to the reader.
+        if self.evaluating.len() >= Self::MAX_DEPTH || !self.evaluating.insert(position) {
+            return None;
+        }
+        let result = evaluate(&formula, &mut |row, col| self.value((row, col)));
+        self.evaluating.remove(&position);
+        self.results.insert(position, result.clone());
+        result
+    }
+}
+
+/// Recomputes the cached values of the formulas the engine can evaluate, so
+/// that readers which do not recalculate see values based on the mapped
+/// cells. Other formulas lose their cached values, which would be based on
+/// the template's cells; `fullCalcOnLoad` makes spreadsheets compute them.
+fn recalculate_sheet_xml(sheet_xml: &[u8], shared_strings: &[String]) -> Result<Vec<u8>, String> {
+    let cells = read_sheet_cells(sheet_xml, shared_strings)?;
+    let formulas = cells
+        .iter()
+        .filter(|(_, cell)| matches!(cell, SheetCell::Formula(_)))
+        .map(|(position, _)| *position)
+        .collect::<Vec<_>>();
+    if formulas.is_empty() {
+        return Ok(sheet_xml.to_vec());
+    }
+
+    let mut sheet = FormulaSheet {
+        cells,
+        results: HashMap::new(),
+        evaluating: HashSet::new(),
+    };
+    let results = formulas
+        .into_iter()
+        .map(|position| (position, sheet.value(position)))
+        .collect::<HashMap<_, _>>();
+    write_formula_results(sheet_xml, &results)
+}
+
+/// The cell being read by `read_sheet_cells`.
+struct OpenCell {
+    position: (u32, u32),
+    kind: String,
+    formula: Option<String>,
+    value: String,
+    inline: String,
+    unsupported: bool,
+}
+
+/// Reads the values and formulas of all cells of a sheet.
+fn read_sheet_cells(
+    sheet_xml: &[u8],
+    shared_strings: &[String],
+) -> Result<HashMap<(u32, u32), SheetCell>, String> {
+    let mut reader = XmlReader::from_reader(sheet_xml);
+    reader.trim_text(false);
+    let mut buffer = Vec::new();
+    let mut cells = HashMap::new();
+    let mut last_row: Option<u32> = None;
+    let mut last_col: Option<u32> = None;
+    let mut cell: Option<OpenCell> = None;
+    let mut text_target: Option<&[u8]> = None;
+    let mut in_phonetic = false;
+
+    loop {
+        buffer.clear();
+        match reader
+            .read_event_into(&mut buffer)
+            .map_err(|err| err.to_string())?
+        {
+            Event::Eof => break,
+            Event::Start(start) | Event::Empty(start) if start.name().as_ref() == b"row" => {
+                last_row = Some(row_index(&start, last_row));
+                last_col = None;
+            }
+            Event::Start(start) if start.name().as_ref() == b"c" => {
+                let col = cell_column(&start, last_col);
+                last_col = Some(col);
+                cell = Some(OpenCell {
+                    position: (last_row.unwrap_or_default(), col),
+                    kind: attribute_value(&start, b"t").unwrap_or_default(),
+                    formula: None,
+                    value: String::new(),
+                    inline: String::new(),
+                    unsupported: false,
+                });
+            }
+            Event::Empty(start) if start.name().as_ref() == b"c" => {
+                last_col = Some(cell_column(&start, last_col));
+            }
+            Event::Start(start) => {
+                let Some(cell) = cell.as_mut() else {
+                    continue;
+                };
+                match start.name().as_ref() {
+                    b"f" => {
+                        cell.unsupported |=
+                            attribute_value(&start, b"t").as_deref() == Some("dataTable");
+                        cell.formula = Some(String::new());
+                        text_target = Some(b"f");
+                    }
+                    b"v" => text_target = Some(b"v"),
+                    b"t" if !in_phonetic => text_target = Some(b"t"),
+                    b"rPh" => in_phonetic = true,
+                    _ => {}
+                }
+            }
+            // Cells sharing the formula of another cell.
+            Event::Empty(start) if start.name().as_ref() == b"f" => {
+                if let Some(cell) = cell.as_mut() {
+                    cell.unsupported = true;
+                }
+            }
+            Event::Text(text) => {
+                if let (Some(target), Some(cell)) = (text_target, cell.as_mut()) {
+                    let text = text.unescape().map_err(|err| err.to_string())?;
+                    match target {
+                        b"f" => cell.formula.get_or_insert_with(String::new).push_str(&text),
+                        b"v" => cell.value.push_str(&text),
+                        _ => cell.inline.push_str(&text),
+                    }
+                }
+            }
+            Event::End(end) => match end.name().as_ref() {
+                b"c" => {
+                    if let Some(cell) = cell.take() {
+                        let value = match cell.formula {
+                            _ if cell.unsupported => SheetCell::Value(None),
+                            Some(formula) if !formula.trim().is_empty() => {
+                                SheetCell::Formula(formula)
+                            }
+                            _ => SheetCell::Value(cell_value(
+                                &cell.kind,
+                                &cell.value,
+                                &cell.inline,
+                                shared_strings,
+                            )),
+                        };
+                        cells.insert(cell.position, value);
+                    }
+                }
+                b"f" | b"v" | b"t" => text_target = None,
+                b"rPh" => in_phonetic = false,
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+
+    Ok(cells)
+}
+
+/// The value of a constant cell from its `t` attribute and content.
+fn cell_value(kind: &str, value: &str, inline: &str, shared_strings: &[String]) -> Option<Value> {
+    match kind {
+        "s" => value
+            .trim()
+            .parse::<usize>()
+            .ok()
+            .and_then(|index| shared_strings.get(index))
+            .map(|text| Value::Text(text.clone())),
+        "inlineStr" => Some(Value::Text(inline.to_string())),
+        "str" => Some(Value::Text(value.to_string())),
+        "b" => Some(Value::Boolean(value.trim() == "1")),
+        "e" | "d" => None,
+        _ if value.trim().is_empty() => Some(Value::Empty),
+        _ => value.trim().parse::<f64>().ok().map(Value::Number),
+    }
+}
+
+/// Replaces the cached values of formula cells with `results`, removing
+/// them for `None`.
+fn write_formula_results(
+    sheet_xml: &[u8],
+    results: &HashMap<(u32, u32), Option<Value>>,
+) -> Result<Vec<u8>, String> {
+    let mut reader = XmlReader::from_reader(sheet_xml);
+    reader.trim_text(false);
+    let mut writer = XmlWriter::new(Vec::new());
+    let mut buffer = Vec::new();
+    let mut last_row: Option<u32> = None;
+    let mut last_col: Option<u32> = None;
+    // The result for the open cell and whether its `<v>` was written.
+    let mut result: Option<(Option<&Value>, bool)> = None;
+    let mut in_value = false;
+
+    loop {
+        buffer.clear();
+        let event = reader
+            .read_event_into(&mut buffer)
+            .map_err(|err| err.to_string())?;
+        match event {
+            Event::Eof => break,
+            Event::Start(start) if start.name().as_ref() == b"row" => {
+                last_row = Some(row_index(&start, last_row));
+                last_col = None;
+                writer
+                    .write_event(Event::Start(start.into_owned()))
+                    .map_err(|err| err.to_string())?;
+            }
+            Event::Empty(start) if start.name().as_ref() == b"row" => {
+                last_row = Some(row_index(&start, last_row));
+                writer
+                    .write_event(Event::Empty(start.into_owned()))
+                    .map_err(|err| err.to_string())?;
+            }
+            Event::Start(start) if start.name().as_ref() == b"c" => {
+                let col = cell_column(&start, last_col);
+                last_col = Some(col);
+                let position = (last_row.unwrap_or_default(), col);
+                match results.get(&position) {
+                    Some(value) => {
+                        let mut updated = BytesStart::new("c");
+                        for attr in start.attributes().with_checks(false).flatten() {
+                            if attr.key.as_ref() != b"t" {
+                                updated.push_attribute(attr);
+                            }
+                        }
+                        match value {
+                            Some(Value::Text(_)) => updated.push_attribute(("t", "str")),
+                            Some(Value::Boolean(_)) => updated.push_attribute(("t", "b")),
+                            _ => {}
+                        }
+                        writer
+                            .write_event(Event::Start(updated))
+                            .map_err(|err| err.to_string())?;
+                        result = Some((value.as_ref(), false));
+                    }
+                    None => writer
+                        .write_event(Event::Start(start.into_owned()))
+                        .map_err(|err| err.to_string())?,
+                }
+            }
+            Event::Empty(start) if start.name().as_ref() == b"c" => {
+                last_col = Some(cell_column(&start, last_col));
+                writer
+                    .write_event(Event::Empty(start.into_owned()))
+                    .map_err(|err| err.to_string())?;
+            }
+            Event::Start(start) if start.name().as_ref() == b"v" && result.is_some() => {
+                if let Some((Some(value), written)) = result.as_mut() {
+                    write_cached_value(&mut writer, value)?;
+                    *written = true;
+                }
+                in_value = true;
+            }
+            Event::Empty(start) if start.name().as_ref() == b"v" && result.is_some() => {
+                if let Some((Some(value), written)) = result.as_mut() {
+                    write_cached_value(&mut writer, value)?;
+                    *written = true;
+                }
+            }
+            Event::End(end) if end.name().as_ref() == b"v" && in_value => in_value = false,
+            _ if in_value => {}
+            Event::End(end) if end.name().as_ref() == b"c" => {
+                if let Some((Some(value), false)) = result.take() {
+                    write_cached_value(&mut writer, value)?;
+                }
+                writer
+                    .write_event(Event::End(end.into_owned()))
+                    .map_err(|err| err.to_string())?;
+            }
+            event => writer
+                .write_event(event.into_owned())
+                .map_err(|err| err.to_string())?,
+        }
+    }
+
+    Ok(writer.into_inner())
+}
+
+fn write_cached_value(writer: &mut XmlWriter<Vec<u8>>, value: &Value) -> Result<(), String> {
+    let text = match value {
+        Value::Empty => "0".to_string(),
+        Value::Number(number) => number.to_string(),
+        Value::Text(text) => xml_escape(text),
+        Value::Boolean(flag) => u8::from(*flag).to_string(),
+    };
+    writer
+        .get_mut()
+        .write_all(format!("<v>{}</v>", text).as_bytes())
+        .map_err(|err| err.to_string())
+}
+
+/// Reads the text of every shared string, including all rich text runs.
+/// Rich text of cell strings: runs keep their `<t>` elements.
+const CELL_TEXT_MARKUP: PlaceholderMarkup = PlaceholderMarkup {
+    paragraph_tags: &[b"is"],
+    text_tag: Some(b"t"),
+    line_break: None,
+};
+
+/// Header and footer text, in which `&` starts a formatting code.
+const HEADER_FOOTER_MARKUP: PlaceholderMarkup = PlaceholderMarkup {
+    paragraph_tags: &[
+        b"oddHeader",
+        b"oddFooter",
+        b"evenHeader",
+        b"evenFooter",
+        b"firstHeader",
+        b"firstFooter",
+    ],
+    text_tag: None,
+    line_break: None,
+};
+
+/// Turns cells whose shared string contains a `{{placeholder}}` into inline
+/// strings with the same runs, so that every generated sheet can fill in its
+/// own values.
+fn inline_placeholder_strings(
+    sheet_xml: &[u8],
+    shared_strings: &[String],
+    shared_string_markup: &[String],
+) -> Result<Vec<u8>, String> {
+    let has_placeholders = |index: usize| {
+        shared_strings
+            .get(index)
+            .is_some_and(|text| !find_placeholders(text).is_empty())
+    };
+    if !(0..shared_strings.len()).any(has_placeholders) {
+        return Ok(sheet_xml.to_vec());
+    }
+
+    let mut reader = XmlReader::from_reader(sheet_xml);
+    reader.trim_text(false);
+    let mut writer = XmlWriter::new(Vec::new());
+    let mut buffer = Vec::new();
+
+    loop {
+        buffer.clear();
+        let event = reader
+            .read_event_into(&mut buffer)
+            .map_err(|err| err.to_string())?
+            .into_owned();
+        let start = match event {
+            Event::Eof => break,
+            Event::Start(start)
+                if start.name().as_ref() == b"c"
+                    && attribute_value(&start, b"t").as_deref() == Some("s") =>
+            {
+                start
+            }
+            event => {
+                writer.write_event(event).map_err(|err| err.to_string())?;
+                continue;
+            }
+        };
+
+        // Buffer the cell to find its shared string index.
+        let mut cell_events = Vec::new();
+        let mut index = None;
+        let mut in_value = false;
+        loop {
+            buffer.clear();
+            let event = reader
+                .read_event_into(&mut buffer)
+                .map_err(|err| err.to_string())?
+                .into_owned();
+            match &event {
+                Event::Eof => break,
+                Event::End(end) if end.name().as_ref() == b"c" => break,
+                Event::Start(start) if start.name().as_ref() == b"v" => in_value = true,
+                Event::End(end) if end.name().as_ref() == b"v" => in_value = false,
+                Event::Text(text) if in_value => {
+                    index = text
+                        .unescape()
+                        .ok()
+                        .and_then(|text| text.trim().parse::<usize>().ok());
+                }
+                _ => {}
+            }
+            cell_events.push(event);
+        }
+
+        match index.filter(|index| has_placeholders(*index)) {
+            Some(index) => {
+                let mut cell = BytesStart::new("c");
+                for attr in start.attributes().with_checks(false).flatten() {
+                    if attr.key.as_ref() != b"t" {
+                        cell.push_attribute(attr);
+                    }
+                }
+                cell.push_attribute(("t", "inlineStr"));
+                writer
+                    .write_event(Event::Start(cell))
+                    .map_err(|err| err.to_string())?;
+                let markup = shared_string_markup
+                    .get(index)
+                    .map(String::as_str)
+                    .unwrap_or_default();
+                writer
+                    .get_mut()
+                    .write_all(format!("<is>{}</is>", markup).as_bytes())
+                    .map_err(|err| err.to_string())?;
+            }
+            None => {
+                writer
+                    .write_event(Event::Start(start))
+                    .map_err(|err| err.to_string())?;
+                for event in cell_events {
+                    writer.write_event(event).map_err(|err| err.to_string())?;
+                }
+            }
+        }
+        writer
+            .write_event(Event::End(BytesEnd::new("c")))
+            .map_err(|err| err.to_string())?;
+    }
+
+    Ok(writer.into_inner())
+}
+
+/// Lists the `{{placeholders}}` in the cell text and headers and footers of
+/// a sheet.
+fn sheet_placeholders(sheet_xml: &[u8]) -> Result<Vec<String>, String> {
+    let mut names = Vec::new();
+    collect_placeholders(sheet_xml, &CELL_TEXT_MARKUP, &mut names)?;
+    collect_placeholders(sheet_xml, &HEADER_FOOTER_MARKUP, &mut names)?;
+    Ok(names)
+}
+
+/// Replaces the `{{placeholders}}` in cell text and in headers and footers.
+fn fill_placeholders(
+    sheet_xml: &[u8],
+    values: &HashMap<String, String>,
+) -> Result<Vec<u8>, String> {
+    if values.is_empty() {
+        return Ok(sheet_xml.to_vec());
+    }
+    let sheet_xml = replace_placeholders(sheet_xml, &CELL_TEXT_MARKUP, values)?;
+    // A single `&` would be read as a formatting code.
+    let header_values = values
+        .iter()
+        .map(|(name, value)| (name.clone(), value.replace('&', "&&")))
+        .collect::<HashMap<_, _>>();
+    replace_placeholders(&sheet_xml, &HEADER_FOOTER_MARKUP, &header_values)
+}
+
+/// The markup inside every `<si>` of the shared strings, including rich text
+/// runs.
+fn read_shared_string_markup(xml: &[u8]) -> Result<Vec<String>, String> {
+    let mut reader = XmlReader::from_reader(xml);
+    reader.trim_text(false);
+    let mut buffer = Vec::new();
+    let mut items = Vec::new();
+    let mut start = None;
+
+    loop {
+        buffer.clear();
+        match reader
+            .read_event_into(&mut buffer)
+            .map_err(|err| err.to_string())?
+        {
+            Event::Eof => break,
+            Event::Start(event) if event.name().as_ref() == b"si" => {
+                start = Some(reader.buffer_position());
+            }
+            Event::Empty(event) if event.name().as_ref() == b"si" => items.push(String::new()),
+            Event::End(event) if event.name().as_ref() == b"si" => {
+                let end_tag_length = event.name().as_ref().len() + 3;
+                if let Some(start) = start.take() {
+                    let end = reader.buffer_position().saturating_sub(end_tag_length);
+                    items.push(String::from_utf8_lossy(&xml[start..end]).into_owned());
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Ok(items)
+}
+
+fn read_shared_strings(xml: &[u8]) -> Result<Vec<String>, String> {
+    let mut reader = XmlReader::from_reader(xml);
+    reader.trim_text(false);
+    let mut buffer = Vec::new();
+    let mut strings = Vec::new();
+    let mut current: Option<String> = None;
+    let mut in_text = false;
+    let mut in_phonetic = false;
+
+    loop {
+        buffer.clear();
+        match reader
+            .read_event_into(&mut buffer)
+            .map_err(|err| err.to_string())?
+        {
+            Event::Eof => break,
+            Event::Start(start) => match start.name().as_ref() {
+                b"si" => current = Some(String::new()),
+                b"t" => in_text = !in_phonetic,
+                b"rPh" => in_phonetic = true,
+                _ => {}
+            },
+            Event::Empty(start) if start.name().as_ref() == b"si" => strings.push(String::new()),
+            Event::Text(text) if in_text => {
+                if let Some(current) = current.as_mut() {
+                    current.push_str(&text.unescape().map_err(|err| err.to_string())?);
+                }
+            }
+            Event::End(end) => match end.name().as_ref() {
+                b"si" => strings.extend(current.take()),
+                b"t" => in_text = false,
+                b"rPh" => in_phonetic = false,
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+
+    Ok(strings)
+}
+
+/// Writes the cell with its new value, keeping the template cell's style so
+/// number and date formats still apply.
+fn write_replaced_cell(
+    writer: &mut XmlWriter<Vec<u8>>,
+    reference: &str,
+    value: &CellValue,
+    attrs: &[(String, String)],
+) -> Result<(), String> {
+    let mut cell = format!("<c r=\"{}\"", reference);
+    for (name, attr_value) in attrs {
+        if name == "r" || name == "t" {
+            continue;
+        }
+        cell.push_str(&format!(" {}=\"{}\"", name, attr_value));
+    }
+    match value {
+        CellValue::Text(text) => {
+            cell.push_str(" t=\"inlineStr\"><is><t>");
+            cell.push_str(&xml_escape(text));
+            cell.push_str("</t></is></c>");
+        }
+        CellValue::Number(number) => cell.push_str(&format!("><v>{}</v></c>", number)),
+        CellValue::Boolean(flag) => {
+            cell.push_str(&format!(" t=\"b\"><v>{}</v></c>", u8::from(*flag)))
+        }
+        CellValue::DateTime(date_time) => cell.push_str(&format!(
+            "><v>{}</v></c>",
+            CellValue::date_serial(date_time, false)
+        )),
+    }
+    writer
+        .get_mut()
+        .write_all(cell.as_bytes())
+        .map_err(|err| err.to_string())
+}
+
+fn collect_cell_attributes(event: &BytesStart) -> Vec<(String, String)> {
+    event
+        .attributes()
+        .with_checks(false)
+        .filter_map(|attr| attr.ok())
+        .map(|attr| {
+            (
+                String::from_utf8_lossy(attr.key.as_ref()).into_owned(),
+                String::from_utf8_lossy(attr.value.as_ref()).into_owned(),
+            )
+        })
+        .collect()
+}
+
+/// Workbook elements that follow `calcPr`, before which a missing `calcPr`
+/// is inserted.
+const AFTER_CALC_PROPERTIES: [&[u8]; 9] = [
+    b"oleSize",
+    b"customWorkbookViews",
+    b"pivotCaches",
+    b"smartTagPr",
+    b"smartTagTypes",
+    b"webPublishing",
+    b"fileRecoveryPr",
+    b"webPublishObjects",
+    b"extLst",
+];
+
+/// Rebuilds `workbook.xml` for the output sheets, keeping the template's
+/// workbook settings. Defined names scoped to the template sheet are
+/// repeated for every generated sheet; names scoped to sheets that were left
+/// out are dropped.
+fn build_workbook_xml(
+    original: &str,
+    source_sheets: &[WorkbookSheet],
+    sheets: &[WorksheetExport],
+) -> Result<Vec<u8>, String> {
+    let mut reader = XmlReader::from_str(original);
+    reader.trim_text(false);
+    let mut writer = XmlWriter::new(Vec::new());
+    let mut buffer = Vec::new();
+    let mut skip_depth = 0usize;
+    let mut has_calc_properties = false;
+    let mut defined_name: Option<(BytesStart<'static>, String)> = None;
+
+    loop {
+        buffer.clear();
+        let event = reader
+            .read_event_into(&mut buffer)
+            .map_err(|err| err.to_string())?;
+        if skip_depth > 0 {
+            match event {
+                Event::Start(_) => skip_depth += 1,
+                Event::End(_) => skip_depth -= 1,
+                Event::Eof => break,
+                _ => {}
+            }
+            continue;
+        }
+        if let Some((start, text)) = defined_name.as_mut() {
+            match event {
+                Event::Text(event) => {
+                    text.push_str(&event.unescape().map_err(|err| err.to_string())?)
+                }
+                Event::End(_) => {
+                    write_defined_names(&mut writer, start, text, source_sheets, sheets)?;
+                    defined_name = None;
+                }
+                Event::Eof => break,
+                _ => {}
+            }
+            continue;
+        }
+
+        let (start, is_empty) = match event {
+            Event::Start(start) => (start, false),
+            Event::Empty(start) => (start, true),
+            Event::End(end) => {
+                if end.name().as_ref() == b"workbook" && !has_calc_properties {
+                    write_calc_properties(&mut writer, None)?;
+                }
+                writer
+                    .write_event(Event::End(end.into_owned()))
+                    .map_err(|err| err.to_string())?;
+                continue;
+            }
+            Event::Eof => break,
+            event => {
+                writer
+                    .write_event(event.into_owned())
+                    .map_err(|err| err.to_string())?;
+                continue;
+            }
+        };
+
+        let updated = match start.name().as_ref() {
+            b"sheets" => {
+                write_sheet_list(&mut writer, source_sheets, sheets)?;
+                skip_depth = usize::from(!is_empty);
+                continue;
+            }
+            b"definedName" if is_empty => {
+                write_defined_names(&mut writer, &start, "", source_sheets, sheets)?;
+                continue;
+            }
+            b"definedName" => {
+                defined_name = Some((start.into_owned(), String::new()));
+                continue;
+            }
+            b"calcPr" => {
+                has_calc_properties = true;
+                if is_empty {
+                    write_calc_properties(&mut writer, Some(&start))?;
+                    continue;
+                }
+                with_attribute(&start, "fullCalcOnLoad", "1")
+            }
+            // The original active tab may not exist any more.
+            b"workbookView" => without_attributes(&start, &[b"activeTab", b"firstSheet"]),
+            name => {
+                if !has_calc_properties && AFTER_CALC_PROPERTIES.contains(&name) {
+                    write_calc_properties(&mut writer, None)?;
+                    has_calc_properties = true;
+                }
+                start.into_owned()
+            }
+        };
+        let event = if is_empty {
+            Event::Empty(updated)
+        } else {
+            Event::Start(updated)
+        };
+        writer.write_event(event).map_err(|err| err.to_string())?;
+    }
+
+    Ok(writer.into_inner())
+}
+
+/// Writes `calcPr`, based on the template's if it has one, so that
+/// formulas the engine cannot evaluate are recalculated on load.
+fn write_calc_properties(
+    writer: &mut XmlWriter<Vec<u8>>,
+    original: Option<&BytesStart>,
+) -> Result<(), String> {
+    let start = match original {
+        Some(original) => with_attribute(original, "fullCalcOnLoad", "1"),
+        None => {
+            let mut start = BytesStart::new("calcPr");
+            start.push_attribute(("fullCalcOnLoad", "1"));
+            start
+        }
+    };
+    writer
+        .write_event(Event::Empty(start))
+        .map_err(|err| err.to_string())
+}
+
+fn write_sheet_list(
+    writer: &mut XmlWriter<Vec<u8>>,
+    source_sheets: &[WorkbookSheet],
+    sheets: &[WorksheetExport],
+) -> Result<(), String> {
+    writer
+        .write_event(Event::Start(BytesStart::new("sheets")))
+        .map_err(|err| err.to_string())?;
+    for sheet in sheets {
+        let mut element = BytesStart::new("sheet");
+        element.push_attribute(("name", sheet.name.as_str()));
+        element.push_attribute(("sheetId", sheet.sheet_id.to_string().as_str()));
+        if let Some(source) = source_sheets.get(sheet.source_index) {
+            for (key, value) in &source.attributes {
+                // A hidden template sheet still produces visible sheets.
+                if key == "state" && sheet.generated {
+                    continue;
+                }
+                element.push_attribute((key.as_str(), value.as_str()));
+            }
+        }
+        element.push_attribute(("r:id", sheet.relationship_id.as_str()));
+        writer
+            .write_event(Event::Empty(element))
+            .map_err(|err| err.to_string())?;
+    }
+    writer
+        .write_event(Event::End(BytesEnd::new("sheets")))
+        .map_err(|err| err.to_string())
+}
+
+/// Writes a defined name once per output sheet it applies to, pointing
+/// `localSheetId` and sheet references at the output sheet and moving ranges
+/// below repeated rows.
+fn write_defined_names(
+    writer: &mut XmlWriter<Vec<u8>>,
+    start: &BytesStart,
+    text: &str,
+    source_sheets: &[WorkbookSheet],
+    sheets: &[WorksheetExport],
+) -> Result<(), String> {
+    let local_sheet = attribute_value(start, b"localSheetId")
+        .and_then(|value| value.trim().parse::<usize>().ok());
+    let targets = match local_sheet {
+        None => vec![(None, global_name_text(text, source_sheets, sheets))],
+        Some(source_index) => sheets
+            .iter()
+            .enumerate()
+            .filter(|(_, sheet)| sheet.source_index == source_index)
+            .map(|(index, sheet)| {
+                let text = match source_sheets.get(source_index) {
+                    Some(source) if source.name != sheet.name => {
+                        rename_sheet_references(text, &source.name, &sheet.name)
+                    }
+                    _ => text.to_string(),
+                };
+                let text = match &sheet.rows {
+                    Some(expansion) => expansion.shift_formula(&text, &sheet.name, None),
+                    None => text,
+                };
+                (Some(index), text)
+            })
+            .collect(),
+    };
+
+    for (index, text) in targets {
+        let start = match index {
+            Some(index) => with_attribute(start, "localSheetId", &index.to_string()),
+            None => start.to_owned(),
+        };
+        writer
+            .write_event(Event::Start(start))
+            .map_err(|err| err.to_string())?;
+        writer
+            .write_event(Event::Text(BytesText::new(&text)))
+            .map_err(|err| err.to_string())?;
+        writer
+            .write_event(Event::End(BytesEnd::new("definedName")))
+            .map_err(|err| err.to_string())?;
+    }
+    Ok(())
+}
+
+/// The formula of a workbook-wide name. Names that refer to sheets that
+/// were not kept become `#REF!`, and references to the template sheet point
+/// at the first sheet generated from it.
+fn global_name_text(
+    text: &str,
+    source_sheets: &[WorkbookSheet],
+    sheets: &[WorksheetExport],
+) -> String {
+    let output = |source_index| {
+        sheets
+            .iter()
+            .find(|sheet| sheet.source_index == source_index)
+    };
+    let removed = source_sheets
+        .iter()
+        .enumerate()
+        .any(|(index, source)| output(index).is_none() && refers_to_sheet(text, &source.name));
+    if removed {
+        return "#REF!".to_string();
+    }
+    let mut text = text.to_string();
+    for (index, source) in source_sheets.iter().enumerate() {
+        let Some(sheet) = output(index) else {
+            continue;
+        };
+        if !refers_to_sheet(&text, &source.name) {
+            continue;
+        }
+        if source.name != sheet.name {
+            text = rename_sheet_references(&text, &source.name, &sheet.name);
+        }
+        if let Some(expansion) = &sheet.rows {
+            text = expansion.shift_formula(&text, &sheet.name, None);
+        }
+    }
+    text
+}
+
+fn without_attributes(start: &BytesStart, keys: &[&[u8]]) -> BytesStart<'static> {
+    let name = String::from_utf8_lossy(start.name().as_ref()).into_owned();
+    let mut updated = BytesStart::new(name);
+    for attr in start.attributes().with_checks(false).flatten() {
+        if !keys.contains(&attr.key.as_ref()) {
+            updated.push_attribute(attr);
+        }
+    }
+    updated
+}
+
+fn build_workbook_rels(preserved: &[WorkbookRelationship], sheets: &[WorksheetExport]) -> Vec<u8> {
+    let mut xml = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?><Relationships xmlns=\"http://schemas.openxmlformats.org/package/2006/relationships\">",
+    );
+    for rel in preserved {
+        xml.push_str(&format!(
+            "<Relationship Id=\"{}\" Type=\"{}\" Target=\"{}\"/>",
+            xml_escape(&rel.id),
+            xml_escape(&rel.type_attr),
+            xml_escape(&rel.target)
+        ));
+    }
+    for sheet in sheets {
+        xml.push_str(&format!(
+            "<Relationship Id=\"{}\" Type=\"http://schemas.openxmlformats.org/officeDocument/2006/relationships/worksheet\" Target=\"{}\"/>",
+            xml_escape(&sheet.relationship_id),
+            xml_escape(&sheet.target)
+        ));
+    }
+    xml.push_str("</Relationships>");
+    xml.into_bytes()
+}
+
+/// Rewrites `[Content_Types].xml` with overrides for the output sheets and
+/// their copied parts instead of the template's sheets and `removed` parts.
+fn build_content_types(
+    original: &str,
+    sheets: &[WorksheetExport],
+    removed: &HashSet<String>,
+) -> Result<Vec<u8>, String> {
+    let mut reader = XmlReader::from_str(original);
+    reader.trim_text(false);
+    let mut writer = XmlWriter::new(Vec::new());
+    let mut buffer = Vec::new();
+
+    loop {
+        match reader
+            .read_event_into(&mut buffer)
+            .map_err(|err| err.to_string())?
+        {
+            Event::Eof => break,
+            Event::Empty(event) => {
+                if event.name().as_ref() == b"Override"
+                    && let Some(part_name) = attribute_value(&event, b"PartName")
+                    && (part_name.contains("/xl/worksheets/")
+                        || part_name == format!("/{}", CALC_CHAIN_ENTRY)
+                        || removed.contains(part_name.trim_start_matches('/')))
+                {
+                    buffer.clear();
+                    continue;
+                }
+                writer
+                    .write_event(Event::Empty(event.into_owned()))
+                    .map_err(|err| err.to_string())?;
+            }
+            Event::End(event) => {
+                if event.name().as_ref() == b"Types" {
+                    for sheet in sheets {
+                        let override_line = format!(
+                            "\n    <Override PartName=\"/xl/{}\" ContentType=\"application/vnd.openxmlformats-officedocument.spreadsheetml.worksheet+xml\"/>",
+                            sheet.target
+                        );
+                        writer
+                            .get_mut()
+                            .write_all(override_line.as_bytes())
+                            .map_err(|err| err.to_string())?;
+                        for part in &sheet.parts {
+                            let Some(content_type) = &part.content_type else {
+                                continue;
+                            };
+                            let override_line = format!(
+                                "\n    <Override PartName=\"/{}\" ContentType=\"{}\"/>",
+                                xml_escape(&part.name),
+                                xml_escape(content_type)
+                            );
+                            writer
+                                .get_mut()
+                                .write_all(override_line.as_bytes())
+                                .map_err(|err| err.to_string())?;
+                        }
+                    }
+                }
+                writer
+                    .write_event(Event::End(event.into_owned()))
+                    .map_err(|err| err.to_string())?;
+            }
+            other => {
+                writer
+                    .write_event(other.into_owned())
+                    .map_err(|err| err.to_string())?;
+            }
+        }
+        buffer.clear();
+    }
+
+    Ok(writer.into_inner())
+}
+
+fn build_root_relationships() -> Vec<u8> {
+    b"<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?><Relationships xmlns=\"http://schemas.openxmlformats.org/package/2006/relationships\"><Relationship Id=\"rId1\" Type=\"http://schemas.openxmlformats.org/officeDocument/2006/relationships/officeDocument\" Target=\"xl/workbook.xml\"/><Relationship Id=\"rId2\" Type=\"http://schemas.openxmlformats.org/package/2006/relationships/metadata/core-properties\" Target=\"docProps/core.xml\"/><Relationship Id=\"rId3\" Type=\"http://schemas.openxmlformats.org/officeDocument/2006/relationships/extended-properties\" Target=\"docProps/app.xml\"/></Relationships>".to_vec()
+}
+
+fn build_app_doc(sheets: &[WorksheetExport]) -> Vec<u8> {
+    let mut titles = String::new();
+    for sheet in sheets {
+        titles.push_str(&format!("<vt:lpstr>{}</vt:lpstr>", xml_escape(&sheet.name)));
+    }
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?><Properties xmlns=\"http://schemas.openxmlformats.org/officeDocument/2006/extended-properties\" xmlns:vt=\"http://schemas.openxmlformats.org/officeDocument/2006/docPropsVTypes\"><Application>Bulk Sheet Editor</Application><DocSecurity>0</DocSecurity><ScaleCrop>false</ScaleCrop><HeadingPairs><vt:vector size=\"2\" baseType=\"variant\"><vt:variant><vt:lpstr>Worksheets</vt:lpstr></vt:variant><vt:variant><vt:i4>{}</vt:i4></vt:variant></vt:vector></HeadingPairs><TitlesOfParts><vt:vector size=\"{}\" baseType=\"lpstr\">{}</vt:vector></TitlesOfParts><Company></Company><LinksUpToDate>false</LinksUpToDate><SharedDoc>false</SharedDoc><HyperlinksChanged>false</HyperlinksChanged><AppVersion>16.0300</AppVersion></Properties>",
+        sheets.len(),
+        sheets.len(),
+        titles
+    )
+    .into_bytes()
+}
+
+fn build_core_doc() -> Vec<u8> {
+    b"<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?><cp:coreProperties xmlns:cp=\"http://schemas.openxmlformats.org/package/2006/metadata/core-properties\" xmlns:dc=\"http://purl.org/dc/elements/1.1/\" xmlns:dcterms=\"http://purl.org/dc/terms/\" xmlns:dcmitype=\"http://purl.org/dc/dcmitype/\" xmlns:xsi=\"http://www.w3.org/2001/XMLSchema-instance\"><dc:creator>Bulk Sheet Editor</dc:creator><cp:lastModifiedBy>Bulk Sheet Editor</cp:lastModifiedBy><dcterms:created xsi:type=\"dcterms:W3CDTF\">2024-01-01T00:00:00Z</dcterms:created><dcterms:modified xsi:type=\"dcterms:W3CDTF\">2024-01-01T00:00:00Z</dcterms:modified></cp:coreProperties>".to_vec()
+}
+
+fn should_skip_entry(name: &str) -> bool {
+    name == "[Content_Types].xml"
+        || name == "_rels/.rels"
+        || name == "docProps/app.xml"
+        || name == "docProps/core.xml"
+        || name == "xl/workbook.xml"
+        || name == "xl/_rels/workbook.xml.rels"
+        || name == CALC_CHAIN_ENTRY
+        || name.starts_with("xl/worksheets/")
+}
+
+struct WorksheetExport {
+    name: String,
+    /// Position of the source sheet in the template workbook.
+    source_index: usize,
+    /// Whether this is a copy of the template sheet.
+    generated: bool,
+    /// The repeated rows of a generated sheet, which move print areas.
+    rows: Option<RowExpansion>,
+    relationship_id: String,
+    target: String,
+    sheet_id: u32,
+    data: Vec<u8>,
+    relationship_part: Option<Vec<u8>>,
+    /// Copies of the template sheet's tables, drawings and comments.
+    parts: Vec<CopiedPart>,
+}
+
+#[derive(Clone)]
+struct WorkbookRelationship {
+    id: String,
+    target: String,
+    type_attr: String,
+}
+
+/// A `<sheet>` entry of the template's `workbook.xml`.
+struct WorkbookSheet {
+    name: String,
+    relationship_id: String,
+    /// Remaining attributes such as `state`, unescaped.
+    attributes: Vec<(String, String)>,
+}
+
+/// The XML of a template worksheet and of its relationships.
+struct SheetPart {
+    xml: Vec<u8>,
+    relationships: Option<Vec<u8>>,
+}
+
+/// A workbook template whose selected sheet is copied once per generated
+/// sheet. Sheets listed as kept are copied unchanged before or after them.
+pub struct XlsxTemplate {
+    entries: BTreeMap<String, Vec<u8>>,
+    content_types_xml: String,
+    workbook_xml: String,
+    workbook_sheets: Vec<WorkbookSheet>,
+    preserved_relationships: Vec<WorkbookRelationship>,
+    next_relationship_index: u32,
+    template_index: usize,
+    template_sheet: SheetPart,
+    /// Tables, drawings, charts and comments of the template sheet, copied
+    /// for every generated sheet.
+    template_parts: DependentParts,
+    /// Entries replaced by the copies of `template_parts`.
+    replaced_entries: HashSet<String>,
+    /// Names in use by the rest of the package.
+    part_names: PartNames,
+    /// Kept sheets with their position in the template, in workbook order.
+    kept_before: Vec<(usize, SheetPart)>,
+    kept_after: Vec<(usize, SheetPart)>,
+    shared_strings: Vec<String>,
+    placeholders: Vec<String>,
+    date1904: bool,
+}
+
+impl XlsxTemplate {
+    pub fn load(path: &Path, sheet_name: &str, kept_sheets: &[KeptSheet]) -> Result<Self, String> {
+        let file = File::open(path).map_err(|err| err.to_string())?;
+        let mut archive = ZipArchive::new(file).map_err(|err| err.to_string())?;
+        let mut entries = BTreeMap::new();
+        for index in 0..archive.len() {
+            let mut file = archive.by_index(index).map_err(|err| err.to_string())?;
+            if !file.is_file() {
+                continue;
+            }
+            let mut data = Vec::new();
+            file.read_to_end(&mut data).map_err(|err| err.to_string())?;
+            entries.insert(file.name().to_string(), data);
+        }
+
+        let content_types_xml = entries
+            .get("[Content_Types].xml")
+            .ok_or_else(|| "Workbook content types missing".to_string())?
+            .clone();
+        let content_types_xml =
+            String::from_utf8(content_types_xml).map_err(|err| err.to_string())?;
+
+        let workbook_xml = entries
+            .get("xl/workbook.xml")
+            .ok_or_else(|| "Workbook definition missing".to_string())?
+            .clone();
+        let workbook_xml = String::from_utf8(workbook_xml).map_err(|err| err.to_string())?;
+
+        let workbook_rels = entries
+            .get("xl/_rels/workbook.xml.rels")
+            .ok_or_else(|| "Workbook relationships missing".to_string())?
+            .clone();
+        let workbook_rels = String::from_utf8(workbook_rels).map_err(|err| err.to_string())?;
+
+        let (workbook_sheets, date1904) = parse_workbook(&workbook_xml)?;
+        let WorkbookRelationships {
+            worksheet_targets,
+            preserved: preserved_relationships,
+            max_id: next_relationship_index,
+        } = parse_workbook_relationships(&workbook_rels)?;
+
+        let sheet_part = |index: usize| -> Result<SheetPart, String> {
+            let sheet = &workbook_sheets[index];
+            let target = worksheet_targets
+                .get(&sheet.relationship_id)
+                .ok_or_else(|| format!("Relationship of sheet \"{}\" missing", sheet.name))?;
+            let xml = entries
+                .get(&format!("xl/{}", target))
+                .ok_or_else(|| format!("XML of sheet \"{}\" missing", sheet.name))?
+                .clone();
+            let relationships = entries
+                .get(&relationships_path(&format!("xl/{}", target)))
+                .cloned();
+            Ok(SheetPart { xml, relationships })
+        };
+
+        let template_index = workbook_sheets
+            .iter()
+            .position(|sheet| sheet.name == sheet_name)
+            .ok_or_else(|| "Template sheet not found".to_string())?;
+        let mut template_sheet = sheet_part(template_index)?;
+        let template_entry = format!(
+            "xl/{}",
+            worksheet_targets[&workbook_sheets[template_index].relationship_id]
+        );
+        let template_parts = DependentParts::collect(
+            &entries,
+            &template_entry,
+            &content_type_overrides(&content_types_xml)?,
+        )?;
+        let replaced_entries = template_parts.entry_names();
+        let part_names = PartNames::new(&entries, &replaced_entries);
+
+        let mut kept_before = Vec::new();
+        let mut kept_after = Vec::new();
+        for kept in kept_sheets {
+            if kept.name == sheet_name {
+                return Err(format!(
+                    "The template sheet \"{}\" cannot be kept as well",
+                    kept.name
+                ));
+            }
+            let index = workbook_sheets
+                .iter()
+                .position(|sheet| sheet.name == kept.name)
+                .ok_or_else(|| {
+                    let names = workbook_sheets
+                        .iter()
+                        .map(|sheet| sheet.name.as_str())
+                        .collect::<Vec<_>>();
+                    format!(
+                        "Sheet \"{}\" not found; the template has: {}",
+                        kept.name,
+                        names.join(", ")
+                    )
+                })?;
+            let part = (index, sheet_part(index)?);
+            match kept.position {
+                SheetPosition::Before => kept_before.push(part),
+                SheetPosition::After => kept_after.push(part),
+            }
+        }
+        kept_before.sort_by_key(|(index, _)| *index);
+        kept_after.sort_by_key(|(index, _)| *index);
+        kept_before.dedup_by_key(|(index, _)| *index);
+        kept_after.dedup_by_key(|(index, _)| *index);
+
+        let (shared_strings, shared_string_markup) = match entries.get("xl/sharedStrings.xml") {
+            Some(xml) => (read_shared_strings(xml)?, read_shared_string_markup(xml)?),
+            None => (Vec::new(), Vec::new()),
+        };
+        template_sheet.xml = inline_placeholder_strings(
+            &template_sheet.xml,
+            &shared_strings,
+            &shared_string_markup,
+        )?;
+        let placeholders = sheet_placeholders(&template_sheet.xml)?;
+
+        Ok(Self {
+            entries,
+            content_types_xml,
+            workbook_xml,
+            workbook_sheets,
+            preserved_relationships,
+            next_relationship_index,
+            template_index,
+            template_sheet,
+            template_parts,
+            replaced_entries,
+            part_names,
+            kept_before,
+            kept_after,
+            shared_strings,
+            placeholders,
+            date1904,
+        })
+    }
+
+    /// The `{{placeholders}}` in the template sheet's text, headers and
+    /// footers.
+    pub fn placeholders(&self) -> &[String] {
+        &self.placeholders
+    }
+
+    /// Builds a workbook with one copy of the template sheet per entry,
+    /// between the kept sheets.
+    pub fn render(&self, sheets: &[SheetContent]) -> Result<Vec<u8>, String> {
+        let template_name = &self.workbook_sheets[self.template_index].name;
+        let mut part_names = self.part_names.clone();
+        let mut sources = Vec::new();
+        for (index, part) in &self.kept_before {
+            sources.push(SheetSource::kept(
+                &self.workbook_sheets[*index],
+                *index,
+                part,
+            ));
+        }
+        for sheet in sheets {
+            let parts = self.template_parts.copy_for_sheet(
+                &mut part_names,
+                self.template_sheet.relationships.as_deref(),
+                template_name,
+                &sheet.name,
+                sheet.rows.as_ref(),
+            )?;
+            let sheet_xml = match &sheet.rows {
+                Some(expansion) => {
+                    expand_sheet_rows(&self.template_sheet.xml, expansion, template_name)?
+                }
+                None => self.template_sheet.xml.clone(),
+            };
+            let sheet_xml = match &sheet.cards {
+                Some(cards) => tile_sheet_cards(&sheet_xml, cards, template_name)?,
+                None => sheet_xml,
+            };
+            // Placeholders first, so that mapped cells replace whole cells.
+            let sheet_xml = fill_placeholders(&sheet_xml, sheet.placeholders)?;
+            let sheet_xml = if self.date1904 {
+                update_sheet_xml(&sheet_xml, &dates_as_1904(sheet.cells))?
+            } else {
+                update_sheet_xml(&sheet_xml, sheet.cells)?
+            };
+            let sheet_xml = recalculate_sheet_xml(&sheet_xml, &self.shared_strings)?;
+            let sheet_xml = rename_table_formulas(&sheet_xml, &parts.renamed_tables)?;
+            sources.push(SheetSource {
+                name: sheet.name.clone(),
+                source_index: self.template_index,
+                generated: true,
+                rows: sheet.rows,
+                data: sheet_xml,
+                relationship_part: parts.relationships,
+                parts: parts.parts,
+            });
+        }
+        for (index, part) in &self.kept_after {
+            sources.push(SheetSource::kept(
+                &self.workbook_sheets[*index],
+                *index,
+                part,
+            ));
+        }
+
+        let sheet_exports = sources
+            .into_iter()
+            .enumerate()
+            .map(|(sheet_index, source)| WorksheetExport {
+                name: source.name,
+                source_index: source.source_index,
+                generated: source.generated,
+                rows: source.rows,
+                relationship_id: format!(
+                    "rId{}",
+                    self.next_relationship_index + sheet_index as u32 + 1
+                ),
+                target: format!("worksheets/sheet{}.xml", sheet_index + 1),
+                sheet_id: (sheet_index + 1) as u32,
+                data: source.data,
+                relationship_part: source.relationship_part,
+                parts: source.parts,
+            })
+            .collect::<Vec<_>>();
+
+        write_workbook_from_template(self, &sheet_exports)
+    }
+}
+
+/// An output sheet before it gets its place in the workbook.
+struct SheetSource {
+    name: String,
+    source_index: usize,
+    generated: bool,
+    rows: Option<RowExpansion>,
+    data: Vec<u8>,
+    relationship_part: Option<Vec<u8>>,
+    parts: Vec<CopiedPart>,
+}
+
+impl SheetSource {
+    /// A kept sheet, which still uses its own tables and drawings.
+    fn kept(sheet: &WorkbookSheet, source_index: usize, part: &SheetPart) -> Self {
+        Self {
+            name: sheet.name.clone(),
+            source_index,
+            generated: false,
+            rows: None,
+            data: part.xml.clone(),
+            relationship_part: part.relationships.clone(),
+            parts: Vec::new(),
+        }
+    }
+}
+
+/// Replaces dates by their serial in the 1904 date system, which
+/// `write_replaced_cell` does not know about.
+fn dates_as_1904(replacements: &CellReplacements) -> CellReplacements {
+    replacements
+        .iter()
+        .map(|(position, value)| {
+            let value = match value {
+                CellValue::DateTime(date_time) => {
+                    CellValue::Number(CellValue::date_serial(date_time, true))
+                }
+                value => value.clone(),
+            };
+            (*position, value)
+        })
+        .collect()
+}
+
+/// Reads the sheet list and the date system of `workbook.xml`.
+fn parse_workbook(workbook_xml: &str) -> Result<(Vec<WorkbookSheet>, bool), String> {
+    let mut reader = XmlReader::from_str(workbook_xml);
+    reader.trim_text(true);
+    let mut buffer = Vec::new();
+    let mut sheets = Vec::new();
+    let mut date1904 = false;
+
+    loop {
+        match reader
+            .read_event_into(&mut buffer)
+            .map_err(|err| err.to_string())?
+        {
+            Event::Eof => break,
+            Event::Empty(event) | Event::Start(event) if event.name().as_ref() == b"sheet" => {
+                let mut name = None;
+                let mut rel_id = None;
+                let mut attributes = Vec::new();
+                for attr in event.attributes().with_checks(false) {
+                    let attr = attr.map_err(|err| err.to_string())?;
+                    let key = String::from_utf8_lossy(attr.key.as_ref()).into_owned();
+                    let value = attr
+                        .unescape_value()
+                        .map_err(|err| err.to_string())?
+                        .into_owned();
+                    match key.as_str() {
+                        "name" => name = Some(value),
+                        "r:id" => rel_id = Some(value),
+                        "sheetId" => {}
+                        _ => attributes.push((key, value)),
+                    }
+                }
+                if let (Some(name), Some(relationship_id)) = (name, rel_id) {
+                    sheets.push(WorkbookSheet {
+                        name,
+                        relationship_id,
+                        attributes,
+                    });
+                }
+            }
+            Event::Empty(event) | Event::Start(event) if event.name().as_ref() == b"workbookPr" => {
+                date1904 = attribute_value(&event, b"date1904")
+                    .is_some_and(|value| value == "1" || value == "true");
+            }
+            _ => {}
+        }
+        buffer.clear();
+    }
+
+    Ok((sheets, date1904))
+}
+
+/// The relationships of `workbook.xml`.
+struct WorkbookRelationships {
+    /// Worksheet targets by relationship id.
+    worksheet_targets: HashMap<String, String>,
+    /// Other relationships to keep.
+    preserved: Vec<WorkbookRelationship>,
+    /// The highest `rId` number in use.
+    max_id: u32,
+}
+
+fn parse_workbook_relationships(xml: &str) -> Result<WorkbookRelationships, String> {
+    let mut reader = XmlReader::from_str(xml);
+    reader.trim_text(true);
+    let mut buffer = Vec::new();
+    let mut worksheet_targets = HashMap::new();
+    let mut preserved = Vec::new();
+    let mut max_id = 0u32;
+
+    loop {
+        match reader
+            .read_event_into(&mut buffer)
+            .map_err(|err| err.to_string())?
+        {
+            Event::Eof => break,
+            Event::Empty(event) if event.name().as_ref() == b"Relationship" => {
+                let mut id = None;
+                let mut target = None;
+                let mut kind = None;
+                for attr in event.attributes().with_checks(false) {
+                    let attr = attr.map_err(|err| err.to_string())?;
+                    let key = attr.key.as_ref();
+                    let value = String::from_utf8_lossy(attr.value.as_ref()).into_owned();
+                    if key == b"Id" {
+                        id = Some(value.clone());
+                        if let Some(suffix) = value.strip_prefix("rId")
+                            && let Ok(number) = suffix.parse::<u32>()
+                        {
+                            max_id = max_id.max(number);
+                        }
+                    } else if key == b"Target" {
+                        target = Some(value);
+                    } else if key == b"Type" {
+                        kind = Some(value);
+                    }
+                }
+
+                let id = id.ok_or_else(|| "Relationship id missing".to_string())?;
+                let target = target.ok_or_else(|| "Relationship target missing".to_string())?;
+                let kind = kind.ok_or_else(|| "Relationship type missing".to_string())?;
+
+                if kind.ends_with("/worksheet") {
+                    worksheet_targets.insert(id, target);
+                } else if !kind.ends_with("/calcChain") {
+                    // The calculation chain lists cells of the template
+                    // sheets and is dropped; applications rebuild it.
+                    preserved.push(WorkbookRelationship {
+                        id,
+                        target,
+                        type_attr: kind,
+                    });
+                }
+            }
+            _ => {}
+        }
+        buffer.clear();
+    }
+
+    Ok(WorkbookRelationships {
+        worksheet_targets,
+        preserved,
+        max_id,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::row_blocks::RowBlock;
+
+    #[test]
+    fn drops_cached_values_it_cannot_compute() {
+        let sheet = br#"<sheetData><row r="1"><c r="A1"><v>6</v></c><c r="B1"><v>0</v></c><c r="C1"><f>IF(B1=0,0,A1/B1)</f><v>3</v></c><c r="D1"><f>A1/B1</f><v>3</v></c><c r="E1" t="str"><f>Lookup!A1</f><v>old</v></c></row></sheetData>"#;
+        let updated = String::from_utf8(recalculate_sheet_xml(sheet, &[]).unwrap()).unwrap();
+        assert!(updated.contains(r#"<c r="C1"><f>IF(B1=0,0,A1/B1)</f><v>0</v></c>"#));
+        assert!(updated.contains(r#"<c r="D1"><f>A1/B1</f></c>"#));
+        assert!(updated.contains(r#"<c r="E1"><f>Lookup!A1</f></c>"#));
+    }
+
+    #[test]
+    fn points_workbook_names_at_output_sheets() {
+        let source = |name: &str| WorkbookSheet {
+            name: name.to_string(),
+            relationship_id: String::new(),
+            attributes: Vec::new(),
+        };
+        let output = |name: &str, source_index, rows| WorksheetExport {
+            name: name.to_string(),
+            source_index,
+            generated: true,
+            rows,
+            relationship_id: String::new(),
+            target: String::new(),
+            sheet_id: 1,
+            data: Vec::new(),
+            relationship_part: None,
+            parts: Vec::new(),
+        };
+        let sources = [source("Invoice"), source("Lookup"), source("Notes")];
+        let rows = RowExpansion {
+            block: RowBlock::parse("A5:C6").unwrap(),
+            count: 3,
+        };
+        let sheets = [
+            output("Invoice 1", 0, Some(rows)),
+            output("Invoice 2", 0, None),
+            output("Notes", 2, None),
+        ];
+        let text = |text| global_name_text(text, &sources, &sheets);
+        assert_eq!(text("Lookup!$A$1"), "#REF!");
+        assert_eq!(text("Invoice!$A$1*'Lookup'!B2"), "#REF!");
+        assert_eq!(text("Invoice!$B$8"), "'Invoice 1'!$B$12");
+        assert_eq!(text("Notes!$A$1:$A$4"), "Notes!$A$1:$A$4");
+        assert_eq!(text("\"Lookup!\"&Notes!A1"), "\"Lookup!\"&Notes!A1");
+    }
+
+    #[test]
+    fn fills_placeholders_in_shared_strings_and_headers() {
+        let sheet = concat!(
+            r#"<sheetData><row r="1"><c r="A1" s="2" t="s"><v>0</v></c><c r="B1" t="s"><v>1</v></c></row></sheetData>"#,
+            r#"<headerFooter><oddHeader>&amp;L{{Name}}&amp;R&amp;P</oddHeader></headerFooter>"#,
+        );
+        let shared_strings = ["Hi {{Name}}".to_string(), "plain".to_string()];
+        let markup = [
+            "<r><rPr><b/></rPr><t>Hi {{Na</t></r><r><t>me}}</t></r>".to_string(),
+            "<t>plain</t>".to_string(),
+        ];
+        let sheet = inline_placeholder_strings(sheet.as_bytes(), &shared_strings, &markup).unwrap();
+        assert_eq!(sheet_placeholders(&sheet).unwrap(), ["Name"]);
+
+        let values = HashMap::from([("Name".to_string(), "A & B".to_string())]);
+        let filled = String::from_utf8(fill_placeholders(&sheet, &values).unwrap()).unwrap();
+        assert_eq!(
+            filled,
+            concat!(
+                r#"<sheetData><row r="1"><c r="A1" s="2" t="inlineStr"><is><r><rPr><b/></rPr><t xml:space="preserve">Hi A &amp; B</t></r>"#,
+                r#"<r><t xml:space="preserve"></t></r></is></c><c r="B1" t="s"><v>1</v></c></row></sheetData>"#,
+                r#"<headerFooter><oddHeader>&amp;LA &amp;&amp; B&amp;R&amp;P</oddHeader></headerFooter>"#,
+            )
+        );
+    }
+}
@@ -0,0 +1,49 @@
+use quick_xml::events::BytesStart;
+
+pub fn attribute_value(event: &BytesStart, key: &[u8]) -> Option<String> {
+    event
+        .attributes()
+        .with_checks(false)
+        .filter_map(|attr| attr.ok())
+        .find(|attr| attr.key.as_ref() == key)
+        .map(|attr| String::from_utf8_lossy(attr.value.as_ref()).into_owned())
+}
+
+/// Like `attribute_value`, but with entities such as `&amp;` resolved.
+pub fn unescaped_attribute(event: &BytesStart, key: &[u8]) -> Option<String> {
+    event
+        .attributes()
+        .with_checks(false)
+        .flatten()
+        .find(|attr| attr.key.as_ref() == key)
+        .and_then(|attr| attr.unescape_value().ok().map(|value| value.into_owned()))
+}
+
+/// Returns a copy of the tag with the attribute set to `value`, keeping the
+/// position of an existing attribute.
+pub fn with_attribute(event: &BytesStart, key: &str, value: &str) -> BytesStart<'static> {
+    let name = String::from_utf8_lossy(event.name().as_ref()).into_owned();
+    let mut updated = BytesStart::new(name);
+    let mut replaced = false;
+    for attr in event.attributes().with_checks(false).flatten() {
+        if attr.key.as_ref() == key.as_bytes() {
+            updated.push_attribute((key, value));
+            replaced = true;
+        } else {
+            updated.push_attribute(attr);
+        }
+    }
+    if !replaced {
+        updated.push_attribute((key, value));
+    }
+    updated
+}
+
+pub fn xml_escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
@@ -0,0 +1,535 @@
+use crate::ui_step_modules::{Project, SharedState, job, project_base_dir, validate_inputs};
+use bulk_sheet_engine::{
+    KeptSheet, Mapping, OutputMode, OutputTarget, SheetPosition, SortKey, TemplateFormat,
+    ValueType, parse_cell_targets, row_replacements,
+};
+use clap::{Args, Parser, Subcommand};
+use std::path::{Path, PathBuf};
+
+/// Generation finished and all output was written.
+pub const EXIT_SUCCESS: i32 = 0;
+// Invalid command line arguments exit with code 2 through clap.
+/// The CSV file, template or mappings could not be used.
+pub const EXIT_INPUT_ERROR: i32 = 3;
+/// Building or writing the output failed.
+pub const EXIT_OUTPUT_ERROR: i32 = 4;
+
+#[derive(Parser)]
+#[command(
+    version,
+    about = "Bulk-create sheets and documents from a template and a CSV file"
+)]
+pub struct Cli {
+    /// Runs without opening a window. Starts the GUI when omitted.
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Fill the template with every CSV row and write the result
+    Generate(GenerateArgs),
+}
+
+#[derive(Args)]
+pub struct GenerateArgs {
+    /// Saved project to run; the other options override its settings
+    #[arg(long)]
+    project: Option<PathBuf>,
+    /// CSV file, JSON or NDJSON file, XLSX/XLS/ODS workbook or SQLite
+    /// database with one record per row
+    #[arg(long, alias = "data", required_unless_present = "project")]
+    csv: Option<PathBuf>,
+    /// Treat the first CSV row as data instead of column names
+    #[arg(long)]
+    no_headers: bool,
+    /// CSV field delimiter such as `;` or `tab`; detected by default
+    #[arg(long, value_name = "CHAR", value_parser = parse_char)]
+    delimiter: Option<char>,
+    /// CSV quote character; detected by default
+    #[arg(long, value_name = "CHAR", value_parser = parse_char)]
+    quote: Option<char>,
+    /// Escape character inside CSV quotes such as `\`; quotes are doubled
+    /// otherwise
+    #[arg(long, value_name = "CHAR", value_parser = parse_char)]
+    escape: Option<char>,
+    /// Ignore CSV lines starting with this character, e.g. `#`
+    #[arg(long, value_name = "CHAR", value_parser = parse_char)]
+    comment: Option<char>,
+    /// CSV encoding such as `utf-8`, `windows-1252` or `utf-16le`; detected
+    /// by default
+    #[arg(long)]
+    encoding: Option<String>,
+    /// Preamble lines above the CSV header row
+    #[arg(long, value_name = "N")]
+    skip_lines: Option<usize>,
+    /// Trim whitespace around CSV values
+    #[arg(long)]
+    trim: bool,
+    /// Accept CSV rows with more or fewer fields than the others
+    #[arg(long)]
+    flexible: bool,
+    /// Dotted path of the array of records in a JSON file, e.g.
+    /// `data.orders`; detected by default
+    #[arg(long, value_name = "PATH")]
+    records: Option<String>,
+    /// Array within each JSON record written as one row per element, e.g.
+    /// `items`; other arrays become indexed columns such as `tags.0`
+    #[arg(long, value_name = "PATH")]
+    expand: Option<String>,
+    /// Table or view of a SQLite database to read
+    #[arg(long)]
+    table: Option<String>,
+    /// SQL query whose result rows are read from a SQLite database, instead
+    /// of a table
+    #[arg(long, value_name = "SQL")]
+    query: Option<String>,
+    /// Sheet of a workbook data file; defaults to the first sheet
+    #[arg(long)]
+    data_sheet: Option<String>,
+    /// Rows above the header row of a workbook data file, such as a title
+    #[arg(long, value_name = "N")]
+    skip_rows: Option<usize>,
+    /// Cells of a workbook data file to read, e.g. `B3:F200`
+    #[arg(long, value_name = "RANGE")]
+    data_range: Option<String>,
+    /// Template workbook (XLSX/ODS) or text document (ODT/DOCX)
+    #[arg(long, required_unless_present = "project")]
+    template: Option<PathBuf>,
+    /// Template sheet to copy; defaults to the first sheet
+    #[arg(long)]
+    sheet: Option<String>,
+    /// Template rows written once per row of a group, e.g. `A10:F10`; the
+    /// rows below move down
+    #[arg(long, value_name = "RANGE")]
+    repeat_rows: Option<String>,
+    /// Template range filled once per row, such as a label in `A1:C6`; with
+    /// --tiles, every sheet holds several cards
+    #[arg(long, value_name = "RANGE", requires = "tiles")]
+    card: Option<String>,
+    /// Cards across and down per sheet, e.g. `2x5`
+    #[arg(long, value_name = "ACROSSxDOWN", requires = "card")]
+    tiles: Option<String>,
+    /// Column to cell mapping such as `Amount=B4`, `Name=B2,H40` or
+    /// `Total=B2:D2`; repeat for more columns. Columns without headers are
+    /// named `Column 1`, `Column 2`, …
+    #[arg(long = "map", value_name = "COLUMN=CELLS")]
+    mappings: Vec<String>,
+    /// Derived value such as `{{First}} {{Last}}=B2`, `{{Amount * 1.19}}=F30`
+    /// or `{{upper(City)}}=C4`; repeat for more cells
+    #[arg(long = "derive", value_name = "EXPRESSION=CELLS")]
+    derived: Vec<String>,
+    /// Column written for every row of a group, one row below the other,
+    /// e.g. `Item=A10`; repeat for more columns
+    #[arg(long = "detail", value_name = "COLUMN=CELLS")]
+    details: Vec<String>,
+    /// Cell type of a mapped column such as `Amount=number` or
+    /// `Date=date:%d.%m.%Y`; derived values are named by their cells, e.g.
+    /// `F30=number`. Types: auto, text, number, integer, boolean, date,
+    /// datetime
+    #[arg(long = "type", value_name = "COLUMN=TYPE[:FORMAT]")]
+    types: Vec<String>,
+    /// Output file, or the folder or ZIP archive with --per-row
+    #[arg(long, short)]
+    output: PathBuf,
+    /// Write one file per CSV row instead of a single file
+    #[arg(long)]
+    per_row: bool,
+    /// Pack the per-row files into a ZIP archive
+    #[arg(long)]
+    zip: bool,
+    /// File name pattern for per-row output, e.g. `invoice_{{CustomerId}}.xlsx`
+    #[arg(long)]
+    file_name: Option<String>,
+    /// Sheet name pattern, e.g. `{{LastName}}, {{FirstName}}`
+    #[arg(long)]
+    sheet_name: Option<String>,
+    /// CSV rows to generate, e.g. `1-20,35` or `40-`; all rows by default
+    #[arg(long, value_name = "RANGES")]
+    rows: Option<String>,
+    /// Only generate rows matching a condition such as `Status == "active"`
+    /// or `Amount > 0 and Country != "DE"`
+    #[arg(long = "where", value_name = "CONDITION")]
+    condition: Option<String>,
+    /// Sort the rows by a column, e.g. `Date:desc`; repeat for tie breakers
+    #[arg(long = "sort", value_name = "COLUMN[:asc|desc]")]
+    sort_by: Vec<String>,
+    /// Combine rows with the same value into one sheet or file, e.g.
+    /// `CustomerId`; repeat for composite keys
+    #[arg(long = "group-by", value_name = "COLUMN")]
+    group_by: Vec<String>,
+    /// Another template sheet to copy unchanged, after the generated sheets
+    /// unless `:before` is given; repeat for more sheets
+    #[arg(long = "keep-sheet", value_name = "NAME[:before|after]")]
+    keep_sheets: Vec<String>,
+}
+
+/// Runs a command and returns the process exit code.
+pub fn run(command: Command) -> i32 {
+    match command {
+        Command::Generate(args) => generate(args),
+    }
+}
+
+fn generate(args: GenerateArgs) -> i32 {
+    // The template is loaded up front so that a template that cannot be
+    // used is an input error rather than a failure to write.
+    let job = match prepare_state(&args).and_then(|state| {
+        let job = job(&state, &args.output)?;
+        job.check_template()?;
+        Ok(job)
+    }) {
+        Ok(job) => job,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            return EXIT_INPUT_ERROR;
+        }
+    };
+
+    match job.run() {
+        Ok(report) => {
+            println!("{}", report);
+            println!("Rows: {}", report.rows);
+            println!("Output: {}", args.output.display());
+            EXIT_SUCCESS
+        }
+        Err(err) => {
+            eprintln!("error: Cannot write {}: {}", args.output.display(), err);
+            EXIT_OUTPUT_ERROR
+        }
+    }
+}
+
+fn prepare_state(args: &GenerateArgs) -> Result<SharedState, String> {
+    let (mut project, base_dir) = match &args.project {
+        Some(path) => (
+            Project::load(path)
+                .map_err(|err| format!("Cannot read {}: {}", path.display(), err))?,
+            project_base_dir(path),
+        ),
+        None => (Project::default(), PathBuf::from(".")),
+    };
+    if args.project.is_none() || args.no_headers {
+        project.csv.has_headers = !args.no_headers;
+    }
+    // Paths given on the command line are relative to the working directory,
+    // not to the project file.
+    if let Some(csv) = &args.csv {
+        project.csv.path = Some(absolute_path(csv));
+        project.csv.sheet = None;
+    }
+    let options = &mut project.csv.options;
+    options.delimiter = args.delimiter.or(options.delimiter);
+    options.quote = args.quote.or(options.quote);
+    options.escape = args.escape.or(options.escape);
+    options.comment = args.comment.or(options.comment);
+    if let Some(encoding) = &args.encoding {
+        options.encoding = Some(encoding.clone());
+    }
+    if let Some(skip_lines) = args.skip_lines {
+        options.skip_lines = skip_lines;
+    }
+    options.trim |= args.trim;
+    options.flexible |= args.flexible;
+    if let Some(records) = &args.records {
+        project.csv.json.records = records.clone();
+    }
+    if let Some(expand) = &args.expand {
+        project.csv.json.expand = expand.clone();
+    }
+    if let Some(table) = &args.table {
+        project.csv.sqlite.table = table.clone();
+        project.csv.sqlite.query.clear();
+    }
+    if let Some(query) = &args.query {
+        project.csv.sqlite.query = query.clone();
+    }
+    if let Some(sheet) = &args.data_sheet {
+        project.csv.sheet = Some(sheet.clone());
+    }
+    if let Some(skip_rows) = args.skip_rows {
+        project.csv.skip_rows = skip_rows;
+    }
+    if let Some(range) = &args.data_range {
+        project.csv.range = range.clone();
+    }
+    if let Some(template) = &args.template {
+        project.template.path = Some(absolute_path(template));
+        project.template.sheet = None;
+    }
+    if let Some(sheet) = &args.sheet {
+        project.template.sheet = Some(sheet.clone());
+    }
+    if let Some(rows) = &args.repeat_rows {
+        project.template.repeat_rows = rows.clone();
+    }
+    if let (Some(card), Some(tiles)) = (&args.card, &args.tiles) {
+        project.template.card = card.clone();
+        project.template.tiles = tiles.clone();
+    }
+
+    let mut mappings = Vec::new();
+    let columns = args
+        .mappings
+        .iter()
+        .map(|mapping| (mapping, false))
+        .chain(args.details.iter().map(|mapping| (mapping, true)));
+    for (mapping, detail) in columns {
+        let (column, cell) = mapping
+            .split_once('=')
+            .ok_or_else(|| format!("Mapping \"{}\" must look like COLUMN=CELL", mapping))?;
+        let cell = cell.trim().to_ascii_uppercase();
+        parse_cell_targets(&cell)?;
+        mappings.push(Mapping::new(column.trim(), cell).with_detail(detail));
+    }
+    for derived in &args.derived {
+        let (expression, cell) = derived.rsplit_once('=').ok_or_else(|| {
+            format!(
+                "Derived value \"{}\" must look like EXPRESSION=CELLS",
+                derived
+            )
+        })?;
+        let cell = cell.trim().to_ascii_uppercase();
+        parse_cell_targets(&cell)?;
+        mappings.push(Mapping::derived(expression, cell));
+    }
+    if !mappings.is_empty() {
+        let is_document = project
+            .template
+            .path
+            .as_deref()
+            .is_some_and(|path| TemplateFormat::from_path(Path::new(path)).is_document());
+        if is_document {
+            return Err(
+                "Document templates are filled through {{placeholders}}; --map and --derive are not used"
+                    .to_string(),
+            );
+        }
+        project.mappings.retain(|saved| {
+            !mappings.iter().any(|mapping| match &saved.expression {
+                Some(_) => {
+                    mapping.expression.is_some() && mapping.cell.eq_ignore_ascii_case(&saved.cell)
+                }
+                None => mapping.expression.is_none() && mapping.column == saved.column,
+            })
+        });
+        project.mappings.extend(mappings);
+    }
+    for value_type in &args.types {
+        let (column, value_type) = value_type
+            .split_once('=')
+            .ok_or_else(|| format!("Type \"{}\" must look like COLUMN=TYPE", value_type))?;
+        let (value_type, format) = match value_type.split_once(':') {
+            Some((value_type, format)) => (value_type, Some(format.to_string())),
+            None => (value_type, None),
+        };
+        let value_type = ValueType::from_label(value_type)
+            .ok_or_else(|| format!("Unknown cell type \"{}\"", value_type))?;
+        let mapping = project
+            .mappings
+            .iter_mut()
+            .find(|mapping| match &mapping.expression {
+                Some(_) => mapping.cell.eq_ignore_ascii_case(column.trim()),
+                None => mapping.column == column.trim(),
+            })
+            .ok_or_else(|| format!("Column \"{}\" is not mapped to a cell", column.trim()))?;
+        mapping.value_type = value_type;
+        mapping.format = format;
+    }
+
+    if args.per_row {
+        project.output.mode = OutputMode::FilePerRow;
+    }
+    if args.zip {
+        project.output.target = OutputTarget::ZipArchive;
+    }
+    if let Some(pattern) = &args.file_name {
+        project.output.file_name_pattern = pattern.clone();
+    }
+    if let Some(pattern) = &args.sheet_name {
+        project.output.sheet_name_pattern = pattern.clone();
+    }
+    if let Some(rows) = &args.rows {
+        project.filter.rows = rows.clone();
+    }
+    if let Some(condition) = &args.condition {
+        project.filter.condition = condition.clone();
+    }
+    if !args.sort_by.is_empty() {
+        project.grouping.sort_by = args.sort_by.iter().map(|key| parse_sort_key(key)).collect();
+    }
+    if !args.group_by.is_empty() {
+        project.grouping.group_by = args.group_by.clone();
+    }
+    for kept in &args.keep_sheets {
+        let kept = parse_kept_sheet(kept);
+        project
+            .output
+            .kept_sheets
+            .retain(|saved| saved.name != kept.name);
+        project.output.kept_sheets.push(kept);
+    }
+
+    let mut state = SharedState::default();
+    project.apply(&mut state, &base_dir)?;
+    if state.output_mode == OutputMode::SingleFile && (args.zip || args.file_name.is_some()) {
+        return Err("--zip and --file-name need per-row output (--per-row)".to_string());
+    }
+    validate_inputs(&state)?;
+    if !state
+        .template_format()
+        .is_some_and(|format| format.is_document())
+    {
+        // Surface values that do not match their cell type as input errors.
+        // Only the preview rows are checked here; values further down a large
+        // file fail while generating, with exit code 4.
+        row_replacements(&state.selected_data()?, &state.mappings())?;
+    }
+    state.grouping.groups(&state.data_table())?;
+    if !state.has_all_rows() {
+        eprintln!(
+            "note: checked the first {} data rows; later rows are checked while generating",
+            state.csv_rows.len()
+        );
+    }
+    Ok(state)
+}
+
+/// Parses `COLUMN[:asc|desc]`; other suffixes are part of the column name.
+fn parse_sort_key(value: &str) -> SortKey {
+    let (column, descending) = match value.rsplit_once(':') {
+        Some((column, "desc")) => (column, true),
+        Some((column, "asc")) => (column, false),
+        _ => (value, false),
+    };
+    SortKey {
+        column: column.to_string(),
+        descending,
+    }
+}
+
+/// Parses `NAME[:before|after]`; other suffixes are part of the name.
+fn parse_kept_sheet(value: &str) -> KeptSheet {
+    let (name, position) = match value.rsplit_once(':') {
+        Some((name, "before")) => (name, SheetPosition::Before),
+        Some((name, "after")) => (name, SheetPosition::After),
+        _ => (value, SheetPosition::After),
+    };
+    KeptSheet {
+        name: name.to_string(),
+        position,
+    }
+}
+
+/// Parses a single character, or `tab` for a tab.
+fn parse_char(value: &str) -> Result<char, String> {
+    if value.eq_ignore_ascii_case("tab") || value == "\\t" {
+        return Ok('\t');
+    }
+    let mut chars = value.chars();
+    match (chars.next(), chars.next()) {
+        (Some(ch), None) => Ok(ch),
+        _ => Err(format!("\"{}\" is not a single character", value)),
+    }
+}
+
+fn absolute_path(path: &Path) -> String {
+    std::path::absolute(path)
+        .unwrap_or_else(|_| path.to_path_buf())
+        .to_string_lossy()
+        .into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use zip::write::{FileOptions, ZipWriter};
+
+    /// A folder with `data.csv` and a workbook `template.xlsx` whose sheet
+    /// `Invoice` is the only one.
+    fn fixture(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("cli-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("data.csv"), "Name,Amount\nAda,1\nBob,2\n").unwrap();
+
+        let parts = [
+            (
+                "[Content_Types].xml",
+                r#"<?xml version="1.0" encoding="UTF-8"?><Types xmlns="http://schemas.openxmlformats.org/package/2006/content-types"><Default Extension="rels" ContentType="application/vnd.openxmlformats-package.relationships+xml"/><Default Extension="xml" ContentType="application/xml"/><Override PartName="/xl/workbook.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.sheet.main+xml"/><Override PartName="/xl/worksheets/sheet1.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.worksheet+xml"/></Types>"#,
+            ),
+            (
+                "_rels/.rels",
+                r#"<?xml version="1.0" encoding="UTF-8"?><Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships"><Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/officeDocument" Target="xl/workbook.xml"/></Relationships>"#,
+            ),
+            (
+                "xl/workbook.xml",
+                r#"<?xml version="1.0" encoding="UTF-8"?><workbook xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main" xmlns:r="http://schemas.openxmlformats.org/officeDocument/2006/relationships"><sheets><sheet name="Invoice" sheetId="1" r:id="rId1"/></sheets></workbook>"#,
+            ),
+            (
+                "xl/_rels/workbook.xml.rels",
+                r#"<?xml version="1.0" encoding="UTF-8"?><Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships"><Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/worksheet" Target="worksheets/sheet1.xml"/></Relationships>"#,
+            ),
+            (
+                "xl/worksheets/sheet1.xml",
+                r#"<?xml version="1.0" encoding="UTF-8"?><worksheet xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main"><sheetData><row r="1"><c r="A1" t="inlineStr"><is><t>Name</t></is></c></row></sheetData></worksheet>"#,
+            ),
+        ];
+        let mut zip = ZipWriter::new(std::fs::File::create(dir.join("template.xlsx")).unwrap());
+        for (name, data) in parts {
+            zip.start_file(name, FileOptions::default()).unwrap();
+            zip.write_all(data.as_bytes()).unwrap();
+        }
+        zip.finish().unwrap();
+        dir
+    }
+
+    fn generate(dir: &Path, output: &str, extra: &[&str]) -> i32 {
+        let mut args = vec![
+            "bulk-sheet-editor".to_string(),
+            "generate".to_string(),
+            "--csv".to_string(),
+            dir.join("data.csv").display().to_string(),
+            "--template".to_string(),
+            dir.join("template.xlsx").display().to_string(),
+            "--map".to_string(),
+            "Name=B2".to_string(),
+            "-o".to_string(),
+            dir.join(output).display().to_string(),
+        ];
+        args.extend(extra.iter().map(|arg| arg.to_string()));
+        let command = Cli::try_parse_from(args).unwrap().command.unwrap();
+        run(command)
+    }
+
+    #[test]
+    fn exits_with_success() {
+        let dir = fixture("success");
+        assert_eq!(generate(&dir, "out.xlsx", &[]), EXIT_SUCCESS);
+        assert!(dir.join("out.xlsx").is_file());
+    }
+
+    #[test]
+    fn reports_input_errors() {
+        let dir = fixture("input");
+        assert_eq!(
+            generate(&dir, "out.xlsx", &["--keep-sheet", "Missing"]),
+            EXIT_INPUT_ERROR
+        );
+        assert_eq!(
+            generate(&dir, "out.xlsx", &["--map", "Missing=B3"]),
+            EXIT_INPUT_ERROR
+        );
+        std::fs::write(dir.join("template.xlsx"), "not a workbook").unwrap();
+        assert_eq!(generate(&dir, "out.xlsx", &[]), EXIT_INPUT_ERROR);
+        assert!(!dir.join("out.xlsx").exists());
+    }
+
+    #[test]
+    fn reports_output_errors() {
+        let dir = fixture("output");
+        std::fs::create_dir(dir.join("taken.xlsx")).unwrap();
+        assert_eq!(generate(&dir, "taken.xlsx", &[]), EXIT_OUTPUT_ERROR);
+    }
+}
@@ -1,21 +1,29 @@
+mod cli;
 mod csv_loader;
 mod ui_step_modules;
 
 extern crate alloc;
 
+use crate::cli::Cli;
 use crate::ui_step_modules::{
-    BulkCreateModule, CsvImportModule, OdfImportModule, SharedState, UiStepModule,
+    BulkCreateModule, CsvImportModule, OdfImportModule, PROJECT_EXTENSION, Project, SharedState,
+    UiStepModule, project_base_dir,
 };
 use alloc::string::String;
 use catppuccin_egui::{LATTE, MOCHA, set_theme};
+use clap::Parser;
 use egui::{Align, Color32, FontId, Layout, RichText, Vec2};
 use std::cell::RefCell;
+use std::path::PathBuf;
 use std::rc::Rc;
 
 #[derive(Default)]
 pub struct BulkSheetEditorApp {
     dark_theme: bool,
+    shared_state: Rc<RefCell<SharedState>>,
     ui_step_modules: Vec<Box<dyn UiStepModule>>,
+    project_path: Option<PathBuf>,
+    project_error: Option<String>,
 }
 
 impl BulkSheetEditorApp {
@@ -28,7 +36,42 @@ impl BulkSheetEditorApp {
         ];
         Self {
             dark_theme: false,
+            shared_state,
             ui_step_modules,
+            project_path: None,
+            project_error: None,
+        }
+    }
+
+    fn open_project(&mut self, path: PathBuf) {
+        for module in &mut self.ui_step_modules {
+            module.reset();
+        }
+        let result = Project::load(&path).and_then(|project| {
+            project.apply(
+                &mut self.shared_state.borrow_mut(),
+                &project_base_dir(&path),
+            )
+        });
+        self.project_error = result.err();
+        self.project_path = Some(path);
+    }
+
+    fn save_project(&mut self, path: PathBuf) {
+        let project = Project::from_state(&self.shared_state.borrow(), &project_base_dir(&path));
+        self.project_error = project.save(&path).err();
+        self.project_path = Some(path);
+    }
+
+    fn project_dialog(&self) -> rfd::FileDialog {
+        let dialog = rfd::FileDialog::new().add_filter("Project", &[PROJECT_EXTENSION]);
+        match &self.project_path {
+            Some(path) => dialog.set_file_name(
+                path.file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .unwrap_or_default(),
+            ),
+            None => dialog.set_file_name(format!("project.{}", PROJECT_EXTENSION)),
         }
     }
 }
@@ -57,8 +100,21 @@ impl eframe::App for BulkSheetEditorApp {
                         self.dark_theme = !self.dark_theme;
                         set_theme(ctx, if self.dark_theme { MOCHA } else { LATTE });
                     }
+                    if ui.button("Open project…").clicked()
+                        && let Some(path) = self.project_dialog().pick_file()
+                    {
+                        self.open_project(path);
+                    }
+                    if ui.button("Save project…").clicked()
+                        && let Some(path) = self.project_dialog().save_file()
+                    {
+                        self.save_project(path);
+                    }
                 },
             );
+            if let Some(error) = &self.project_error {
+                ui.colored_label(Color32::DARK_RED, error);
+            }
             ui.separator();
             ui.add_space(25.0);
 
@@ -117,6 +173,11 @@ fn main() -> eframe::Result {
     // init env logger
     env_logger::init();
 
+    // run headless when a command is given
+    if let Some(command) = Cli::parse().command {
+        std::process::exit(cli::run(command));
+    }
+
     // run the app
     eframe::run_native(
         "File Kraken",
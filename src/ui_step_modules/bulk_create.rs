@@ -1,25 +1,37 @@
-use crate::ui_step_modules::{
-    SharedState, UiStepModule, column_label_from_index, parse_cell_reference,
+use crate::ui_step_modules::{SharedState, UiStepModule};
+use bulk_sheet_engine::{
+    CardLayout, DataTable, Grouping, Job, KeptSheet, OutputMode, OutputTarget, Report, RowBlock,
+    RowFilter, SheetPosition, SortKey, TemplateFormat, format_row_ranges, output_file_names,
+    parse_row_ranges, sheet_names,
 };
-use egui::Ui;
-use quick_xml::events::{BytesStart, Event};
-use quick_xml::{Reader as XmlReader, Writer as XmlWriter};
+use egui::{ComboBox, Grid, ScrollArea, Ui};
 use std::cell::RefCell;
-use std::collections::BTreeMap;
-use std::fs::File;
-use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
 use std::rc::Rc;
-use zip::write::FileOptions;
-use zip::{CompressionMethod, ZipArchive, ZipWriter};
+use std::time::Duration;
 
 pub struct BulkCreateModule {
     state: Rc<RefCell<SharedState>>,
     save_path: Option<PathBuf>,
     status_message: Option<String>,
     error_message: Option<String>,
+    selection: Option<RowSelection>,
 }
 
+/// The rows chosen by the row filter and their groups, kept until the
+/// filter, the grouping or the CSV data change.
+struct RowSelection {
+    filter: RowFilter,
+    grouping: Grouping,
+    csv_path: Option<PathBuf>,
+    row_count: usize,
+    indices: Result<Vec<usize>, String>,
+    groups: Result<Vec<Vec<usize>>, String>,
+}
+
+const CONDITION_HELP: &str = "Only rows for which the condition holds, e.g. Status == \"active\", \
+    Amount > 0 or Country != \"DE\" and not Paid. Use [Column name] for names with spaces";
+
 impl BulkCreateModule {
     pub fn new(state: Rc<RefCell<SharedState>>) -> Self {
         Self {
@@ -27,732 +39,660 @@ impl BulkCreateModule {
             save_path: None,
             status_message: None,
             error_message: None,
+            selection: None,
         }
     }
 
-    fn validate_inputs(&self) -> Result<(), String> {
+    /// Updates the cached row selection if its inputs changed.
+    fn update_selection(&mut self) -> &RowSelection {
         let state = self.state.borrow();
-        if state.csv_rows.is_empty() {
-            return Err("Import a CSV file before generating sheets.".to_string());
-        }
-        if state.odf_path.is_none() || state.selected_sheet.is_none() {
-            return Err("Select a template workbook and sheet.".to_string());
-        }
-        if state
-            .cell_mappings
-            .iter()
-            .all(|mapping| mapping.cell_ref.trim().is_empty())
-        {
-            return Err("Assign at least one CSV column to a template cell.".to_string());
+        let is_current = self.selection.as_ref().is_some_and(|selection| {
+            selection.filter == state.row_filter
+                && selection.grouping == state.grouping
+                && selection.csv_path == state.csv_path
+                && selection.row_count == state.csv_rows.len()
+        });
+        if !is_current {
+            let data = state.data_table();
+            let indices = state.row_filter.indices(&data);
+            // Groups refer to the CSV rows, not to the selected ones.
+            let groups = match &indices {
+                Ok(indices) => state.grouping.groups(&data.select(indices)).map(|groups| {
+                    groups
+                        .into_iter()
+                        .map(|group| group.into_iter().map(|index| indices[index]).collect())
+                        .collect()
+                }),
+                Err(err) => Err(err.clone()),
+            };
+            self.selection = Some(RowSelection {
+                filter: state.row_filter.clone(),
+                grouping: state.grouping.clone(),
+                csv_path: state.csv_path.clone(),
+                row_count: state.csv_rows.len(),
+                indices,
+                groups,
+            });
         }
-        Ok(())
+        self.selection.as_ref().expect("row selection was just set")
     }
 
-    fn generate_and_save(&mut self, path: PathBuf) {
-        match self.build_and_write(path.as_path()) {
-            Ok(sheet_count) => {
-                self.status_message = Some(format!(
-                    "Created {} sheet(s) using the template.",
-                    sheet_count
-                ));
-                self.error_message = None;
-                self.save_path = Some(path.clone());
-                self.state.borrow_mut().last_output_path = Some(path);
-            }
-            Err(err) => {
-                self.error_message = Some(err);
-            }
-        }
+    /// Indices of the CSV rows that pass the row filter.
+    fn selected_indices(&mut self) -> Result<Vec<usize>, String> {
+        self.update_selection().indices.clone()
     }
 
-    fn build_and_write(&self, output_path: &Path) -> Result<usize, String> {
+    /// The first rows of the first groups, or of the first sheets of cards,
+    /// for name previews.
+    fn preview_data(&mut self) -> DataTable {
+        let groups = self.update_selection().groups.clone().unwrap_or_default();
         let state = self.state.borrow();
-        let template_path = state
-            .odf_path
-            .clone()
-            .ok_or_else(|| "Template workbook missing".to_string())?;
-        let template_sheet_name = state
-            .selected_sheet
-            .clone()
-            .ok_or_else(|| "Template sheet missing".to_string())?;
-        let mappings = state
-            .cell_mappings
-            .iter()
-            .filter(|mapping| !mapping.cell_ref.trim().is_empty())
-            .cloned()
-            .collect::<Vec<_>>();
-        let rows = state.csv_rows.clone();
-        drop(state);
-
-        if rows.is_empty() {
-            return Err("CSV file does not contain data rows.".to_string());
+        let per_sheet =
+            CardLayout::parse(&state.card, &state.card_tiles).map_or(1, |cards| cards.per_sheet());
+        let groups = groups.iter().step_by(per_sheet).take(3).collect::<Vec<_>>();
+        let row_numbers = if state.grouping.group_by.is_empty() && per_sheet == 1 {
+            groups.iter().map(|group| group[0] + 1).collect()
+        } else {
+            (1..=groups.len()).collect()
+        };
+        DataTable {
+            headers: state.csv_headers.clone(),
+            rows: groups
+                .iter()
+                .map(|group| state.csv_rows[group[0]].clone())
+                .collect(),
+            row_numbers,
+            typed: Vec::new(),
         }
-        if mappings.is_empty() {
-            return Err("No column mappings configured.".to_string());
-        }
-
-        let context = TemplateContext::load(&template_path, &template_sheet_name)?;
-        let mut sheet_exports = Vec::new();
-        let mut next_rel_index = context.next_relationship_index;
+    }
 
-        for (row_index, row_values) in rows.iter().enumerate() {
-            let mut replacements = BTreeMap::new();
-            for mapping in &mappings {
-                if let Some((row, col)) = parse_cell_reference(&mapping.cell_ref)
-                    && let Some(value) = row_values.get(mapping.column_index)
+    fn draw_grouping(&mut self, ui: &mut Ui) {
+        let groups = self.update_selection().groups.clone();
+        let mut state = self.state.borrow_mut();
+        let headers = state.csv_headers.clone();
+        let heading = match &groups {
+            Ok(groups) if !state.grouping.group_by.is_empty() => {
+                format!("Sorting and grouping ({} groups)", groups.len())
+            }
+            _ => "Sorting and grouping".to_string(),
+        };
+        egui::CollapsingHeader::new(heading)
+            .id_salt("sorting_and_grouping")
+            .default_open(!state.grouping.is_empty())
+            .show(ui, |ui| {
+                let grouping = &mut state.grouping;
+                let mut removed = None;
+                for (index, key) in grouping.sort_by.iter_mut().enumerate() {
+                    ui.horizontal(|ui| {
+                        ui.label(if index == 0 { "Sort by:" } else { "Then by:" });
+                        column_combo(ui, ("sort_column", index), &headers, &mut key.column);
+                        ui.radio_value(&mut key.descending, false, "Ascending");
+                        ui.radio_value(&mut key.descending, true, "Descending");
+                        if ui.small_button("✖").on_hover_text("Remove").clicked() {
+                            removed = Some(index);
+                        }
+                    });
+                }
+                if let Some(index) = removed {
+                    grouping.sort_by.remove(index);
+                }
+                if ui.button("Add sort column").clicked()
+                    && let Some(column) = headers.first()
                 {
-                    let label = format!("{}{}", column_label_from_index(col), row + 1);
-                    replacements.insert(label, value.clone());
+                    grouping.sort_by.push(SortKey {
+                        column: column.clone(),
+                        descending: false,
+                    });
                 }
-            }
 
-            let sheet_xml = update_sheet_xml(&context.template_sheet_xml, &replacements)?;
-            next_rel_index += 1;
-            sheet_exports.push(WorksheetExport {
-                name: format!("{} {}", template_sheet_name, row_index + 1),
-                relationship_id: format!("rId{}", next_rel_index),
-                target: format!("worksheets/sheet{}.xml", row_index + 1),
-                sheet_id: (row_index + 1) as u32,
-                data: sheet_xml,
-                relationship_part: context.template_sheet_relationship.clone(),
+                ui.add_space(5.0);
+                let mut removed = None;
+                for (index, column) in grouping.group_by.iter_mut().enumerate() {
+                    ui.horizontal(|ui| {
+                        ui.label(if index == 0 { "Group by:" } else { "And by:" });
+                        column_combo(ui, ("group_column", index), &headers, column);
+                        if ui.small_button("✖").on_hover_text("Remove").clicked() {
+                            removed = Some(index);
+                        }
+                    });
+                }
+                if let Some(index) = removed {
+                    grouping.group_by.remove(index);
+                }
+                if ui
+                    .button("Add group column")
+                    .on_hover_text("Rows with the same values in these columns share one sheet")
+                    .clicked()
+                    && let Some(column) = headers.first()
+                {
+                    grouping.group_by.push(column.clone());
+                }
+                if !grouping.group_by.is_empty() {
+                    ui.label(
+                        "Each group fills one sheet or file from its first row; \
+                         mappings marked as detail list every row of the group.",
+                    );
+                }
+                if let Err(err) = &groups {
+                    ui.colored_label(egui::Color32::DARK_RED, err);
+                }
             });
-        }
-
-        write_workbook_from_template(output_path, &context, &sheet_exports)?;
-        Ok(sheet_exports.len())
     }
-}
 
-impl UiStepModule for BulkCreateModule {
-    fn get_title(&self) -> String {
-        "Generate Workbook".to_string()
+    fn draw_row_selection(&mut self, ui: &mut Ui) {
+        let selection = self.selected_indices();
+        let mut state = self.state.borrow_mut();
+        let row_count = state.csv_rows.len();
+        let has_all_rows = state.has_all_rows();
+        // Only the loaded rows are filtered; the full count arrives later.
+        let heading = match (&selection, &state.csv_row_count) {
+            (Err(_), _) => "Row selection".to_string(),
+            (Ok(indices), _) if has_all_rows => {
+                format!("Row selection ({} of {} rows)", indices.len(), row_count)
+            }
+            (Ok(indices), Some(Ok(total))) => format!(
+                "Row selection ({} of the first {} rows, {} rows in total)",
+                indices.len(),
+                row_count,
+                total
+            ),
+            (Ok(indices), Some(Err(_))) => format!(
+                "Row selection ({} of the first {} rows)",
+                indices.len(),
+                row_count
+            ),
+            (Ok(indices), None) => format!(
+                "Row selection ({} of the first {} rows, counting the rest…)",
+                indices.len(),
+                row_count
+            ),
+        };
+        egui::CollapsingHeader::new(heading)
+            .id_salt("row_selection")
+            .default_open(!state.row_filter.is_empty())
+            .show(ui, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("Rows:");
+                    ui.add(
+                        egui::TextEdit::singleline(&mut state.row_filter.rows)
+                            .hint_text("all, or e.g. 1-20,35"),
+                    );
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Condition:");
+                    ui.add(
+                        egui::TextEdit::singleline(&mut state.row_filter.condition)
+                            .hint_text("e.g. Status == \"active\""),
+                    )
+                    .on_hover_text(CONDITION_HELP);
+                });
+                let indices = match &selection {
+                    Ok(indices) => indices,
+                    Err(err) => {
+                        ui.colored_label(egui::Color32::DARK_RED, err);
+                        return;
+                    }
+                };
+
+                let ranges = parse_row_ranges(&state.row_filter.rows).unwrap_or_default();
+                let in_ranges = |number: usize| {
+                    ranges.is_empty()
+                        || ranges
+                            .iter()
+                            .any(|(first, last)| (*first..=*last).contains(&number))
+                };
+                let column_count = state.csv_headers.len().min(5);
+                let row_height = ui.spacing().interact_size.y;
+                let mut toggled = None;
+                ScrollArea::vertical()
+                    .id_salt("row_selection_rows")
+                    .max_height(200.0)
+                    .show_rows(ui, row_height, row_count, |ui, visible| {
+                        Grid::new("row_selection_grid")
+                            .striped(true)
+                            .show(ui, |ui| {
+                                for index in visible {
+                                    let number = index + 1;
+                                    let mut checked = indices.binary_search(&index).is_ok();
+                                    let excluded = in_ranges(number) && !checked;
+                                    let response = ui.add_enabled(
+                                        !excluded,
+                                        egui::Checkbox::new(&mut checked, number.to_string()),
+                                    );
+                                    if excluded {
+                                        response
+                                            .on_disabled_hover_text("Excluded by the condition");
+                                    } else if response.changed() {
+                                        toggled = Some((number, checked));
+                                    }
+                                    for column in 0..column_count {
+                                        ui.label(
+                                            state.csv_rows[index]
+                                                .get(column)
+                                                .map(String::as_str)
+                                                .unwrap_or_default(),
+                                        );
+                                    }
+                                    ui.end_row();
+                                }
+                            });
+                    });
+
+                // Checkboxes edit the row ranges; the last row cannot be
+                // unchecked since empty ranges select every row.
+                if let Some((number, checked)) = toggled {
+                    let mut numbers = (1..=row_count)
+                        .filter(|current| *current != number && in_ranges(*current))
+                        .collect::<Vec<_>>();
+                    if checked {
+                        numbers.push(number);
+                        numbers.sort_unstable();
+                    }
+                    let rows = match has_all_rows {
+                        true => format_row_ranges(&numbers),
+                        false => ranges_with_tail(&numbers, &ranges, row_count),
+                    };
+                    if (has_all_rows && numbers.len() == row_count) || rows == "1-" {
+                        state.row_filter.rows.clear();
+                    } else if !numbers.is_empty() {
+                        state.row_filter.rows = rows;
+                    }
+                }
+            });
     }
 
-    fn draw_ui(&mut self, ui: &mut Ui) {
-        ui.label("Create sheets for each CSV row and save them as a workbook");
-
-        match self.validate_inputs() {
-            Ok(_) => {
-                let state = self.state.borrow();
-                ui.label(format!("Rows ready for export: {}", state.csv_rows.len()));
-                if let Some(path) = &state.odf_path {
-                    ui.label(format!("Template file: {}", path.display()));
-                }
-                if let Some(sheet) = &state.selected_sheet {
-                    ui.label(format!("Template sheet: {}", sheet));
-                }
+    fn generate_and_save(&mut self, path: PathBuf) {
+        let result = build_and_write(&self.state.borrow(), &path);
+        match result {
+            Ok(report) => {
+                self.status_message = Some(report.to_string());
+                self.error_message = None;
+                self.save_path = Some(path.clone());
+                self.state.borrow_mut().last_output_path = Some(path);
             }
-            Err(reason) => {
-                ui.colored_label(egui::Color32::DARK_RED, reason);
-                return;
+            Err(err) => {
+                self.error_message = Some(err);
             }
         }
+    }
+
+    fn draw_file_name_settings(&mut self, ui: &mut Ui) {
+        let preview_data = self.preview_data();
+        let mut state = self.state.borrow_mut();
+        if state.file_name_pattern.is_empty() {
+            state.file_name_pattern = state.effective_file_name_pattern();
+        }
 
-        ui.add_space(10.0);
         ui.horizontal(|ui| {
-            if ui.button("Save as…").clicked()
-                && let Some(path) = rfd::FileDialog::new()
-                    .add_filter("Excel", &["xlsx"])
-                    .set_file_name("bulk_output.xlsx")
-                    .save_file()
-            {
-                self.generate_and_save(path);
-            }
-            if let Some(path) = &self.save_path {
-                ui.label(path.display().to_string());
-            }
+            ui.label("File name pattern:");
+            ui.text_edit_singleline(&mut state.file_name_pattern);
+        });
+        ui.label("Use {{column}} for CSV values and {{#}} for the row number.");
+        ui.horizontal(|ui| {
+            ui.label("Write to:");
+            ui.radio_value(&mut state.output_target, OutputTarget::Directory, "Folder");
+            ui.radio_value(
+                &mut state.output_target,
+                OutputTarget::ZipArchive,
+                "ZIP archive",
+            );
         });
 
-        if let Some(message) = &self.status_message {
-            ui.colored_label(egui::Color32::DARK_GREEN, message);
-        }
-        if let Some(error) = &self.error_message {
-            ui.colored_label(egui::Color32::DARK_RED, error);
-        }
+        let (_, extension) = state
+            .template_format()
+            .unwrap_or(TemplateFormat::Xlsx)
+            .output_filter();
+        let file_names = output_file_names(
+            &state.effective_file_name_pattern(),
+            extension,
+            &preview_data,
+        );
+        ui.label(format!("Example: {}", file_names.join(", ")));
     }
 
-    fn is_complete(&self) -> bool {
-        self.state.borrow().last_output_path.is_some()
-    }
+    fn draw_sheet_name_settings(&mut self, ui: &mut Ui) {
+        let preview_data = self.preview_data();
+        let mut state = self.state.borrow_mut();
+        let default_pattern = state.effective_sheet_name_pattern();
+        ui.horizontal(|ui| {
+            ui.label("Sheet name pattern:");
+            ui.add(
+                egui::TextEdit::singleline(&mut state.sheet_name_pattern)
+                    .hint_text(default_pattern),
+            );
+        });
 
-    fn reset(&mut self) {
-        self.save_path = None;
-        self.status_message = None;
-        self.error_message = None;
-        self.state.borrow_mut().last_output_path = None;
+        let names = sheet_names(
+            &state.effective_sheet_name_pattern(),
+            &preview_data,
+            state.output_mode == OutputMode::SingleFile,
+            &state.output_options(Path::new("")).kept_sheet_names(),
+        );
+        ui.label(format!("Sheet names: {}", names.join(", ")));
     }
-}
 
-// -- Template workbook helpers -------------------------------------------------
-
-fn write_workbook_from_template(
-    path: &Path,
-    context: &TemplateContext,
-    sheets: &[WorksheetExport],
-) -> Result<(), String> {
-    let file = File::create(path).map_err(|err| err.to_string())?;
-    let mut zip = ZipWriter::new(file);
-    let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
-
-    let content_types = build_content_types(&context.content_types_xml, sheets)?;
-    let root_rels = build_root_relationships();
-    let app_doc = build_app_doc(sheets);
-    let core_doc = build_core_doc();
-    let workbook_xml = build_workbook_xml(sheets);
-    let workbook_rels = build_workbook_rels(&context.preserved_relationships, sheets);
-
-    zip.start_file("[Content_Types].xml", options)
-        .map_err(|err| err.to_string())?;
-    zip.write_all(&content_types)
-        .map_err(|err| err.to_string())?;
-
-    zip.start_file("_rels/.rels", options)
-        .map_err(|err| err.to_string())?;
-    zip.write_all(&root_rels).map_err(|err| err.to_string())?;
-
-    zip.start_file("docProps/app.xml", options)
-        .map_err(|err| err.to_string())?;
-    zip.write_all(&app_doc).map_err(|err| err.to_string())?;
-
-    zip.start_file("docProps/core.xml", options)
-        .map_err(|err| err.to_string())?;
-    zip.write_all(&core_doc).map_err(|err| err.to_string())?;
-
-    zip.start_file("xl/workbook.xml", options)
-        .map_err(|err| err.to_string())?;
-    zip.write_all(&workbook_xml)
-        .map_err(|err| err.to_string())?;
-
-    zip.start_file("xl/_rels/workbook.xml.rels", options)
-        .map_err(|err| err.to_string())?;
-    zip.write_all(&workbook_rels)
-        .map_err(|err| err.to_string())?;
-
-    for sheet in sheets {
-        zip.start_file(format!("xl/{}", sheet.target), options)
-            .map_err(|err| err.to_string())?;
-        zip.write_all(&sheet.data).map_err(|err| err.to_string())?;
-
-        if let Some(rel_data) = &sheet.relationship_part
-            && let Some(rel_path) = sheet_relationship_path(&sheet.target)
-        {
-            zip.start_file(format!("xl/{}", rel_path), options)
-                .map_err(|err| err.to_string())?;
-            zip.write_all(rel_data).map_err(|err| err.to_string())?;
+    fn draw_kept_sheet_settings(&mut self, ui: &mut Ui) {
+        let mut state = self.state.borrow_mut();
+        let other_sheets = state
+            .odf_sheet_names
+            .iter()
+            .filter(|name| Some(*name) != state.selected_sheet.as_ref())
+            .cloned()
+            .collect::<Vec<_>>();
+        if other_sheets.is_empty() {
+            return;
         }
-    }
 
-    for (name, data) in &context.entries {
-        if should_skip_entry(name) {
-            continue;
+        ui.add_space(5.0);
+        ui.label("Other sheets of the template:");
+        for name in other_sheets {
+            let mut position = state
+                .kept_sheets
+                .iter()
+                .find(|kept| kept.name == name)
+                .map(|kept| kept.position);
+            ui.horizontal(|ui| {
+                ComboBox::from_id_salt(("kept_sheet", &name))
+                    .selected_text(kept_position_label(position))
+                    .show_ui(ui, |ui| {
+                        for option in [
+                            None,
+                            Some(SheetPosition::Before),
+                            Some(SheetPosition::After),
+                        ] {
+                            ui.selectable_value(&mut position, option, kept_position_label(option));
+                        }
+                    });
+                ui.label(&name);
+            });
+
+            state.kept_sheets.retain(|kept| kept.name != name);
+            if let Some(position) = position {
+                state.kept_sheets.push(KeptSheet { name, position });
+            }
         }
-        zip.start_file(name, options)
-            .map_err(|err| err.to_string())?;
-        zip.write_all(data).map_err(|err| err.to_string())?;
     }
-
-    zip.finish().map_err(|err| err.to_string()).map(|_| ())
 }
 
-fn update_sheet_xml(
-    template: &[u8],
-    replacements: &BTreeMap<String, String>,
-) -> Result<Vec<u8>, String> {
-    if replacements.is_empty() {
-        return Ok(template.to_vec());
-    }
-
-    let mut reader = XmlReader::from_reader(template);
-    reader.trim_text(false);
-    let mut writer = XmlWriter::new(Vec::new());
-    let mut buffer = Vec::new();
-    let mut skip_depth: usize = 0;
-
-    loop {
-        match reader
-            .read_event_into(&mut buffer)
-            .map_err(|err| err.to_string())?
-        {
-            Event::Eof => break,
-            Event::Start(event) => {
-                if skip_depth > 0 {
-                    skip_depth += 1;
-                    continue;
-                }
-
-                if event.name().as_ref() == b"c"
-                    && let Some(cell_ref) = attribute_value(&event, b"r")
-                    && let Some(value) = replacements.get(&cell_ref)
-                {
-                    let attrs = collect_cell_attributes(&event);
-                    write_replaced_cell(&mut writer, &cell_ref, value, &attrs)?;
-                    skip_depth = 1;
-                    continue;
-                }
-
-                writer
-                    .write_event(Event::Start(event.into_owned()))
-                    .map_err(|err| err.to_string())?;
+fn column_combo(ui: &mut Ui, id_salt: (&str, usize), headers: &[String], column: &mut String) {
+    ComboBox::from_id_salt(id_salt)
+        .selected_text(column.as_str())
+        .show_ui(ui, |ui| {
+            for header in headers {
+                ui.selectable_value(column, header.clone(), header);
             }
-            Event::Empty(event) => {
-                if skip_depth > 0 {
-                    continue;
-                }
-
-                if event.name().as_ref() == b"c"
-                    && let Some(cell_ref) = attribute_value(&event, b"r")
-                    && let Some(value) = replacements.get(&cell_ref)
-                {
-                    let attrs = collect_cell_attributes(&event);
-                    write_replaced_cell(&mut writer, &cell_ref, value, &attrs)?;
-                    continue;
-                }
-
-                writer
-                    .write_event(Event::Empty(event.into_owned()))
-                    .map_err(|err| err.to_string())?;
-            }
-            Event::End(event) => {
-                if skip_depth > 0 {
-                    if skip_depth == 1 && event.name().as_ref() == b"c" {
-                        skip_depth = 0;
-                    } else if skip_depth > 1 {
-                        skip_depth -= 1;
-                    }
-                    continue;
-                }
+        });
+}
 
-                writer
-                    .write_event(Event::End(event.into_owned()))
-                    .map_err(|err| err.to_string())?;
-            }
-            Event::Text(event) => {
-                if skip_depth > 0 {
-                    continue;
-                }
-                writer
-                    .write_event(Event::Text(event))
-                    .map_err(|err| err.to_string())?;
-            }
-            Event::Comment(event) => {
-                if skip_depth > 0 {
-                    continue;
-                }
-                writer
-                    .write_event(Event::Comment(event))
-                    .map_err(|err| err.to_string())?;
-            }
-            Event::CData(event) => {
-                if skip_depth > 0 {
-                    continue;
-                }
-                writer
-                    .write_event(Event::CData(event))
-                    .map_err(|err| err.to_string())?;
-            }
-            Event::Decl(event) => {
-                if skip_depth > 0 {
-                    continue;
-                }
-                writer
-                    .write_event(Event::Decl(event.into_owned()))
-                    .map_err(|err| err.to_string())?;
-            }
-            Event::PI(event) => {
-                if skip_depth > 0 {
-                    continue;
-                }
-                writer
-                    .write_event(Event::PI(event.into_owned()))
-                    .map_err(|err| err.to_string())?;
-            }
-            Event::DocType(event) => {
-                if skip_depth > 0 {
-                    continue;
-                }
-                writer
-                    .write_event(Event::DocType(event.into_owned()))
-                    .map_err(|err| err.to_string())?;
-            }
-        }
-        buffer.clear();
+fn kept_position_label(position: Option<SheetPosition>) -> &'static str {
+    match position {
+        None => "Leave out",
+        Some(SheetPosition::Before) => "Keep before",
+        Some(SheetPosition::After) => "Keep after",
     }
-
-    Ok(writer.into_inner())
 }
 
-fn write_replaced_cell(
-    writer: &mut XmlWriter<Vec<u8>>,
-    reference: &str,
-    value: &str,
-    attrs: &[(String, String)],
-) -> Result<(), String> {
-    let mut cell = format!("<c r=\"{}\"", reference);
-    for (name, attr_value) in attrs {
-        if name == "r" || name == "t" {
-            continue;
+/// Checks that the state holds everything needed to generate output.
+pub fn validate_inputs(state: &SharedState) -> Result<(), String> {
+    if state.csv_rows.is_empty() {
+        return Err("Import a CSV file before generating sheets.".to_string());
+    }
+    if let Some(format) = state.template_format()
+        && format.is_document()
+    {
+        if state.bound_placeholders().is_empty() {
+            return Err("Bind at least one placeholder to a CSV column.".to_string());
         }
-        cell.push_str(&format!(" {}=\"{}\"", name, attr_value));
+        return Ok(());
     }
-    cell.push_str(" t=\"inlineStr\"><is><t>");
-    cell.push_str(&xml_escape(value));
-    cell.push_str("</t></is></c>");
-    writer
-        .get_mut()
-        .write_all(cell.as_bytes())
-        .map_err(|err| err.to_string())
-}
-
-fn attribute_value(event: &BytesStart, key: &[u8]) -> Option<String> {
-    event
-        .attributes()
-        .with_checks(false)
-        .filter_map(|attr| attr.ok())
-        .find(|attr| attr.key.as_ref() == key)
-        .map(|attr| String::from_utf8_lossy(attr.value.as_ref()).into_owned())
-}
-
-fn collect_cell_attributes(event: &BytesStart) -> Vec<(String, String)> {
-    event
-        .attributes()
-        .with_checks(false)
-        .filter_map(|attr| attr.ok())
-        .map(|attr| {
-            (
-                String::from_utf8_lossy(attr.key.as_ref()).into_owned(),
-                String::from_utf8_lossy(attr.value.as_ref()).into_owned(),
-            )
-        })
-        .collect()
-}
-
-fn build_workbook_xml(sheets: &[WorksheetExport]) -> Vec<u8> {
-    let mut xml = String::from(
-        "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?><workbook xmlns=\"http://schemas.openxmlformats.org/spreadsheetml/2006/main\" xmlns:r=\"http://schemas.openxmlformats.org/officeDocument/2006/relationships\"><sheets>",
-    );
-    for sheet in sheets {
-        xml.push_str(&format!(
-            "<sheet name=\"{}\" sheetId=\"{}\" r:id=\"{}\"/>",
-            xml_escape(&sheet.name),
-            sheet.sheet_id,
-            sheet.relationship_id
-        ));
+    if state.odf_path.is_none() || state.selected_sheet.is_none() {
+        return Err("Select a template workbook and sheet.".to_string());
     }
-    xml.push_str("</sheets></workbook>");
-    xml.into_bytes()
-}
-
-fn build_workbook_rels(preserved: &[WorkbookRelationship], sheets: &[WorksheetExport]) -> Vec<u8> {
-    let mut xml = String::from(
-        "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?><Relationships xmlns=\"http://schemas.openxmlformats.org/package/2006/relationships\">",
-    );
-    for rel in preserved {
-        xml.push_str(&format!(
-            "<Relationship Id=\"{}\" Type=\"{}\" Target=\"{}\"/>",
-            xml_escape(&rel.id),
-            xml_escape(&rel.type_attr),
-            xml_escape(&rel.target)
+    if !state.repeat_rows.trim().is_empty() && RowBlock::parse(&state.repeat_rows).is_none() {
+        return Err(format!(
+            "\"{}\" is not a row range such as A10:F10",
+            state.repeat_rows.trim()
         ));
     }
-    for sheet in sheets {
-        xml.push_str(&format!(
-            "<Relationship Id=\"{}\" Type=\"http://schemas.openxmlformats.org/officeDocument/2006/relationships/worksheet\" Target=\"{}\"/>",
-            xml_escape(&sheet.relationship_id),
-            xml_escape(&sheet.target)
-        ));
+    if !state.card.trim().is_empty() || !state.card_tiles.trim().is_empty() {
+        CardLayout::parse(&state.card, &state.card_tiles)?;
+        if !state.repeat_rows.trim().is_empty() {
+            return Err("Repeated rows cannot be combined with cards.".to_string());
+        }
     }
-    xml.push_str("</Relationships>");
-    xml.into_bytes()
+    if state.mappings().is_empty() && state.bound_placeholders().is_empty() {
+        return Err(
+            "Assign at least one CSV column to a template cell or {{placeholder}}.".to_string(),
+        );
+    }
+    Ok(())
 }
 
-fn build_content_types(original: &str, sheets: &[WorksheetExport]) -> Result<Vec<u8>, String> {
-    let mut reader = XmlReader::from_str(original);
-    reader.trim_text(false);
-    let mut writer = XmlWriter::new(Vec::new());
-    let mut buffer = Vec::new();
-
-    loop {
-        match reader
-            .read_event_into(&mut buffer)
-            .map_err(|err| err.to_string())?
-        {
-            Event::Eof => break,
-            Event::Empty(event) => {
-                if event.name().as_ref() == b"Override"
-                    && let Some(part_name) = attribute_value(&event, b"PartName")
-                    && part_name.contains("/xl/worksheets/")
-                {
-                    buffer.clear();
-                    continue;
-                }
-                writer
-                    .write_event(Event::Empty(event.into_owned()))
-                    .map_err(|err| err.to_string())?;
-            }
-            Event::End(event) => {
-                if event.name().as_ref() == b"Types" {
-                    for sheet in sheets {
-                        let override_line = format!(
-                            "\n    <Override PartName=\"/xl/{}\" ContentType=\"application/vnd.openxmlformats-officedocument.spreadsheetml.worksheet+xml\"/>",
-                            sheet.target
-                        );
-                        writer
-                            .get_mut()
-                            .write_all(override_line.as_bytes())
-                            .map_err(|err| err.to_string())?;
-                    }
+/// The row ranges of the checked `numbers` of a partly loaded file. Rows
+/// past the `row_count` loaded ones keep the ranges that selected them, or
+/// stay selected when there were none.
+fn ranges_with_tail(numbers: &[usize], ranges: &[(usize, usize)], row_count: usize) -> String {
+    let tail = if ranges.is_empty() {
+        vec![(row_count + 1, usize::MAX)]
+    } else {
+        ranges
+            .iter()
+            .filter(|(_, last)| *last > row_count)
+            .map(|(first, last)| ((*first).max(row_count + 1), *last))
+            .collect()
+    };
+    let mut parts = match numbers.is_empty() {
+        true => Vec::new(),
+        false => vec![format_row_ranges(numbers)],
+    };
+    for (index, (first, last)) in tail.into_iter().enumerate() {
+        let end = match last {
+            usize::MAX => String::new(),
+            last => last.to_string(),
+        };
+        // Continue the checked rows when they reach the last loaded row.
+        let start = match parts.pop() {
+            Some(part)
+                if index == 0 && first == row_count + 1 && numbers.last() == Some(&row_count) =>
+            {
+                let (head, run) = part.rsplit_once(',').unwrap_or(("", &part));
+                if !head.is_empty() {
+                    parts.push(head.to_string());
                 }
-                writer
-                    .write_event(Event::End(event.into_owned()))
-                    .map_err(|err| err.to_string())?;
+                run.split('-').next().unwrap_or_default().to_string()
             }
-            other => {
-                writer
-                    .write_event(other.into_owned())
-                    .map_err(|err| err.to_string())?;
+            Some(part) => {
+                parts.push(part);
+                first.to_string()
             }
-        }
-        buffer.clear();
-    }
-
-    Ok(writer.into_inner())
-}
-
-fn build_root_relationships() -> Vec<u8> {
-    b"<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?><Relationships xmlns=\"http://schemas.openxmlformats.org/package/2006/relationships\"><Relationship Id=\"rId1\" Type=\"http://schemas.openxmlformats.org/officeDocument/2006/relationships/officeDocument\" Target=\"xl/workbook.xml\"/><Relationship Id=\"rId2\" Type=\"http://schemas.openxmlformats.org/package/2006/relationships/metadata/core-properties\" Target=\"docProps/core.xml\"/><Relationship Id=\"rId3\" Type=\"http://schemas.openxmlformats.org/officeDocument/2006/relationships/extended-properties\" Target=\"docProps/app.xml\"/></Relationships>".to_vec()
-}
-
-fn build_app_doc(sheets: &[WorksheetExport]) -> Vec<u8> {
-    let mut titles = String::new();
-    for sheet in sheets {
-        titles.push_str(&format!("<vt:lpstr>{}</vt:lpstr>", xml_escape(&sheet.name)));
+            None => first.to_string(),
+        };
+        parts.push(match start == end {
+            true => start,
+            false => format!("{}-{}", start, end),
+        });
     }
-    format!(
-        "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?><Properties xmlns=\"http://schemas.openxmlformats.org/officeDocument/2006/extended-properties\" xmlns:vt=\"http://schemas.openxmlformats.org/officeDocument/2006/docPropsVTypes\"><Application>Bulk Sheet Editor</Application><DocSecurity>0</DocSecurity><ScaleCrop>false</ScaleCrop><HeadingPairs><vt:vector size=\"2\" baseType=\"variant\"><vt:variant><vt:lpstr>Worksheets</vt:lpstr></vt:variant><vt:variant><vt:i4>{}</vt:i4></vt:variant></vt:vector></HeadingPairs><TitlesOfParts><vt:vector size=\"{}\" baseType=\"lpstr\">{}</vt:vector></TitlesOfParts><Company></Company><LinksUpToDate>false</LinksUpToDate><SharedDoc>false</SharedDoc><HyperlinksChanged>false</HyperlinksChanged><AppVersion>16.0300</AppVersion></Properties>",
-        sheets.len(),
-        sheets.len(),
-        titles
-    )
-    .into_bytes()
+    parts.join(",")
 }
 
-fn build_core_doc() -> Vec<u8> {
-    b"<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?><cp:coreProperties xmlns:cp=\"http://schemas.openxmlformats.org/package/2006/metadata/core-properties\" xmlns:dc=\"http://purl.org/dc/elements/1.1/\" xmlns:dcterms=\"http://purl.org/dc/terms/\" xmlns:dcmitype=\"http://purl.org/dc/dcmitype/\" xmlns:xsi=\"http://www.w3.org/2001/XMLSchema-instance\"><dc:creator>Bulk Sheet Editor</dc:creator><cp:lastModifiedBy>Bulk Sheet Editor</cp:lastModifiedBy><dcterms:created xsi:type=\"dcterms:W3CDTF\">2024-01-01T00:00:00Z</dcterms:created><dcterms:modified xsi:type=\"dcterms:W3CDTF\">2024-01-01T00:00:00Z</dcterms:modified></cp:coreProperties>".to_vec()
+/// Generates the output for the selected rows of the whole data file and
+/// writes it to `output_path`, which is a file or, for per-row output, a
+/// folder or ZIP archive.
+fn build_and_write(state: &SharedState, output_path: &Path) -> Result<Report, String> {
+    job(state, output_path)?.run()
 }
 
-fn should_skip_entry(name: &str) -> bool {
-    name == "[Content_Types].xml"
-        || name == "_rels/.rels"
-        || name == "docProps/app.xml"
-        || name == "docProps/core.xml"
-        || name == "xl/workbook.xml"
-        || name == "xl/_rels/workbook.xml.rels"
-        || name.starts_with("xl/worksheets/")
+/// The generation run for the current state.
+pub fn job(state: &SharedState, output_path: &Path) -> Result<Job, String> {
+    let template = state
+        .template()
+        .ok_or_else(|| "Template workbook missing".to_string())?;
+    let data_path = state
+        .csv_path
+        .as_deref()
+        .ok_or_else(|| "Data file missing".to_string())?;
+    Ok(Job {
+        data_source: state.data_source(data_path),
+        template,
+        mappings: state.mappings(),
+        filter: state.row_filter.clone(),
+        grouping: state.grouping.clone(),
+        output: state.output_options(output_path),
+    })
 }
 
-fn sheet_relationship_path(target: &str) -> Option<String> {
-    let (folder, file) = target.rsplit_once('/')?;
-    Some(format!("{}/_rels/{}.rels", folder, file))
-}
-
-#[derive(Clone)]
-struct WorksheetExport {
-    name: String,
-    relationship_id: String,
-    target: String,
-    sheet_id: u32,
-    data: Vec<u8>,
-    relationship_part: Option<Vec<u8>>,
-}
-
-#[derive(Clone)]
-struct WorkbookRelationship {
-    id: String,
-    target: String,
-    type_attr: String,
-}
-
-struct TemplateContext {
-    entries: BTreeMap<String, Vec<u8>>,
-    content_types_xml: String,
-    preserved_relationships: Vec<WorkbookRelationship>,
-    next_relationship_index: u32,
-    template_sheet_xml: Vec<u8>,
-    template_sheet_relationship: Option<Vec<u8>>,
-}
-
-impl TemplateContext {
-    fn load(path: &Path, sheet_name: &str) -> Result<Self, String> {
-        let file = File::open(path).map_err(|err| err.to_string())?;
-        let mut archive = ZipArchive::new(file).map_err(|err| err.to_string())?;
-        let mut entries = BTreeMap::new();
-        for index in 0..archive.len() {
-            let mut file = archive.by_index(index).map_err(|err| err.to_string())?;
-            if !file.is_file() {
-                continue;
-            }
-            let mut data = Vec::new();
-            file.read_to_end(&mut data).map_err(|err| err.to_string())?;
-            entries.insert(file.name().to_string(), data);
+impl UiStepModule for BulkCreateModule {
+    fn get_title(&self) -> String {
+        match self.state.borrow().template_format() {
+            Some(format) if format.is_document() => "Generate Documents".to_string(),
+            _ => "Generate Workbook".to_string(),
         }
-
-        let content_types_xml = entries
-            .get("[Content_Types].xml")
-            .ok_or_else(|| "Workbook content types missing".to_string())?
-            .clone();
-        let content_types_xml =
-            String::from_utf8(content_types_xml).map_err(|err| err.to_string())?;
-
-        let workbook_xml = entries
-            .get("xl/workbook.xml")
-            .ok_or_else(|| "Workbook definition missing".to_string())?
-            .clone();
-        let workbook_xml = String::from_utf8(workbook_xml).map_err(|err| err.to_string())?;
-
-        let workbook_rels = entries
-            .get("xl/_rels/workbook.xml.rels")
-            .ok_or_else(|| "Workbook relationships missing".to_string())?
-            .clone();
-        let workbook_rels = String::from_utf8(workbook_rels).map_err(|err| err.to_string())?;
-
-        let template_rel_id = parse_sheet_mapping(&workbook_xml, sheet_name)?;
-        let (template_target, preserved_relationships, next_relationship_index) =
-            parse_workbook_relationships(&workbook_rels, &template_rel_id)?;
-
-        let sheet_entry = format!("xl/{}", template_target);
-        let template_sheet_xml = entries
-            .get(&sheet_entry)
-            .ok_or_else(|| "Template sheet XML missing".to_string())?
-            .clone();
-
-        let relationship_part = sheet_relationship_path(&template_target)
-            .and_then(|path| entries.get(&format!("xl/{}", path)).cloned());
-
-        Ok(Self {
-            entries,
-            content_types_xml,
-            preserved_relationships,
-            next_relationship_index,
-            template_sheet_xml,
-            template_sheet_relationship: relationship_part,
-        })
     }
-}
 
-fn parse_sheet_mapping(workbook_xml: &str, sheet_name: &str) -> Result<String, String> {
-    let mut reader = XmlReader::from_str(workbook_xml);
-    reader.trim_text(true);
-    let mut buffer = Vec::new();
-    let mut template_rel = None;
-
-    loop {
-        match reader
-            .read_event_into(&mut buffer)
-            .map_err(|err| err.to_string())?
-        {
-            Event::Eof => break,
-            Event::Empty(event) => {
-                if event.name().as_ref() == b"sheet" {
-                    let mut name = None;
-                    let mut rel_id = None;
-                    for attr in event.attributes().with_checks(false) {
-                        let attr = attr.map_err(|err| err.to_string())?;
-                        let key = attr.key.as_ref();
-                        let value = String::from_utf8_lossy(attr.value.as_ref()).into_owned();
-                        if key == b"name" {
-                            name = Some(value);
-                        } else if key == b"r:id" {
-                            rel_id = Some(value);
-                        }
-                    }
+    fn draw_ui(&mut self, ui: &mut Ui) {
+        let output_format = self
+            .state
+            .borrow()
+            .template_format()
+            .unwrap_or(TemplateFormat::Xlsx);
+        if output_format.is_document() {
+            ui.label("Fill the document template for each CSV row");
+        } else {
+            ui.label("Create sheets for each CSV row and save them as a workbook");
+        }
 
-                    if name.as_deref() == Some(sheet_name)
-                        && let Some(rel) = rel_id
-                    {
-                        template_rel = Some(rel);
+        if self.state.borrow_mut().update_row_count() {
+            ui.ctx().request_repaint_after(Duration::from_millis(200));
+        }
+        let selected_rows = self.selected_indices().map(|indices| indices.len());
+        match validate_inputs(&self.state.borrow()) {
+            Ok(_) => {
+                let state = self.state.borrow();
+                match selected_rows {
+                    Ok(count) if state.has_all_rows() => ui.label(format!(
+                        "Rows ready for export: {} of {}",
+                        count,
+                        state.csv_rows.len()
+                    )),
+                    Ok(count) => ui.label(format!(
+                        "Rows ready for export: {} of the loaded rows. {}",
+                        count,
+                        state.row_count_text()
+                    )),
+                    Err(_) => {
+                        ui.colored_label(egui::Color32::DARK_RED, "The row selection is invalid.")
                     }
+                };
+                if let Some(path) = &state.odf_path {
+                    ui.label(format!("Template file: {}", path.display()));
+                }
+                if let Some(sheet) = &state.selected_sheet {
+                    ui.label(format!("Template sheet: {}", sheet));
                 }
             }
-            _ => {}
+            Err(reason) => {
+                ui.colored_label(egui::Color32::DARK_RED, reason);
+                return;
+            }
         }
-        buffer.clear();
-    }
-
-    template_rel.ok_or_else(|| "Template sheet not found".to_string())
-}
+        self.draw_row_selection(ui);
+        self.draw_grouping(ui);
 
-fn parse_workbook_relationships(
-    xml: &str,
-    template_rel_id: &str,
-) -> Result<(String, Vec<WorkbookRelationship>, u32), String> {
-    let mut reader = XmlReader::from_str(xml);
-    reader.trim_text(true);
-    let mut buffer = Vec::new();
-    let mut template_target = None;
-    let mut preserved = Vec::new();
-    let mut max_id = 0u32;
-
-    loop {
-        match reader
-            .read_event_into(&mut buffer)
-            .map_err(|err| err.to_string())?
-        {
-            Event::Eof => break,
-            Event::Empty(event) => {
-                if event.name().as_ref() == b"Relationship" {
-                    let mut id = None;
-                    let mut target = None;
-                    let mut kind = None;
-                    for attr in event.attributes().with_checks(false) {
-                        let attr = attr.map_err(|err| err.to_string())?;
-                        let key = attr.key.as_ref();
-                        let value = String::from_utf8_lossy(attr.value.as_ref()).into_owned();
-                        if key == b"Id" {
-                            id = Some(value.clone());
-                            if let Some(suffix) = value.strip_prefix("rId")
-                                && let Ok(number) = suffix.parse::<u32>()
-                            {
-                                max_id = max_id.max(number);
-                            }
-                        } else if key == b"Target" {
-                            target = Some(value);
-                        } else if key == b"Type" {
-                            kind = Some(value);
-                        }
-                    }
+        let (filter_name, extension) = output_format.output_filter();
+        let mut output_mode = self.state.borrow().output_mode;
+        ui.add_space(5.0);
+        ui.horizontal(|ui| {
+            if output_format.is_document() {
+                ui.radio_value(
+                    &mut output_mode,
+                    OutputMode::SingleFile,
+                    "Single merged document",
+                );
+                ui.radio_value(
+                    &mut output_mode,
+                    OutputMode::FilePerRow,
+                    "One document per row",
+                );
+            } else {
+                ui.radio_value(&mut output_mode, OutputMode::SingleFile, "Single workbook");
+                ui.radio_value(
+                    &mut output_mode,
+                    OutputMode::FilePerRow,
+                    "One workbook per row",
+                );
+            }
+        });
+        self.state.borrow_mut().output_mode = output_mode;
 
-                    let id = id.ok_or_else(|| "Relationship id missing".to_string())?;
-                    let target = target.ok_or_else(|| "Relationship target missing".to_string())?;
-                    let kind = kind.ok_or_else(|| "Relationship type missing".to_string())?;
+        match output_mode {
+            OutputMode::SingleFile if output_format == TemplateFormat::Docx => {
+                ui.label("Each record gets its own section with its own headers and footers.");
+            }
+            OutputMode::SingleFile if output_format.is_document() => {
+                ui.label("Each record starts on a new page with its own headers and footers.");
+            }
+            OutputMode::SingleFile => {}
+            OutputMode::FilePerRow => self.draw_file_name_settings(ui),
+        }
+        if !output_format.is_document() {
+            self.draw_sheet_name_settings(ui);
+            self.draw_kept_sheet_settings(ui);
+        }
 
-                    if kind.ends_with("/worksheet") {
-                        if id == template_rel_id {
-                            template_target = Some(target);
-                        }
-                    } else {
-                        preserved.push(WorkbookRelationship {
-                            id,
-                            target,
-                            type_attr: kind,
-                        });
+        let output_target = self.state.borrow().output_target;
+        ui.add_space(10.0);
+        ui.horizontal(|ui| {
+            match (output_mode, output_target) {
+                (OutputMode::FilePerRow, OutputTarget::Directory) => {
+                    if ui.button("Save to folder…").clicked()
+                        && let Some(path) = rfd::FileDialog::new().pick_folder()
+                    {
+                        self.generate_and_save(path);
+                    }
+                }
+                (OutputMode::FilePerRow, OutputTarget::ZipArchive) => {
+                    if ui.button("Save as…").clicked()
+                        && let Some(path) = rfd::FileDialog::new()
+                            .add_filter("ZIP archive", &["zip"])
+                            .set_file_name("bulk_output.zip")
+                            .save_file()
+                    {
+                        self.generate_and_save(path);
                     }
                 }
+                (OutputMode::SingleFile, _) => {
+                    if ui.button("Save as…").clicked()
+                        && let Some(path) = rfd::FileDialog::new()
+                            .add_filter(filter_name, &[extension])
+                            .set_file_name(format!("bulk_output.{}", extension))
+                            .save_file()
+                    {
+                        self.generate_and_save(path);
+                    }
+                }
+            }
+            if let Some(path) = &self.save_path {
+                ui.label(path.display().to_string());
             }
-            _ => {}
+        });
+
+        if let Some(message) = &self.status_message {
+            ui.colored_label(egui::Color32::DARK_GREEN, message);
+        }
+        if let Some(error) = &self.error_message {
+            ui.colored_label(egui::Color32::DARK_RED, error);
         }
-        buffer.clear();
     }
 
-    let target =
-        template_target.ok_or_else(|| "Template sheet relationship missing".to_string())?;
-    Ok((target, preserved, max_id))
-}
+    fn is_complete(&self) -> bool {
+        self.state.borrow().last_output_path.is_some()
+    }
 
-fn xml_escape(value: &str) -> String {
-    value
-        .replace('&', "&amp;")
-        .replace('<', "&lt;")
-        .replace('>', "&gt;")
-        .replace('"', "&quot;")
-        .replace('\'', "&apos;")
+    fn reset(&mut self) {
+        self.save_path = None;
+        self.status_message = None;
+        self.error_message = None;
+        self.selection = None;
+        self.state.borrow_mut().last_output_path = None;
+    }
 }
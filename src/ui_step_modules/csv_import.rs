@@ -1,10 +1,83 @@
-use crate::ui_step_modules::{ColumnPreview, SharedState, UiStepModule};
+use crate::ui_step_modules::{ColumnPreview, PREVIEW_ROWS, SharedState, UiStepModule};
+use bulk_sheet_engine::{
+    CellValue, CsvDialect, CsvOptions, DataSource, DataTable, JsonOptions, SqliteOptions,
+    detect_csv_dialect, is_json_data, is_sqlite_data, is_workbook_data, read_sheet_names,
+    read_sqlite_tables,
+};
 use egui::{ScrollArea, Ui};
 use std::cell::RefCell;
 use std::path::PathBuf;
 use std::rc::Rc;
+use std::time::Duration;
 
-type CsvPreviewData = (Vec<String>, Vec<Vec<String>>, Vec<ColumnPreview>);
+/// Delimiters offered in the CSV options; `None` detects it.
+const DELIMITERS: [(Option<char>, &str); 6] = [
+    (None, "Detect"),
+    (Some(','), "Comma"),
+    (Some(';'), "Semicolon"),
+    (Some('\t'), "Tab"),
+    (Some('|'), "Pipe"),
+    (Some(':'), "Colon"),
+];
+
+const QUOTES: [(Option<char>, &str); 3] = [
+    (None, "Detect"),
+    (Some('"'), "Double \""),
+    (Some('\''), "Single '"),
+];
+
+const ESCAPES: [(Option<char>, &str); 2] = [(None, "Doubled quotes"), (Some('\\'), "Backslash")];
+
+const COMMENTS: [(Option<char>, &str); 4] = [
+    (None, "None"),
+    (Some('#'), "#"),
+    (Some('%'), "%"),
+    (Some('/'), "/"),
+];
+
+/// Encoding labels offered in the CSV options; `None` detects it.
+const ENCODINGS: [Option<&str>; 6] = [
+    None,
+    Some("UTF-8"),
+    Some("UTF-16LE"),
+    Some("UTF-16BE"),
+    Some("windows-1252"),
+    Some("ISO-8859-15"),
+];
+
+/// The first rows of a data file with previews of its columns. `sheet_names` are only
+/// set for workbooks, `tables` for SQLite databases and `dialect` for CSV
+/// files.
+pub struct DataPreview {
+    pub headers: Vec<String>,
+    pub rows: Vec<Vec<String>>,
+    pub typed: Vec<Vec<Option<CellValue>>>,
+    pub columns: Vec<ColumnPreview>,
+    pub sheet_names: Vec<String>,
+    pub tables: Vec<String>,
+    pub dialect: Option<CsvDialect>,
+}
+
+impl DataPreview {
+    /// Stores the data in the state, choosing the first sheet of a workbook
+    /// when none is selected.
+    pub fn store(self, state: &mut SharedState, path: PathBuf) {
+        if state.data_sheet.is_none() {
+            state.data_sheet = self.sheet_names.first().cloned();
+        }
+        state.use_database = is_sqlite_data(&path);
+        state.csv_path = Some(path);
+        state.csv_headers = self.headers;
+        state.csv_rows = self.rows;
+        state.csv_typed = self.typed;
+        state.reset_row_count();
+        state.csv_preview = self.columns;
+        state.data_sheet_names = self.sheet_names;
+        state.csv_dialect = self.dialect;
+        state.sqlite_tables = self.tables;
+        state.ensure_cell_mappings();
+    }
+}
 
 pub struct CsvImportModule {
     state: Rc<RefCell<SharedState>>,
@@ -20,23 +93,250 @@ impl CsvImportModule {
     }
 
     fn open_csv(&mut self, path: PathBuf) {
-        let has_headers = self.state.borrow().csv_has_headers;
-        match load_csv_preview(&path, has_headers) {
-            Ok((headers, rows, preview)) => {
-                let mut state = self.state.borrow_mut();
-                state.csv_path = Some(path);
-                state.csv_headers = headers;
-                state.csv_rows = rows;
-                state.csv_preview = preview;
-                state.ensure_cell_mappings();
+        let source = self.state.borrow().data_source(&path);
+        match load_data_preview(&source) {
+            Ok(preview) => {
+                preview.store(&mut self.state.borrow_mut(), path);
                 self.load_error = None;
             }
             Err(err) => {
+                // Keep the file so that its import options can be fixed.
+                let mut state = self.state.borrow_mut();
+                state.csv_path = Some(path);
+                state.csv_rows.clear();
+                state.csv_typed.clear();
+                state.reset_row_count();
+                state.csv_preview.clear();
+                state.csv_dialect = None;
                 self.load_error = Some(err);
             }
         }
     }
 
+    /// Opens another data file, forgetting the settings of the last. Whether
+    /// a CSV file starts with column names is detected.
+    fn open_new_file(&mut self, path: PathBuf) {
+        {
+            let mut state = self.state.borrow_mut();
+            state.data_sheet = None;
+            state.data_skip_rows = 0;
+            state.data_range.clear();
+            state.csv_options = CsvOptions::default();
+            state.json_options = JsonOptions::default();
+            state.sqlite_options = SqliteOptions::default();
+            if is_sqlite_data(&path) {
+                state.sqlite_tables = read_sqlite_tables(&path).unwrap_or_default();
+                state.sqlite_options.table =
+                    state.sqlite_tables.first().cloned().unwrap_or_default();
+            } else if !is_workbook_data(&path)
+                && let Ok(dialect) = detect_csv_dialect(&path, &state.csv_options)
+            {
+                state.csv_has_headers = dialect.has_headers;
+            }
+        }
+        self.open_csv(path);
+    }
+
+    /// Replaces the file picker while a SQLite database is the source.
+    fn draw_database(&mut self, ui: &mut Ui) {
+        let selected_path = self
+            .state
+            .borrow()
+            .csv_path
+            .as_ref()
+            .map(|p| p.display().to_string())
+            .unwrap_or_else(|| "No database selected".to_string());
+        ui.horizontal(|ui| {
+            ui.label(selected_path);
+            if ui.button("Browse…").clicked()
+                && let Some(path) = rfd::FileDialog::new()
+                    .add_filter("SQLite", &["sqlite", "sqlite3", "db", "db3"])
+                    .pick_file()
+            {
+                self.open_new_file(path);
+            }
+        });
+        if self.state.borrow().csv_path.is_none() {
+            return;
+        }
+
+        let mut state = self.state.borrow_mut();
+        let mut changed = false;
+        ui.horizontal(|ui| {
+            ui.label("Table:");
+            let selected = state.sqlite_options.table.clone();
+            egui::ComboBox::from_id_salt("sqlite_table")
+                .selected_text(selected.as_str())
+                .show_ui(ui, |ui| {
+                    for table in state.sqlite_tables.clone() {
+                        if ui.selectable_label(table == selected, &table).clicked() {
+                            state.sqlite_options.table = table;
+                            state.sqlite_options.query.clear();
+                            changed = true;
+                        }
+                    }
+                });
+        });
+        ui.label("SQL query, instead of the whole table:");
+        ui.add(
+            egui::TextEdit::multiline(&mut state.sqlite_options.query)
+                .code_editor()
+                .desired_rows(3)
+                .desired_width(f32::INFINITY)
+                .hint_text("SELECT name, total FROM orders WHERE total > 0"),
+        );
+        ui.horizontal(|ui| {
+            changed |= ui.button("Run query").clicked();
+            ui.label(state.row_count_text());
+        });
+        drop(state);
+        if changed {
+            self.update_headers();
+        }
+    }
+
+    fn draw_csv_options(&mut self, ui: &mut Ui) {
+        let mut state = self.state.borrow_mut();
+        let detected = state.csv_dialect.as_ref().map(|dialect| {
+            let mut detected = format!(
+                "Read as {} separated, {} quoted, {}",
+                delimiter_name(dialect.delimiter),
+                dialect.quote,
+                dialect.encoding.name()
+            );
+            if dialect.skipped_lines > 0 {
+                detected += &format!(", skipping {} lines", dialect.skipped_lines);
+            }
+            detected
+        });
+        let mut changed = false;
+        egui::CollapsingHeader::new("CSV options")
+            .id_salt("csv_options")
+            .show(ui, |ui| {
+                if let Some(detected) = detected {
+                    ui.label(detected);
+                }
+                let options = &mut state.csv_options;
+                egui::Grid::new("csv_options_grid")
+                    .num_columns(2)
+                    .show(ui, |ui| {
+                        ui.label("Delimiter:");
+                        changed |=
+                            char_choice(ui, "delimiter", &mut options.delimiter, &DELIMITERS);
+                        ui.end_row();
+                        ui.label("Quote:");
+                        changed |= char_choice(ui, "quote", &mut options.quote, &QUOTES);
+                        ui.end_row();
+                        ui.label("Escape:");
+                        changed |= char_choice(ui, "escape", &mut options.escape, &ESCAPES);
+                        ui.end_row();
+                        ui.label("Comment lines:");
+                        changed |= char_choice(ui, "comment", &mut options.comment, &COMMENTS);
+                        ui.end_row();
+                        ui.label("Encoding:");
+                        egui::ComboBox::from_id_salt("csv_encoding")
+                            .selected_text(options.encoding.as_deref().unwrap_or("Detect"))
+                            .show_ui(ui, |ui| {
+                                for encoding in ENCODINGS {
+                                    let encoding = encoding.map(str::to_string);
+                                    let label = encoding.as_deref().unwrap_or("Detect").to_string();
+                                    changed |= ui
+                                        .selectable_value(&mut options.encoding, encoding, label)
+                                        .changed();
+                                }
+                            });
+                        ui.end_row();
+                        ui.label("Lines to skip:");
+                        changed |= ui
+                            .add(egui::DragValue::new(&mut options.skip_lines).range(0..=1000))
+                            .changed();
+                        ui.end_row();
+                    });
+                changed |= ui
+                    .checkbox(&mut options.trim, "Trim whitespace around values")
+                    .changed();
+                changed |= ui
+                    .checkbox(&mut options.flexible, "Allow rows of different lengths")
+                    .changed();
+            });
+        drop(state);
+        if changed {
+            self.update_headers();
+        }
+    }
+
+    fn draw_workbook_settings(&mut self, ui: &mut Ui) {
+        let mut state = self.state.borrow_mut();
+        let mut changed = false;
+        ui.horizontal(|ui| {
+            ui.label("Sheet:");
+            let selected = state.data_sheet.clone().unwrap_or_default();
+            egui::ComboBox::from_id_salt("data_sheet")
+                .selected_text(selected.as_str())
+                .show_ui(ui, |ui| {
+                    for name in state.data_sheet_names.clone() {
+                        if ui.selectable_label(name == selected, &name).clicked() {
+                            state.data_sheet = Some(name);
+                            changed = true;
+                        }
+                    }
+                });
+        });
+        ui.horizontal(|ui| {
+            ui.label("Rows above the header:");
+            changed |= ui
+                .add(egui::DragValue::new(&mut state.data_skip_rows).range(0..=1000))
+                .changed();
+        });
+        ui.horizontal(|ui| {
+            ui.label("Cell range:");
+            changed |= ui
+                .add(
+                    egui::TextEdit::singleline(&mut state.data_range)
+                        .hint_text("whole sheet, or e.g. A3:F200")
+                        .desired_width(140.0),
+                )
+                .lost_focus();
+        });
+        drop(state);
+        if changed {
+            self.update_headers();
+        }
+    }
+
+    fn draw_json_settings(&mut self, ui: &mut Ui) {
+        let mut state = self.state.borrow_mut();
+        let options = &mut state.json_options;
+        let mut changed = false;
+        egui::Grid::new("json_options_grid")
+            .num_columns(2)
+            .show(ui, |ui| {
+                ui.label("Records:");
+                changed |= ui
+                    .add(
+                        egui::TextEdit::singleline(&mut options.records)
+                            .hint_text("detect, or e.g. data.orders")
+                            .desired_width(180.0),
+                    )
+                    .lost_focus();
+                ui.end_row();
+                ui.label("Rows per element of:");
+                changed |= ui
+                    .add(
+                        egui::TextEdit::singleline(&mut options.expand)
+                            .hint_text("none, or e.g. items")
+                            .desired_width(180.0),
+                    )
+                    .lost_focus();
+                ui.end_row();
+            });
+        ui.label("Nested fields become columns such as customer.address.city.");
+        drop(state);
+        if changed {
+            self.update_headers();
+        }
+    }
+
     fn update_headers(&mut self) {
         let path = self.state.borrow().csv_path.clone();
         if let Some(path) = path {
@@ -47,7 +347,7 @@ impl CsvImportModule {
 
 impl UiStepModule for CsvImportModule {
     fn get_title(&self) -> String {
-        "Import CSV".to_string()
+        "Import data".to_string()
     }
 
     fn draw_ui(&mut self, ui: &mut Ui) {
@@ -59,26 +359,66 @@ impl UiStepModule for CsvImportModule {
             .map(|p| p.display().to_string())
             .unwrap_or_else(|| "No file selected".to_string());
 
-        ui.label("Choose a CSV file and preview its columns");
+        let mut use_database = self.state.borrow().use_database;
+        ui.horizontal(|ui| {
+            ui.label("Source:");
+            ui.radio_value(&mut use_database, false, "File");
+            ui.radio_value(&mut use_database, true, "SQLite database");
+        });
+        if use_database != self.state.borrow().use_database {
+            self.load_error = None;
+            let mut state = self.state.borrow_mut();
+            state.reset_csv();
+            state.use_database = use_database;
+        }
+
         let has_selection = self.state.borrow().csv_path.is_some();
+        if use_database {
+            self.draw_database(ui);
+        } else {
+            ui.label("Choose a CSV, JSON or spreadsheet file and preview its columns");
+        }
         ui.horizontal(|ui| {
+            if use_database {
+                return;
+            }
             ui.label(selected_path);
             if ui.button("Browse…").clicked()
                 && let Some(path) = rfd::FileDialog::new()
+                    .add_filter(
+                        "Data",
+                        &[
+                            "csv", "json", "ndjson", "jsonl", "xlsx", "xlsm", "xls", "ods",
+                        ],
+                    )
                     .add_filter("CSV", &["csv"])
+                    .add_filter("JSON", &["json", "ndjson", "jsonl"])
+                    .add_filter("Spreadsheet", &["xlsx", "xlsm", "xls", "ods"])
                     .pick_file()
             {
-                self.open_csv(path);
+                self.open_new_file(path);
             }
             if has_selection && ui.button("Clear").clicked() {
                 self.state.borrow_mut().reset_csv();
             }
         });
 
+        let path = self.state.borrow().csv_path.clone();
+        let is_json = path.as_deref().is_some_and(is_json_data);
+        match path {
+            _ if use_database => {}
+            Some(_) if is_json => self.draw_json_settings(ui),
+            Some(path) if is_workbook_data(&path) => self.draw_workbook_settings(ui),
+            Some(_) => self.draw_csv_options(ui),
+            None => {}
+        }
+
         let mut has_headers = self.state.borrow().csv_has_headers;
-        if ui
-            .checkbox(&mut has_headers, "Treat first row as headers")
-            .changed()
+        if !is_json
+            && !use_database
+            && ui
+                .checkbox(&mut has_headers, "Treat first row as headers")
+                .changed()
         {
             self.state.borrow_mut().csv_has_headers = has_headers;
             self.update_headers();
@@ -90,9 +430,15 @@ impl UiStepModule for CsvImportModule {
 
         let state_snapshot = self.state.borrow().csv_preview.clone();
         if state_snapshot.is_empty() {
-            ui.label("Load a CSV file to see column previews.");
+            ui.label("Load a data file to see column previews.");
             return;
         }
+        if self.state.borrow_mut().update_row_count() {
+            ui.ctx().request_repaint_after(Duration::from_millis(200));
+        }
+        if !use_database {
+            ui.label(self.state.borrow().row_count_text());
+        }
 
         ui.add_space(10.0);
         ui.heading("Column previews");
@@ -128,66 +474,75 @@ fn draw_column_preview(ui: &mut Ui, preview: &ColumnPreview) {
     ui.add_space(6.0);
 }
 
-fn load_csv_preview(path: &PathBuf, has_headers: bool) -> Result<CsvPreviewData, String> {
-    let mut reader = csv::ReaderBuilder::new()
-        .has_headers(has_headers)
-        .from_path(path)
-        .map_err(|err| err.to_string())?;
-
-    let headers: Vec<String> = if has_headers {
-        reader
-            .headers()
-            .map_err(|err| err.to_string())?
-            .iter()
-            .enumerate()
-            .map(|(idx, value)| {
-                if value.is_empty() {
-                    format!("Column {}", idx + 1)
-                } else {
-                    value.to_string()
-                }
-            })
-            .collect()
-    } else {
-        Vec::new()
-    };
-
-    let mut rows: Vec<Vec<String>> = Vec::new();
-    for record in reader.records() {
-        let record = record.map_err(|err| err.to_string())?;
-        rows.push(record.iter().map(|cell| cell.to_string()).collect());
-    }
-
-    let column_count = if has_headers {
-        headers
-            .len()
-            .max(rows.iter().map(|row| row.len()).max().unwrap_or(0))
-    } else {
-        rows.iter().map(|row| row.len()).max().unwrap_or(0)
+pub fn load_data_preview(source: &DataSource) -> Result<DataPreview, String> {
+    let DataTable {
+        headers,
+        rows,
+        typed,
+        ..
+    } = source.load_preview(PREVIEW_ROWS)?;
+    let (sheet_names, tables, dialect) = match source {
+        DataSource::Workbook { path, .. } => (read_sheet_names(path)?, Vec::new(), None),
+        DataSource::Sqlite { path, .. } => (Vec::new(), read_sqlite_tables(path)?, None),
+        DataSource::Json { .. } => (Vec::new(), Vec::new(), None),
+        DataSource::Csv { path, options, .. } => (
+            Vec::new(),
+            Vec::new(),
+            Some(detect_csv_dialect(path, options)?),
+        ),
     };
+    let previews = headers
+        .iter()
+        .enumerate()
+        .map(|(index, header)| ColumnPreview {
+            index,
+            header: header.clone(),
+            samples: rows
+                .iter()
+                .take(5)
+                .map(|row| row.get(index).cloned().unwrap_or_default())
+                .collect(),
+        })
+        .collect();
 
-    let headers = if has_headers {
-        headers
-    } else {
-        (0..column_count)
-            .map(|index| format!("Column {}", index + 1))
-            .collect()
-    };
+    Ok(DataPreview {
+        headers,
+        rows,
+        typed,
+        columns: previews,
+        sheet_names,
+        tables,
+        dialect,
+    })
+}
 
-    let mut previews = Vec::new();
-    for index in 0..column_count {
-        let header = headers.get(index).cloned().unwrap_or_default();
-        let samples = rows
-            .iter()
-            .take(5)
-            .map(|row| row.get(index).cloned().unwrap_or_default())
-            .collect();
-        previews.push(ColumnPreview {
-            index,
-            header,
-            samples,
+/// A combo box of `choices`; returns whether the choice changed.
+fn char_choice(
+    ui: &mut Ui,
+    id_salt: &str,
+    value: &mut Option<char>,
+    choices: &[(Option<char>, &str)],
+) -> bool {
+    let selected = choices
+        .iter()
+        .find(|(choice, _)| choice == value)
+        .map(|(_, label)| label.to_string())
+        .unwrap_or_else(|| value.map(String::from).unwrap_or_default());
+    let mut changed = false;
+    egui::ComboBox::from_id_salt(("csv", id_salt))
+        .selected_text(selected)
+        .show_ui(ui, |ui| {
+            for (choice, label) in choices {
+                changed |= ui.selectable_value(value, *choice, *label).changed();
+            }
         });
-    }
+    changed
+}
 
-    Ok((headers, rows, previews))
+fn delimiter_name(delimiter: char) -> String {
+    DELIMITERS
+        .iter()
+        .find(|(choice, _)| *choice == Some(delimiter))
+        .map(|(_, label)| label.to_lowercase())
+        .unwrap_or_else(|| format!("\"{}\"", delimiter))
 }
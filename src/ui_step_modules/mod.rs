@@ -1,12 +1,14 @@
 mod bulk_create;
 mod csv_import;
 mod odf_import;
+mod project;
 mod shared_state;
 
-pub use bulk_create::BulkCreateModule;
-pub use csv_import::CsvImportModule;
+pub use bulk_create::{BulkCreateModule, job, validate_inputs};
+pub use csv_import::{CsvImportModule, load_data_preview};
 pub use odf_import::OdfImportModule;
-pub use shared_state::{ColumnPreview, SharedState, column_label_from_index, parse_cell_reference};
+pub use project::{PROJECT_EXTENSION, Project, project_base_dir};
+pub use shared_state::{ColumnPreview, DerivedMapping, PREVIEW_ROWS, SharedState};
 
 pub trait UiStepModule {
     fn get_title(&self) -> String;
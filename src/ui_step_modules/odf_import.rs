@@ -1,11 +1,12 @@
-use crate::ui_step_modules::{
-    SharedState, UiStepModule, column_label_from_index, parse_cell_reference,
+use crate::ui_step_modules::{DerivedMapping, SharedState, UiStepModule};
+use bulk_sheet_engine::{
+    CardLayout, Expression, RowBlock, TemplateFormat, ValueType, cell_label, parse_cell_targets,
+    read_sheet_cells, read_sheet_names, read_sheet_placeholders, read_template_placeholders,
 };
-use calamine::{Data, DataType, Reader, open_workbook_auto};
 use egui::{ComboBox, Grid, Ui};
 use std::cell::RefCell;
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
 
 pub struct OdfImportModule {
@@ -24,14 +25,20 @@ impl OdfImportModule {
     }
 
     fn open_template(&mut self, path: PathBuf) {
+        if TemplateFormat::from_path(&path).is_document() {
+            self.open_document(path);
+            return;
+        }
         match read_sheet_names(&path) {
             Ok(sheet_names) => {
                 let selected_sheet = sheet_names.first().cloned();
                 let mut cell_values = HashMap::new();
+                let mut placeholders = Vec::new();
                 if let Some(sheet) = &selected_sheet {
-                    match read_sheet_cells(&path, sheet) {
-                        Ok(map) => {
+                    match read_sheet_contents(&path, sheet) {
+                        Ok((map, names)) => {
                             cell_values = map;
+                            placeholders = names;
                             self.sheet_error = None;
                         }
                         Err(err) => {
@@ -44,7 +51,26 @@ impl OdfImportModule {
                 state.odf_sheet_names = sheet_names;
                 state.selected_sheet = selected_sheet;
                 state.template_cell_values = cell_values;
+                state.template_placeholders = placeholders;
+                self.load_error = None;
+            }
+            Err(err) => {
+                self.load_error = Some(err);
+            }
+        }
+    }
+
+    fn open_document(&mut self, path: PathBuf) {
+        match read_template_placeholders(&path) {
+            Ok(placeholders) => {
+                let mut state = self.state.borrow_mut();
+                state.odf_path = Some(path);
+                state.odf_sheet_names.clear();
+                state.selected_sheet = None;
+                state.template_cell_values.clear();
+                state.template_placeholders = placeholders;
                 self.load_error = None;
+                self.sheet_error = None;
             }
             Err(err) => {
                 self.load_error = Some(err);
@@ -52,6 +78,89 @@ impl OdfImportModule {
         }
     }
 
+    fn draw_repeated_rows(&self, ui: &mut Ui) {
+        let mut state = self.state.borrow_mut();
+        ui.horizontal(|ui| {
+            ui.label("Repeated rows:");
+            ui.add(
+                egui::TextEdit::singleline(&mut state.repeat_rows)
+                    .hint_text("none, or e.g. A10:F10")
+                    .desired_width(120.0),
+            )
+            .on_hover_text(REPEATED_ROWS_HELP);
+            let text = state.repeat_rows.trim();
+            if !text.is_empty() && RowBlock::parse(text).is_none() {
+                ui.colored_label(egui::Color32::DARK_RED, "Not a row range");
+            }
+        });
+    }
+
+    fn draw_cards(&self, ui: &mut Ui) {
+        let mut state = self.state.borrow_mut();
+        ui.horizontal(|ui| {
+            ui.label("Cards:");
+            ui.add(
+                egui::TextEdit::singleline(&mut state.card)
+                    .hint_text("none, or e.g. A1:C6")
+                    .desired_width(120.0),
+            )
+            .on_hover_text(CARDS_HELP);
+            ui.label("across × down:");
+            ui.add(
+                egui::TextEdit::singleline(&mut state.card_tiles)
+                    .hint_text("e.g. 2x5")
+                    .desired_width(60.0),
+            )
+            .on_hover_text(CARDS_HELP);
+            let (card, tiles) = (state.card.trim(), state.card_tiles.trim());
+            if (!card.is_empty() || !tiles.is_empty())
+                && let Err(err) = CardLayout::parse(card, tiles)
+            {
+                ui.colored_label(egui::Color32::DARK_RED, err);
+            }
+        });
+    }
+
+    fn draw_placeholder_bindings(&self, ui: &mut Ui) {
+        let state = self.state.borrow();
+        if state.template_placeholders.is_empty() {
+            ui.label("No {{placeholders}} found in the selected document.");
+            return;
+        }
+        if state.csv_headers.is_empty() {
+            ui.label("Import a CSV file to bind placeholders to columns.");
+            return;
+        }
+
+        let first_row = state.csv_rows.first().cloned().unwrap_or_default();
+        ui.add_space(10.0);
+        ui.heading("Placeholder to column binding");
+        ui.add_space(5.0);
+        Grid::new("placeholder_column_binding")
+            .striped(true)
+            .show(ui, |ui| {
+                ui.label("Placeholder");
+                ui.label("CSV column");
+                ui.label("New value");
+                ui.end_row();
+
+                for name in &state.template_placeholders {
+                    ui.label(format!("{{{{{}}}}}", name));
+                    match state.csv_headers.iter().position(|header| header == name) {
+                        Some(index) => {
+                            ui.label(name);
+                            ui.label(first_row.get(index).cloned().unwrap_or_default());
+                        }
+                        None => {
+                            ui.colored_label(egui::Color32::DARK_RED, "(no matching column)");
+                            ui.label("");
+                        }
+                    }
+                    ui.end_row();
+                }
+            });
+    }
+
     fn reload_selected_sheet(&mut self) {
         let (path, sheet_name) = {
             let state = self.state.borrow();
@@ -60,9 +169,11 @@ impl OdfImportModule {
                 _ => return,
             }
         };
-        match read_sheet_cells(&path, &sheet_name) {
-            Ok(map) => {
-                self.state.borrow_mut().template_cell_values = map;
+        match read_sheet_contents(&path, &sheet_name) {
+            Ok((map, placeholders)) => {
+                let mut state = self.state.borrow_mut();
+                state.template_cell_values = map;
+                state.template_placeholders = placeholders;
                 self.sheet_error = None;
             }
             Err(err) => {
@@ -72,6 +183,91 @@ impl OdfImportModule {
     }
 }
 
+const DERIVED_VALUE_HELP: &str = "Text with {{…}} tokens such as {{First}} {{Last}}, {{#}} for the row number, \
+    or expressions like {{Amount * 1.19}}, {{upper(Last)}}, {{default(Phone, \"n/a\")}}, \
+    {{date(Due, \"%d.%m.%Y\")}} and {{today()}}";
+
+const REPEATED_ROWS_HELP: &str = "Template rows such as A10:F10 that are written once per row of a \
+    group, e.g. the item row of an invoice. Rows below move down; formulas such as SUM(F10:F10), \
+    merged cells and the print area grow with them";
+
+const CARDS_HELP: &str = "A range such as A1:C6 that holds one label or badge, tiled e.g. 2x5 \
+    times per sheet. Each card is filled with the next row, left to right and top to bottom; \
+    map columns to the cells of the first card";
+
+const DETAIL_HELP: &str =
+    "Write every row of the group, one row below the other, instead of only its first row";
+
+/// Draws the cell, type, current value and new value columns of a mapping
+/// row. `new_value` is the value for the first CSV row.
+fn draw_cell_columns(
+    ui: &mut Ui,
+    id_salt: (&str, usize),
+    cell_ref: &mut String,
+    value_type: &mut ValueType,
+    format: &mut String,
+    template_values: &HashMap<String, String>,
+    new_value: Result<String, String>,
+) {
+    let mut edited = cell_ref.clone();
+    if ui
+        .text_edit_singleline(&mut edited)
+        .on_hover_text("A cell such as B4, a range such as B2:D2 or a list such as B2, H40")
+        .changed()
+    {
+        *cell_ref = edited.trim().to_ascii_uppercase();
+    }
+
+    ui.horizontal(|ui| {
+        ComboBox::from_id_salt(("value_type", id_salt))
+            .selected_text(value_type.label())
+            .show_ui(ui, |ui| {
+                for option in ValueType::ALL {
+                    ui.selectable_value(value_type, option, option.label());
+                }
+            });
+        if value_type.uses_format() {
+            ui.add(
+                egui::TextEdit::singleline(format)
+                    .hint_text(value_type.default_format())
+                    .desired_width(120.0),
+            );
+        }
+    });
+
+    let existing = match parse_cell_targets(cell_ref) {
+        Ok(cells) => cells
+            .iter()
+            .map(|&(row, col)| {
+                template_values
+                    .get(&cell_label(row, col))
+                    .cloned()
+                    .unwrap_or_else(|| "(empty)".to_string())
+            })
+            .collect::<Vec<_>>()
+            .join(", "),
+        Err(err) => err,
+    };
+    ui.label(existing);
+
+    let format = Some(format.as_str()).filter(|format| !format.is_empty());
+    match new_value.and_then(|value| value_type.convert(&value, format).map(|_| value)) {
+        Ok(value) => ui.label(value),
+        Err(err) => ui.colored_label(egui::Color32::DARK_RED, err),
+    };
+}
+
+/// The cell values and `{{placeholders}}` of a workbook sheet.
+fn read_sheet_contents(
+    path: &Path,
+    sheet: &str,
+) -> Result<(HashMap<String, String>, Vec<String>), String> {
+    Ok((
+        read_sheet_cells(path, sheet)?,
+        read_sheet_placeholders(path, sheet)?,
+    ))
+}
+
 impl UiStepModule for OdfImportModule {
     fn get_title(&self) -> String {
         "Configure Template".to_string()
@@ -84,15 +280,19 @@ impl UiStepModule for OdfImportModule {
             .odf_path
             .as_ref()
             .map(|p| p.display().to_string())
-            .unwrap_or_else(|| "No workbook selected".to_string());
+            .unwrap_or_else(|| "No template selected".to_string());
 
-        ui.label("Select a workbook (ODS/XLSX) and map CSV columns to template cells");
+        ui.label(
+            "Select a workbook (ODS/XLSX) or text document (ODT/DOCX) and map CSV columns to template cells or placeholders",
+        );
         let has_template = self.state.borrow().odf_path.is_some();
         ui.horizontal(|ui| {
             ui.label(template_path);
             if ui.button("Browse…").clicked()
                 && let Some(path) = rfd::FileDialog::new()
+                    .add_filter("Templates", &["ods", "xlsx", "xlsm", "xls", "odt", "docx"])
                     .add_filter("Spreadsheets", &["ods", "xlsx", "xlsm", "xls"])
+                    .add_filter("Text documents", &["odt", "docx"])
                     .pick_file()
             {
                 self.open_template(path);
@@ -106,8 +306,12 @@ impl UiStepModule for OdfImportModule {
             ui.colored_label(egui::Color32::DARK_RED, err);
         }
 
-        if self.state.borrow().odf_path.is_none() {
-            ui.label("Select a workbook file to continue.");
+        let Some(format) = self.state.borrow().template_format() else {
+            ui.label("Select a template file to continue.");
+            return;
+        };
+        if format.is_document() {
+            self.draw_placeholder_bindings(ui);
             return;
         }
 
@@ -145,6 +349,8 @@ impl UiStepModule for OdfImportModule {
         if let Some(err) = &self.sheet_error {
             ui.colored_label(egui::Color32::DARK_RED, err);
         }
+        self.draw_repeated_rows(ui);
+        self.draw_cards(ui);
 
         if self.state.borrow().csv_headers.is_empty() {
             ui.label("Import a CSV file to configure column mappings.");
@@ -156,16 +362,19 @@ impl UiStepModule for OdfImportModule {
         let first_row = state.csv_rows.first().cloned().unwrap_or_default();
         let template_values = state.template_cell_values.clone();
         let mapping_len = state.cell_mappings.len();
+        let mut removed = None;
         ui.add_space(10.0);
         ui.heading("Column to cell mapping");
         ui.add_space(5.0);
         Grid::new("column_cell_mapping")
             .striped(true)
             .show(ui, |ui| {
-                ui.label("CSV column");
-                ui.label("Template cell");
+                ui.label("CSV column or derived value");
+                ui.label("Template cells");
+                ui.label("Type");
                 ui.label("Current value");
                 ui.label("New value");
+                ui.label("Detail").on_hover_text(DETAIL_HELP);
                 ui.end_row();
 
                 for index in 0..mapping_len {
@@ -175,41 +384,87 @@ impl UiStepModule for OdfImportModule {
                         .cloned()
                         .unwrap_or_else(|| format!("Column {}", mapping.column_index + 1));
                     ui.label(header);
-
-                    let mut cell_ref = mapping.cell_ref.clone();
-                    if ui.text_edit_singleline(&mut cell_ref).changed() {
-                        mapping.cell_ref = cell_ref.trim().to_ascii_uppercase();
-                    }
-
-                    let is_valid_cell = parse_cell_reference(&mapping.cell_ref).is_some();
-                    let existing = if !is_valid_cell {
-                        "(invalid cell)".to_string()
-                    } else {
-                        template_values
-                            .get(&mapping.cell_ref)
-                            .cloned()
-                            .unwrap_or_else(|| "(empty)".to_string())
-                    };
-                    ui.label(existing);
-
                     let new_value = first_row
                         .get(mapping.column_index)
                         .cloned()
                         .unwrap_or_default();
-                    ui.label(new_value);
+                    draw_cell_columns(
+                        ui,
+                        ("column", index),
+                        &mut mapping.cell_ref,
+                        &mut mapping.value_type,
+                        &mut mapping.format,
+                        &template_values,
+                        Ok(new_value),
+                    );
+                    ui.checkbox(&mut mapping.detail, "")
+                        .on_hover_text(DETAIL_HELP);
+                    ui.end_row();
+                }
+
+                for (index, mapping) in state.derived_mappings.iter_mut().enumerate() {
+                    ui.horizontal(|ui| {
+                        ui.add(
+                            egui::TextEdit::singleline(&mut mapping.expression)
+                                .hint_text("{{First}} {{Last}}")
+                                .desired_width(160.0),
+                        )
+                        .on_hover_text(DERIVED_VALUE_HELP);
+                        if ui.small_button("✖").on_hover_text("Remove").clicked() {
+                            removed = Some(index);
+                        }
+                    });
+                    let new_value = Expression::parse(&mapping.expression, &headers)
+                        .and_then(|expression| expression.evaluate(&first_row, 1));
+                    draw_cell_columns(
+                        ui,
+                        ("derived", index),
+                        &mut mapping.cell_ref,
+                        &mut mapping.value_type,
+                        &mut mapping.format,
+                        &template_values,
+                        new_value,
+                    );
+                    ui.checkbox(&mut mapping.detail, "")
+                        .on_hover_text(DETAIL_HELP);
                     ui.end_row();
                 }
             });
+        if let Some(index) = removed {
+            state.derived_mappings.remove(index);
+        }
+        if ui
+            .button("Add derived value")
+            .on_hover_text(DERIVED_VALUE_HELP)
+            .clicked()
+        {
+            state.derived_mappings.push(DerivedMapping::default());
+        }
+        let has_placeholders = !state.template_placeholders.is_empty();
+        drop(state);
+        if has_placeholders {
+            self.draw_placeholder_bindings(ui);
+        }
     }
 
     fn is_complete(&self) -> bool {
         let state = self.state.borrow();
+        if let Some(format) = state.template_format()
+            && format.is_document()
+        {
+            return !state.bound_placeholders().is_empty();
+        }
         state.odf_path.is_some()
             && state.selected_sheet.is_some()
-            && state
+            && (state
                 .cell_mappings
                 .iter()
                 .all(|mapping| !mapping.cell_ref.trim().is_empty())
+                || state
+                    .mappings()
+                    .iter()
+                    .any(|mapping| mapping.expression.is_some())
+                || !state.bound_placeholders().is_empty())
     }
 
     fn reset(&mut self) {
@@ -218,38 +473,3 @@ impl UiStepModule for OdfImportModule {
         self.state.borrow_mut().reset_template();
     }
 }
-
-fn read_sheet_names(path: &PathBuf) -> Result<Vec<String>, String> {
-    let workbook = open_workbook_auto(path).map_err(|err| err.to_string())?;
-    Ok(workbook.sheet_names().to_vec())
-}
-
-fn read_sheet_cells(path: &PathBuf, sheet: &str) -> Result<HashMap<String, String>, String> {
-    let mut workbook = open_workbook_auto(path).map_err(|err| err.to_string())?;
-    let range = workbook
-        .worksheet_range(sheet)
-        .map_err(|err| err.to_string())?;
-
-    let mut values = HashMap::new();
-    for (row, col, value) in range.cells() {
-        if value.is_empty() {
-            continue;
-        }
-        let label = format!("{}{}", column_label_from_index(col as u32), row + 1);
-        values.insert(label, stringify_data(value));
-    }
-    Ok(values)
-}
-
-fn stringify_data(data: &Data) -> String {
-    match data {
-        Data::String(value) => value.clone(),
-        Data::Float(value) => format!("{}", value),
-        Data::Int(value) => value.to_string(),
-        Data::Bool(value) => value.to_string(),
-        Data::DateTimeIso(value) | Data::DurationIso(value) => value.clone(),
-        Data::DateTime(value) => format!("{:?}", value),
-        Data::Error(err) => format!("Error: {:?}", err),
-        Data::Empty => String::new(),
-    }
-}
@@ -0,0 +1,407 @@
+use crate::ui_step_modules::{DerivedMapping, SharedState, load_data_preview};
+use bulk_sheet_engine::{
+    CsvOptions, Grouping, JsonOptions, KeptSheet, Mapping, OutputMode, OutputTarget, RowFilter,
+    SqliteOptions, Template, TemplateFormat, read_sheet_names, read_sheet_placeholders,
+    read_template_placeholders,
+};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+/// File extension of saved projects.
+pub const PROJECT_EXTENSION: &str = "toml";
+
+/// The wizard configuration as stored in a project file. Paths are stored
+/// relative to the project file where possible, with `/` separators, so that
+/// projects can be shared next to their data.
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Project {
+    pub csv: CsvSettings,
+    pub template: TemplateSettings,
+    /// Mappings refer to CSV columns by name, so they survive reordered
+    /// columns.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub mappings: Vec<Mapping>,
+    #[serde(skip_serializing_if = "RowFilter::is_empty")]
+    pub filter: RowFilter,
+    #[serde(skip_serializing_if = "Grouping::is_empty")]
+    pub grouping: Grouping,
+    pub output: OutputSettings,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CsvSettings {
+    pub path: Option<String>,
+    pub has_headers: bool,
+    /// Sheet, rows above the header row and cell range of a workbook data
+    /// file.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sheet: Option<String>,
+    #[serde(skip_serializing_if = "is_zero")]
+    pub skip_rows: usize,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub range: String,
+    /// Import options of a CSV file.
+    #[serde(flatten)]
+    pub options: CsvOptions,
+    /// Records and expanded array of a JSON file.
+    #[serde(flatten)]
+    pub json: JsonOptions,
+    /// Table or query of a SQLite database.
+    #[serde(flatten)]
+    pub sqlite: SqliteOptions,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TemplateSettings {
+    pub path: Option<String>,
+    pub sheet: Option<String>,
+    /// Rows repeated per row of a group, such as `A10:F10`.
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub repeat_rows: String,
+    /// Card range and tiling such as `A1:C6` and `2x5` for several records
+    /// per sheet.
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub card: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub tiles: String,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OutputSettings {
+    pub mode: OutputMode,
+    pub target: OutputTarget,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub file_name_pattern: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub sheet_name_pattern: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub kept_sheets: Vec<KeptSheet>,
+}
+
+impl Project {
+    pub fn from_state(state: &SharedState, base_dir: &Path) -> Self {
+        Self {
+            csv: CsvSettings {
+                path: state
+                    .csv_path
+                    .as_deref()
+                    .map(|path| store_path(path, base_dir)),
+                has_headers: state.csv_has_headers,
+                sheet: state
+                    .data_sheet
+                    .clone()
+                    .filter(|_| !state.data_sheet_names.is_empty()),
+                skip_rows: state.data_skip_rows,
+                range: state.data_range.clone(),
+                options: state.csv_options.clone(),
+                json: state.json_options.clone(),
+                sqlite: state.sqlite_options.clone(),
+            },
+            template: TemplateSettings {
+                path: state
+                    .odf_path
+                    .as_deref()
+                    .map(|path| store_path(path, base_dir)),
+                sheet: state.selected_sheet.clone(),
+                repeat_rows: state.repeat_rows.clone(),
+                card: state.card.clone(),
+                tiles: state.card_tiles.clone(),
+            },
+            mappings: state.mappings(),
+            filter: state.row_filter.clone(),
+            grouping: state.grouping.clone(),
+            output: OutputSettings {
+                mode: state.output_mode,
+                target: state.output_target,
+                file_name_pattern: state.file_name_pattern.clone(),
+                sheet_name_pattern: state.sheet_name_pattern.clone(),
+                kept_sheets: state.kept_sheets.clone(),
+            },
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|err| err.to_string())?;
+        toml::from_str(&text).map_err(|err| err.to_string())
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let text = toml::to_string_pretty(self).map_err(|err| err.to_string())?;
+        fs::write(path, text).map_err(|err| err.to_string())
+    }
+
+    /// Replaces the state with the project's configuration, reading the CSV
+    /// file and template again. Relative paths are resolved against
+    /// `base_dir`.
+    pub fn apply(&self, state: &mut SharedState, base_dir: &Path) -> Result<(), String> {
+        *state = SharedState {
+            csv_has_headers: self.csv.has_headers,
+            data_sheet: self.csv.sheet.clone(),
+            data_skip_rows: self.csv.skip_rows,
+            data_range: self.csv.range.clone(),
+            csv_options: self.csv.options.clone(),
+            json_options: self.csv.json.clone(),
+            sqlite_options: self.csv.sqlite.clone(),
+            row_filter: self.filter.clone(),
+            grouping: self.grouping.clone(),
+            output_mode: self.output.mode,
+            output_target: self.output.target,
+            file_name_pattern: self.output.file_name_pattern.clone(),
+            sheet_name_pattern: self.output.sheet_name_pattern.clone(),
+            kept_sheets: self.output.kept_sheets.clone(),
+            ..SharedState::default()
+        };
+
+        if let Some(stored) = &self.csv.path {
+            let path = resolve_path(stored, base_dir);
+            load_data_preview(&state.data_source(&path))
+                .map_err(|err| format!("Cannot read {}: {}", path.display(), err))?
+                .store(state, path);
+        }
+
+        if let Some(stored) = &self.template.path {
+            let path = resolve_path(stored, base_dir);
+            if TemplateFormat::from_path(&path).is_document() {
+                state.template_placeholders = read_template_placeholders(&path)
+                    .map_err(|err| format!("Cannot read {}: {}", path.display(), err))?;
+            } else {
+                let sheet_names = read_sheet_names(&path)
+                    .map_err(|err| format!("Cannot read {}: {}", path.display(), err))?;
+                let template = Template {
+                    path: path.clone(),
+                    sheet: self.template.sheet.clone(),
+                    repeat_rows: None,
+                    cards: None,
+                };
+                let sheet_name = template.sheet_name()?;
+                if self
+                    .output
+                    .kept_sheets
+                    .iter()
+                    .any(|kept| kept.name == sheet_name)
+                {
+                    return Err(format!(
+                        "The template sheet \"{}\" cannot be kept as well",
+                        sheet_name
+                    ));
+                }
+                if let Some(kept) = self
+                    .output
+                    .kept_sheets
+                    .iter()
+                    .find(|kept| !sheet_names.contains(&kept.name))
+                {
+                    return Err(format!(
+                        "Sheet \"{}\" not found; the template has: {}",
+                        kept.name,
+                        sheet_names.join(", ")
+                    ));
+                }
+                state.template_placeholders = read_sheet_placeholders(&path, &sheet_name)
+                    .map_err(|err| format!("Cannot read {}: {}", path.display(), err))?;
+                state.selected_sheet = Some(sheet_name);
+                state.odf_sheet_names = sheet_names;
+                state.repeat_rows = self.template.repeat_rows.clone();
+                state.card = self.template.card.clone();
+                state.card_tiles = self.template.tiles.clone();
+            }
+            state.odf_path = Some(path);
+        }
+
+        for mapping in &self.mappings {
+            if let Some(expression) = &mapping.expression {
+                state.derived_mappings.push(DerivedMapping {
+                    expression: expression.clone(),
+                    cell_ref: mapping.cell.trim().to_ascii_uppercase(),
+                    value_type: mapping.value_type,
+                    format: mapping.format.clone().unwrap_or_default(),
+                    detail: mapping.detail,
+                });
+                continue;
+            }
+            let column_index = state
+                .csv_headers
+                .iter()
+                .position(|header| header == &mapping.column)
+                .ok_or_else(|| format!("CSV column \"{}\" not found", mapping.column))?;
+            let cell_mapping = &mut state.cell_mappings[column_index];
+            if !cell_mapping.cell_ref.is_empty() {
+                return Err(format!("CSV column \"{}\" is mapped twice", mapping.column));
+            }
+            cell_mapping.cell_ref = mapping.cell.trim().to_ascii_uppercase();
+            cell_mapping.value_type = mapping.value_type;
+            cell_mapping.format = mapping.format.clone().unwrap_or_default();
+            cell_mapping.detail = mapping.detail;
+        }
+        Ok(())
+    }
+}
+
+/// The directory that relative paths in the project file refer to.
+pub fn project_base_dir(project_path: &Path) -> PathBuf {
+    match project_path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+        _ => PathBuf::from("."),
+    }
+}
+
+/// Expresses `path` relative to `base_dir` when both are on the same drive,
+/// using `/` as separator.
+fn store_path(path: &Path, base_dir: &Path) -> String {
+    let (Ok(path), Ok(base_dir)) = (std::path::absolute(path), std::path::absolute(base_dir))
+    else {
+        return path.to_string_lossy().into_owned();
+    };
+    let path_components = normalized_components(&path);
+    let base_components = normalized_components(&base_dir);
+    if path_components.first() != base_components.first() {
+        return path.to_string_lossy().into_owned();
+    }
+
+    let common = path_components
+        .iter()
+        .zip(&base_components)
+        .take_while(|(left, right)| left == right)
+        .count();
+    let parts = base_components[common..]
+        .iter()
+        .map(|_| "..".to_string())
+        .chain(
+            path_components[common..]
+                .iter()
+                .map(|component| component.as_os_str().to_string_lossy().into_owned()),
+        )
+        .collect::<Vec<_>>();
+    parts.join("/")
+}
+
+fn resolve_path(stored: &str, base_dir: &Path) -> PathBuf {
+    let path = PathBuf::from(stored);
+    if path.is_absolute() {
+        path
+    } else {
+        normalized_components(&base_dir.join(path)).iter().collect()
+    }
+}
+
+/// Drops `.` components and folds `..` into the preceding directory.
+fn normalized_components(path: &Path) -> Vec<Component<'_>> {
+    let mut components: Vec<Component> = Vec::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir if matches!(components.last(), Some(Component::Normal(_))) => {
+                components.pop();
+            }
+            component => components.push(component),
+        }
+    }
+    components
+}
+
+fn is_zero(value: &usize) -> bool {
+    *value == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("project-{}-{}", std::process::id(), name))
+    }
+
+    #[test]
+    fn stores_paths_relative_to_the_project() {
+        let base_dir = temp_dir("paths").join("projects");
+        let stored = |path: PathBuf| {
+            let stored = store_path(&path, &base_dir);
+            assert_eq!(resolve_path(&stored, &base_dir), path);
+            stored
+        };
+        assert_eq!(stored(base_dir.join("data.csv")), "data.csv");
+        assert_eq!(stored(base_dir.join("in").join("data.csv")), "in/data.csv");
+        assert_eq!(
+            stored(base_dir.with_file_name("shared").join("data.csv")),
+            "../shared/data.csv"
+        );
+        assert_eq!(
+            store_path(
+                &base_dir.join(".").join("x").join("..").join("a.csv"),
+                &base_dir
+            ),
+            "a.csv"
+        );
+        assert_eq!(
+            resolve_path("./in/../a.csv", &base_dir),
+            base_dir.join("a.csv")
+        );
+
+        assert_eq!(project_base_dir(Path::new("monthly.toml")), Path::new("."));
+        assert_eq!(
+            project_base_dir(&base_dir.join("monthly.toml")),
+            base_dir.as_path()
+        );
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn keeps_paths_on_other_drives_absolute() {
+        assert_eq!(
+            store_path(Path::new(r"D:\data\a.csv"), Path::new(r"C:\projects")),
+            r"D:\data\a.csv"
+        );
+        assert_eq!(
+            resolve_path(r"D:\data\a.csv", Path::new(r"C:\projects")),
+            Path::new(r"D:\data\a.csv")
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn relates_paths_through_the_root_directory() {
+        assert_eq!(
+            resolve_path("/data/a.csv", Path::new("/home/projects")),
+            Path::new("/data/a.csv")
+        );
+        assert_eq!(
+            store_path(Path::new("/data/a.csv"), Path::new("/home/projects")),
+            "../../data/a.csv"
+        );
+    }
+
+    #[test]
+    fn saves_and_loads_relative_paths() {
+        let dir = temp_dir("round-trip");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("projects")).unwrap();
+        let project_path = dir.join("projects").join("monthly.toml");
+        let state = SharedState {
+            csv_path: Some(dir.join("data").join("orders.csv")),
+            odf_path: Some(dir.join("projects").join("invoice.xlsx")),
+            selected_sheet: Some("Invoice".to_string()),
+            ..SharedState::default()
+        };
+
+        Project::from_state(&state, &project_base_dir(&project_path))
+            .save(&project_path)
+            .unwrap();
+        let text = std::fs::read_to_string(&project_path).unwrap();
+        assert!(text.contains("path = \"../data/orders.csv\""), "{}", text);
+
+        let project = Project::load(&project_path).unwrap();
+        let base_dir = project_base_dir(&project_path);
+        let resolved = |path: &Option<String>| resolve_path(path.as_deref().unwrap(), &base_dir);
+        assert_eq!(Some(resolved(&project.csv.path)), state.csv_path);
+        assert_eq!(Some(resolved(&project.template.path)), state.odf_path);
+        assert_eq!(project.template.path.as_deref(), Some("invoice.xlsx"));
+        assert_eq!(project.template.sheet.as_deref(), Some("Invoice"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,18 +1,70 @@
+use bulk_sheet_engine::{
+    CardLayout, CellValue, CsvDialect, CsvOptions, DataSource, DataTable, Grouping, JsonOptions,
+    KeptSheet, Mapping, OutputMode, OutputOptions, OutputTarget, RowBlock, RowFilter,
+    SqliteOptions, Template, TemplateFormat, ValueType, bind_placeholders, is_json_data,
+    is_sqlite_data, is_workbook_data,
+};
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+
+/// Rows of a data file loaded for the preview, the mappings and the row
+/// selection; generation reads the whole file.
+pub const PREVIEW_ROWS: usize = 1000;
 
 #[derive(Default)]
 pub struct SharedState {
     pub csv_path: Option<PathBuf>,
     pub csv_has_headers: bool,
+    /// Import options of a CSV file and the dialect it was read with.
+    pub csv_options: CsvOptions,
+    pub csv_dialect: Option<CsvDialect>,
+    /// Records array and the array expanded into rows of a JSON file.
+    pub json_options: JsonOptions,
+    /// The data step reads a SQLite database instead of a file, with the
+    /// table or query to read and the tables of the database.
+    pub use_database: bool,
+    pub sqlite_options: SqliteOptions,
+    pub sqlite_tables: Vec<String>,
     pub csv_headers: Vec<String>,
+    /// The first [`PREVIEW_ROWS`] rows of the data file.
     pub csv_rows: Vec<Vec<String>>,
+    /// Rows of the whole data file, once counted in the background.
+    pub csv_row_count: Option<Result<usize, String>>,
+    pub row_count_receiver: Option<Receiver<Result<usize, String>>>,
+    /// Numbers, booleans and dates of a workbook data file by row and column.
+    pub csv_typed: Vec<Vec<Option<CellValue>>>,
+    /// Sheet, rows above the header row and cell range read from a workbook
+    /// data file.
+    pub data_sheet_names: Vec<String>,
+    pub data_sheet: Option<String>,
+    pub data_skip_rows: usize,
+    pub data_range: String,
     pub csv_preview: Vec<ColumnPreview>,
+    /// CSV rows chosen for generation.
+    pub row_filter: RowFilter,
+    /// Sort order and key columns that combine rows into one sheet or file.
+    pub grouping: Grouping,
     pub odf_path: Option<PathBuf>,
     pub odf_sheet_names: Vec<String>,
     pub selected_sheet: Option<String>,
     pub template_cell_values: HashMap<String, String>,
+    pub template_placeholders: Vec<String>,
+    /// Template rows repeated per row of a group, such as `A10:F10`.
+    pub repeat_rows: String,
+    /// Card range such as `A1:C6` filled once per record, and the cards
+    /// across and down per sheet such as `2x5`.
+    pub card: String,
+    pub card_tiles: String,
     pub cell_mappings: Vec<CellMapping>,
+    pub derived_mappings: Vec<DerivedMapping>,
+    pub output_mode: OutputMode,
+    pub output_target: OutputTarget,
+    pub file_name_pattern: String,
+    pub sheet_name_pattern: String,
+    /// Template sheets copied unchanged next to the generated sheets.
+    pub kept_sheets: Vec<KeptSheet>,
     pub last_output_path: Option<PathBuf>,
 }
 
@@ -21,18 +73,91 @@ impl SharedState {
         self.csv_path = None;
         self.csv_headers.clear();
         self.csv_rows.clear();
+        self.csv_typed.clear();
+        self.reset_row_count();
         self.csv_preview.clear();
+        self.data_sheet_names.clear();
+        self.data_sheet = None;
+        self.data_skip_rows = 0;
+        self.data_range.clear();
         self.csv_has_headers = false;
+        self.csv_options = CsvOptions::default();
+        self.csv_dialect = None;
+        self.json_options = JsonOptions::default();
+        self.sqlite_options = SqliteOptions::default();
+        self.sqlite_tables.clear();
+        self.row_filter = RowFilter::default();
+        self.grouping = Grouping::default();
         self.cell_mappings.clear();
     }
 
+    /// Forgets the row count after `csv_rows` changed. It is known right away
+    /// when fewer than [`PREVIEW_ROWS`] rows were loaded.
+    pub fn reset_row_count(&mut self) {
+        self.row_count_receiver = None;
+        self.csv_row_count =
+            (self.csv_rows.len() < PREVIEW_ROWS).then_some(Ok(self.csv_rows.len()));
+    }
+
+    /// Counts the rows of a partly loaded data file on a background thread.
+    /// Call it every frame; returns whether the count is still running.
+    pub fn update_row_count(&mut self) -> bool {
+        if let Some(receiver) = &self.row_count_receiver {
+            self.csv_row_count = match receiver.try_recv() {
+                Ok(count) => Some(count),
+                Err(TryRecvError::Empty) => return true,
+                Err(TryRecvError::Disconnected) => Some(Err("Counting failed".to_string())),
+            };
+            self.row_count_receiver = None;
+            return false;
+        }
+        let Some(path) = &self.csv_path else {
+            return false;
+        };
+        if self.csv_row_count.is_some() || self.csv_rows.is_empty() {
+            return false;
+        }
+        let source = self.data_source(path);
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            // The receiver is gone when another file was loaded meanwhile.
+            let _ = sender.send(source.count_rows());
+        });
+        self.row_count_receiver = Some(receiver);
+        true
+    }
+
+    /// Whether `csv_rows` holds every row of the data file.
+    pub fn has_all_rows(&self) -> bool {
+        matches!(self.csv_row_count, Some(Ok(count)) if count == self.csv_rows.len())
+    }
+
+    /// How many rows were loaded, e.g. `First 1000 of 250000 rows loaded`.
+    pub fn row_count_text(&self) -> String {
+        let loaded = self.csv_rows.len();
+        match &self.csv_row_count {
+            _ if self.has_all_rows() => format!("{} rows", loaded),
+            Some(Ok(count)) => format!("First {} of {} rows loaded", loaded, count),
+            Some(Err(_)) => format!("First {} rows loaded", loaded),
+            None => format!("First {} rows loaded, counting the rest…", loaded),
+        }
+    }
+
     pub fn reset_template(&mut self) {
         self.odf_path = None;
         self.odf_sheet_names.clear();
         self.selected_sheet = None;
         self.template_cell_values.clear();
+        self.template_placeholders.clear();
+        self.repeat_rows.clear();
+        self.card.clear();
+        self.card_tiles.clear();
+        self.file_name_pattern.clear();
+        self.sheet_name_pattern.clear();
+        self.kept_sheets.clear();
+        self.derived_mappings.clear();
         for mapping in &mut self.cell_mappings {
-            mapping.cell_ref.clear();
+            *mapping = CellMapping::new(mapping.column_index, String::new());
         }
     }
 
@@ -46,6 +171,148 @@ impl SharedState {
                 .push(CellMapping::new(column_index, String::new()));
         }
     }
+
+    pub fn template_format(&self) -> Option<TemplateFormat> {
+        self.odf_path.as_deref().map(TemplateFormat::from_path)
+    }
+
+    pub fn template(&self) -> Option<Template> {
+        self.odf_path.as_ref().map(|path| Template {
+            path: path.clone(),
+            sheet: self.selected_sheet.clone(),
+            repeat_rows: RowBlock::parse(&self.repeat_rows),
+            cards: CardLayout::parse(&self.card, &self.card_tiles).ok(),
+        })
+    }
+
+    /// How the data file at `path` is read with the current settings.
+    pub fn data_source(&self, path: &Path) -> DataSource {
+        if is_sqlite_data(path) {
+            DataSource::Sqlite {
+                path: path.to_path_buf(),
+                options: self.sqlite_options.clone(),
+            }
+        } else if is_json_data(path) {
+            DataSource::Json {
+                path: path.to_path_buf(),
+                options: self.json_options.clone(),
+            }
+        } else if is_workbook_data(path) {
+            DataSource::Workbook {
+                path: path.to_path_buf(),
+                sheet: self.data_sheet.clone(),
+                has_headers: self.csv_has_headers,
+                skip_rows: self.data_skip_rows,
+                range: Some(self.data_range.clone()).filter(|range| !range.trim().is_empty()),
+            }
+        } else {
+            DataSource::Csv {
+                path: path.to_path_buf(),
+                has_headers: self.csv_has_headers,
+                options: self.csv_options.clone(),
+            }
+        }
+    }
+
+    pub fn data_table(&self) -> DataTable {
+        DataTable {
+            headers: self.csv_headers.clone(),
+            rows: self.csv_rows.clone(),
+            row_numbers: Vec::new(),
+            typed: self.csv_typed.clone(),
+        }
+    }
+
+    /// The CSV rows that pass the row filter, keeping their row numbers.
+    pub fn selected_data(&self) -> Result<DataTable, String> {
+        let data = self.data_table();
+        if self.has_all_rows() {
+            return self.row_filter.apply(&data);
+        }
+        // Rows past the preview may still match.
+        Ok(data.select(&self.row_filter.indices(&data)?))
+    }
+
+    /// The configured cell mappings, referring to columns by name, followed
+    /// by the derived ones.
+    pub fn mappings(&self) -> Vec<Mapping> {
+        let columns = self
+            .cell_mappings
+            .iter()
+            .filter(|mapping| !mapping.cell_ref.trim().is_empty())
+            .filter_map(|mapping| {
+                self.csv_headers.get(mapping.column_index).map(|column| {
+                    Mapping::new(column.clone(), mapping.cell_ref.clone())
+                        .with_type(
+                            mapping.value_type,
+                            source_format(mapping.value_type, &mapping.format),
+                        )
+                        .with_detail(mapping.detail)
+                })
+            });
+        let derived = self
+            .derived_mappings
+            .iter()
+            .filter(|mapping| {
+                !mapping.cell_ref.trim().is_empty() && !mapping.expression.trim().is_empty()
+            })
+            .map(|mapping| {
+                Mapping::derived(mapping.expression.clone(), mapping.cell_ref.clone())
+                    .with_type(
+                        mapping.value_type,
+                        source_format(mapping.value_type, &mapping.format),
+                    )
+                    .with_detail(mapping.detail)
+            });
+        columns.chain(derived).collect()
+    }
+
+    /// The per-row file name pattern, falling back to one built from the
+    /// template name.
+    pub fn effective_file_name_pattern(&self) -> String {
+        match self.template() {
+            Some(template) => self
+                .output_options(Path::new(""))
+                .effective_file_name_pattern(&template),
+            None => self.file_name_pattern.clone(),
+        }
+    }
+
+    /// The sheet name pattern, falling back to the template sheet name.
+    pub fn effective_sheet_name_pattern(&self) -> String {
+        let sheet = self.selected_sheet.as_deref().unwrap_or("Sheet");
+        self.output_options(Path::new(""))
+            .effective_sheet_name_pattern(sheet)
+    }
+
+    pub fn output_options(&self, path: &Path) -> OutputOptions {
+        OutputOptions {
+            path: path.to_path_buf(),
+            mode: self.output_mode,
+            target: self.output_target,
+            file_name_pattern: self.file_name_pattern.clone(),
+            sheet_name_pattern: self.sheet_name_pattern.clone(),
+            // The template sheet is always copied per row.
+            kept_sheets: self
+                .kept_sheets
+                .iter()
+                .filter(|kept| Some(&kept.name) != self.selected_sheet.as_ref())
+                .cloned()
+                .collect(),
+        }
+    }
+
+    /// Placeholders of the template or template sheet that match a CSV header.
+    pub fn bound_placeholders(&self) -> Vec<(String, usize)> {
+        bind_placeholders(&self.template_placeholders, &self.csv_headers)
+    }
+}
+
+/// The date format of a mapping, if its type uses one and it is set.
+fn source_format(value_type: ValueType, format: &str) -> Option<String> {
+    Some(format.trim())
+        .filter(|format| value_type.uses_format() && !format.is_empty())
+        .map(str::to_string)
 }
 
 #[derive(Clone, Default)]
@@ -59,6 +326,11 @@ pub struct ColumnPreview {
 pub struct CellMapping {
     pub column_index: usize,
     pub cell_ref: String,
+    pub value_type: ValueType,
+    /// Source format of date values; empty for the default.
+    pub format: String,
+    /// Writes every row of a group below each other.
+    pub detail: bool,
 }
 
 impl CellMapping {
@@ -66,40 +338,22 @@ impl CellMapping {
         Self {
             column_index,
             cell_ref: cell_ref.into(),
+            value_type: ValueType::Auto,
+            format: String::new(),
+            detail: false,
         }
     }
 }
 
-pub fn parse_cell_reference(cell: &str) -> Option<(u32, u32)> {
-    if cell.is_empty() {
-        return None;
-    }
-
-    let mut col_index: u32 = 0;
-    let mut row_part = String::new();
-    for ch in cell.chars() {
-        if ch.is_ascii_alphabetic() {
-            col_index = col_index * 26 + u32::from((ch.to_ascii_uppercase() as u8) - b'A' + 1);
-        } else if ch.is_ascii_digit() {
-            row_part.push(ch);
-        } else {
-            return None;
-        }
-    }
-    if col_index == 0 || row_part.is_empty() {
-        return None;
-    }
-    let row = row_part.parse::<u32>().ok()?.saturating_sub(1);
-    Some((row, col_index - 1))
-}
-
-pub fn column_label_from_index(index: u32) -> String {
-    let mut idx = index + 1;
-    let mut label = String::new();
-    while idx > 0 {
-        let rem = ((idx - 1) % 26) as u8;
-        label.insert(0, char::from(b'A' + rem));
-        idx = (idx - 1) / 26;
-    }
-    label
+/// A template cell filled with a value derived from the row, such as
+/// `{{First}} {{Last}}`.
+#[derive(Clone, Default)]
+pub struct DerivedMapping {
+    pub expression: String,
+    pub cell_ref: String,
+    pub value_type: ValueType,
+    /// Source format of date values; empty for the default.
+    pub format: String,
+    /// Writes every row of a group below each other.
+    pub detail: bool,
 }